@@ -1,18 +1,27 @@
 mod bufferedstream {
 	use std::io::{self, Read, Write};
+	const DEFAULT_BUF_SIZE: usize = 8 * 1024; // that of BufReader and BufWriter
 	#[derive(Debug)]
 	pub struct BufferedStream<T: Read + Write> {
 		stream: io::BufReader<T>,
+		write_capacity: usize,
 	}
 	impl<T: Read + Write> BufferedStream<T> {
 		pub fn new(stream: T) -> Self {
+			Self::with_capacity(stream, DEFAULT_BUF_SIZE, DEFAULT_BUF_SIZE)
+		}
+
+		/// Buffer reads with a buffer of `read_capacity` bytes, and writes through [`write()`](BufferedStream::write) with one of `write_capacity` bytes. Larger buffers mean fewer syscalls for large transfers.
+		pub fn with_capacity(stream: T, read_capacity: usize, write_capacity: usize) -> Self {
 			Self {
-				stream: io::BufReader::new(stream),
+				stream: io::BufReader::with_capacity(read_capacity, stream),
+				write_capacity,
 			}
 		}
 
 		pub fn write(&mut self) -> BufferedStreamWriter<T> {
-			BufferedStreamWriter(io::BufWriter::new(self))
+			let write_capacity = self.write_capacity;
+			BufferedStreamWriter(io::BufWriter::with_capacity(write_capacity, self))
 		}
 
 		pub fn get_ref(&self) -> &T {
@@ -104,23 +113,41 @@ pub use self::to_hex::ToHex;
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 mod rand_stream {
-	use rand;
+	use rand::{self, SeedableRng};
+
+	/// An RNG that makes the same choices whenever it's created with the same `seed`, for reproducible runs.
+	pub fn seeded_rng(seed: u64) -> rand::XorShiftRng {
+		let mut bytes = [0; 16];
+		for (i, byte) in bytes.iter_mut().enumerate() {
+			*byte = (seed >> (8 * (i % 8))) as u8;
+		}
+		rand::XorShiftRng::from_seed(bytes)
+	}
+
 	#[derive(Debug)]
 	pub struct Rand<T> {
 		res: Option<T>,
-		count: usize,
+		total: u64,
 	}
 	impl<T> Rand<T> {
 		pub fn new() -> Self {
 			Self {
 				res: None,
-				count: 0,
+				total: 0,
 			}
 		}
 
 		pub fn push<R: rand::Rng>(&mut self, x: T, rng: &mut R) {
-			self.count += 1;
-			if rng.gen_range(0, self.count) == 0 {
+			self.push_weighted(x, 1, rng)
+		}
+
+		/// Push `x`, such that it's chosen with probability proportional to `weight`. A zero `weight` is never chosen.
+		pub fn push_weighted<R: rand::Rng>(&mut self, x: T, weight: u64, rng: &mut R) {
+			if weight == 0 {
+				return;
+			}
+			self.total += weight;
+			if rng.gen_range(0, self.total) < weight {
 				self.res = Some(x);
 			}
 		}
@@ -134,45 +161,54 @@ mod rand_stream {
 			Self::new()
 		}
 	}
+
+	/// Choose up to `k` items uniformly at random from a stream, by reservoir sampling (Algorithm R).
+	#[derive(Debug)]
+	pub struct RandK<T> {
+		res: Vec<T>,
+		k: usize,
+		total: u64,
+	}
+	impl<T> RandK<T> {
+		pub fn new(k: usize) -> Self {
+			Self {
+				res: Vec::with_capacity(k),
+				k,
+				total: 0,
+			}
+		}
+
+		pub fn push<R: rand::Rng>(&mut self, x: T, rng: &mut R) {
+			self.total += 1;
+			if self.res.len() < self.k {
+				self.res.push(x);
+			} else {
+				let i = rng.gen_range(0, self.total);
+				if i < self.k as u64 {
+					self.res[i as usize] = x;
+				}
+			}
+		}
+
+		/// The chosen items: all of them if fewer than `k` were pushed. Their order isn't random.
+		pub fn get(self) -> Vec<T> {
+			self.res
+		}
+	}
 }
-pub use self::rand_stream::Rand;
+pub use self::rand_stream::{seeded_rng, Rand, RandK};
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Parse a size like `512`, `100MiB` or `1.5GiB` into a number of bytes, rounded down. Returns an error, rather than panicking, on anything else, or if the size doesn't fit in a `u64`.
 pub fn parse_binary_size(input: &str) -> Result<u64, ()> {
-	let mut index = 0;
-	if index == input.len() {
-		return Err(());
-	}
-	index = input
-		.chars()
-		.position(|c| !c.is_ascii_digit())
-		.unwrap_or(input.len());
-	let a: u64 = input[..index].parse().unwrap();
-	if index == input.len() {
-		return Ok(a);
-	}
-	let b: u64 = if input[index..=index].chars().nth(0).ok_or(())? == '.' {
-		index += 1;
-		let _zeros = input[index..].chars().position(|c| c != '0').unwrap_or(0);
-		let index1 = index;
-		index = index + input[index..]
-			.chars()
-			.position(|c| !c.is_ascii_digit())
-			.unwrap_or(input.len() - index);
-		if index != index1 {
-			input[index1..index].parse().unwrap()
-		} else {
-			0
-		}
-	} else {
-		0
-	};
-	if index == input.len() {
-		return Ok(a);
-	}
-	let c: u64 = match &input[index..] {
-		"B" => 1,
+	let (number, unit) = input.split_at(
+		input
+			.find(|c: char| !c.is_ascii_digit() && c != '.')
+			.unwrap_or(input.len()),
+	);
+	let multiplier: u64 = match unit {
+		"" | "B" => 1,
 		"KiB" => 1024,
 		"MiB" => 1024_u64.pow(2),
 		"GiB" => 1024_u64.pow(3),
@@ -181,8 +217,595 @@ pub fn parse_binary_size(input: &str) -> Result<u64, ()> {
 		"EiB" => 1024_u64.pow(6),
 		_ => return Err(()),
 	};
-	if b > 0 {
-		unimplemented!();
+	let (whole, fraction) = match number.find('.') {
+		Some(index) => (&number[..index], Some(&number[index + 1..])),
+		None => (number, None),
+	};
+	if whole.is_empty() {
+		return Err(());
+	}
+	let mut bytes = whole
+		.parse::<u64>()
+		.map_err(|_| ())?
+		.checked_mul(multiplier)
+		.ok_or(())?;
+	if let Some(fraction) = fraction {
+		if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+			return Err(());
+		}
+		// Digits past the 19th are worth less than a byte even in EiB, and could overflow
+		let fraction = fraction.get(..19).unwrap_or(fraction);
+		let numerator = fraction.parse::<u128>().unwrap() * u128::from(multiplier);
+		let fraction = numerator / 10_u128.pow(fraction.len() as u32);
+		bytes = bytes.checked_add(fraction as u64).ok_or(())?;
+	}
+	Ok(bytes)
+}
+
+/// Parse a 128-bit key written as 32 hex digits.
+pub fn parse_key(input: &str) -> Option<[u8; 16]> {
+	if input.len() != 32 || !input.is_ascii() {
+		return None;
+	}
+	let mut key = [0; 16];
+	for (i, byte) in key.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&input[i * 2..i * 2 + 2], 16).ok()?;
+	}
+	Some(key)
+}
+
+pub fn format_binary_size(bytes: u64) -> String {
+	let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+	let (mut size, mut unit) = (bytes, 0);
+	while size != 0 && size % 1024 == 0 && unit < units.len() - 1 {
+		size /= 1024;
+		unit += 1;
+	}
+	format!("{}{}", size, units[unit])
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(unix)]
+mod file_hash {
+	use sha2::{Digest, Sha256};
+	use std::{convert::TryFrom, fs, io, os::unix::fs::FileExt};
+
+	/// The SHA-256 of the contents of `file`, without moving its offset. It's strong enough that contents can be identified by it alone.
+	pub fn file_hash(file: &fs::File) -> Result<[u8; 32], io::Error> {
+		let mut hasher = Sha256::new();
+		let mut buf = vec![0; 64 * 1024];
+		let mut offset: u64 = 0;
+		loop {
+			let n = file.read_at(&mut buf, offset)?;
+			if n == 0 {
+				break Ok(finish(hasher));
+			}
+			hasher.input(&buf[..n]);
+			offset += u64::try_from(n).unwrap();
+		}
+	}
+
+	/// The SHA-256 of `bytes`, the same as [`file_hash()`] would give for a file of them.
+	pub fn bytes_hash(bytes: &[u8]) -> [u8; 32] {
+		let mut hasher = Sha256::new();
+		hasher.input(bytes);
+		finish(hasher)
+	}
+
+	fn finish(hasher: Sha256) -> [u8; 32] {
+		let mut hash = [0; 32];
+		hash.copy_from_slice(&hasher.result());
+		hash
+	}
+}
+#[cfg(unix)]
+pub use self::file_hash::{bytes_hash, file_hash};
+
+/// The HMAC-SHA-256 of `message` with `key`, which only a holder of `key` can produce.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+	use sha2::{Digest, Sha256};
+	const BLOCK: usize = 64;
+	let mut block = [0; BLOCK];
+	if key.len() > BLOCK {
+		block[..32].copy_from_slice(&Sha256::digest(key));
+	} else {
+		block[..key.len()].copy_from_slice(key);
+	}
+	let mut inner = Sha256::new();
+	inner.input(&block.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+	inner.input(message);
+	let mut outer = Sha256::new();
+	outer.input(&block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+	outer.input(&inner.result());
+	let mut hmac = [0; 32];
+	hmac.copy_from_slice(&outer.result());
+	hmac
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+mod layout {
+	use serde::de::{
+		self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor
+	};
+	use std::{error, fmt};
+
+	/// How deeply nested a type is traced, so that recursive types terminate.
+	const DEPTH_MAX: usize = 32;
+	/// How many passes are made, each taking a different variant of each enum.
+	const PASSES_MAX: usize = 64;
+
+	/// A hash of the shape `T` deserializes as: the names, fields and variants of its structs and enums, and the primitives they're made of.
+	///
+	/// Unlike hashing its `type_name()` and `size_of()` with `DefaultHasher`, it's the same whichever compiler or target it's built with, and it changes if and only if the shape does. Each pass over `T` takes a different variant of each enum, so every variant of an outermost enum is covered. Tracing stops at a value `T` rejects, like a string it parses, so what's after it isn't covered.
+	pub fn layout_hash<T: DeserializeOwned>() -> u64 {
+		let mut tracer = Tracer {
+			hash: 0xcbf2_9ce4_8422_2325,
+			depth: 0,
+			pass: 0,
+			passes: 1,
+		};
+		while tracer.pass < tracer.passes {
+			tracer.depth = 0;
+			let _ = T::deserialize(&mut tracer);
+			tracer.pass += 1;
+		}
+		tracer.hash
+	}
+
+	struct Tracer {
+		/// FNV-1a, as it's simple and fixed.
+		hash: u64,
+		depth: usize,
+		pass: usize,
+		passes: usize,
+	}
+	impl Tracer {
+		fn write(&mut self, name: &str) {
+			// Each name ends with a byte that can't occur in a str, so consecutive names are unambiguous
+			for &byte in name.as_bytes().iter().chain(&[0xff]) {
+				self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+			}
+		}
+
+		fn nested<R, F: FnOnce(&mut Self) -> Result<R, Error>>(
+			&mut self, f: F,
+		) -> Result<R, Error> {
+			if self.depth == DEPTH_MAX {
+				return Err(Error);
+			}
+			self.depth += 1;
+			let ret = f(self);
+			self.depth -= 1;
+			ret
+		}
+	}
+
+	#[derive(Debug)]
+	struct Error;
+	impl fmt::Display for Error {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			write!(f, "untraceable")
+		}
+	}
+	impl error::Error for Error {
+		fn description(&self) -> &str {
+			"untraceable"
+		}
+	}
+	impl de::Error for Error {
+		fn custom<T: fmt::Display>(_msg: T) -> Self {
+			Error
+		}
+	}
+
+	macro_rules! primitives {
+		($($method:ident $name:expr, $visit:ident $value:expr;)*) => {$(
+			fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+				self.write($name);
+				visitor.$visit($value)
+			}
+		)*};
+	}
+
+	impl<'a, 'de> Deserializer<'de> for &'a mut Tracer {
+		type Error = Error;
+
+		primitives! {
+			deserialize_bool "bool", visit_bool false;
+			deserialize_i8 "i8", visit_i8 0;
+			deserialize_i16 "i16", visit_i16 0;
+			deserialize_i32 "i32", visit_i32 0;
+			deserialize_i64 "i64", visit_i64 0;
+			deserialize_u8 "u8", visit_u8 0;
+			deserialize_u16 "u16", visit_u16 0;
+			deserialize_u32 "u32", visit_u32 0;
+			deserialize_u64 "u64", visit_u64 0;
+			deserialize_f32 "f32", visit_f32 0.0;
+			deserialize_f64 "f64", visit_f64 0.0;
+			deserialize_char "char", visit_char '\0';
+			deserialize_str "str", visit_str "";
+			deserialize_string "str", visit_str "";
+			deserialize_bytes "bytes", visit_bytes &[];
+			deserialize_byte_buf "bytes", visit_bytes &[];
+			deserialize_identifier "identifier", visit_u32 0;
+		}
+
+		fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+			self.write("any");
+			visitor.visit_unit()
+		}
+
+		fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+			visitor.visit_unit()
+		}
+
+		fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+			self.write("unit");
+			visitor.visit_unit()
+		}
+
+		fn deserialize_unit_struct<V: Visitor<'de>>(
+			self, name: &'static str, visitor: V,
+		) -> Result<V::Value, Error> {
+			self.write("unit_struct");
+			self.write(name);
+			visitor.visit_unit()
+		}
+
+		fn deserialize_newtype_struct<V: Visitor<'de>>(
+			self, name: &'static str, visitor: V,
+		) -> Result<V::Value, Error> {
+			self.write("newtype_struct");
+			self.write(name);
+			self.nested(|tracer| visitor.visit_newtype_struct(tracer))
+		}
+
+		fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+			self.write("option");
+			self.nested(|tracer| visitor.visit_some(tracer))
+		}
+
+		fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+			self.write("seq");
+			self.nested(|tracer| visitor.visit_seq(Elements(tracer, 1)))
+		}
+
+		fn deserialize_tuple<V: Visitor<'de>>(
+			self, len: usize, visitor: V,
+		) -> Result<V::Value, Error> {
+			self.write("tuple");
+			self.write(&len.to_string());
+			self.nested(|tracer| visitor.visit_seq(Elements(tracer, len)))
+		}
+
+		fn deserialize_tuple_struct<V: Visitor<'de>>(
+			self, name: &'static str, len: usize, visitor: V,
+		) -> Result<V::Value, Error> {
+			self.write("tuple_struct");
+			self.write(name);
+			self.write(&len.to_string());
+			self.nested(|tracer| visitor.visit_seq(Elements(tracer, len)))
+		}
+
+		fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+			self.write("map");
+			self.nested(|tracer| visitor.visit_map(Elements(tracer, 1)))
+		}
+
+		fn deserialize_struct<V: Visitor<'de>>(
+			self, name: &'static str, fields: &'static [&'static str], visitor: V,
+		) -> Result<V::Value, Error> {
+			self.write("struct");
+			self.write(name);
+			for field in fields {
+				self.write(field);
+			}
+			self.nested(|tracer| visitor.visit_seq(Elements(tracer, fields.len())))
+		}
+
+		fn deserialize_enum<V: Visitor<'de>>(
+			self, name: &'static str, variants: &'static [&'static str], visitor: V,
+		) -> Result<V::Value, Error> {
+			self.write("enum");
+			self.write(name);
+			for variant in variants {
+				self.write(variant);
+			}
+			if variants.is_empty() {
+				return Err(Error);
+			}
+			self.passes = self.passes.max(variants.len()).min(PASSES_MAX);
+			let variant = (self.pass % variants.len()) as u32;
+			self.nested(|tracer| visitor.visit_enum(Variant(tracer, variant)))
+		}
+
+		fn is_human_readable(&self) -> bool {
+			false
+		}
+	}
+
+	/// The elements of a seq, tuple or struct, or the entries of a map: as many as a tuple or struct has, or one, to trace the type of those of a seq or map.
+	struct Elements<'a>(&'a mut Tracer, usize);
+	impl<'a, 'de> SeqAccess<'de> for Elements<'a> {
+		type Error = Error;
+
+		fn next_element_seed<S: DeserializeSeed<'de>>(
+			&mut self, seed: S,
+		) -> Result<Option<S::Value>, Error> {
+			if self.1 == 0 {
+				return Ok(None);
+			}
+			self.1 -= 1;
+			seed.deserialize(&mut *self.0).map(Some)
+		}
+	}
+	impl<'a, 'de> MapAccess<'de> for Elements<'a> {
+		type Error = Error;
+
+		fn next_key_seed<S: DeserializeSeed<'de>>(
+			&mut self, seed: S,
+		) -> Result<Option<S::Value>, Error> {
+			self.next_element_seed(seed)
+		}
+
+		fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Error> {
+			seed.deserialize(&mut *self.0)
+		}
+	}
+
+	/// The variant of an enum taken in this pass.
+	struct Variant<'a>(&'a mut Tracer, u32);
+	impl<'a, 'de> EnumAccess<'de> for Variant<'a> {
+		type Error = Error;
+		type Variant = Self;
+
+		fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), Error> {
+			let variant =
+				seed.deserialize(IntoDeserializer::<'de, Error>::into_deserializer(self.1))?;
+			Ok((variant, self))
+		}
+	}
+	impl<'a, 'de> VariantAccess<'de> for Variant<'a> {
+		type Error = Error;
+
+		fn unit_variant(self) -> Result<(), Error> {
+			Ok(())
+		}
+
+		fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Error> {
+			seed.deserialize(self.0)
+		}
+
+		fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+			self.0.deserialize_tuple(len, visitor)
+		}
+
+		fn struct_variant<V: Visitor<'de>>(
+			self, fields: &'static [&'static str], visitor: V,
+		) -> Result<V::Value, Error> {
+			self.0.deserialize_struct("", fields, visitor)
+		}
+	}
+}
+pub use self::layout::layout_hash;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{self, SeedableRng};
+
+	#[test]
+	fn seeded_rng_reproducible() {
+		let choices = |seed| {
+			let mut rng = seeded_rng(seed);
+			(0..100)
+				.map(|_| {
+					let mut rand = Rand::new();
+					for i in 0..10 {
+						rand.push(i, &mut rng);
+					}
+					rand.get().unwrap()
+				})
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(choices(42), choices(42));
+		assert_ne!(choices(42), choices(43));
+		assert_ne!(choices(1), choices(1 << 32));
+	}
+
+	#[test]
+	fn rand_k() {
+		let mut rng = rand::XorShiftRng::from_seed([1; 16]);
+		let mut rand = RandK::new(3);
+		rand.push(0, &mut rng);
+		rand.push(1, &mut rng);
+		assert_eq!(rand.get(), vec![0, 1]);
+		let mut rand = RandK::new(0);
+		rand.push(0, &mut rng);
+		assert_eq!(rand.get(), Vec::<i32>::new());
+
+		// Each of 5 items is chosen by 2 of 5 samples
+		let mut counts = [0; 5];
+		for _ in 0..5000 {
+			let mut rand = RandK::new(2);
+			for i in 0..5 {
+				rand.push(i, &mut rng);
+			}
+			let chosen = rand.get();
+			assert_eq!(chosen.len(), 2);
+			assert_ne!(chosen[0], chosen[1]);
+			for i in chosen {
+				counts[i] += 1;
+			}
+		}
+		assert!(
+			counts.iter().all(|&count| 1800 < count && count < 2200),
+			"{:?}",
+			counts
+		);
+	}
+
+	#[test]
+	fn rand_weighted() {
+		let mut rng = rand::XorShiftRng::from_seed([1; 16]);
+		let mut counts = [0; 3];
+		for _ in 0..4000 {
+			let mut rand = Rand::new();
+			rand.push_weighted(0, 1, &mut rng);
+			rand.push_weighted(1, 0, &mut rng);
+			rand.push_weighted(2, 3, &mut rng);
+			counts[rand.get().unwrap()] += 1;
+		}
+		assert_eq!(counts[1], 0);
+		assert!(2700 < counts[2] && counts[2] < 3300, "{:?}", counts);
+		let mut rand = Rand::new();
+		rand.push_weighted((), 0, &mut rng);
+		assert_eq!(rand.get(), None);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn bytes_hash_sha256() {
+		assert_eq!(
+			bytes_hash(b"abc"),
+			[
+				0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+				0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+				0xf2, 0x00, 0x15, 0xad
+			]
+		);
+	}
+
+	#[test]
+	fn hmac_sha256_rfc4231() {
+		assert_eq!(
+			hmac_sha256(&[0x0b; 20], b"Hi There"),
+			[
+				0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b,
+				0xf1, 0x2b, 0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c,
+				0x2e, 0x32, 0xcf, 0xf7
+			]
+		);
+		assert_eq!(
+			hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+			[
+				0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+				0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+				0x64, 0xec, 0x38, 0x43
+			]
+		);
+	}
+
+	#[test]
+	fn parse_binary_size_valid() {
+		assert_eq!(parse_binary_size("0"), Ok(0));
+		assert_eq!(parse_binary_size("512"), Ok(512));
+		assert_eq!(parse_binary_size("512B"), Ok(512));
+		assert_eq!(parse_binary_size("100MiB"), Ok(100 * 1024 * 1024));
+		assert_eq!(parse_binary_size("1.5GiB"), Ok(3 * 512 * 1024 * 1024));
+		assert_eq!(parse_binary_size("0.001KiB"), Ok(1));
+		assert_eq!(parse_binary_size("1.5"), Ok(1));
+		assert_eq!(parse_binary_size("15EiB"), Ok(15 * 1024_u64.pow(6)));
+		assert_eq!(
+			parse_binary_size("1.00000000000000000000000001KiB"),
+			Ok(1024)
+		);
+	}
+
+	#[test]
+	fn parse_binary_size_invalid() {
+		for input in &[
+			"",
+			"GiB",
+			".5GiB",
+			"1.GiB",
+			"1.2.3GiB",
+			"1 GiB",
+			"1GB",
+			"-1",
+			"1é",
+			"é",
+			"16EiB",
+			"18446744073709551616",
+			"17179869184GiB",
+		] {
+			assert_eq!(parse_binary_size(input), Err(()), "{:?}", input);
+		}
+	}
+
+	#[derive(Deserialize)]
+	struct Struct {
+		_a: u32,
+		_b: String,
+	}
+	mod same {
+		#[derive(Deserialize)]
+		pub struct Struct {
+			_a: u32,
+			_b: Box<str>,
+		}
+	}
+	#[derive(Deserialize)]
+	#[serde(rename = "Struct")]
+	struct Wider {
+		_a: u64,
+		_b: String,
+	}
+	#[derive(Deserialize)]
+	#[serde(rename = "Struct")]
+	struct Renamed {
+		_a: u32,
+		_c: String,
+	}
+	#[derive(Deserialize)]
+	enum Enum {
+		_A,
+		_B(Vec<Struct>),
+	}
+	#[derive(Deserialize)]
+	#[serde(rename = "Enum")]
+	enum LastVariantWider {
+		_A,
+		_B(Vec<Wider>),
+	}
+	#[derive(Deserialize)]
+	struct List(Option<Box<List>>);
+	#[derive(Deserialize)]
+	enum Tree {
+		_Node(Vec<Tree>, Box<Tree>),
+		_Leaf,
+	}
+
+	#[test]
+	fn layout_hash_stable() {
+		assert_eq!(layout_hash::<(u8, String)>(), 0xc3ee_851b_6b90_6aad);
+		assert_eq!(layout_hash::<Struct>(), layout_hash::<same::Struct>());
+		assert_eq!(layout_hash::<Vec<u8>>(), layout_hash::<Box<[u8]>>());
+	}
+
+	#[test]
+	fn layout_hash_differs() {
+		let hashes = [
+			layout_hash::<Struct>(),
+			layout_hash::<Wider>(),
+			layout_hash::<Renamed>(),
+			layout_hash::<Enum>(),
+			layout_hash::<LastVariantWider>(),
+			layout_hash::<(u8, String)>(),
+			layout_hash::<(String, u8)>(),
+			layout_hash::<Option<u8>>(),
+		];
+		for (i, a) in hashes.iter().enumerate() {
+			for b in &hashes[i + 1..] {
+				assert_ne!(a, b);
+			}
+		}
+	}
+
+	#[test]
+	fn layout_hash_recursive() {
+		assert_ne!(layout_hash::<List>(), layout_hash::<Tree>());
 	}
-	Ok(a * c)
 }
@@ -1,9 +1,9 @@
-use super::{DeployOutputEvent, Pid, ToHex};
+use super::{Color, DeployOutputEvent, OutputEncoding, Pid, ResourceUsage, ToHex};
 use aes_frast;
 use ansi_term;
 use rand::{self, Rng, SeedableRng};
 use std::{
-	borrow, fmt, fs, io::{self, Write}, mem, os::{self, unix::io::IntoRawFd}
+	borrow, collections::HashMap, env, fmt, fs, io::{self, Write}, iter, mem, os::{self, unix::io::IntoRawFd}, str
 };
 
 const STDOUT: os::unix::io::RawFd = 1;
@@ -60,6 +60,9 @@ impl Writer {
 	}
 }
 
+/// The most output of a process's stdout or stderr that's held waiting for a newline.
+const PARTIAL_MAX: usize = 64 * 1024;
+
 #[derive(Debug)]
 pub struct Formatter {
 	// TODO: if we get half a multi-byte character/combined thing, then something else, then rest of it, it'll be malformatted. deadline cache?
@@ -67,11 +70,15 @@ pub struct Formatter {
 	pid: Pid,
 	nl: Option<os::unix::io::RawFd>,
 	style_support: StyleSupport,
+	encoding: OutputEncoding,
+	/// The color given to each pid seen so far. Usually derived from the pid alone, so it's the same across runs, but see [`Formatter::pretty_pid()`].
+	colors: HashMap<Pid, (u8, u8, u8)>,
+	/// Output of each process's stdout and stderr after its last newline, held until the line is completed or the process exits, so that lines from different processes don't interleave. A line longer than [`PARTIAL_MAX`] is written as it is, rather than held without bound.
+	partial: HashMap<(Pid, os::unix::io::RawFd), Vec<u8>>,
 }
 impl Formatter {
-	pub fn new(pid: Pid, style_support: StyleSupport) -> Self {
-		eprintln!("{}:", pretty_pid(&pid, true, style_support));
-		Self {
+	pub fn new(pid: Pid, style_support: StyleSupport, encoding: OutputEncoding) -> Self {
+		let mut formatter = Self {
 			writer: Writer {
 				fd: STDERR,
 				stdout: io::stdout(),
@@ -80,7 +87,12 @@ impl Formatter {
 			pid,
 			nl: None,
 			style_support,
-		}
+			encoding,
+			colors: HashMap::new(),
+			partial: HashMap::new(),
+		};
+		eprintln!("{}:", formatter.pretty_pid(pid, true));
+		formatter
 	}
 
 	pub fn write(&mut self, event: &DeployOutputEvent) {
@@ -93,108 +105,268 @@ impl Formatter {
 				}
 				if pid_ != self.pid {
 					self.pid = pid_;
-					self.writer.write_fmt(
-						STDERR,
-						format_args!("{}:\n", pretty_pid(&self.pid, true, self.style_support)),
-					);
+					let pid = self.pretty_pid(self.pid, true);
+					self.writer.write_fmt(STDERR, format_args!("{}:\n", pid));
 				}
+				let new_pid = self.pretty_pid(new_pid, false);
 				self.writer.write_fmt(
 					STDERR,
 					format_args!(
 						"   {} {}\n",
 						self.style_support.style().bold().paint("spawned:"),
-						pretty_pid(&new_pid, false, self.style_support)
+						new_pid
 					),
 				);
 			}
 			DeployOutputEvent::Output(pid_, fd, ref output) => {
-				if !output.is_empty() {
-					if fd == STDOUT || fd == STDERR {
-						if pid_ != self.pid {
-							self.pid = pid_;
-							if self.nl.is_some() {
-								self.writer.write(STDERR, b"\n");
-								self.nl = None;
-							}
-							self.writer.write_fmt(
-								STDERR,
-								format_args!(
-									"{}:\n",
-									pretty_pid(&self.pid, true, self.style_support)
-								),
-							);
-						}
-						if self.nl.is_some() && self.nl.unwrap() != fd {
-							self.writer.write(STDERR, b"\n");
-							self.nl = None;
-						}
-						if self.nl.is_none() {
-							self.writer.write(STDERR, b"   ");
-						}
-						let total_len = output.len();
-						let mut output = output.split(|&x| x == b'\n');
-						let first = output.next().unwrap();
-						self.writer.write(fd, first);
-						let mut index = first.len();
-						self.nl = Some(fd);
-						for output in output {
-							self.writer.write(fd, b"\n");
-							index += 1;
-							if index == total_len {
-								assert_eq!(output.len(), 0);
-								self.nl = None;
-								break;
-							}
-							self.writer.write(STDERR, b"   ");
-							// assert!(!output.is_empty());
-							if !output.is_empty() {
-								// TODO?
-								self.writer.write(fd, output);
-								index += output.len();
-							}
+				if fd == STDOUT || fd == STDERR {
+					if !output.is_empty() {
+						let lines = complete_lines(
+							self.partial.entry((pid_, fd)).or_insert_with(Vec::new),
+							output,
+						);
+						if let Some(lines) = lines {
+							self.write_output(pid_, fd, &lines);
 						}
 					} else {
-						self.writer.write(fd, &*output);
+						// The process closed the fd
+						self.flush_partial(pid_, fd);
 					}
-				} else {
-					// TODO: need some form of refcounting??
-					// if fd == STDOUT {
-					// 	io::stdout().flush().unwrap();
-					// } else if fd == STDERR {
-					// 	io::stderr().flush().unwrap();
-					// }
-					// let fd = unsafe{fs::File::from_raw_fd(fd)};
+				} else if !output.is_empty() {
+					self.writer.write(fd, &*output);
 				}
 			}
-			DeployOutputEvent::Exit(pid_, exit_code_) => {
+			DeployOutputEvent::Panic(pid_, ref panic) => {
+				self.flush_partial(pid_, STDOUT);
+				self.flush_partial(pid_, STDERR);
 				if self.nl.is_some() {
 					self.writer.write(STDERR, b"\n");
 					self.nl = None;
 				}
 				if pid_ != self.pid {
 					self.pid = pid_;
-					self.writer.write_fmt(
-						STDERR,
-						format_args!("{}:\n", pretty_pid(&self.pid, true, self.style_support)),
-					);
+					let pid = self.pretty_pid(self.pid, true);
+					self.writer.write_fmt(STDERR, format_args!("{}:\n", pid));
+				}
+				self.writer.write_fmt(
+					STDERR,
+					format_args!(
+						"   {}\n",
+						self.style_support.style().bold().paint("panicked:")
+					),
+				);
+				for line in panic.lines() {
+					self.writer.write_fmt(STDERR, format_args!("   {}\n", line));
+				}
+			}
+			DeployOutputEvent::Exit(pid_, exit_code_, usage) => {
+				self.flush_partial(pid_, STDOUT);
+				self.flush_partial(pid_, STDERR);
+				if self.nl.is_some() {
+					self.writer.write(STDERR, b"\n");
+					self.nl = None;
+				}
+				if pid_ != self.pid {
+					self.pid = pid_;
+					let pid = self.pretty_pid(self.pid, true);
+					self.writer.write_fmt(STDERR, format_args!("{}:\n", pid));
 				}
 				if exit_code_.success() {
 					self.writer.write_fmt(
 						STDERR,
-						format_args!("   {}\n", self.style_support.style().bold().paint("exited")),
+						format_args!(
+							"   {} ({})\n",
+							self.style_support.style().bold().paint("exited"),
+							format_usage(&usage)
+						),
 					);
 				} else {
 					self.writer.write_fmt(
 						STDERR,
 						format_args!(
-							"   {} {:?}\n",
+							"   {} {:?} ({})\n",
 							self.style_support.style().bold().paint("exited:"),
-							exit_code_
+							exit_code_,
+							format_usage(&usage)
 						),
 					);
 				}
+				let _ = self.colors.remove(&pid_);
 				// self.writer.write_fmt(STDERR, format_args!("   {} {:?}\nremaining: {}\n", self.style_support.style().bold().paint("exited:"), exit_code_, std::slice::SliceConcatExt::join(&*xyz.iter().map(|pid|pretty_pid(pid,false).to_string()).collect::<Vec<_>>(), ",")));
 			}
+			DeployOutputEvent::Metric(pid_, ref name, value) => {
+				if self.nl.is_some() {
+					self.writer.write(STDERR, b"\n");
+					self.nl = None;
+				}
+				if pid_ != self.pid {
+					self.pid = pid_;
+					let pid = self.pretty_pid(self.pid, true);
+					self.writer.write_fmt(STDERR, format_args!("{}:\n", pid));
+				}
+				self.writer.write_fmt(
+					STDERR,
+					format_args!(
+						"   {} {} = {}\n",
+						self.style_support.style().bold().paint("metric:"),
+						name,
+						value
+					),
+				);
+			}
+			DeployOutputEvent::Deadlock(ref pids) => {
+				if self.nl.is_some() {
+					self.writer.write(STDERR, b"\n");
+					self.nl = None;
+				}
+				let pids = pids
+					.iter()
+					.map(|&pid| self.pretty_pid(pid, false).to_string())
+					.collect::<Vec<_>>()
+					.join(", ");
+				self.writer.write_fmt(
+					STDERR,
+					format_args!(
+						"{} no progress from: {}\n",
+						self.style_support
+							.style()
+							.bold()
+							.paint("possible deadlock:"),
+						pids
+					),
+				);
+			}
+		}
+	}
+
+	/// The short form of `pid`, in its color.
+	///
+	/// The color is derived from a hash of the pid, so a given pid renders the same color across runs. If that's too close to the color of a pid already seen, the next few colors derived from the hash are tried in turn, falling back to the first if none are distinct.
+	fn pretty_pid(&mut self, pid: Pid, bold: bool) -> ansi_term::ANSIGenericString<'static, str> {
+		let (r, g, b) = if let Some(&color) = self.colors.get(&pid) {
+			color
+		} else {
+			let candidates = pid_colors(&pid).take(COLOR_ATTEMPTS).collect::<Vec<_>>();
+			let color = *candidates
+				.iter()
+				.find(|&&candidate| {
+					self.colors
+						.values()
+						.all(|&color| color_distance(color, candidate) >= COLOR_DISTANCE_MIN)
+				})
+				.unwrap_or(&candidates[0]);
+			let _ = self.colors.insert(pid, color);
+			color
+		};
+		let mut color = self.style_support.style().color(r, g, b);
+		if bold {
+			color = color.bold();
+		}
+		color.paint(pid_short(&pid))
+	}
+
+	/// Write any output held after the last newline of `pid`'s `fd`.
+	fn flush_partial(&mut self, pid: Pid, fd: os::unix::io::RawFd) {
+		if let Some(partial) = self.partial.remove(&(pid, fd)) {
+			if !partial.is_empty() {
+				self.write_output(pid, fd, &partial);
+			}
+		}
+	}
+
+	fn write_output(&mut self, pid_: Pid, fd: os::unix::io::RawFd, output: &[u8]) {
+		if pid_ != self.pid {
+			self.pid = pid_;
+			if self.nl.is_some() {
+				self.writer.write(STDERR, b"\n");
+				self.nl = None;
+			}
+			let pid = self.pretty_pid(self.pid, true);
+			self.writer.write_fmt(STDERR, format_args!("{}:\n", pid));
+		}
+		if self.nl.is_some() && self.nl.unwrap() != fd {
+			self.writer.write(STDERR, b"\n");
+			self.nl = None;
+		}
+		if self.nl.is_none() {
+			self.writer.write(STDERR, b"   ");
+		}
+		let output = &*encode(self.encoding, output);
+		let total_len = output.len();
+		let mut output = output.split(|&x| x == b'\n');
+		let first = output.next().unwrap();
+		self.writer.write(fd, first);
+		let mut index = first.len();
+		self.nl = Some(fd);
+		for output in output {
+			self.writer.write(fd, b"\n");
+			index += 1;
+			if index == total_len {
+				assert_eq!(output.len(), 0);
+				self.nl = None;
+				break;
+			}
+			self.writer.write(STDERR, b"   ");
+			// assert!(!output.is_empty());
+			if !output.is_empty() {
+				// TODO?
+				self.writer.write(fd, output);
+				index += output.len();
+			}
+		}
+	}
+}
+
+/// Append `output` to `partial`, and take from it what's ready to be written: up to and including its last newline, or all of it if it's reached [`PARTIAL_MAX`].
+fn complete_lines(partial: &mut Vec<u8>, output: &[u8]) -> Option<Vec<u8>> {
+	partial.extend_from_slice(output);
+	let end = if partial.len() >= PARTIAL_MAX {
+		Some(partial.len())
+	} else {
+		partial
+			.iter()
+			.rposition(|&x| x == b'\n')
+			.map(|last| last + 1)
+	};
+	end.map(|end| {
+		let rest = partial.split_off(end);
+		mem::replace(partial, rest)
+	})
+}
+
+/// Process output as it's to be written, per `encoding`. Newlines are kept as they are.
+fn encode(encoding: OutputEncoding, output: &[u8]) -> borrow::Cow<[u8]> {
+	match encoding {
+		OutputEncoding::Raw => borrow::Cow::Borrowed(output),
+		OutputEncoding::Lossy => match String::from_utf8_lossy(output) {
+			borrow::Cow::Borrowed(_) => borrow::Cow::Borrowed(output),
+			borrow::Cow::Owned(output) => borrow::Cow::Owned(output.into_bytes()),
+		},
+		OutputEncoding::Escape => {
+			let mut ret = String::with_capacity(output.len());
+			let mut output = output;
+			while !output.is_empty() {
+				let (valid, invalid) = match str::from_utf8(output) {
+					Ok(valid) => (valid, 0),
+					Err(err) => (
+						str::from_utf8(&output[..err.valid_up_to()]).unwrap(),
+						err.error_len()
+							.unwrap_or_else(|| output.len() - err.valid_up_to()),
+					),
+				};
+				for c in valid.chars() {
+					if c.is_ascii_control() && c != '\n' && c != '\t' {
+						ret.push_str(&format!("\\x{:02x}", c as u8));
+					} else {
+						ret.push(c);
+					}
+				}
+				for byte in &output[valid.len()..valid.len() + invalid] {
+					ret.push_str(&format!("\\x{:02x}", byte));
+				}
+				output = &output[valid.len() + invalid..];
+			}
+			borrow::Cow::Owned(ret.into_bytes())
 		}
 	}
 }
@@ -207,6 +379,26 @@ pub enum StyleSupport {
 	TwentyFourBit,
 }
 impl StyleSupport {
+	/// The style support to use for `color`, given whether stderr is a terminal. `NO_COLOR` only overrides [`Color::Auto`], as an explicit `CONSTELLATION_COLOR` is more specific.
+	pub fn from_color(color: Color, tty: bool) -> Self {
+		let no_color = env::var_os("NO_COLOR").map_or(false, |x| !x.is_empty());
+		match color {
+			Color::Auto if !tty || no_color => StyleSupport::None,
+			Color::Auto | Color::Always => {
+				let truecolor =
+					env::var_os("COLORTERM").map_or(false, |x| x == "truecolor" || x == "24bit");
+				if truecolor {
+					StyleSupport::TwentyFourBit
+				} else {
+					StyleSupport::EightBit
+				}
+			}
+			Color::Never => StyleSupport::None,
+			Color::EightBit => StyleSupport::EightBit,
+			Color::TrueColor => StyleSupport::TwentyFourBit,
+		}
+	}
+
 	pub fn style(&self) -> Style {
 		Style(*self, ansi_term::Style::new())
 	}
@@ -252,34 +444,61 @@ impl Style {
 	}
 }
 
-pub(crate) fn pretty_pid(
-	pid: &Pid, bold: bool, style_support: StyleSupport,
-) -> ansi_term::ANSIGenericString<str> {
-	// impl std::fmt::Display + 'a {
+/// How many of the colors derived from a pid are tried before settling for one close to another pid's.
+const COLOR_ATTEMPTS: usize = 8;
+/// The Manhattan distance in RGB below which two colors are considered hard to tell apart.
+const COLOR_DISTANCE_MIN: u16 = 96;
+
+/// Summarise `usage` like `1.2GiB peak, 3.4s CPU`.
+fn format_usage(usage: &ResourceUsage) -> String {
+	let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+	let (mut size, mut unit) = (usage.max_rss as f64, 0);
+	while size >= 1024.0 && unit < units.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	let cpu = usage.cpu_time();
+	format!(
+		"{:.1}{} peak, {:.1}s CPU",
+		size,
+		units[unit],
+		cpu.as_secs() as f64 + f64::from(cpu.subsec_nanos()) / 1e9
+	)
+}
+
+fn pid_short(pid: &Pid) -> String {
 	let key: [u8; 16] = [0; 16];
 
 	let bytes = encrypt(pid.0, key);
 	let decrypted_data = decrypt(bytes, key);
 	assert_eq!(&pid.0, &decrypted_data);
 
-	let x = bytes.to_hex().take(7).collect::<String>();
+	bytes.to_hex().take(7).collect::<String>()
+}
+
+/// An endless sequence of bright colors derived from `pid`, the first of which it's usually shown in.
+fn pid_colors(pid: &Pid) -> impl Iterator<Item = (u8, u8, u8)> {
+	let key: [u8; 16] = [0; 16];
+	let bytes = encrypt(pid.0, key);
 	let mut rng = rand::XorShiftRng::from_seed([
 		bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8],
 		bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15],
 	]);
-	let (r, g, b) = loop {
+	iter::repeat(()).filter_map(move |()| {
 		let (r_, g_, b_): (u8, u8, u8) = rng.gen();
 		let (r, g, b) = (u16::from(r_), u16::from(g_), u16::from(b_));
 		if (r + r + g + g + g + b) / 6 > 100 {
 			// https://stackoverflow.com/questions/596216/formula-to-determine-brightness-of-rgb-color/596241#596241
-			break (r_, g_, b_);
+			Some((r_, g_, b_))
+		} else {
+			None
 		}
-	};
-	let mut color = style_support.style().color(r, g, b);
-	if bold {
-		color = color.bold();
-	}
-	color.paint(x)
+	})
+}
+
+fn color_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u16 {
+	let channel = |a: u8, b: u8| u16::from(if a > b { a - b } else { b - a });
+	channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
 }
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -298,3 +517,32 @@ pub(crate) fn decrypt(input: [u8; 16], key: [u8; 16]) -> [u8; 16] {
 	aes_frast::aes_core::block_dec_k128(&input, &mut output, &round_keys);
 	output
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn complete_lines_held() {
+		let mut partial = Vec::new();
+		assert_eq!(complete_lines(&mut partial, b"a"), None);
+		assert_eq!(
+			complete_lines(&mut partial, b"b\nc\nd"),
+			Some(b"ab\nc\n".to_vec())
+		);
+		assert_eq!(partial, b"d");
+		assert_eq!(complete_lines(&mut partial, b"\n"), Some(b"d\n".to_vec()));
+		assert!(partial.is_empty());
+	}
+
+	#[test]
+	fn complete_lines_bounded() {
+		let mut partial = Vec::new();
+		assert_eq!(complete_lines(&mut partial, &[b'a'; PARTIAL_MAX - 1]), None);
+		assert_eq!(
+			complete_lines(&mut partial, b"a\nb"),
+			Some([&[b'a'; PARTIAL_MAX][..], b"\nb"].concat())
+		);
+		assert!(partial.is_empty());
+	}
+}
@@ -26,10 +26,14 @@ extern crate aes_frast;
 extern crate ansi_term;
 extern crate bincode;
 extern crate cargo_metadata as cargo_metadata_;
+#[macro_use]
+extern crate lazy_static;
 #[cfg(unix)]
 extern crate nix;
 extern crate rand;
+extern crate serde;
 extern crate serde_json;
+extern crate sha2;
 #[cfg(windows)]
 extern crate winapi;
 
@@ -38,7 +42,9 @@ mod format;
 
 #[cfg(unix)]
 use nix::sys::signal;
-use std::{convert::TryInto, env, ffi::OsString, fmt, io, net, ops};
+use std::{
+	collections::{HashMap, HashSet}, convert::{TryFrom, TryInto}, env, error, ffi::OsString, fmt, io, net, ops, path, str, sync, thread, time
+};
 
 #[cfg(target_family = "unix")]
 type Fd = std::os::unix::io::RawFd;
@@ -48,6 +54,10 @@ type Fd = std::os::windows::io::RawHandle;
 pub use ext::*;
 pub use format::*;
 
+lazy_static! {
+	static ref LOCAL_NODE: sync::RwLock<Option<NodeId>> = sync::RwLock::new(None);
+}
+
 /// An opaque identifier for a process.
 ///
 /// The current process's `Pid` can be retrieved with [pid()](pid).
@@ -83,17 +93,124 @@ impl Pid {
 					0,
 				])
 			}
-			_ => unimplemented!(),
+			net::IpAddr::V6(ip) => {
+				// Only addresses that fit in 4 bytes, like ::1, are supported; marked by byte 6
+				let ip = ip.octets();
+				assert!(ip[..12].iter().all(|&x| x == 0), "unsupported IPv6 address");
+				Pid([
+					ip[12],
+					ip[13],
+					ip[14],
+					ip[15],
+					(port >> 8).try_into().unwrap(),
+					(port & 0xff).try_into().unwrap(),
+					6,
+					0,
+					0,
+					0,
+					0,
+					0,
+					0,
+					0,
+					0,
+					0,
+				])
+			}
 		}
 	}
 
-	pub(crate) fn addr(&self) -> net::SocketAddr {
+	/// The address that channels to this process connect to.
+	///
+	/// For a process on this machine when running natively, this is a loopback address like `127.0.0.1:port`, so it may not be reachable from other machines.
+	pub fn addr(&self) -> net::SocketAddr {
+		let ip: net::IpAddr = if self.0[6] == 6 {
+			net::Ipv6Addr::from([
+				0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, self.0[0], self.0[1], self.0[2], self.0[3],
+			])
+			.into()
+		} else {
+			[self.0[0], self.0[1], self.0[2], self.0[3]].into()
+		};
 		net::SocketAddr::new(
-			[self.0[0], self.0[1], self.0[2], self.0[3]].into(),
+			ip,
 			((u16::from(self.0[4])) << 8) | (u16::from(self.0[5])),
 		)
 	}
 
+	/// Reconstruct a `Pid` from its [`addr()`](Pid::addr), for example one obtained out of band. `Pid::from_addr(pid.addr()) == pid` for every `Pid`.
+	///
+	/// Panics if `addr` is an IPv6 address that doesn't fit in 4 bytes, as no `Pid` has such an address.
+	pub fn from_addr(addr: net::SocketAddr) -> Self {
+		Self::new(addr.ip(), addr.port())
+	}
+
+	/// A short, URL-safe string encoding of this `Pid`, that can be parsed back with [`Pid::from_compact()`](Pid::from_compact).
+	///
+	/// It is the lowercase base32 encoding of the `Pid`'s bytes, with trailing zero bytes omitted.
+	pub fn to_compact(&self) -> String {
+		let len = self.0.iter().rposition(|&x| x != 0).map_or(0, |i| i + 1);
+		let mut ret = String::new();
+		let (mut buffer, mut bits) = (0_u32, 0);
+		for &byte in &self.0[..len] {
+			buffer = (buffer << 8) | u32::from(byte);
+			bits += 8;
+			while bits >= 5 {
+				bits -= 5;
+				ret.push(char::from(
+					COMPACT_ALPHABET[((buffer >> bits) & 0x1f) as usize],
+				));
+			}
+		}
+		if bits > 0 {
+			ret.push(char::from(
+				COMPACT_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize],
+			));
+		}
+		ret
+	}
+
+	/// Parse a `Pid` from the encoding produced by [`Pid::to_compact()`](Pid::to_compact).
+	pub fn from_compact(s: &str) -> Result<Self, ParsePidError> {
+		let mut bytes = [0; 16];
+		let (mut buffer, mut bits, mut len) = (0_u32, 0, 0);
+		for c in s.chars() {
+			let value = COMPACT_ALPHABET
+				.iter()
+				.position(|&x| char::from(x) == c.to_ascii_lowercase())
+				.ok_or(ParsePidError::InvalidCharacter(c))?;
+			buffer = (buffer << 5) | u32::try_from(value).unwrap();
+			bits += 5;
+			if bits >= 8 {
+				bits -= 8;
+				if len == bytes.len() {
+					return Err(ParsePidError::InvalidLength);
+				}
+				bytes[len] = ((buffer >> bits) & 0xff).try_into().unwrap();
+				len += 1;
+			}
+		}
+		// Leftover bits must be padding, and less than a character's worth
+		if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+			return Err(ParsePidError::InvalidLength);
+		}
+		Ok(Pid(bytes))
+	}
+
+	/// The [NodeId] of the node this process is running on.
+	pub fn node(&self) -> NodeId {
+		NodeId::new(self.addr().ip())
+	}
+
+	/// Whether this process is running on the same node as the current process.
+	///
+	/// When running natively all processes are on the same machine, so this is always `true`. When deployed to a cluster it compares the [NodeId]s of the two processes.
+	pub fn is_local(&self) -> bool {
+		self.node()
+			== LOCAL_NODE.read().unwrap().expect(
+				"You must call init() immediately inside your application's main() function",
+			)
+	}
+
 	fn format<'a>(&'a self) -> impl Iterator<Item = char> + 'a {
 		let key: [u8; 16] = [0; 16];
 		encrypt(self.0, key)
@@ -114,19 +231,71 @@ impl fmt::Debug for Pid {
 			.finish()
 	}
 }
+const COMPACT_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Error returned by [`Pid::from_compact()`](Pid::from_compact).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParsePidError {
+	/// The string contained a character that isn't in the encoding's alphabet.
+	InvalidCharacter(char),
+	/// The string was too long, or ended partway through a byte.
+	InvalidLength,
+}
+impl fmt::Display for ParsePidError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParsePidError::InvalidCharacter(c) => write!(f, "Invalid character {:?} in pid", c),
+			ParsePidError::InvalidLength => write!(f, "Invalid pid length"),
+		}
+	}
+}
+impl error::Error for ParsePidError {
+	fn description(&self) -> &str {
+		match *self {
+			ParsePidError::InvalidCharacter(_) => "invalid character in pid",
+			ParsePidError::InvalidLength => "invalid pid length",
+		}
+	}
+}
+
+/// An opaque identifier for a node: a machine of the cluster that processes run on.
+///
+/// The node of a process can be retrieved with [`Pid::node()`](Pid::node). Placing processes near the data they use is then possible with [`spawn_on()`](spawn_on).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Debug)]
+pub struct NodeId(net::IpAddr);
+impl NodeId {
+	pub(crate) fn new(ip: net::IpAddr) -> Self {
+		NodeId(ip)
+	}
+}
+impl fmt::Display for NodeId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+pub trait NodeIdInternal {
+	fn new(ip: net::IpAddr) -> NodeId;
+	fn set_local(node: NodeId);
+}
+#[doc(hidden)]
+impl NodeIdInternal for NodeId {
+	fn new(ip: net::IpAddr) -> Self {
+		Self::new(ip)
+	}
+	fn set_local(node: Self) {
+		*LOCAL_NODE.write().unwrap() = Some(node);
+	}
+}
+
 pub trait PidInternal {
 	fn new(ip: net::IpAddr, port: u16) -> Pid;
-	fn addr(&self) -> net::SocketAddr;
 }
 #[doc(hidden)]
 impl PidInternal for Pid {
 	fn new(ip: net::IpAddr, port: u16) -> Self {
 		Self::new(ip, port)
 	}
-
-	fn addr(&self) -> net::SocketAddr {
-		Self::addr(self)
-	}
 }
 
 #[derive(Clone, Debug)]
@@ -134,8 +303,33 @@ pub struct Envs {
 	pub deploy: Option<Option<Deploy>>,
 	pub version: Option<Option<bool>>,
 	pub recce: Option<Option<bool>>,
+	pub recce_tree: Option<Option<bool>>,
 	pub format: Option<Option<Format>>,
 	pub resources: Option<Option<Resources>>,
+	pub deadlock_timeout: Option<Option<time::Duration>>,
+	pub loopback: Option<Option<net::IpAddr>>,
+	pub panic_capture: Option<Option<bool>>,
+	pub socket_buffer: Option<Option<usize>>,
+	pub nodelay: Option<Option<bool>>,
+	pub output_buffer: Option<Option<usize>>,
+	pub spawn_concurrency: Option<Option<usize>>,
+	pub color: Option<Option<Color>>,
+	pub output_encoding: Option<Option<OutputEncoding>>,
+	pub mem_limit: Option<Option<bool>>,
+	pub keep_binary: Option<Option<bool>>,
+	pub scheduler_timeout: Option<Option<time::Duration>>,
+	pub keepalive_idle: Option<Option<u16>>,
+	pub keepalive_interval: Option<Option<u16>>,
+	pub keepalive_count: Option<Option<u16>>,
+	pub liveness_port: Option<Option<u16>>,
+	pub liveness_host: Option<Option<net::IpAddr>>,
+	pub listen_backlog: Option<Option<usize>>,
+	pub select_seed: Option<Option<u64>>,
+	pub channel_token: Option<Option<[u8; 16]>>,
+	pub tls_cert: Option<Option<path::PathBuf>>,
+	pub tls_key: Option<Option<path::PathBuf>>,
+	pub tls_ca: Option<Option<path::PathBuf>>,
+	pub tls_name: Option<Option<String>>,
 }
 impl Envs {
 	pub fn from_env() -> Self {
@@ -161,6 +355,13 @@ impl Envs {
 				_ => None,
 			})
 		});
+		let recce_tree = env::var_os("CONSTELLATION_RECCE_TREE").map(|x| {
+			x.into_string().ok().and_then(|x| match &*x {
+				"0" => Some(false),
+				"1" => Some(true),
+				_ => None,
+			})
+		});
 		let format = env::var_os("CONSTELLATION_FORMAT").map(|x| {
 			x.into_string()
 				.ok()
@@ -175,12 +376,110 @@ impl Envs {
 				.ok()
 				.and_then(|x| serde_json::from_str(&x).ok())
 		});
+		let deadlock_timeout = env::var_os("CONSTELLATION_DEADLOCK_TIMEOUT").map(|x| {
+			x.into_string()
+				.ok()
+				.and_then(|x| x.parse().ok())
+				.map(time::Duration::from_secs)
+		});
+		let loopback = env::var_os("CONSTELLATION_LOOPBACK")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let panic_capture = env::var_os("CONSTELLATION_PANIC_CAPTURE").map(|x| {
+			x.into_string().ok().and_then(|x| match &*x {
+				"0" => Some(false),
+				"1" => Some(true),
+				_ => None,
+			})
+		});
+		let socket_buffer = env::var_os("CONSTELLATION_SOCKET_BUFFER")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let nodelay = env::var_os("CONSTELLATION_NODELAY").map(|x| {
+			x.into_string().ok().and_then(|x| match &*x {
+				"0" => Some(false),
+				"1" => Some(true),
+				_ => None,
+			})
+		});
+		let output_buffer = env::var_os("CONSTELLATION_OUTPUT_BUFFER")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let spawn_concurrency = env::var_os("CONSTELLATION_SPAWN_CONCURRENCY")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let color = env::var_os("CONSTELLATION_COLOR")
+			.map(|x| x.into_string().ok().and_then(|x| Color::parse(&x)));
+		let output_encoding = env::var_os("CONSTELLATION_OUTPUT_ENCODING")
+			.map(|x| x.into_string().ok().and_then(|x| OutputEncoding::parse(&x)));
+		let mem_limit = env::var_os("CONSTELLATION_MEM_LIMIT").map(|x| {
+			x.into_string().ok().and_then(|x| match &*x {
+				"0" => Some(false),
+				"1" => Some(true),
+				_ => None,
+			})
+		});
+		let keep_binary = env::var_os("CONSTELLATION_KEEP_BINARY").map(|x| {
+			x.into_string().ok().and_then(|x| match &*x {
+				"0" => Some(false),
+				"1" => Some(true),
+				_ => None,
+			})
+		});
+		let scheduler_timeout = env::var_os("CONSTELLATION_SCHEDULER_TIMEOUT").map(|x| {
+			x.into_string()
+				.ok()
+				.and_then(|x| x.parse().ok())
+				.filter(|&x| x > 0)
+				.map(time::Duration::from_secs)
+		});
+		let keepalive_idle = env::var_os("CONSTELLATION_KEEPALIVE_IDLE")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let keepalive_interval = env::var_os("CONSTELLATION_KEEPALIVE_INTERVAL")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let keepalive_count = env::var_os("CONSTELLATION_KEEPALIVE_COUNT")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let liveness_port = env::var_os("CONSTELLATION_LIVENESS_PORT")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let liveness_host = env::var_os("CONSTELLATION_LIVENESS_HOST")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let listen_backlog = env::var_os("CONSTELLATION_LISTEN_BACKLOG")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let select_seed = env::var_os("CONSTELLATION_SELECT_SEED")
+			.map(|x| x.into_string().ok().and_then(|x| x.parse().ok()));
+		let channel_token = env::var_os("CONSTELLATION_CHANNEL_TOKEN")
+			.map(|x| x.into_string().ok().and_then(|x| parse_key(&x)));
+		let tls_cert = env::var_os("CONSTELLATION_TLS_CERT").map(|x| Some(x.into()));
+		let tls_key = env::var_os("CONSTELLATION_TLS_KEY").map(|x| Some(x.into()));
+		let tls_ca = env::var_os("CONSTELLATION_TLS_CA").map(|x| Some(x.into()));
+		let tls_name = env::var_os("CONSTELLATION_TLS_NAME").map(|x| x.into_string().ok());
 		Self {
 			deploy,
 			version,
 			recce,
+			recce_tree,
 			format,
 			resources,
+			deadlock_timeout,
+			loopback,
+			panic_capture,
+			socket_buffer,
+			nodelay,
+			output_buffer,
+			spawn_concurrency,
+			color,
+			output_encoding,
+			mem_limit,
+			keep_binary,
+			scheduler_timeout,
+			keepalive_idle,
+			keepalive_interval,
+			keepalive_count,
+			liveness_port,
+			liveness_host,
+			listen_backlog,
+			select_seed,
+			channel_token,
+			tls_cert,
+			tls_key,
+			tls_ca,
+			tls_name,
 		}
 	}
 
@@ -211,6 +510,16 @@ impl Envs {
 				_ => None,
 			})
 		});
+		let recce_tree = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_RECCE_TREE")
+			.map(|x| {
+				x.1.clone().into_string().ok().and_then(|x| match &*x {
+					"0" => Some(false),
+					"1" => Some(true),
+					_ => None,
+				})
+			});
 		let format = env
 			.iter()
 			.find(|x| &x.0 == "CONSTELLATION_FORMAT")
@@ -233,12 +542,177 @@ impl Envs {
 					.ok()
 					.and_then(|x| serde_json::from_str(&x).ok())
 			});
+		let deadlock_timeout = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_DEADLOCK_TIMEOUT")
+			.map(|x| {
+				x.1.clone()
+					.into_string()
+					.ok()
+					.and_then(|x| x.parse().ok())
+					.map(time::Duration::from_secs)
+			});
+		let loopback = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_LOOPBACK")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let panic_capture = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_PANIC_CAPTURE")
+			.map(|x| {
+				x.1.clone().into_string().ok().and_then(|x| match &*x {
+					"0" => Some(false),
+					"1" => Some(true),
+					_ => None,
+				})
+			});
+		let socket_buffer = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_SOCKET_BUFFER")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let nodelay = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_NODELAY")
+			.map(|x| {
+				x.1.clone().into_string().ok().and_then(|x| match &*x {
+					"0" => Some(false),
+					"1" => Some(true),
+					_ => None,
+				})
+			});
+		let output_buffer = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_OUTPUT_BUFFER")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let spawn_concurrency = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_SPAWN_CONCURRENCY")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let color = env.iter().find(|x| &x.0 == "CONSTELLATION_COLOR").map(|x| {
+			x.1.clone()
+				.into_string()
+				.ok()
+				.and_then(|x| Color::parse(&x))
+		});
+		let output_encoding = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_OUTPUT_ENCODING")
+			.map(|x| {
+				x.1.clone()
+					.into_string()
+					.ok()
+					.and_then(|x| OutputEncoding::parse(&x))
+			});
+		let mem_limit = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_MEM_LIMIT")
+			.map(|x| {
+				x.1.clone().into_string().ok().and_then(|x| match &*x {
+					"0" => Some(false),
+					"1" => Some(true),
+					_ => None,
+				})
+			});
+		let keep_binary = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_KEEP_BINARY")
+			.map(|x| {
+				x.1.clone().into_string().ok().and_then(|x| match &*x {
+					"0" => Some(false),
+					"1" => Some(true),
+					_ => None,
+				})
+			});
+		let scheduler_timeout = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_SCHEDULER_TIMEOUT")
+			.map(|x| {
+				x.1.clone()
+					.into_string()
+					.ok()
+					.and_then(|x| x.parse().ok())
+					.filter(|&x| x > 0)
+					.map(time::Duration::from_secs)
+			});
+		let keepalive_idle = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_KEEPALIVE_IDLE")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let keepalive_interval = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_KEEPALIVE_INTERVAL")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let keepalive_count = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_KEEPALIVE_COUNT")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let liveness_port = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_LIVENESS_PORT")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let liveness_host = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_LIVENESS_HOST")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let listen_backlog = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_LISTEN_BACKLOG")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let select_seed = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_SELECT_SEED")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| x.parse().ok()));
+		let channel_token = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_CHANNEL_TOKEN")
+			.map(|x| x.1.clone().into_string().ok().and_then(|x| parse_key(&x)));
+		let tls_cert = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_TLS_CERT")
+			.map(|x| Some(x.1.clone().into()));
+		let tls_key = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_TLS_KEY")
+			.map(|x| Some(x.1.clone().into()));
+		let tls_ca = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_TLS_CA")
+			.map(|x| Some(x.1.clone().into()));
+		let tls_name = env
+			.iter()
+			.find(|x| &x.0 == "CONSTELLATION_TLS_NAME")
+			.map(|x| x.1.clone().into_string().ok());
 		Self {
 			deploy,
 			version,
 			recce,
+			recce_tree,
 			format,
 			resources,
+			deadlock_timeout,
+			loopback,
+			panic_capture,
+			socket_buffer,
+			nodelay,
+			output_buffer,
+			spawn_concurrency,
+			color,
+			output_encoding,
+			mem_limit,
+			keep_binary,
+			scheduler_timeout,
+			keepalive_idle,
+			keepalive_interval,
+			keepalive_count,
+			liveness_port,
+			liveness_host,
+			listen_backlog,
+			select_seed,
+			channel_token,
+			tls_cert,
+			tls_key,
+			tls_ca,
+			tls_name,
 		}
 	}
 }
@@ -254,6 +728,51 @@ pub enum Format {
 	Human,
 	Json,
 }
+/// Whether the human output format is colored, from `CONSTELLATION_COLOR`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Color {
+	/// Colored if stderr is a terminal and `NO_COLOR` isn't set.
+	Auto,
+	/// Colored, with 24-bit color if `COLORTERM` says the terminal supports it.
+	Always,
+	Never,
+	/// 8-bit (256) color.
+	EightBit,
+	/// 24-bit color.
+	TrueColor,
+}
+impl Color {
+	fn parse(x: &str) -> Option<Self> {
+		match &*x.to_ascii_lowercase() {
+			"auto" => Some(Color::Auto),
+			"always" => Some(Color::Always),
+			"never" => Some(Color::Never),
+			"256" => Some(Color::EightBit),
+			"truecolor" => Some(Color::TrueColor),
+			_ => None,
+		}
+	}
+}
+/// How the human output format writes process output that isn't valid UTF-8, from `CONSTELLATION_OUTPUT_ENCODING`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputEncoding {
+	/// Invalid UTF-8 is replaced with the replacement character U+FFFD.
+	Lossy,
+	/// Invalid UTF-8, and control characters other than newline and tab, are replaced with `\xNN` escapes of their bytes.
+	Escape,
+	/// Bytes are written as they are.
+	Raw,
+}
+impl OutputEncoding {
+	fn parse(x: &str) -> Option<Self> {
+		match &*x.to_ascii_lowercase() {
+			"lossy" => Some(OutputEncoding::Lossy),
+			"escape" => Some(OutputEncoding::Escape),
+			"raw" => Some(OutputEncoding::Raw),
+			_ => None,
+		}
+	}
+}
 
 /// Memory and CPU requirements for a process.
 ///
@@ -262,10 +781,12 @@ pub enum Format {
 /// Best effort is made to enforce these as limits to avoid buggy/greedy processes starving others.
 #[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Resources {
-	/// Memory requirement in bytes
+	/// Memory requirement in bytes. Must be nonzero.
 	pub mem: u64,
 	/// CPU requirement as a fraction of one logical core. Any positive value is valid.
 	pub cpu: f32,
+	/// Scratch disk requirement in bytes. Used by the scheduler for placement; not enforced when run natively.
+	pub disk: u64,
 }
 impl Default for Resources {
 	fn default() -> Self {
@@ -276,20 +797,146 @@ impl Default for Resources {
 pub const RESOURCES_DEFAULT: Resources = Resources {
 	mem: 1024 * 1024 * 1024,
 	cpu: 0.05,
+	disk: 0,
+};
+/// Sent to the scheduler in place of a spawn request's [Resources] to ask for the free resources instead. The scheduler replies with a `Result<Resources, SpawnFailure>`, which is encoded the same as the `Result<Pid, SpawnFailure>` of a spawn when it's an error: schedulers that don't understand the query can never satisfy it, so reply with an error.
+pub const RESOURCES_QUERY: Resources = Resources {
+	mem: std::u64::MAX,
+	cpu: std::f32::INFINITY,
+	disk: std::u64::MAX,
 };
+/// The would-be process tree recorded by a recce with `CONSTELLATION_RECCE_TREE` set: the [Resources] of the initial process, and of each process that would have been spawned, with the process that would have spawned it.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct RecceManifest {
+	/// Resources of the initial process.
+	pub root: Resources,
+	/// Resources of each process that would have been spawned, in the order they were, with the index of the process that would have spawned it: `0` for the initial process, or `i` for `spawns[i - 1]`.
+	pub spawns: Vec<(usize, Resources)>,
+}
+impl RecceManifest {
+	/// The aggregate resource footprint of the process tree.
+	pub fn total(&self) -> Resources {
+		self.spawns
+			.iter()
+			.fold(self.root, |total, &(_, spawn)| Resources {
+				mem: total.mem.saturating_add(spawn.mem),
+				cpu: total.cpu + spawn.cpu,
+				disk: total.disk.saturating_add(spawn.disk),
+			})
+	}
+}
+/// Parses a comma-separated list of `key=value` pairs, e.g. `mem=2GiB,cpu=4`.
+///
+/// `mem` and `disk` take a size such as `512MiB`, and `cpu` takes a number. Fields that aren't given are taken from [RESOURCES_DEFAULT].
+impl str::FromStr for Resources {
+	type Err = ParseResourcesError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut resources = RESOURCES_DEFAULT;
+		for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+			let mut pair = pair.splitn(2, '=');
+			let (key, value) = (pair.next().unwrap(), pair.next());
+			let value = value.ok_or_else(|| ParseResourcesError::MissingValue(key.to_owned()))?;
+			let invalid = || ParseResourcesError::InvalidValue(key.to_owned());
+			match key {
+				"mem" => resources.mem = parse_binary_size(value).map_err(|()| invalid())?,
+				"disk" => resources.disk = parse_binary_size(value).map_err(|()| invalid())?,
+				"cpu" => resources.cpu = value.parse().map_err(|_| invalid())?,
+				_ => return Err(ParseResourcesError::UnknownKey(key.to_owned())),
+			}
+		}
+		Ok(resources)
+	}
+}
+
+/// Renders as `mem=1GiB,cpu=0.05,disk=0B`, which parses back to the same [Resources].
+impl fmt::Display for Resources {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"mem={},cpu={},disk={}",
+			format_binary_size(self.mem),
+			self.cpu,
+			format_binary_size(self.disk)
+		)
+	}
+}
+
+/// Error returned by parsing a [Resources] from a string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseResourcesError {
+	/// The key isn't one of `mem`, `cpu` or `disk`.
+	UnknownKey(String),
+	/// The key wasn't followed by `=` and a value.
+	MissingValue(String),
+	/// The value for the key couldn't be parsed.
+	InvalidValue(String),
+}
+impl fmt::Display for ParseResourcesError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParseResourcesError::UnknownKey(ref key) => write!(f, "Unknown resource {:?}", key),
+			ParseResourcesError::MissingValue(ref key) => {
+				write!(f, "Missing value for resource {:?}", key)
+			}
+			ParseResourcesError::InvalidValue(ref key) => {
+				write!(f, "Invalid value for resource {:?}", key)
+			}
+		}
+	}
+}
+impl error::Error for ParseResourcesError {
+	fn description(&self) -> &str {
+		match *self {
+			ParseResourcesError::UnknownKey(_) => "unknown resource",
+			ParseResourcesError::MissingValue(_) => "missing value for resource",
+			ParseResourcesError::InvalidValue(_) => "invalid value for resource",
+		}
+	}
+}
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(/*tag = "event", */rename_all = "lowercase")]
 pub enum DeployOutputEvent {
 	Spawn(Pid, Pid),
 	Output(Pid, Fd, Vec<u8>),
-	Exit(Pid, ExitStatus),
+	Panic(Pid, String),
+	Exit(Pid, ExitStatus, ResourceUsage),
+	Deadlock(Vec<Pid>),
+	Metric(Pid, String, f64),
 }
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum DeployInputEvent {
 	Input(Pid, Fd, Vec<u8>),
 	Kill(Option<Pid>),
 }
+/// Why the scheduler didn't spawn a process, sent in place of its [Pid].
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum SpawnFailure {
+	/// No node, or not the node requested, had the resources free.
+	Resources,
+	/// No node, or not the node requested, has the resources even when nothing else is running on it.
+	Unsatisfiable,
+	/// The node couldn't open the working directory the process was to be started in.
+	Directory,
+}
+/// The resources a process used, measured by its monitor once it has exited. This includes any processes it forked and waited on itself, but not those it spawned, which are measured separately.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+pub struct ResourceUsage {
+	/// The peak resident set size, in bytes.
+	pub max_rss: u64,
+	/// The CPU time spent in user mode.
+	pub user_time: time::Duration,
+	/// The CPU time spent in the kernel.
+	pub system_time: time::Duration,
+}
+impl ResourceUsage {
+	/// The total CPU time.
+	pub fn cpu_time(&self) -> time::Duration {
+		self.user_time + self.system_time
+	}
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub enum ExitStatus {
 	Success,
@@ -321,10 +968,14 @@ impl ExitStatus {
 		ExitStatus::Error(ExitStatusError::Unix(ExitStatusUnix::Signal(s.into())))
 	}
 }
+/// Combine the exit statuses of several processes, as for that of a whole deployment: [`Success`](ExitStatus::Success) if they all succeeded, otherwise the error they failed with if those that failed all failed the same way, otherwise [`ExitStatusError::Indeterminate`].
+///
+/// This is commutative and associative, with `Success` as the identity, so the result doesn't depend on the order in which the processes exited.
 impl ops::Add for ExitStatus {
 	type Output = Self;
 	fn add(self, other: Self) -> Self {
 		match (self, other) {
+			(ExitStatus::Success, a) | (a, ExitStatus::Success) => a,
 			(a, b) if a == b => a,
 			_ => ExitStatus::Error(ExitStatusError::Indeterminate),
 		}
@@ -336,11 +987,16 @@ impl ops::AddAssign for ExitStatus {
 	}
 }
 
+/// The status a process exits with when an allocation fails under `CONSTELLATION_MEM_LIMIT`, so that it can be reported as [`ExitStatusError::MemoryLimit`].
+pub const EXIT_MEM_LIMIT: u8 = 102;
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub enum ExitStatusError {
 	Unix(ExitStatusUnix),
 	Windows(u32), // https://msdn.microsoft.com/en-gb/library/cc231199.aspx
 	Indeterminate,
+	/// Failed to allocate within its `Resources::mem`, which is enforced when `CONSTELLATION_MEM_LIMIT` is set.
+	MemoryLimit,
 }
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub enum ExitStatusUnix {
@@ -511,17 +1167,19 @@ impl From<Signal> for signal::Signal {
 	}
 }
 
+/// The code to exit with to convey `exit_status`, as a shell would: the status itself, or 128 plus the signal number. It's always in `0..=255`, and nonzero for an error, as codes outside that range would be truncated by `exit()`, possibly to 0.
 impl From<ExitStatus> for i32 {
 	fn from(exit_status: ExitStatus) -> Self {
 		match exit_status {
 			ExitStatus::Success => 0,
 			ExitStatus::Error(error) => match error {
 				ExitStatusError::Unix(ExitStatusUnix::Signal(signal)) => {
-					signal::Signal::from(signal) as Self | (1 << 7)
+					(signal::Signal::from(signal) as Self | (1 << 7)).min(255)
 				}
-				ExitStatusError::Unix(ExitStatusUnix::Status(status)) => Self::from(status),
-				ExitStatusError::Windows(code) => code.try_into().unwrap(),
+				ExitStatusError::Unix(ExitStatusUnix::Status(status)) => Self::from(status.max(1)),
+				ExitStatusError::Windows(code) => Self::try_from(code.max(1).min(255)).unwrap(),
 				ExitStatusError::Indeterminate => 101,
+				ExitStatusError::MemoryLimit => Self::from(EXIT_MEM_LIMIT),
 			},
 		}
 	}
@@ -529,18 +1187,294 @@ impl From<ExitStatus> for i32 {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ProcessOutputEvent {
-	Spawn(Pid),
+	/// A newly spawned process, and whether it's detached. A detached process isn't this process's child: its exit isn't reported to this process, and this process can't kill it.
+	Spawn(Pid, bool),
 	Output(Fd, Vec<u8>),
-	Exit(ExitStatus),
+	Panic(String),
+	Exit(ExitStatus, ResourceUsage),
+	/// Kill a child of this process. Ignored if it has already exited.
+	Kill(Pid),
+	/// Kill every descendant of this process that hasn't yet exited.
+	KillDescendants,
+	/// A named custom metric of this process.
+	Metric(String, f64),
+	/// A newly spawned relay bridge. It's detached, and reports to this process's bridge like a process, but relays the events of the processes this process spawns through it.
+	SpawnRelay(Pid),
+	/// A newly spawned child of this process, that reports to the given relay bridge rather than to this process's bridge.
+	RelayedSpawn(Pid, Pid),
+	/// From a relay bridge, an event of a process reporting to it, to be passed on as it is.
+	Relay(DeployOutputEvent),
 }
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ProcessInputEvent {
 	Input(Fd, Vec<u8>),
+	/// Kill this process. A relay bridge instead kills the processes reporting to it, and exits once they have.
 	Kill,
+	ChildExit(Pid, ExitStatus),
+	/// To a relay bridge, a process to relay the events of, and its parent.
+	Adopt(Pid, Pid),
+	/// To a relay bridge, kill a process reporting to it, and if `true` its descendants that report to it too. Ignored if it has already exited.
+	KillRelayed(Pid, bool),
+	/// To a relay bridge, the process that spawned it has exited, so it exits once the processes reporting to it have.
+	Close,
+}
+
+/// Watches the [DeployOutputEvent]s of a deployment, and calls back with the live pids if none of them have made progress for `timeout`.
+///
+/// This is a heuristic: processes busy computing without output look the same as processes deadlocked in `recv()`. It reports once per stall, and its thread exits once every process has exited.
+#[derive(Debug)]
+pub struct Watchdog {
+	state: sync::Arc<sync::Mutex<WatchdogState>>,
+}
+#[derive(Debug)]
+struct WatchdogState {
+	progress: time::Instant,
+	reported: bool,
+	pids: HashSet<Pid>,
+}
+impl Watchdog {
+	pub fn new<F: FnMut(Vec<Pid>) + Send + 'static>(
+		pid: Pid, timeout: time::Duration, mut report: F,
+	) -> Self {
+		let state = sync::Arc::new(sync::Mutex::new(WatchdogState {
+			progress: time::Instant::now(),
+			reported: false,
+			pids: Some(pid).into_iter().collect(),
+		}));
+		let state_ = state.clone();
+		let _ = thread::Builder::new()
+			.name(String::from("watchdog"))
+			.spawn(move || loop {
+				let (sleep, stuck) = {
+					let mut state = state_.lock().unwrap();
+					if state.pids.is_empty() {
+						break;
+					}
+					let elapsed = state.progress.elapsed();
+					if elapsed < timeout {
+						(timeout - elapsed, None)
+					} else if !state.reported {
+						state.reported = true;
+						(timeout, Some(state.pids.iter().cloned().collect()))
+					} else {
+						(timeout, None)
+					}
+				};
+				// Not holding the lock, as report() may block on the thread calling event()
+				if let Some(stuck) = stuck {
+					report(stuck);
+				}
+				thread::sleep(sleep);
+			})
+			.unwrap();
+		Self { state }
+	}
+
+	pub fn event(&self, event: &DeployOutputEvent) {
+		let mut state = self.state.lock().unwrap();
+		match *event {
+			DeployOutputEvent::Spawn(_, new_pid) => {
+				let _ = state.pids.insert(new_pid);
+			}
+			DeployOutputEvent::Output(..)
+			| DeployOutputEvent::Panic(..)
+			| DeployOutputEvent::Metric(..) => (),
+			DeployOutputEvent::Exit(pid, ..) => {
+				let _ = state.pids.remove(&pid);
+			}
+			DeployOutputEvent::Deadlock(_) => return,
+		}
+		state.progress = time::Instant::now();
+		state.reported = false;
+	}
+}
+
+/// Authentication of a channel connection with `CONSTELLATION_CHANNEL_TOKEN`.
+///
+/// Each end sends a random nonce, and then proves it has the token with an HMAC of whether it made or accepted the connection, its nonce, and the remote's. As a proof is tied to the role of the end that produced it, it can't be reflected back to that end, and a nonce reflected back is refused outright. The end that accepted the connection only gives its proof once it has checked the remote's, so it never gives one to a process without the token.
+pub struct ChannelAuth {
+	token: [u8; 16],
+	/// Whether this end made the connection, rather than accepted it.
+	outgoing: bool,
+	nonce: [u8; 16],
+	/// The remote's nonce, once its handshake has been received.
+	remote_nonce: Option<[u8; 16]>,
+	proof_taken: bool,
+	proven: bool,
+}
+impl ChannelAuth {
+	/// `nonce` should be random, and different for every connection.
+	pub fn new(token: [u8; 16], outgoing: bool, nonce: [u8; 16]) -> Self {
+		Self {
+			token,
+			outgoing,
+			nonce,
+			remote_nonce: None,
+			proof_taken: false,
+			proven: false,
+		}
+	}
+
+	/// This end's nonce, to send in its handshake.
+	pub fn nonce(&self) -> [u8; 16] {
+		self.nonce
+	}
+
+	/// Take the remote's nonce from its handshake. Returns `false` if it's this end's own, as it would be were the handshake reflected back, in which case the connection should be failed.
+	pub fn set_remote_nonce(&mut self, nonce: [u8; 16]) -> bool {
+		assert!(self.remote_nonce.is_none());
+		self.remote_nonce = Some(nonce);
+		nonce != self.nonce
+	}
+
+	/// Whether this end's proof is ready to be sent: once the remote's nonce is known, and if this end accepted the connection, once the remote has proven it has the token. It stops being once it's taken.
+	pub fn proof_ready(&self) -> bool {
+		!self.proof_taken && self.remote_nonce.is_some() && (self.outgoing || self.proven)
+	}
+
+	/// This end's proof, if it's [ready](ChannelAuth::proof_ready) to be sent.
+	pub fn take_proof(&mut self) -> Option<[u8; 32]> {
+		if !self.proof_ready() {
+			return None;
+		}
+		self.proof_taken = true;
+		Some(self.proof(self.outgoing, self.nonce, self.remote_nonce.unwrap()))
+	}
+
+	/// Whether this end's proof has been taken to be sent.
+	pub fn proof_taken(&self) -> bool {
+		self.proof_taken
+	}
+
+	/// Check the remote's proof, returning whether it's valid. The connection should be failed if not.
+	pub fn check_proof(&mut self, proof: &[u8; 32]) -> bool {
+		let remote_nonce = self.remote_nonce.unwrap();
+		let expected = self.proof(!self.outgoing, remote_nonce, self.nonce);
+		// Compared in constant time, so how much of it is right isn't leaked
+		self.proven = proof
+			.iter()
+			.zip(expected.iter())
+			.fold(0, |diff, (a, b)| diff | (a ^ b))
+			== 0;
+		self.proven
+	}
+
+	/// Whether the remote has proven it has the token.
+	pub fn proven(&self) -> bool {
+		self.proven
+	}
+
+	/// Whether this end's proof has been taken to be sent, and the remote's checked.
+	pub fn done(&self) -> bool {
+		self.proof_taken && self.proven
+	}
+
+	/// The proof given by the end that did or didn't make the connection, with its nonce and then the other's.
+	fn proof(&self, outgoing: bool, nonce: [u8; 16], remote_nonce: [u8; 16]) -> [u8; 32] {
+		let mut message = [0; 33];
+		message[0] = u8::from(outgoing);
+		message[1..17].copy_from_slice(&nonce);
+		message[17..].copy_from_slice(&remote_nonce);
+		hmac_sha256(&self.token, &message)
+	}
+}
+impl fmt::Debug for ChannelAuth {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// Not the token
+		f.debug_struct("ChannelAuth")
+			.field("outgoing", &self.outgoing)
+			.field("proof_taken", &self.proof_taken)
+			.field("proven", &self.proven)
+			.finish()
+	}
+}
+
+/// Watches the [DeployOutputEvent]s of a deployment, and answers each connection to `addr` with a one-line JSON summary of its processes: `{"alive":2,"exited":5,"failed":1}`.
+///
+/// This lets external systems, like readiness probes, monitor a deployment without parsing its event stream. Its thread lives as long as the process.
+#[derive(Debug)]
+pub struct Liveness {
+	state: sync::Arc<sync::Mutex<LivenessState>>,
+	addr: net::SocketAddr,
+}
+#[derive(Serialize, Debug)]
+struct LivenessState {
+	alive: usize,
+	exited: usize,
+	failed: usize,
+}
+impl Liveness {
+	pub fn new(addr: net::SocketAddr) -> Result<Self, io::Error> {
+		let listener = net::TcpListener::bind(addr)?;
+		let addr = listener.local_addr()?;
+		let state = sync::Arc::new(sync::Mutex::new(LivenessState {
+			alive: 1,
+			exited: 0,
+			failed: 0,
+		}));
+		let state_ = state.clone();
+		let _ = thread::Builder::new()
+			.name(String::from("liveness"))
+			.spawn(move || {
+				for stream in listener.incoming() {
+					if let Ok(mut stream) = stream {
+						let mut status = serde_json::to_vec(&*state_.lock().unwrap()).unwrap();
+						status.push(b'\n');
+						// The prober may already have gone
+						let _ = io::Write::write_all(&mut stream, &status);
+					}
+				}
+			})
+			.unwrap();
+		Ok(Self { state, addr })
+	}
+
+	/// The address it's listening on, with the port filled in if `0` was given.
+	pub fn addr(&self) -> net::SocketAddr {
+		self.addr
+	}
+
+	pub fn event(&self, event: &DeployOutputEvent) {
+		let mut state = self.state.lock().unwrap();
+		match *event {
+			DeployOutputEvent::Spawn(..) => state.alive += 1,
+			DeployOutputEvent::Exit(_, exit_code, _) => {
+				state.alive -= 1;
+				state.exited += 1;
+				if !exit_code.success() {
+					state.failed += 1;
+				}
+			}
+			DeployOutputEvent::Output(..)
+			| DeployOutputEvent::Panic(..)
+			| DeployOutputEvent::Metric(..)
+			| DeployOutputEvent::Deadlock(_) => (),
+		}
+	}
 }
 
 /////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// The bincode configuration for the spawn arg and the streams to the scheduler and bridge, which may be read on a machine of a different architecture to the one that wrote them: little-endian, fixed-width integers, and no size limit.
+///
+/// These are bincode 1's defaults, but are pinned here so that a change to them doesn't silently break processes built against different versions. Channels are encoded by serde_pipe, which can't be given a configuration and uses bincode's defaults; a test checks they still match this.
+pub fn bincode_config() -> bincode::Config {
+	let mut config = bincode::config();
+	let _ = config.little_endian().no_limit();
+	config
+}
+
+/// Whether `pid` is a descendant of `ancestor`, given each process's parent. The chain is broken, and so descendants are missed, where a process in between has exited and been removed.
+pub fn is_descendant(parents: &HashMap<Pid, Pid>, mut pid: Pid, ancestor: Pid) -> bool {
+	while let Some(&parent) = parents.get(&pid) {
+		if parent == ancestor {
+			return true;
+		}
+		pid = parent;
+	}
+	false
+}
+
 pub fn map_bincode_err(err: bincode::Error) -> io::Error {
 	match *err {
 		bincode::ErrorKind::Io(err) => err,
@@ -548,6 +1482,19 @@ pub fn map_bincode_err(err: bincode::Error) -> io::Error {
 	}
 }
 
+/// Retry `f` for as long as it fails with `EINTR`, as blocking syscalls do if a signal handler runs while they're blocked.
+///
+/// Not for `close()`, which on Linux releases the fd even if interrupted, so a retry could close an fd since reused.
+#[cfg(unix)]
+pub fn retry_eintr<T, F: FnMut() -> Result<T, nix::Error>>(mut f: F) -> Result<T, nix::Error> {
+	loop {
+		match f() {
+			Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => (),
+			ret => return ret,
+		}
+	}
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 pub mod cargo_metadata {
@@ -586,3 +1533,154 @@ pub mod cargo_metadata {
 		pub test: bool,
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn resources_round_trip() {
+		for resources in &[
+			RESOURCES_DEFAULT,
+			RESOURCES_QUERY,
+			Resources {
+				mem: 0,
+				cpu: 0.0,
+				disk: 0,
+			},
+			Resources {
+				mem: 3 * 512 * 1024 * 1024,
+				cpu: 1.0 / 3.0,
+				disk: 1,
+			},
+			Resources {
+				mem: 1025,
+				cpu: 1e-20,
+				disk: 15 * 1024_u64.pow(6),
+			},
+			Resources {
+				mem: 1024_u64.pow(6),
+				cpu: std::f32::MAX,
+				disk: 1023 * 1024,
+			},
+		] {
+			assert_eq!(
+				resources.to_string().parse::<Resources>(),
+				Ok(*resources),
+				"{}",
+				resources
+			);
+		}
+	}
+
+	/// Run the authentication between an end that made a connection and one that accepted it, as far as it goes. Returns whether each end accepted the other.
+	fn channel_auth(outgoing: &mut ChannelAuth, incoming: &mut ChannelAuth) -> (bool, bool) {
+		assert!(outgoing.set_remote_nonce(incoming.nonce()));
+		assert!(incoming.set_remote_nonce(outgoing.nonce()));
+		// The end that accepted the connection doesn't give its proof first
+		assert_eq!(incoming.take_proof(), None);
+		let incoming_accepted = incoming.check_proof(&outgoing.take_proof().unwrap());
+		let outgoing_accepted = match incoming.take_proof() {
+			Some(proof) => outgoing.check_proof(&proof),
+			None => false,
+		};
+		(outgoing_accepted, incoming_accepted)
+	}
+
+	#[test]
+	fn channel_auth_token() {
+		let (mut outgoing, mut incoming) = (
+			ChannelAuth::new([1; 16], true, [2; 16]),
+			ChannelAuth::new([1; 16], false, [3; 16]),
+		);
+		assert_eq!(channel_auth(&mut outgoing, &mut incoming), (true, true));
+		assert!(outgoing.done() && incoming.done());
+
+		// A wrong token is rejected, and the end that accepted the connection doesn't give a proof to it
+		let (mut outgoing, mut incoming) = (
+			ChannelAuth::new([4; 16], true, [2; 16]),
+			ChannelAuth::new([1; 16], false, [3; 16]),
+		);
+		assert_eq!(channel_auth(&mut outgoing, &mut incoming), (false, false));
+		assert_eq!(incoming.take_proof(), None);
+		let (mut outgoing, mut incoming) = (
+			ChannelAuth::new([1; 16], true, [2; 16]),
+			ChannelAuth::new([4; 16], false, [3; 16]),
+		);
+		assert_eq!(channel_auth(&mut outgoing, &mut incoming), (false, false));
+	}
+
+	#[test]
+	fn channel_auth_reflected() {
+		// A nonce reflected back is refused, by either end
+		for &outgoing in &[true, false] {
+			let mut auth = ChannelAuth::new([1; 16], outgoing, [2; 16]);
+			let nonce = auth.nonce();
+			assert!(!auth.set_remote_nonce(nonce));
+		}
+
+		// As is a proof reflected back to the end that made the connection, which gives its proof first
+		let mut outgoing = ChannelAuth::new([1; 16], true, [2; 16]);
+		assert!(outgoing.set_remote_nonce([3; 16]));
+		let proof = outgoing.take_proof().unwrap();
+		assert!(!outgoing.check_proof(&proof));
+
+		// And a proof from one connection, made with its nonces swapped, relayed to another
+		let mut victim = ChannelAuth::new([1; 16], true, [2; 16]);
+		assert!(victim.set_remote_nonce([3; 16]));
+		let mut other = ChannelAuth::new([1; 16], true, [3; 16]);
+		assert!(other.set_remote_nonce([2; 16]));
+		assert!(!victim.check_proof(&other.take_proof().unwrap()));
+	}
+
+	#[test]
+	fn liveness_counts() {
+		let liveness = Liveness::new(net::SocketAddr::new(
+			net::IpAddr::V4(net::Ipv4Addr::LOCALHOST),
+			0,
+		))
+		.unwrap();
+		let pid = |port| Pid::new(net::IpAddr::V4(net::Ipv4Addr::LOCALHOST), port);
+		for &(parent, child) in &[(1, 2), (1, 3), (2, 4)] {
+			liveness.event(&DeployOutputEvent::Spawn(pid(parent), pid(child)));
+		}
+		liveness.event(&DeployOutputEvent::Exit(
+			pid(3),
+			ExitStatus::Success,
+			ResourceUsage::default(),
+		));
+		liveness.event(&DeployOutputEvent::Exit(
+			pid(4),
+			ExitStatus::Error(ExitStatusError::Unix(ExitStatusUnix::Status(1))),
+			ResourceUsage::default(),
+		));
+		let mut status = String::new();
+		let _ = io::Read::read_to_string(
+			&mut net::TcpStream::connect(liveness.addr()).unwrap(),
+			&mut status,
+		)
+		.unwrap();
+		assert_eq!(status, "{\"alive\":2,\"exited\":2,\"failed\":1}\n");
+	}
+
+	#[test]
+	fn bincode_config_golden() {
+		let ints: (u8, i16, u32, i64, u64, usize, isize) = (
+			u8::max_value(),
+			i16::min_value(),
+			0xdead_beef,
+			i64::min_value(),
+			u64::max_value(),
+			0x0102_0304,
+			-2,
+		);
+		let golden = [
+			0xff, 0x00, 0x80, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+			0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x04, 0x03, 0x02, 0x01, 0x00,
+			0x00, 0x00, 0x00, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		];
+		assert_eq!(&*bincode_config().serialize(&ints).unwrap(), &golden[..]);
+		// What serde_pipe encodes channels with
+		assert_eq!(&*bincode::serialize(&ints).unwrap(), &golden[..]);
+	}
+}
@@ -0,0 +1,115 @@
+//! A comparison of sending large text messages with and without compression.
+//!
+//! This example sends the same batch of JSON log lines from a child process to
+//! its parent twice: first as plain `Vec<u8>`s, then wrapped in `Compressed`.
+//! It prints the bytes sent and the throughput of each.
+//!
+//! It requires the `compression` feature, and is invoked like:
+//! ```bash
+//! cargo run --example compression --features compression
+//! ```
+//!
+//! By default, 1000 messages of 64 KiB are sent each way. The number of
+//! messages is configurable at the command line like so:
+//! ```bash
+//! cargo run --example compression --features compression -- 42
+//! ```
+//!
+//! Compression trades CPU time in both processes for fewer bytes on the wire,
+//! so it pays off when the network, rather than the CPU, is the bottleneck –
+//! for example when run distributed on a [`constellation`](https://github.com/alecmocatta/constellation)
+//! cluster like so:
+//! ```bash
+//! cargo deploy 10.0.0.1 --example compression --features compression
+//! ```
+
+#[macro_use]
+extern crate serde_closure;
+extern crate constellation;
+
+use constellation::*;
+use std::{env, time};
+
+const MESSAGE_LEN: usize = 64 * 1024;
+
+fn main() {
+	init(Resources::default());
+
+	// Accept the number of messages at the command line, defaulting to 1000
+	let messages = env::args()
+		.nth(1)
+		.and_then(|arg| arg.parse::<usize>().ok())
+		.unwrap_or(1000);
+
+	let child = spawn(
+		Resources::default(),
+		FnOnce!([messages] move |parent| {
+			// Compressible text, like a log of JSON lines
+			let mut message = Vec::with_capacity(MESSAGE_LEN);
+			let mut i = 0;
+			while message.len() < MESSAGE_LEN {
+				message.extend_from_slice(
+					format!("{{\"level\":\"info\",\"seq\":{},\"msg\":\"request handled\"}}\n", i).as_bytes(),
+				);
+				i += 1;
+			}
+			message.truncate(MESSAGE_LEN);
+
+			// Report the bytes sent on each channel, measured by the sender
+			let report = Sender::<u64>::new(parent);
+
+			let bytes_sent = metrics().bytes_sent;
+			let sender = Sender::<Vec<u8>>::new(parent);
+			for _ in 0..messages {
+				sender.send(message.clone());
+			}
+			report.send(metrics().bytes_sent - bytes_sent);
+
+			let bytes_sent = metrics().bytes_sent;
+			let sender = Sender::<Compressed<Vec<u8>>>::new(parent);
+			for _ in 0..messages {
+				sender.send(Compressed::new(&message));
+			}
+			report.send(metrics().bytes_sent - bytes_sent);
+		}),
+	)
+	.expect("Unable to allocate process!");
+
+	let report = Receiver::<u64>::new(child);
+
+	let start = time::Instant::now();
+	let receiver = Receiver::<Vec<u8>>::new(child);
+	for _ in 0..messages {
+		assert_eq!(receiver.recv().unwrap().len(), MESSAGE_LEN);
+	}
+	print_stats(
+		"uncompressed",
+		messages,
+		start.elapsed(),
+		report.recv().unwrap(),
+	);
+
+	let start = time::Instant::now();
+	let receiver = Receiver::<Compressed<Vec<u8>>>::new(child);
+	for _ in 0..messages {
+		let message = receiver.recv().unwrap().decompress().unwrap();
+		assert_eq!(message.len(), MESSAGE_LEN);
+	}
+	print_stats(
+		"compressed",
+		messages,
+		start.elapsed(),
+		report.recv().unwrap(),
+	);
+}
+
+fn print_stats(name: &str, messages: usize, elapsed: time::Duration, bytes_sent: u64) {
+	let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+	println!(
+		"{}: {} bytes sent in {:.2}s, {:.1} MiB/s of messages",
+		name,
+		bytes_sent,
+		secs,
+		(messages * MESSAGE_LEN) as f64 / secs / (1024.0 * 1024.0)
+	);
+}
@@ -0,0 +1,70 @@
+//! A measurement of how fast a process's output is forwarded.
+//!
+//! This example spawns a process that writes lines to stdout as fast as it can,
+//! and reports how long it took for them to be forwarded. As writes block
+//! once the pipe to the forwarding monitor is full, this is the throughput of
+//! the forwarding rather than of the writes. The output itself is best
+//! discarded, with the report going to stderr:
+//! ```bash
+//! cargo run --release --example output_throughput > /dev/null
+//! ```
+//!
+//! By default, 256 MiB is written, 1 KiB at a time. The number of MiB is
+//! configurable at the command line like so:
+//! ```bash
+//! cargo run --release --example output_throughput -- 42 > /dev/null
+//! ```
+//!
+//! The size of the chunks output is forwarded in is set by the env var
+//! `CONSTELLATION_OUTPUT_BUFFER`, so this can compare sizes:
+//! ```bash
+//! CONSTELLATION_OUTPUT_BUFFER=1024 cargo run --release --example output_throughput > /dev/null
+//! ```
+
+#[macro_use]
+extern crate serde_closure;
+extern crate constellation;
+
+use constellation::*;
+use std::{
+	env, io::{self, Write}, time
+};
+
+const LINE_LEN: usize = 1024;
+
+fn main() {
+	init(Resources::default());
+
+	// Accept the number of MiB at the command line, defaulting to 256
+	let mebibytes = env::args()
+		.nth(1)
+		.and_then(|arg| arg.parse::<usize>().ok())
+		.unwrap_or(256);
+
+	let child = spawn(
+		Resources::default(),
+		FnOnce!([mebibytes] move |parent| {
+			let mut line = vec![b'x'; LINE_LEN - 1];
+			line.push(b'\n');
+			let stdout = io::stdout();
+			let mut stdout = stdout.lock();
+			let start = time::Instant::now();
+			for _ in 0..mebibytes * 1024 * 1024 / LINE_LEN {
+				stdout.write_all(&line).unwrap();
+				stdout.flush().unwrap();
+			}
+			Sender::<(u64, u32)>::new(parent)
+				.send((start.elapsed().as_secs(), start.elapsed().subsec_nanos()));
+		}),
+	)
+	.expect("Unable to allocate process!");
+
+	let (secs, nanos) = Receiver::<(u64, u32)>::new(child).recv().unwrap();
+	let secs = secs as f64 + f64::from(nanos) / 1e9;
+	eprintln!(
+		"{} MiB forwarded in {:.2}s, {:.1} MiB/s",
+		mebibytes,
+		secs,
+		mebibytes as f64 / secs
+	);
+}
@@ -0,0 +1,80 @@
+//! A measurement of how fast processes can be spawned.
+//!
+//! This example spawns processes that exit immediately, from as many threads
+//! as `CONSTELLATION_SPAWN_CONCURRENCY` allows spawns at once, and prints how
+//! many were spawned per second. It's most informative run distributed on a
+//! [`constellation`](https://github.com/alecmocatta/constellation) cluster,
+//! where each spawn copies the binary to a node, like so:
+//! ```bash
+//! cargo deploy 10.0.0.1 --release --example spawn_throughput
+//! CONSTELLATION_SPAWN_CONCURRENCY=8 cargo deploy 10.0.0.1 --release --example spawn_throughput
+//! ```
+//!
+//! By default, 100 processes are spawned. The number is configurable at the
+//! command line like so:
+//! ```bash
+//! cargo deploy 10.0.0.1 --release --example spawn_throughput -- 42
+//! ```
+
+#[macro_use]
+extern crate serde_closure;
+extern crate constellation;
+
+use constellation::*;
+use std::{env, thread, time};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+
+	// Accept the number of processes at the command line, defaulting to 100
+	let processes = env::args()
+		.nth(1)
+		.and_then(|arg| arg.parse::<usize>().ok())
+		.unwrap_or(100);
+	// Spawn from as many threads as spawns are allowed at once
+	let threads = env::var("CONSTELLATION_SPAWN_CONCURRENCY")
+		.ok()
+		.and_then(|threads| threads.parse::<usize>().ok())
+		.unwrap_or(1);
+
+	let start = time::Instant::now();
+	let spawners = (0..threads)
+		.map(|i| {
+			thread::spawn(move || {
+				(i..processes)
+					.step_by(threads)
+					.map(|_| {
+						spawn(
+							Resources {
+								mem: 20 * 1024 * 1024,
+								..Resources::default()
+							},
+							FnOnce!(|_parent| ()),
+						)
+						.expect("Unable to allocate process!")
+					})
+					.collect::<Vec<_>>()
+			})
+		})
+		.collect::<Vec<_>>();
+	let children = spawners
+		.into_iter()
+		.flat_map(|spawner| spawner.join().unwrap())
+		.collect::<Vec<_>>();
+	let elapsed = start.elapsed();
+	for child in children {
+		let _ = wait(child);
+	}
+
+	let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+	println!(
+		"{} processes spawned from {} threads in {:.2}s, {:.1} spawns/s",
+		processes,
+		threads,
+		secs,
+		processes as f64 / secs
+	);
+}
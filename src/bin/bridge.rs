@@ -36,15 +36,15 @@ extern crate log;
 
 use proc_self::FdIter;
 use std::{
-	collections::HashMap, convert::TryInto, env, ffi::{CString, OsString}, fs, io::{self, Read}, iter, os::{
+	collections::HashMap, convert::TryInto, env, ffi::{CString, OsString}, fs, io::{self, Read}, iter, net, os::{
 		self, unix::{
 			ffi::OsStringExt, io::{AsRawFd, FromRawFd, IntoRawFd}
 		}
-	}, sync::{self, mpsc}, thread, time
+	}, path::PathBuf, process, sync::{self, mpsc}, thread, time
 };
 
 use constellation_internal::{
-	map_bincode_err, BufferedStream, DeployInputEvent, DeployOutputEvent, ExitStatus, Pid, ProcessInputEvent, ProcessOutputEvent, Resources
+	bincode_config, file_hash, is_descendant, map_bincode_err, retry_eintr, BufferedStream, DeployInputEvent, DeployOutputEvent, Envs, ExitStatus, Liveness, NodeId, Pid, ProcessInputEvent, ProcessOutputEvent, ResourceUsage, Resources, SpawnFailure, Watchdog
 };
 use palaver::{copy, copy_sendfile, fexecve, memfd_create, move_fds, seal, spawn};
 
@@ -57,14 +57,25 @@ const SCHEDULER_FD: Fd = 4;
 
 #[derive(Clone, Debug)]
 enum OutputEventInt {
-	Spawn(Pid, Pid, mpsc::SyncSender<InputEventInt>),
+	Spawn(Pid, Pid, bool, mpsc::SyncSender<InputEventInt>),
 	Output(Pid, Fd, Vec<u8>),
-	Exit(Pid, ExitStatus),
+	Panic(Pid, String),
+	Exit(Pid, ExitStatus, ResourceUsage),
+	Kill(Pid, Pid),
+	KillDescendants(Pid),
+	Metric(Pid, String, f64),
+	SpawnRelay(Pid, Pid, mpsc::SyncSender<InputEventInt>),
+	RelayedSpawn(Pid, Pid, Pid),
+	Relay(DeployOutputEvent),
 }
 #[derive(Clone, Debug)]
 enum InputEventInt {
 	Input(Fd, Vec<u8>),
 	Kill,
+	ChildExit(Pid, ExitStatus),
+	Adopt(Pid, Pid),
+	KillRelayed(Pid, bool),
+	Close,
 }
 
 fn parse_request<R: Read>(
@@ -79,11 +90,18 @@ fn parse_request<R: Read>(
 	),
 	io::Error,
 > {
-	let process = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let args: Vec<OsString> = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let vars: Vec<(OsString, OsString)> =
-		bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let len: u64 = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
+	let process = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let args: Vec<OsString> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let vars: Vec<(OsString, OsString)> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let len: u64 = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
 	// let mut binary = Vec::with_capacity(len as usize);
 	// copy(stream, &mut binary, len as usize)?; assert_eq!(binary.len(), len as usize);
 	let mut binary = unsafe {
@@ -108,7 +126,9 @@ fn parse_request<R: Read>(
 	assert_eq!(x, 0);
 	seal(binary.as_raw_fd());
 
-	let arg: Vec<u8> = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
+	let arg: Vec<u8> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
 	Ok((process, args, vars, binary, arg))
 }
 
@@ -124,6 +144,16 @@ fn monitor_process(
 			let event = match event {
 				InputEventInt::Input(fd, input) => ProcessInputEvent::Input(fd, input),
 				InputEventInt::Kill => ProcessInputEvent::Kill,
+				InputEventInt::Adopt(pid, parent) => ProcessInputEvent::Adopt(pid, parent),
+				InputEventInt::KillRelayed(pid, descendants) => {
+					ProcessInputEvent::KillRelayed(pid, descendants)
+				}
+				InputEventInt::Close => ProcessInputEvent::Close,
+				InputEventInt::ChildExit(pid, exit_code) => {
+					// The parent may have exited, in which case there's no one to tell
+					let _ = sender.try_send(ProcessInputEvent::ChildExit(pid, exit_code));
+					continue;
+				}
 			};
 			sender.send(event);
 			//  {
@@ -131,7 +161,7 @@ fn monitor_process(
 			// 	Err(constellation::ChannelError::Exited) => break, // TODO,
 			// 	Err(e) => panic!("BRIDGE send fail: {:?}", e),
 			// }
-			// if let Err(_) = bincode::serialize_into(&mut sender, &event) {
+			// if let Err(_) = bincode_config().serialize_into(&mut sender, &event) {
 			// 	break; // TODO: remove
 			// }
 		}
@@ -141,7 +171,7 @@ fn monitor_process(
 		trace!("BRIDGE: KILL ({})", x);
 	});
 	loop {
-		// let event: Result<ProcessOutputEvent,_> = bincode::deserialize_from(&mut receiver).map_err(map_bincode_err);
+		// let event: Result<ProcessOutputEvent,_> = bincode_config().deserialize_from(&mut receiver).map_err(map_bincode_err);
 		let event: ProcessOutputEvent = receiver.recv().expect("BRIDGE recv fail");
 		// if event.is_err() {
 		// 	trace!("BRIDGE: {:?} died {:?}", pid, event.err().unwrap());
@@ -150,12 +180,12 @@ fn monitor_process(
 		// }
 		match event {
 			//.unwrap() {
-			ProcessOutputEvent::Spawn(new_pid) => {
+			ProcessOutputEvent::Spawn(new_pid, detached) => {
 				let x = PROCESS_COUNT.fetch_add(1, sync::atomic::Ordering::Relaxed);
 				trace!("BRIDGE: SPAWN ({})", x);
 				let (sender1, receiver1) = mpsc::sync_channel::<_>(0);
 				sender_
-					.send(OutputEventInt::Spawn(pid, new_pid, sender1))
+					.send(OutputEventInt::Spawn(pid, new_pid, detached, sender1))
 					.unwrap();
 				let sender_ = sender_.clone();
 				let _ = spawn(String::from("d"), move || {
@@ -167,15 +197,62 @@ fn monitor_process(
 					.send(OutputEventInt::Output(pid, fd, output))
 					.unwrap();
 			}
-			ProcessOutputEvent::Exit(exit_code) => {
-				sender_.send(OutputEventInt::Exit(pid, exit_code)).unwrap();
+			ProcessOutputEvent::Panic(panic) => {
+				sender_.send(OutputEventInt::Panic(pid, panic)).unwrap();
+			}
+			ProcessOutputEvent::Exit(exit_code, usage) => {
+				sender_
+					.send(OutputEventInt::Exit(pid, exit_code, usage))
+					.unwrap();
 				break;
 			}
+			ProcessOutputEvent::Kill(child) => {
+				sender_.send(OutputEventInt::Kill(pid, child)).unwrap();
+			}
+			ProcessOutputEvent::KillDescendants => {
+				sender_.send(OutputEventInt::KillDescendants(pid)).unwrap();
+			}
+			ProcessOutputEvent::Metric(name, value) => {
+				sender_
+					.send(OutputEventInt::Metric(pid, name, value))
+					.unwrap();
+			}
+			ProcessOutputEvent::SpawnRelay(relay) => {
+				let x = PROCESS_COUNT.fetch_add(1, sync::atomic::Ordering::Relaxed);
+				trace!("BRIDGE: SPAWN RELAY ({})", x);
+				let (sender1, receiver1) = mpsc::sync_channel::<_>(0);
+				sender_
+					.send(OutputEventInt::SpawnRelay(pid, relay, sender1))
+					.unwrap();
+				let sender_ = sender_.clone();
+				let _ = spawn(String::from("d"), move || {
+					monitor_process(relay, sender_, receiver1);
+				});
+			}
+			ProcessOutputEvent::RelayedSpawn(new_pid, relay) => {
+				sender_
+					.send(OutputEventInt::RelayedSpawn(pid, new_pid, relay))
+					.unwrap();
+			}
+			ProcessOutputEvent::Relay(event) => {
+				sender_.send(OutputEventInt::Relay(event)).unwrap();
+			}
 		}
 	}
 	drop(sender_); // placate clippy needless_pass_by_value
 }
 
+/// Tell `parent` of its child's exit without blocking, so a parent that isn't receiving doesn't hold up the events of the others.
+fn child_exit(parent: mpsc::SyncSender<InputEventInt>, pid: Pid, exit_code: ExitStatus) {
+	if let Err(mpsc::TrySendError::Full(event)) =
+		parent.try_send(InputEventInt::ChildExit(pid, exit_code))
+	{
+		let _ = spawn(String::from("child-exit"), move || {
+			let _ = parent.send(event);
+		});
+	}
+}
+
 fn recce(
 	binary: &fs::File, args: &[OsString], vars: &[(OsString, OsString)],
 ) -> Result<Resources, ()> {
@@ -262,7 +339,7 @@ fn recce(
 		thread::sleep(time::Duration::new(1, 0));
 		let _ = nix::sys::signal::kill(child, nix::sys::signal::Signal::SIGKILL);
 	});
-	match nix::sys::wait::waitpid(child, None).unwrap() {
+	match retry_eintr(|| nix::sys::wait::waitpid(child, None)).unwrap() {
 		nix::sys::wait::WaitStatus::Exited(pid, code) if code == 0 => assert_eq!(pid, child),
 		nix::sys::wait::WaitStatus::Signaled(pid, signal, _)
 			if signal == nix::sys::signal::Signal::SIGKILL =>
@@ -272,7 +349,8 @@ fn recce(
 		wait_status => panic!("{:?}", wait_status),
 	}
 	let reader = unsafe { fs::File::from_raw_fd(reader) };
-	bincode::deserialize_from(&mut &reader)
+	bincode_config()
+		.deserialize_from(&mut &reader)
 		.map_err(map_bincode_err)
 		.map_err(|_| ())
 }
@@ -280,7 +358,10 @@ fn recce(
 fn main() {
 	env::set_var("RUST_BACKTRACE", "full");
 	trace!("BRIDGE: Resources: {:?}", ()); // TODO
-	let listener = constellation::bridge_init();
+	let listener = constellation::bridge_init().unwrap_or_else(|err| {
+		eprintln!("BRIDGE: {}", err);
+		process::exit(1)
+	});
 	let (sender, receiver) = mpsc::sync_channel::<_>(0);
 	let _ = spawn(String::from("a"), move || {
 		for stream in listener.incoming() {
@@ -322,7 +403,17 @@ fn main() {
 				if let Ok((process, args, vars, binary, mut arg)) = parse_request(&mut stream_read)
 				{
 					assert_eq!(arg.len(), 0);
-					bincode::serialize_into(&mut arg, &constellation::pid()).unwrap();
+					bincode_config()
+						.serialize_into(&mut arg, &constellation::pid())
+						.unwrap();
+					let deadlock_timeout = Envs::from(&vars).deadlock_timeout.and_then(|x| x);
+					let liveness = Envs::from(&vars).liveness_port.and_then(|x| x).map(|port| {
+						let host = Envs::from(&vars).liveness_host.and_then(|x| x);
+						net::SocketAddr::new(
+							host.unwrap_or(net::IpAddr::V4(net::Ipv4Addr::LOCALHOST)),
+							port,
+						)
+					});
 					let (sender_, receiver) = mpsc::sync_channel::<_>(0);
 					sender
 						.send((
@@ -335,7 +426,9 @@ fn main() {
 						))
 						.unwrap();
 					let pid: Option<Pid> = receiver.recv().unwrap();
-					bincode::serialize_into(&mut stream_write, &pid).unwrap(); // TODO: catch this failing
+					bincode_config()
+						.serialize_into(&mut stream_write, &pid)
+						.unwrap(); // TODO: catch this failing
 					if let Some(pid) = pid {
 						let x = PROCESS_COUNT.fetch_add(1, sync::atomic::Ordering::Relaxed);
 						trace!("BRIDGE: SPAWN ({})", x);
@@ -346,13 +439,30 @@ fn main() {
 						});
 						let hashmap = &sync::Mutex::new(HashMap::new());
 						let _ = hashmap.lock().unwrap().insert(pid, sender1);
+						// Shared with the watchdog, which writes from its own thread
+						let stream_write =
+							sync::Arc::new(sync::Mutex::new(stream.try_clone().unwrap()));
+						let watchdog = deadlock_timeout.map(|timeout| {
+							let stream_write = stream_write.clone();
+							Watchdog::new(pid, timeout, move |pids| {
+								let _ = bincode_config().serialize_into(
+									&mut *stream_write.lock().unwrap(),
+									&DeployOutputEvent::Deadlock(pids),
+								);
+							})
+						});
+						let liveness = liveness.and_then(|addr| {
+							Liveness::new(addr)
+								.map_err(|err| {
+									warn!("Failed to bind liveness address {}: {}", addr, err)
+								})
+								.ok()
+						});
 						crossbeam::scope(|scope| {
 							let _ = scope.spawn(move || {
 								loop {
-									let event: Result<
-										DeployInputEvent,
-										_,
-									> = bincode::deserialize_from(&mut stream_read)
+									let event: Result<DeployInputEvent, _> = bincode_config()
+										.deserialize_from(&mut stream_read)
 										.map_err(map_bincode_err);
 									if event.is_err() {
 										break;
@@ -386,22 +496,140 @@ fn main() {
 									process.send(InputEventInt::Kill).unwrap();
 								}
 							});
+							// Each process's parent, to tell of its exit
+							let mut parents = HashMap::new();
+							// The relay bridge each child spawned through one reports to, until it exits
+							let mut relayed: HashMap<Pid, Pid> = HashMap::new();
+							// Each relay bridge, the process that spawned it, and a queue of events to it, forwarded in order without holding up this loop
+							let mut relays: HashMap<Pid, (Pid, mpsc::Sender<InputEventInt>)> =
+								HashMap::new();
 							for event in receiver.iter() {
 								let event = match event {
-									OutputEventInt::Spawn(pid, new_pid, sender) => {
+									OutputEventInt::Spawn(pid, new_pid, detached, sender) => {
 										let x = hashmap.lock().unwrap().insert(new_pid, sender);
 										assert!(x.is_none());
+										if !detached {
+											let x = parents.insert(new_pid, pid);
+											assert!(x.is_none());
+										}
+										DeployOutputEvent::Spawn(pid, new_pid)
+									}
+									OutputEventInt::SpawnRelay(pid, relay, sender) => {
+										let (queue, queued) = mpsc::channel();
+										let sender_ = sender.clone();
+										let _ = spawn(String::from("relay-queue"), move || {
+											for event in queued {
+												if sender_.send(event).is_err() {
+													break;
+												}
+											}
+										});
+										let x = hashmap.lock().unwrap().insert(relay, sender);
+										assert!(x.is_none());
+										let x = relays.insert(relay, (pid, queue));
+										assert!(x.is_none());
+										DeployOutputEvent::Spawn(pid, relay)
+									}
+									OutputEventInt::RelayedSpawn(pid, new_pid, relay) => {
+										let x = parents.insert(new_pid, pid);
+										assert!(x.is_none());
+										if let Some(&(_, ref queue)) = relays.get(&relay) {
+											let _ = relayed.insert(new_pid, relay);
+											let _ = queue.send(InputEventInt::Adopt(new_pid, pid));
+										} else {
+											warn!(
+												"{} spawned {} through the relay bridge {}, which has exited or doesn't report to the same bridge, so its events are lost",
+												pid, new_pid, relay
+											);
+										}
 										DeployOutputEvent::Spawn(pid, new_pid)
 									}
+									OutputEventInt::Relay(event) => {
+										if let DeployOutputEvent::Exit(child, exit_code, _) = event
+										{
+											// Only a child spawned through the relay by a process here has a parent to tell
+											let parent = relayed
+												.remove(&child)
+												.and_then(|_| parents.remove(&child))
+												.and_then(|parent| {
+													hashmap.lock().unwrap().get(&parent).cloned()
+												});
+											if let Some(parent) = parent {
+												child_exit(parent, child, exit_code);
+											}
+										}
+										event
+									}
 									OutputEventInt::Output(pid, fd, output) => {
 										DeployOutputEvent::Output(pid, fd, output)
 									}
-									OutputEventInt::Exit(pid, exit_code) => {
+									OutputEventInt::Panic(pid, panic) => {
+										DeployOutputEvent::Panic(pid, panic)
+									}
+									OutputEventInt::Exit(pid, exit_code, usage) => {
 										let _ = hashmap.lock().unwrap().remove(&pid).unwrap();
-										DeployOutputEvent::Exit(pid, exit_code)
+										let _ = relays.remove(&pid);
+										for &(_, ref queue) in
+											relays.values().filter(|&&(owner, _)| owner == pid)
+										{
+											let _ = queue.send(InputEventInt::Close);
+										}
+										let parent = parents.remove(&pid).and_then(|parent| {
+											hashmap.lock().unwrap().get(&parent).cloned()
+										});
+										if let Some(parent) = parent {
+											child_exit(parent, pid, exit_code);
+										}
+										DeployOutputEvent::Exit(pid, exit_code, usage)
+									}
+									OutputEventInt::Metric(pid, name, value) => {
+										DeployOutputEvent::Metric(pid, name, value)
+									}
+									OutputEventInt::Kill(pid, child) => {
+										// Only a process's own children, and only if they haven't yet exited
+										if parents.get(&child) == Some(&pid) {
+											if let Some(process) =
+												hashmap.lock().unwrap().get(&child)
+											{
+												let _ = process.send(InputEventInt::Kill);
+											} else if let Some(&(_, ref queue)) = relayed
+												.get(&child)
+												.and_then(|relay| relays.get(relay))
+											{
+												let _ = queue
+													.send(InputEventInt::KillRelayed(child, false));
+											}
+										}
+										continue;
+									}
+									OutputEventInt::KillDescendants(pid) => {
+										for (&child, process) in hashmap.lock().unwrap().iter() {
+											if is_descendant(&parents, child, pid) {
+												let _ = process.send(InputEventInt::Kill);
+											}
+										}
+										for (&child, relay) in &relayed {
+											if !is_descendant(&parents, child, pid) {
+												continue;
+											}
+											if let Some(&(_, ref queue)) = relays.get(relay) {
+												let _ = queue
+													.send(InputEventInt::KillRelayed(child, true));
+											}
+										}
+										continue;
 									}
 								};
-								if bincode::serialize_into(&mut stream_write, &event).is_err() {
+								if let Some(ref watchdog) = watchdog {
+									watchdog.event(&event);
+								}
+								if let Some(ref liveness) = liveness {
+									liveness.event(&event);
+								}
+								if bincode_config()
+									.serialize_into(&mut *stream_write.lock().unwrap(), &event)
+									.is_err()
+								{
 									break;
 								}
 							}
@@ -439,20 +667,52 @@ fn main() {
 		let len: u64 = binary.metadata().unwrap().len();
 		assert_ne!(len, 0);
 		let mut scheduler_write_ = scheduler_write.write();
-		bincode::serialize_into(&mut scheduler_write_, &process).unwrap();
-		bincode::serialize_into(&mut scheduler_write_, &args).unwrap();
-		bincode::serialize_into(&mut scheduler_write_, &vars).unwrap();
-		bincode::serialize_into(&mut scheduler_write_, &len).unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &process)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &None::<NodeId>)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &args)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &vars)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &None::<PathBuf>)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &None::<PathBuf>)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &len)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &file_hash(&binary).unwrap())
+			.unwrap();
 		drop(scheduler_write_);
-		copy_sendfile(&binary, &**scheduler_write.get_ref(), len).unwrap();
+		let cached: bool = bincode_config()
+			.deserialize_from(&mut scheduler_read)
+			.map_err(map_bincode_err)
+			.unwrap();
+		if !cached {
+			copy_sendfile(&binary, &**scheduler_write.get_ref(), len).unwrap();
+		}
 		let mut scheduler_write_ = scheduler_write.write();
-		bincode::serialize_into(&mut scheduler_write_, &arg).unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &arg)
+			.unwrap();
+		bincode_config()
+			.serialize_into(&mut scheduler_write_, &0_u64)
+			.unwrap(); // no payload
 		drop(scheduler_write_);
 
-		let pid: Option<Pid> = bincode::deserialize_from(&mut scheduler_read)
+		let pid: Result<Pid, SpawnFailure> = bincode_config()
+			.deserialize_from(&mut scheduler_read)
 			.map_err(map_bincode_err)
 			.unwrap();
-		sender.send(pid).unwrap();
+		sender.send(pid.ok()).unwrap();
 		drop((scheduler_read, scheduler_write));
 		let _ = scheduler.into_raw_fd();
 	}
@@ -5,7 +5,7 @@
 //!
 //! ## Usage
 //! ```text
-//! constellation master (<addr> <mem> <cpu> [<bridge> <addr>]...)...
+//! constellation master (<addr> <mem> <cpu> [<disk>] [<bridge> <addr>]...)...
 //! constellation <addr>
 //! ```
 //!
@@ -18,7 +18,8 @@
 //! A constellation cluster comprises one or more nodes, where one is declared master.
 //!
 //! The arguments to the master node are the address and resources of each node,
-//! including itself. The arguments for a node can include a binary to spawn
+//! including itself. A node's scratch disk is optional; if omitted, processes'
+//! disk requirements aren't accounted for on that node. The arguments for a node can include a binary to spawn
 //! immediately and an address to reserve for it. This is intended to be used to
 //! spawn the bridge, which works with the deploy command and library to handle
 //! transparent capture and forwarding of output and debug information.
@@ -87,10 +88,10 @@ extern crate serde_json;
 
 mod master;
 
-use constellation_internal::{map_bincode_err, parse_binary_size, BufferedStream, Resources};
+use constellation_internal::{bincode_config, map_bincode_err, parse_binary_size, retry_eintr, BufferedStream, Resources};
 use either::Either;
 #[cfg(unix)]
-use nix::{fcntl, sys::signal, sys::socket, sys::wait, unistd};
+use nix::{fcntl, sys::signal, sys::socket, sys::stat, sys::wait, unistd};
 use palaver::{
 	copy, dup_to, fexecve, is_valgrind, memfd_create, move_fds, seal, socket, spawn, valgrind_start_fd, SockFlag
 };
@@ -118,7 +119,7 @@ Run a constellation node, optionally as master, and optionally start a bridge ru
 ";
 const USAGE: &str = r"
 USAGE:
-    constellation master (<addr> <mem> <cpu> [<bridge> <addr>]...)...
+    constellation master (<addr> <mem> <cpu> [<disk>] [<bridge> <addr>]...)...
     constellation <addr>
 
 OPTIONS:
@@ -129,7 +130,8 @@ const HELP: &str = r"
 A constellation cluster comprises one or more nodes, where one is declared master.
 
 The arguments to the master node are the address and resources of each node,
-including itself. The arguments for a node can include a binary to spawn
+including itself. A node's scratch disk is optional; if omitted, processes'
+disk requirements aren't accounted for on that node. The arguments for a node can include a binary to spawn
 immediately and an address to reserve for it. This is intended to be used to
 spawn the bridge, which works with the deploy command and library to handle
 transparent capture and forwarding of output and debug information.
@@ -163,6 +165,7 @@ struct Node {
 	addr: net::SocketAddr,
 	mem: u64,
 	cpu: f32,
+	disk: u64,
 	run: Vec<Run>,
 }
 #[derive(Debug)]
@@ -186,6 +189,19 @@ impl Arg {
 					) {
 						(None, _, _) if !nodes.is_empty() => break,
 						(Some(Ok(addr)), Some(Ok(mem)), Some(Ok(cpu))) => {
+							let disk = if args.peek().map_or(false, |x| {
+								x.starts_with(|c: char| c.is_ascii_digit())
+									&& x.parse::<net::SocketAddr>().is_err()
+							}) {
+								if let Ok(disk) = parse_binary_size(&args.next().unwrap()) {
+									disk
+								} else {
+									println!("Invalid disk, expecting a size like 2TiB\n{}", USAGE);
+									process::exit(1)
+								}
+							} else {
+								std::u64::MAX
+							};
 							let mut run = Vec::new();
 							while let Some(Err(_binary)) =
 								args.peek().map(|x| x.parse::<net::SocketAddr>())
@@ -206,6 +222,7 @@ impl Arg {
 								addr,
 								mem,
 								cpu,
+								disk,
 								run,
 							});
 						}
@@ -246,42 +263,64 @@ fn parse_request<R: Read>(
 	(
 		Resources,
 		Vec<net::SocketAddr>,
-		fs::File,
+		Either<PathBuf, fs::File>,
 		Vec<OsString>,
 		Vec<(OsString, OsString)>,
+		Option<PathBuf>,
 		fs::File,
 	),
 	io::Error,
 > {
-	let resources = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let ports: Vec<net::SocketAddr> =
-		bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let args: Vec<OsString> = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let vars: Vec<(OsString, OsString)> =
-		bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let len: u64 = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let mut binary = unsafe {
-		fs::File::from_raw_fd(
-			memfd_create(
-				&CString::new(OsStringExt::into_vec(args[0].clone())).unwrap(),
-				true,
+	let resources = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let ports: Vec<net::SocketAddr> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let args: Vec<OsString> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let vars: Vec<(OsString, OsString)> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let exec: Option<PathBuf> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let dir: Option<PathBuf> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let len: u64 = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let binary = if let Some(exec) = exec {
+		// An executable already on this node, rather than sent
+		assert_eq!(len, 0);
+		Either::Left(exec)
+	} else {
+		let mut binary = unsafe {
+			fs::File::from_raw_fd(
+				memfd_create(
+					&CString::new(OsStringExt::into_vec(args[0].clone())).unwrap(),
+					true,
+				)
+				.expect("Failed to memfd_create"),
 			)
-			.expect("Failed to memfd_create"),
-		)
-	};
-	assert!(
-		fcntl::FdFlag::from_bits(
+		};
+		assert!(fcntl::FdFlag::from_bits(
 			fcntl::fcntl(binary.as_raw_fd(), fcntl::FcntlArg::F_GETFD).unwrap()
 		)
 		.unwrap()
-		.contains(fcntl::FdFlag::FD_CLOEXEC)
-	);
-	unistd::ftruncate(binary.as_raw_fd(), len.try_into().unwrap()).unwrap();
-	copy(stream, &mut binary, len)?;
-	let x = unistd::lseek(binary.as_raw_fd(), 0, unistd::Whence::SeekSet).unwrap();
-	assert_eq!(x, 0);
-	seal(binary.as_raw_fd());
-	let spawn_arg: Vec<u8> = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
+		.contains(fcntl::FdFlag::FD_CLOEXEC));
+		unistd::ftruncate(binary.as_raw_fd(), len.try_into().unwrap()).unwrap();
+		copy(stream, &mut binary, len)?;
+		let x = unistd::lseek(binary.as_raw_fd(), 0, unistd::Whence::SeekSet).unwrap();
+		assert_eq!(x, 0);
+		seal(binary.as_raw_fd());
+		Either::Right(binary)
+	};
+	let spawn_arg: Vec<u8> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
 	let mut arg = unsafe {
 		fs::File::from_raw_fd(
 			memfd_create(
@@ -295,7 +334,7 @@ fn parse_request<R: Read>(
 	arg.write_all(&spawn_arg).unwrap();
 	let x = unistd::lseek(arg.as_raw_fd(), 0, unistd::Whence::SeekSet).unwrap();
 	assert_eq!(x, 0);
-	Ok((resources, ports, binary, args, vars, arg))
+	Ok((resources, ports, binary, args, vars, dir, arg))
 }
 
 fn main() {
@@ -315,6 +354,7 @@ fn main() {
 							     addr,
 							     mem,
 							     cpu,
+							     disk,
 							     run,
 							 }| {
 								(
@@ -322,6 +362,7 @@ fn main() {
 									(
 										mem,
 										cpu,
+										disk,
 										run.into_iter()
 											.map(|Run { binary, addr }| (binary, vec![addr]))
 											.collect(),
@@ -350,6 +391,7 @@ fn main() {
 					let receiver = receiver;
 					for (pid, done) in receiver.iter() {
 						match done {
+							Either::Left(0) => println!("FABRIC: failed init"),
 							Either::Left(init) => {
 								count += 1;
 								println!("FABRIC: init({}) {}:{}", count, pid, init);
@@ -359,7 +401,8 @@ fn main() {
 								println!("FABRIC: exit({}) {}:{}", count, pid, exit);
 							}
 						}
-						if bincode::serialize_into(&mut stream_write, &done)
+						if bincode_config()
+							.serialize_into(&mut stream_write, &done)
 							.map_err(map_bincode_err)
 							.is_err()
 						{
@@ -368,9 +411,30 @@ fn main() {
 					}
 					for _done in receiver.iter() {}
 				});
-				while let Ok((resources, ports, binary, args, vars, arg)) =
+				while let Ok((resources, ports, binary, args, vars, dir, arg)) =
 					parse_request(&mut stream_read)
 				{
+					// Opened before forking so that a bad directory fails the spawn rather than the child
+					let dir = match dir.map(|dir| {
+						fcntl::open(
+							&dir,
+							fcntl::OFlag::O_RDONLY
+								| fcntl::OFlag::O_DIRECTORY
+								| fcntl::OFlag::O_CLOEXEC,
+							stat::Mode::empty(),
+						)
+					}) {
+						Some(Ok(dir)) => Some(dir),
+						Some(Err(err)) => {
+							println!("FABRIC: failed to open working directory: {}", err);
+							// Port 0 tells the master the process wasn't started
+							sender
+								.send((unistd::Pid::from_raw(0), Either::Left(0)))
+								.unwrap();
+							continue;
+						}
+						None => None,
+					};
 					let process_listener = socket(
 						socket::AddressFamily::Inet,
 						socket::SockType::Stream,
@@ -409,15 +473,18 @@ fn main() {
 							}
 							unistd::setpgid(unistd::Pid::from_raw(0), unistd::Pid::from_raw(0))
 								.unwrap();
-							let binary = binary.into_raw_fd();
+							if let Some(dir) = dir {
+								unistd::fchdir(dir).unwrap();
+							}
+							let binary = binary.map_right(IntoRawFd::into_raw_fd);
 							let mut binary_desired_fd =
 								BOUND_FD_START + Fd::try_from(ports.len()).unwrap();
 							let arg = arg.into_raw_fd();
-							move_fds(&mut [
-								(arg, ARG_FD),
-								(process_listener, LISTENER_FD),
-								(binary, binary_desired_fd),
-							]);
+							let mut fds = vec![(arg, ARG_FD), (process_listener, LISTENER_FD)];
+							if let Some(&binary) = binary.as_ref().right() {
+								fds.push((binary, binary_desired_fd));
+							}
+							move_fds(&mut fds);
 							for (i, port) in ports.into_iter().enumerate() {
 								let socket: Fd = BOUND_FD_START + Fd::try_from(i).unwrap();
 								let fd = socket::socket(
@@ -462,7 +529,18 @@ fn main() {
 								.unwrap()
 							})
 							.collect::<Vec<_>>();
-							if false {
+							if let Either::Left(exec) = binary {
+								unistd::execve(
+									&CString::new(OsStringExt::into_vec(exec.into_os_string()))
+										.unwrap(),
+									&args
+										.into_iter()
+										.map(|x| CString::new(OsStringExt::into_vec(x)).unwrap())
+										.collect::<Vec<_>>(),
+									&vars,
+								)
+								.expect("Failed to execve");
+							} else if false {
 								unistd::execve(
 									&CString::new(OsStringExt::into_vec(args[0].clone())).unwrap(),
 									&args
@@ -500,12 +578,15 @@ fn main() {
 						unistd::ForkResult::Parent { child, .. } => child,
 					};
 					unistd::close(process_listener).unwrap();
+					if let Some(dir) = dir {
+						unistd::close(dir).unwrap();
+					}
 					let x = pending.write().unwrap().insert(process_id, child);
 					assert!(x.is_none());
 					sender.send((child, Either::Left(process_id))).unwrap();
 					let sender = sender.clone();
 					let _ = scope.spawn(move || {
-						match wait::waitpid(child, None).unwrap() {
+						match retry_eintr(|| wait::waitpid(child, None)).unwrap() {
 							wait::WaitStatus::Exited(pid, code) if code == 0 => {
 								assert_eq!(pid, child)
 							}
@@ -1,31 +1,35 @@
-use bincode;
-use constellation_internal::{map_bincode_err, BufferedStream, Pid, PidInternal, Resources};
+use constellation_internal::{
+	bincode_config, map_bincode_err, BufferedStream, NodeId, NodeIdInternal, Pid, PidInternal, Resources, SpawnFailure, RESOURCES_QUERY
+};
 use crossbeam;
 use either::Either;
 use palaver::{copy, spawn};
 use std::{
-	collections::{HashMap, HashSet, VecDeque}, convert::{TryFrom, TryInto}, env, ffi::OsString, fs, io::{self, Read, Write}, net, path, sync::mpsc
+	collections::{HashMap, HashSet, VecDeque}, convert::{TryFrom, TryInto}, env, ffi::OsString, fs, io::{self, Read, Write}, net, path, sync::{self, mpsc}
 };
 
 #[derive(Debug)]
 pub struct Node {
 	mem: u64,
 	cpu: f32,
+	disk: u64,
 }
 impl Node {
 	fn fits(&self, process: &Resources) -> bool {
-		process.mem <= self.mem && process.cpu <= self.cpu
+		process.mem <= self.mem && process.cpu <= self.cpu && process.disk <= self.disk
 	}
 
 	fn alloc(&mut self, process: &Resources) {
 		assert!(process.cpu <= self.cpu);
 		self.mem -= process.mem;
 		self.cpu -= process.cpu;
+		self.disk -= process.disk;
 	}
 
 	fn free(&mut self, process: &Resources) {
 		self.mem += process.mem;
 		self.cpu += process.cpu;
+		self.disk += process.disk;
 	}
 }
 
@@ -34,45 +38,107 @@ struct SchedulerArg {
 	scheduler: net::SocketAddr,
 }
 
-fn parse_request<R: Read>(
-	mut stream: &mut R,
+/// Binaries and payloads received from processes, keyed by length and SHA-256 `file_hash()`, so that spawning the same binary or with the same payload repeatedly only sends it once. They're shared rather than copied for each spawn.
+type BinaryCache = sync::Mutex<HashMap<(u64, [u8; 32]), sync::Arc<[u8]>>>;
+/// Beyond this many distinct binaries the cache is cleared.
+const BINARY_CACHE_MAX: usize = 16;
+
+fn parse_request<R: Read, W: Write>(
+	mut stream: &mut R, stream_write: &mut W, cache: &BinaryCache,
 ) -> Result<
 	(
 		Resources,
+		Option<NodeId>,
 		Vec<OsString>,
 		Vec<(OsString, OsString)>,
-		Vec<u8>,
+		Option<path::PathBuf>,
+		Option<path::PathBuf>,
+		sync::Arc<[u8]>,
 		Vec<u8>,
 	),
 	io::Error,
 > {
-	let process = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let args = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let vars = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let len: u64 = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	let mut binary = Vec::with_capacity(len.try_into().unwrap());
-	copy(stream, &mut binary, len)?;
-	assert_eq!(binary.len(), usize::try_from(len).unwrap());
-	let arg = bincode::deserialize_from(&mut stream).map_err(map_bincode_err)?;
-	Ok((process, args, vars, binary, arg))
+	let process = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let node = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let args = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let vars = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let exec = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let dir = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let binary = receive_cached(stream, stream_write, cache)?;
+	let mut arg: Vec<u8> = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	// A payload from spawn_with_payload() follows the arg as it is
+	arg.extend_from_slice(&receive_cached(stream, stream_write, cache)?);
+	Ok((process, node, args, vars, exec, dir, binary, arg))
+}
+
+/// Receive a length, then if nonzero a hash and, if it isn't in `cache`, the bytes.
+fn receive_cached<R: Read, W: Write>(
+	mut stream: &mut R, stream_write: &mut W, cache: &BinaryCache,
+) -> Result<sync::Arc<[u8]>, io::Error> {
+	let len: u64 = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	if len == 0 {
+		return Ok(sync::Arc::from(Vec::new()));
+	}
+	let hash: [u8; 32] = bincode_config()
+		.deserialize_from(&mut stream)
+		.map_err(map_bincode_err)?;
+	let cached = cache.lock().unwrap().get(&(len, hash)).cloned();
+	bincode_config()
+		.serialize_into(&mut *stream_write, &cached.is_some())
+		.map_err(map_bincode_err)?;
+	if let Some(bytes) = cached {
+		return Ok(bytes);
+	}
+	let mut bytes = Vec::with_capacity(len.try_into().unwrap());
+	copy(stream, &mut bytes, len)?;
+	assert_eq!(bytes.len(), usize::try_from(len).unwrap());
+	let bytes: sync::Arc<[u8]> = sync::Arc::from(bytes);
+	let mut cache = cache.lock().unwrap();
+	if cache.len() >= BINARY_CACHE_MAX {
+		cache.clear();
+	}
+	let _ = cache.insert((len, hash), bytes.clone());
+	Ok(bytes)
 }
 
 pub fn run(
 	addr: net::SocketAddr,
-	nodes: HashMap<net::SocketAddr, (u64, f32, Vec<(path::PathBuf, Vec<net::SocketAddr>)>)>,
+	nodes: HashMap<net::SocketAddr, (u64, f32, u64, Vec<(path::PathBuf, Vec<net::SocketAddr>)>)>,
 ) {
 	let (sender, receiver) = mpsc::sync_channel::<
 		Either<
-			(
-				Resources,
-				Vec<OsString>,
-				Vec<(OsString, OsString)>,
-				Vec<u8>,
-				Vec<u8>,
-				mpsc::SyncSender<Option<Pid>>,
-				Option<usize>,
-				Vec<net::SocketAddr>,
-			),
+			Either<
+				(
+					Resources,
+					Vec<OsString>,
+					Vec<(OsString, OsString)>,
+					Option<path::PathBuf>,
+					Option<path::PathBuf>,
+					sync::Arc<[u8]>,
+					Vec<u8>,
+					mpsc::SyncSender<Result<Pid, SpawnFailure>>,
+					Option<usize>,
+					Option<NodeId>,
+					Vec<net::SocketAddr>,
+				),
+				mpsc::SyncSender<Resources>,
+			>,
 			(usize, Either<u16, u16>),
 		>,
 	>(0);
@@ -80,8 +146,9 @@ pub fn run(
 	let mut nodes = nodes
 		.into_iter()
 		.enumerate()
-		.map(|(i, (addr, (mem, cpu, bridges)))| {
-			let node = Node { mem, cpu };
+		.map(|(i, (addr, (mem, cpu, disk, bridges)))| {
+			let node = Node { mem, cpu, disk };
+			let total = Node { mem, cpu, disk };
 			let mut check_addresses = HashSet::new();
 			let check_port = check_addresses.insert(addr);
 			assert!(check_port);
@@ -89,7 +156,9 @@ pub fn run(
 				Resources,
 				Vec<OsString>,
 				Vec<(OsString, OsString)>,
-				Vec<u8>,
+				Option<path::PathBuf>,
+				Option<path::PathBuf>,
+				sync::Arc<[u8]>,
 				Vec<u8>,
 				Vec<net::SocketAddr>,
 			)>(0);
@@ -102,24 +171,41 @@ pub fn run(
 					(BufferedStream::new(&stream), BufferedStream::new(&stream));
 				crossbeam::scope(|scope| {
 					let _ = scope.spawn(|| {
-						for (process, args, vars, binary, arg, ports) in receiver {
+						for (process, args, vars, exec, dir, binary, arg, ports) in receiver {
 							let mut stream_write = stream_write.write();
-							bincode::serialize_into(&mut stream_write, &process).unwrap();
-							bincode::serialize_into(&mut stream_write, &ports).unwrap(); // TODO: do all ports before everything else
-							bincode::serialize_into(&mut stream_write, &args).unwrap();
-							bincode::serialize_into(&mut stream_write, &vars).unwrap();
-							bincode::serialize_into(&mut stream_write, &(binary.len() as u64))
+							bincode_config()
+								.serialize_into(&mut stream_write, &process)
+								.unwrap();
+							bincode_config()
+								.serialize_into(&mut stream_write, &ports)
+								.unwrap(); // TODO: do all ports before everything else
+							bincode_config()
+								.serialize_into(&mut stream_write, &args)
+								.unwrap();
+							bincode_config()
+								.serialize_into(&mut stream_write, &vars)
+								.unwrap();
+							bincode_config()
+								.serialize_into(&mut stream_write, &exec)
+								.unwrap();
+							bincode_config()
+								.serialize_into(&mut stream_write, &dir)
+								.unwrap();
+							bincode_config()
+								.serialize_into(&mut stream_write, &(binary.len() as u64))
 								.unwrap();
 							stream_write.write_all(&binary).unwrap();
-							bincode::serialize_into(&mut stream_write, &arg).unwrap();
+							bincode_config()
+								.serialize_into(&mut stream_write, &arg)
+								.unwrap();
 							drop(stream_write);
 						}
 					});
 					let _ = scope.spawn(|| {
 						let sender = sender;
-						while let Ok(done) =
-							bincode::deserialize_from::<_, Either<u16, u16>>(&mut stream_read)
-								.map_err(map_bincode_err)
+						while let Ok(done) = bincode_config()
+							.deserialize_from::<_, Either<u16, u16>>(&mut stream_read)
+							.map_err(map_bincode_err)
 						{
 							sender.send(Either::Right((i, done))).unwrap();
 						}
@@ -141,53 +227,84 @@ pub fn run(
 						.unwrap_or_else(|_| panic!("Failed to open bridge {:?}", &bridge));
 					let mut binary = Vec::new();
 					let _ = file_in.read_to_end(&mut binary).unwrap();
-					let (sender_, receiver) = mpsc::sync_channel::<Option<Pid>>(0);
+					let (sender_, receiver) = mpsc::sync_channel::<Result<Pid, SpawnFailure>>(0);
 					sender
-						.send(Either::Left((
-							Resources { mem: 0, cpu: 0.0 },
+						.send(Either::Left(Either::Left((
+							Resources {
+								mem: 0,
+								cpu: 0.0,
+								disk: 0,
+							},
 							vec![OsString::from(bridge)],
 							Vec::new(),
-							binary,
+							None,
+							None,
+							sync::Arc::from(binary),
 							Vec::new(),
 							sender_,
 							Some(i),
+							None,
 							ports,
-						)))
+						))))
 						.unwrap();
-					let pid: Option<Pid> = receiver.recv().unwrap();
+					let pid: Result<Pid, SpawnFailure> = receiver.recv().unwrap();
 					println!("bridge at {:?}", pid.unwrap());
 				});
 			}
-			(sender_a, node, addr.ip(), local_addr, VecDeque::new())
+			(
+				sender_a,
+				node,
+				addr.ip(),
+				local_addr,
+				VecDeque::new(),
+				total,
+			)
 		})
 		.collect::<Vec<_>>();
 
 	let listener = net::TcpListener::bind(addr).unwrap();
+	let cache: sync::Arc<BinaryCache> = sync::Arc::new(sync::Mutex::new(HashMap::new()));
 	let _ = spawn(String::from(""), move || {
 		for stream in listener.incoming() {
 			// println!("accepted");
 			let mut stream = stream.unwrap();
 			let sender = sender.clone();
+			let cache = cache.clone();
 			let _ = spawn(String::from(""), move || {
 				let (mut stream_read, mut stream_write) = (BufferedStream::new(&stream), &stream);
-				while let Ok((process, args, vars, binary, arg)) = parse_request(&mut stream_read) {
-					// println!("parsed");
-					let (sender_, receiver) = mpsc::sync_channel::<Option<Pid>>(0);
-					sender
-						.send(Either::Left((
-							process,
-							args,
-							vars,
-							binary,
-							arg,
-							sender_,
-							None,
-							vec![],
-						)))
-						.unwrap();
-					let pid: Option<Pid> = receiver.recv().unwrap();
-					// let mut stream_write = stream_write.write();
-					if bincode::serialize_into(&mut stream_write, &pid).is_err() {
+				while let Ok((process, node, args, vars, exec, dir, binary, arg)) =
+					parse_request(&mut stream_read, &mut stream_write, &cache)
+				{
+					let err = if process == RESOURCES_QUERY {
+						let (sender_, receiver) = mpsc::sync_channel::<Resources>(0);
+						sender.send(Either::Left(Either::Right(sender_))).unwrap();
+						let free = receiver.recv().unwrap();
+						// Read as a Result<Resources, SpawnFailure>, as schedulers that predate the query reply with a spawn's error
+						bincode_config()
+							.serialize_into(&mut stream_write, &Ok::<_, SpawnFailure>(free))
+					} else {
+						// println!("parsed");
+						let (sender_, receiver) =
+							mpsc::sync_channel::<Result<Pid, SpawnFailure>>(0);
+						sender
+							.send(Either::Left(Either::Left((
+								process,
+								args,
+								vars,
+								exec,
+								dir,
+								binary,
+								arg,
+								sender_,
+								None,
+								node,
+								vec![],
+							))))
+							.unwrap();
+						let pid = receiver.recv().unwrap();
+						bincode_config().serialize_into(&mut stream_write, &pid)
+					};
+					if err.is_err() {
 						break;
 					}
 				}
@@ -199,10 +316,42 @@ pub fn run(
 
 	for msg in receiver.iter() {
 		match msg {
-			Either::Left((process, args, vars, binary, arg, sender, force, ports)) => {
+			Either::Left(Either::Right(sender)) => {
+				let free = nodes.iter().fold(
+					Resources {
+						mem: 0,
+						cpu: 0.0,
+						disk: 0,
+					},
+					|free, node| Resources {
+						mem: free.mem + node.1.mem,
+						cpu: free.cpu + node.1.cpu,
+						disk: free.disk.saturating_add(node.1.disk),
+					},
+				);
+				println!("query {:?}", free);
+				sender.send(free).unwrap();
+			}
+			Either::Left(Either::Left((
+				process,
+				args,
+				vars,
+				exec,
+				dir,
+				binary,
+				arg,
+				sender,
+				force,
+				place,
+				ports,
+			))) => {
 				println!("spawn {:?}", process);
 				let node = if force.is_none() {
-					nodes.iter().position(|node| node.1.fits(&process))
+					// If placed on a node, fail rather than fall back to another
+					nodes.iter().position(|node| {
+						place.map_or(true, |place| NodeId::new(node.2) == place)
+							&& node.1.fits(&process)
+					})
 				} else {
 					Some(force.unwrap())
 				};
@@ -211,16 +360,17 @@ pub fn run(
 					node.1.alloc(&process);
 
 					let mut sched_arg = Vec::new();
-					bincode::serialize_into(
-						&mut sched_arg,
-						&SchedulerArg {
-							scheduler: net::SocketAddr::new(node.3, addr.port()),
-						},
-					)
-					.unwrap();
+					bincode_config()
+						.serialize_into(
+							&mut sched_arg,
+							&SchedulerArg {
+								scheduler: net::SocketAddr::new(node.3, addr.port()),
+							},
+						)
+						.unwrap();
 					sched_arg.extend(arg);
 					node.0
-						.send((process, args, vars, binary, sched_arg, ports))
+						.send((process, args, vars, exec, dir, binary, sched_arg, ports))
 						.unwrap();
 					node.4.push_back((sender, process));
 				} else {
@@ -228,9 +378,26 @@ pub fn run(
 						"Failing a spawn! Cannot allocate process {:#?} to nodes {:#?}",
 						process, nodes
 					);
-					sender.send(None).unwrap();
+					// Distinguish a process that has to wait for others to exit from one that will never fit
+					let failure = if nodes.iter().any(|node| {
+						place.map_or(true, |place| NodeId::new(node.2) == place)
+							&& node.5.fits(&process)
+					}) {
+						SpawnFailure::Resources
+					} else {
+						SpawnFailure::Unsatisfiable
+					};
+					sender.send(Err(failure)).unwrap();
 				}
 			}
+			Either::Right((node_, Either::Left(0))) => {
+				// The node couldn't start the process, e.g. as its working directory is missing
+				println!("failed init {}", node_);
+				let node = &mut nodes[node_];
+				let (sender, process) = node.4.pop_front().unwrap();
+				node.1.free(&process);
+				sender.send(Err(SpawnFailure::Directory)).unwrap();
+			}
 			Either::Right((node_, Either::Left(init))) => {
 				println!("init {}:{}", node_, init);
 				let node = &mut nodes[node_];
@@ -238,7 +405,7 @@ pub fn run(
 				let x = processes.insert((node_, init), process);
 				assert!(x.is_none());
 				let pid = Pid::new(node.2, init);
-				sender.send(Some(pid)).unwrap();
+				sender.send(Ok(pid)).unwrap();
 			}
 			Either::Right((node, Either::Right(done))) => {
 				let process = processes.remove(&(node, done)).unwrap();
@@ -41,12 +41,12 @@ extern crate either;
 extern crate palaver;
 
 use constellation_internal::{
-	map_bincode_err, BufferedStream, DeployInputEvent, DeployOutputEvent, Envs, ExitStatus, Format, Formatter, Pid, Resources, StyleSupport
+	bincode_config, map_bincode_err, BufferedStream, Color, DeployInputEvent, DeployOutputEvent, Envs, ExitStatus, Format, Formatter, OutputEncoding, Pid, Resources, StyleSupport
 };
 use either::Either;
 use palaver::copy_sendfile;
 use std::{
-	collections::HashSet, env, ffi, fs, io::{self, Read, Write}, iter, mem, net, path, process
+	collections::HashSet, env, ffi, fs, io::{self, Read, Write}, iter, net, path, process
 };
 
 #[global_allocator]
@@ -65,6 +65,8 @@ OPTIONS:
     --format=<fmt>     Output format [possible values: human, json] [defa ult: human]
 
 Note: --format can also be given as an env var, such as CONSTELLATION_FORMAT=json
+Color can be set with CONSTELLATION_COLOR=always|never|auto|256|truecolor
+Output that isn't UTF-8 can be written with CONSTELLATION_OUTPUT_ENCODING=lossy|escape|raw
 ";
 
 #[derive(Debug, Deserialize)]
@@ -91,6 +93,12 @@ fn main() {
 				.map(|x| x.expect("CONSTELLATION_FORMAT must be json or human"))
 		})
 		.unwrap_or(Format::Human);
+	let color = envs.color.map_or(Color::Auto, |x| {
+		x.expect("CONSTELLATION_COLOR must be always, never, auto, 256 or truecolor")
+	});
+	let output_encoding = envs.output_encoding.map_or(OutputEncoding::Lossy, |x| {
+		x.expect("CONSTELLATION_OUTPUT_ENCODING must be lossy, escape or raw")
+	});
 	let bridge_address: net::SocketAddr = args.arg_host.parse().unwrap();
 	let path = args.arg_binary;
 	let args: Vec<ffi::OsString> = iter::once(ffi::OsString::from(path.clone()))
@@ -105,17 +113,28 @@ fn main() {
 	let len: u64 = binary.metadata().unwrap().len();
 	assert_ne!(len, 0);
 	let mut stream_write_ = stream_write.write();
-	bincode::serialize_into(&mut stream_write_, &None::<Resources>).unwrap();
-	bincode::serialize_into(&mut stream_write_, &args).unwrap();
-	bincode::serialize_into(&mut stream_write_, &vars).unwrap();
-	bincode::serialize_into(&mut stream_write_, &len).unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &None::<Resources>)
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &args)
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &vars)
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &len)
+		.unwrap();
 	drop(stream_write_);
 	copy_sendfile(&binary, &**stream_write.get_ref(), len).unwrap();
 	let mut stream_write_ = stream_write.write();
 	let arg: Vec<u8> = Vec::new();
-	bincode::serialize_into(&mut stream_write_, &arg).unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &arg)
+		.unwrap();
 	drop(stream_write_);
-	let pid: Option<Pid> = bincode::deserialize_from(&mut stream_read)
+	let pid: Option<Pid> = bincode_config()
+		.deserialize_from(&mut stream_read)
 		.map_err(map_bincode_err)
 		.unwrap();
 	let pid = pid.unwrap_or_else(|| {
@@ -125,13 +144,14 @@ fn main() {
 		let _ = scope.spawn(|| {
 			let mut stdin = io::stdin();
 			loop {
-				let mut buf: [u8; 1024] = unsafe { mem::uninitialized() };
+				let mut buf = [0; 1024];
 				let n = stdin.read(&mut buf).unwrap();
-				bincode::serialize_into(
-					&mut stream_write.write(),
-					&DeployInputEvent::Input(pid, 0, buf[..n].to_owned()),
-				)
-				.unwrap();
+				bincode_config()
+					.serialize_into(
+						&mut stream_write.write(),
+						&DeployInputEvent::Input(pid, 0, buf[..n].to_owned()),
+					)
+					.unwrap();
 				if n == 0 {
 					break;
 				}
@@ -144,17 +164,15 @@ fn main() {
 		let mut formatter = if let Format::Human = format {
 			Either::Left(Formatter::new(
 				pid,
-				if atty::is(atty::Stream::Stderr) {
-					StyleSupport::EightBit
-				} else {
-					StyleSupport::None
-				},
+				StyleSupport::from_color(color, atty::is(atty::Stream::Stderr)),
+				output_encoding,
 			))
 		} else {
 			Either::Right(io::stdout())
 		};
 		loop {
-			let event: DeployOutputEvent = bincode::deserialize_from(&mut stream_read)
+			let event: DeployOutputEvent = bincode_config()
+				.deserialize_from(&mut stream_read)
 				.map_err(map_bincode_err)
 				.expect("Bridge died");
 			match formatter {
@@ -175,7 +193,14 @@ fn main() {
 				DeployOutputEvent::Output(pid, _fd, _output) => {
 					assert!(pids.contains(&pid));
 				}
-				DeployOutputEvent::Exit(pid, exit_code_) => {
+				DeployOutputEvent::Panic(pid, _panic) => {
+					assert!(pids.contains(&pid));
+				}
+				DeployOutputEvent::Deadlock(_) => (),
+				DeployOutputEvent::Metric(pid, _name, _value) => {
+					assert!(pids.contains(&pid));
+				}
+				DeployOutputEvent::Exit(pid, exit_code_, _) => {
 					exit_code += exit_code_;
 					ref_count -= 1;
 					let x = pids.remove(&pid);
@@ -0,0 +1,479 @@
+#[cfg(feature = "tls")]
+use super::tls::{self, Tls};
+use super::{unix, KeepaliveNotifier};
+use either::Either;
+use std::{
+	collections::VecDeque, net, sync::atomic::{AtomicBool, Ordering}
+};
+use tcp_typed::{self, Notifier};
+
+/// Whether this process listens for connections from processes on the same node on a [`unix::Listener`], and so connects to them likewise. Only set in user processes: the bridge and each monitor share their addresses with other processes, and so stay on TCP.
+pub static UNIX: AtomicBool = AtomicBool::new(false);
+
+/// What a [Connection] is carried over: a unix domain socket if the remote is a process on the same node that listens on one, otherwise TCP.
+#[derive(Debug)]
+pub enum Transport {
+	Tcp(tcp_typed::Connection),
+	Unix(unix::Connection),
+}
+impl Transport {
+	pub fn connect(
+		local: net::SocketAddr, remote: net::SocketAddr, notifier: &impl Notifier,
+	) -> Self {
+		if UNIX.load(Ordering::Relaxed) && remote.ip() == local.ip() {
+			if let Some(connection) = unix::Connection::connect(local, remote, notifier) {
+				return Transport::Unix(connection);
+			}
+		}
+		Transport::Tcp(tcp_typed::Connection::connect(
+			local,
+			remote,
+			&KeepaliveNotifier(notifier),
+		))
+	}
+
+	pub fn connecting(&self) -> bool {
+		match *self {
+			Transport::Tcp(ref connection) => connection.connecting(),
+			Transport::Unix(ref connection) => connection.connecting(),
+		}
+	}
+
+	pub fn valid(&self) -> bool {
+		match *self {
+			Transport::Tcp(ref connection) => connection.valid(),
+			Transport::Unix(ref connection) => connection.valid(),
+		}
+	}
+
+	pub fn sendable(&self) -> bool {
+		match *self {
+			Transport::Tcp(ref connection) => connection.sendable(),
+			Transport::Unix(ref connection) => connection.sendable(),
+		}
+	}
+
+	pub fn send_avail(&self) -> Option<usize> {
+		match *self {
+			Transport::Tcp(ref connection) => connection.send_avail(),
+			Transport::Unix(ref connection) => connection.send_avail(),
+		}
+	}
+
+	pub fn send<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce(u8) + 'a> {
+		match *self {
+			Transport::Tcp(ref mut connection) => connection.send(notifier).map(Either::Left),
+			Transport::Unix(ref mut connection) => connection.send(notifier).map(Either::Right),
+		}
+		.map(|send| {
+			move |byte| match send {
+				Either::Left(send) => send(byte),
+				Either::Right(send) => send(byte),
+			}
+		})
+	}
+
+	pub fn recvable(&self) -> bool {
+		match *self {
+			Transport::Tcp(ref connection) => connection.recvable(),
+			Transport::Unix(ref connection) => connection.recvable(),
+		}
+	}
+
+	pub fn recv_avail(&self) -> Option<usize> {
+		match *self {
+			Transport::Tcp(ref connection) => connection.recv_avail(),
+			Transport::Unix(ref connection) => connection.recv_avail(),
+		}
+	}
+
+	pub fn recv<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
+		match *self {
+			Transport::Tcp(ref mut connection) => connection.recv(notifier).map(Either::Left),
+			Transport::Unix(ref mut connection) => connection.recv(notifier).map(Either::Right),
+		}
+		.map(|recv| {
+			move || match recv {
+				Either::Left(recv) => recv(),
+				Either::Right(recv) => recv(),
+			}
+		})
+	}
+
+	pub fn closable(&self) -> bool {
+		match *self {
+			Transport::Tcp(ref connection) => connection.closable(),
+			Transport::Unix(ref connection) => connection.closable(),
+		}
+	}
+
+	pub fn close<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		match *self {
+			Transport::Tcp(ref mut connection) => connection.close(notifier).map(Either::Left),
+			Transport::Unix(ref mut connection) => connection.close(notifier).map(Either::Right),
+		}
+		.map(|close| {
+			move || match close {
+				Either::Left(close) => close(),
+				Either::Right(close) => close(),
+			}
+		})
+	}
+
+	pub fn closed(&self) -> bool {
+		match *self {
+			Transport::Tcp(ref connection) => connection.closed(),
+			Transport::Unix(ref connection) => connection.closed(),
+		}
+	}
+
+	pub fn kill<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		match *self {
+			Transport::Tcp(ref mut connection) => connection.kill(notifier).map(Either::Left),
+			Transport::Unix(ref mut connection) => connection.kill(notifier).map(Either::Right),
+		}
+		.map(|kill| {
+			move || match kill {
+				Either::Left(kill) => kill(),
+				Either::Right(kill) => kill(),
+			}
+		})
+	}
+
+	pub fn poll(&mut self, notifier: &impl Notifier) {
+		match *self {
+			Transport::Tcp(ref mut connection) => connection.poll(notifier),
+			Transport::Unix(ref mut connection) => connection.poll(notifier),
+		}
+	}
+}
+
+/// Sent first on a TCP connection by each end, saying whether it wants the connection encrypted.
+const PLAINTEXT: u8 = 0;
+const TLS: u8 = 1;
+
+/// Why a [Connection] failed, for channels to fail with the corresponding [`ChannelError`](super::ChannelError).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Failure {
+	/// The remote isn't speaking this protocol.
+	Mismatch,
+	/// The two ends disagreed on whether to encrypt the connection, or its TLS session failed.
+	Tls,
+}
+
+/// Until each end of a TCP connection has told the other whether it wants TLS.
+#[derive(Debug)]
+struct Negotiation {
+	/// Whether this process wants TLS, per [`tls::wanted()`].
+	tls: bool,
+	/// Whether this process made the connection, and so is the TLS client.
+	#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+	outgoing: bool,
+	/// How much the transport could buffer before this process's byte was sent on it, to tell when it's been written.
+	capacity: Option<usize>,
+	received: Option<u8>,
+}
+
+/// A connection carrying a channel, with the methods of [`tcp_typed::Connection`] that channels use. TCP connections are encrypted if both ends want it, which is only with processes on other nodes, and agreed before anything else is sent.
+#[derive(Debug)]
+pub struct Connection {
+	transport: Transport,
+	negotiation: Option<Negotiation>,
+	#[cfg(feature = "tls")]
+	tls: Option<Tls>,
+	/// The plaintext sent and received on the connection if it's encrypted, on its way through the TLS session.
+	send: VecDeque<u8>,
+	recv: VecDeque<u8>,
+	/// Closed by this process while negotiating, to be passed on once it's done.
+	closing: bool,
+	failed: Option<Failure>,
+}
+impl Connection {
+	pub fn connect(
+		local: net::SocketAddr, remote: net::SocketAddr, notifier: &impl Notifier,
+	) -> Self {
+		Self::new(Transport::connect(local, remote, notifier), local, remote, true)
+	}
+
+	/// Wrap a connection accepted from `remote`.
+	pub fn accepted(transport: Transport, local: net::SocketAddr, remote: net::SocketAddr) -> Self {
+		Self::new(transport, local, remote, false)
+	}
+
+	fn new(
+		transport: Transport, local: net::SocketAddr, remote: net::SocketAddr, outgoing: bool,
+	) -> Self {
+		let negotiation = match transport {
+			Transport::Tcp(_) => Some(Negotiation {
+				tls: wanted(&local, &remote),
+				outgoing,
+				capacity: None,
+				received: None,
+			}),
+			Transport::Unix(_) => None,
+		};
+		Self {
+			transport,
+			negotiation,
+			#[cfg(feature = "tls")]
+			tls: None,
+			send: VecDeque::new(),
+			recv: VecDeque::new(),
+			closing: false,
+			failed: None,
+		}
+	}
+
+	/// Why the connection failed, if it was for a reason other than the remote dying or the connection being killed.
+	pub fn failed(&self) -> Option<Failure> {
+		self.failed
+	}
+
+	/// Whether the connection is still being made, TLS being negotiated, or its TLS handshake under way.
+	pub fn connecting(&self) -> bool {
+		self.transport.connecting()
+			|| (self.transport.valid() && (self.negotiation.is_some() || self.handshaking()))
+	}
+
+	#[cfg(feature = "tls")]
+	fn handshaking(&self) -> bool {
+		self.tls
+			.as_ref()
+			.map_or(false, |tls| tls.handshaking(&self.transport))
+	}
+	#[cfg(not(feature = "tls"))]
+	fn handshaking(&self) -> bool {
+		false
+	}
+
+	#[cfg(feature = "tls")]
+	fn encrypted(&self) -> bool {
+		self.tls.is_some()
+	}
+	#[cfg(not(feature = "tls"))]
+	fn encrypted(&self) -> bool {
+		false
+	}
+
+	pub fn valid(&self) -> bool {
+		self.transport.valid()
+	}
+
+	pub fn sendable(&self) -> bool {
+		#[cfg(feature = "tls")]
+		{
+			if let Some(ref tls) = self.tls {
+				return tls.sendable(&self.transport);
+			}
+		}
+		self.transport.sendable()
+	}
+
+	/// If the connection is encrypted, this is only back to what it was before anything was sent once it's all been encrypted and written.
+	pub fn send_avail(&self) -> Option<usize> {
+		#[cfg(feature = "tls")]
+		{
+			if let Some(ref tls) = self.tls {
+				return if tls.sendable(&self.transport) {
+					Some(
+						tls::BUFFER
+							.saturating_sub(self.send.len() + tls.unflushed(&self.transport)),
+					)
+				} else {
+					None
+				};
+			}
+		}
+		self.transport.send_avail()
+	}
+
+	pub fn send<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce(u8) + 'a> {
+		let send = if self.encrypted() {
+			if self.send_avail().map_or(false, |avail| avail > 0) {
+				Some(Either::Left(&mut self.send))
+			} else {
+				None
+			}
+		} else {
+			self.transport.send(notifier).map(Either::Right)
+		};
+		send.map(|send| {
+			move |byte| match send {
+				Either::Left(buffer) => buffer.push_back(byte),
+				Either::Right(send) => send(byte),
+			}
+		})
+	}
+
+	pub fn recvable(&self) -> bool {
+		#[cfg(feature = "tls")]
+		{
+			if let Some(ref tls) = self.tls {
+				return self.transport.valid() && !(tls.remote_closed() && self.recv.is_empty());
+			}
+		}
+		self.transport.recvable()
+	}
+
+	pub fn recv_avail(&self) -> Option<usize> {
+		if self.encrypted() {
+			return if self.recvable() {
+				Some(self.recv.len())
+			} else {
+				None
+			};
+		}
+		self.transport.recv_avail()
+	}
+
+	pub fn recv<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
+		let recv = if self.encrypted() {
+			if self.recv_avail().map_or(false, |avail| avail > 0) {
+				Some(Either::Left(&mut self.recv))
+			} else {
+				None
+			}
+		} else {
+			self.transport.recv(notifier).map(Either::Right)
+		};
+		recv.map(|recv| {
+			move || match recv {
+				Either::Left(buffer) => buffer.pop_front().unwrap(),
+				Either::Right(recv) => recv(),
+			}
+		})
+	}
+
+	pub fn closable(&self) -> bool {
+		#[cfg(feature = "tls")]
+		{
+			if let Some(ref tls) = self.tls {
+				return tls.closable(&self.transport);
+			}
+		}
+		if self.negotiation.is_some() {
+			return !self.closing && self.transport.valid();
+		}
+		self.transport.closable()
+	}
+
+	pub fn close<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		if !self.closable() {
+			return None;
+		}
+		Some(move || {
+			#[cfg(feature = "tls")]
+			{
+				if let Some(ref mut tls) = self.tls {
+					tls.close();
+				}
+			}
+			if self.negotiation.is_some() {
+				self.closing = true;
+			} else if self.encrypted() {
+				self.poll(notifier);
+			} else {
+				self.transport.close(notifier).unwrap()();
+			}
+		})
+	}
+
+	pub fn closed(&self) -> bool {
+		self.transport.closed()
+	}
+
+	pub fn kill<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		self.transport.kill(notifier)
+	}
+
+	pub fn poll(&mut self, notifier: &impl Notifier) {
+		self.transport.poll(notifier);
+		if self.negotiation.is_some() {
+			self.negotiate(notifier);
+			// Written now, so that it can be seen to have been
+			self.transport.poll(notifier);
+			self.negotiate(notifier);
+		}
+		#[cfg(feature = "tls")]
+		{
+			let result = if let Some(ref mut tls) = self.tls {
+				tls.poll(&mut self.transport, &mut self.send, &mut self.recv, notifier)
+			} else {
+				Ok(())
+			};
+			if let Err(err) = result {
+				warn!("TLS failed on a channel connection: {}", err);
+				self.fail(Failure::Tls, notifier);
+			}
+		}
+	}
+
+	/// Send this process's byte saying whether it wants TLS, and receive the remote's. Once both are done, start the TLS session if both want it, and fail the connection if they disagree.
+	fn negotiate(&mut self, notifier: &impl Notifier) {
+		if self.transport.connecting() || !self.transport.valid() {
+			return;
+		}
+		{
+			let negotiation = self.negotiation.as_mut().unwrap();
+			if negotiation.capacity.is_none() {
+				let capacity = self.transport.send_avail();
+				if let Some(send) = self.transport.send(notifier) {
+					send(if negotiation.tls { TLS } else { PLAINTEXT });
+					negotiation.capacity = capacity;
+				}
+			}
+			if negotiation.received.is_none() {
+				if let Some(recv) = self.transport.recv(notifier) {
+					negotiation.received = Some(recv());
+				}
+			}
+			if negotiation.received.is_none() {
+				if !self.transport.recvable() {
+					// Closed by the remote before it said
+					self.transport.kill(notifier).unwrap()();
+				}
+				return;
+			}
+			if negotiation.capacity.is_none() || self.transport.send_avail() != negotiation.capacity
+			{
+				return;
+			}
+		}
+		let negotiation = self.negotiation.take().unwrap();
+		match (negotiation.tls, negotiation.received.unwrap()) {
+			(false, PLAINTEXT) => (),
+			#[cfg(feature = "tls")]
+			(true, TLS) => self.tls = Some(Tls::new(negotiation.outgoing, &self.transport)),
+			(_, PLAINTEXT) | (_, TLS) => {
+				warn!(
+					"A channel connection failed as {} wanted TLS",
+					if negotiation.tls { "only this process" } else { "only the remote" }
+				);
+				return self.fail(Failure::Tls, notifier);
+			}
+			(_, _) => return self.fail(Failure::Mismatch, notifier),
+		}
+		if self.closing {
+			self.closing = false;
+			if let Some(close) = self.close(notifier) {
+				close();
+			}
+		}
+	}
+
+	fn fail(&mut self, failure: Failure, notifier: &impl Notifier) {
+		self.failed = Some(failure);
+		if let Some(kill) = self.transport.kill(notifier) {
+			kill();
+		}
+	}
+}
+
+#[cfg(feature = "tls")]
+fn wanted(local: &net::SocketAddr, remote: &net::SocketAddr) -> bool {
+	tls::wanted(local, remote)
+}
+#[cfg(not(feature = "tls"))]
+fn wanted(_local: &net::SocketAddr, _remote: &net::SocketAddr) -> bool {
+	false
+}
@@ -1,4 +1,5 @@
 use super::*;
+use constellation_internal::bincode_config;
 use tcp_typed::Notifier;
 
 #[derive(Debug)]
@@ -10,6 +11,8 @@ pub enum Inner {
 	LocalClosed(InnerLocalClosed),
 	Closing(InnerClosing),
 	Closed,
+	Mismatched,
+	TlsFailed,
 	Killed,
 }
 impl Inner {
@@ -31,6 +34,8 @@ impl Inner {
 			Inner::LocalClosed(local_closed) => local_closed.poll(notifier).into(),
 			Inner::Closing(closing) => closing.poll(notifier).into(),
 			Inner::Closed => Inner::Closed,
+			Inner::Mismatched => Inner::Mismatched,
+			Inner::TlsFailed => Inner::TlsFailed,
 			Inner::Killed => Inner::Killed,
 		};
 		if let &mut Inner::RemoteClosed(_) = self {
@@ -69,14 +74,12 @@ impl Inner {
 		}
 	}
 
-	pub fn recv_avail<T: serde::de::DeserializeOwned + 'static, E: Notifier>(
-		&mut self, notifier: &E,
-	) -> Option<bool> {
+	pub fn recv_avail(&mut self, notifier: &impl Notifier) -> Option<bool> {
 		if self.recvable() {
 			Some(match self {
-				&mut Inner::Connected(ref mut connected) => connected.recv_avail::<T, E>(notifier),
+				&mut Inner::Connected(ref mut connected) => connected.recv_avail(notifier),
 				&mut Inner::LocalClosed(ref mut local_closed) => {
-					local_closed.recv_avail::<T, E>(notifier)
+					local_closed.recv_avail(notifier)
 				}
 				_ => unreachable!(),
 			})
@@ -85,12 +88,22 @@ impl Inner {
 		}
 	}
 
-	pub fn recv<T: serde::de::DeserializeOwned + 'static>(
-		&mut self, notifier: &impl Notifier,
-	) -> T {
+	/// Receive the next message, deserializing it with `seed`, once [`recv_avail()`](Inner::recv_avail) has returned true.
+	pub fn recv<S: serde::de::DeserializeSeed<'static>>(
+		&mut self, seed: S, notifier: &impl Notifier,
+	) -> S::Value {
 		match self {
-			&mut Inner::Connected(ref mut connected) => connected.recv(notifier),
-			&mut Inner::LocalClosed(ref mut local_closed) => local_closed.recv(notifier),
+			&mut Inner::Connected(ref mut connected) => connected.recv(seed, notifier),
+			&mut Inner::LocalClosed(ref mut local_closed) => local_closed.recv(seed, notifier),
+			_ => panic!(),
+		}
+	}
+
+	/// Receive into `buf` as many bytes as are available in one go, once [`recv_avail()`](Inner::recv_avail) has returned true and if the messages are `u8`s. Returns how many were received.
+	pub fn recv_into(&mut self, buf: &mut [u8], notifier: &impl Notifier) -> usize {
+		match self {
+			&mut Inner::Connected(ref mut connected) => connected.recv_into(buf, notifier),
+			&mut Inner::LocalClosed(ref mut local_closed) => local_closed.recv_into(buf, notifier),
 			_ => panic!(),
 		}
 	}
@@ -114,14 +127,46 @@ impl Inner {
 		}
 	}
 
-	pub fn send<T: serde::ser::Serialize + 'static>(&mut self, x: T, notifier: &impl Notifier) {
+	/// Whether everything sent has been written to the connection's socket, so isn't lost if this process then exits.
+	pub fn flushed(&self) -> bool {
 		match self {
-			&mut Inner::Connected(ref mut connected) => connected.send(x, notifier),
-			&mut Inner::RemoteClosed(ref mut remote_closed) => remote_closed.send(x, notifier),
+			&Inner::Connected(ref connected) => connected.flushed(),
+			&Inner::RemoteClosed(ref remote_closed) => remote_closed.flushed(),
+			_ => true,
+		}
+	}
+
+	/// Send `x`, which the remote receives as `len` messages.
+	pub fn send<T: serde::ser::Serialize + 'static>(
+		&mut self, x: T, len: u64, notifier: &impl Notifier,
+	) {
+		self.send_serialized(bincode_config().serialize(&x).unwrap(), len, notifier)
+	}
+
+	/// Send `bytes`, which the remote receives as a message per byte.
+	pub fn send_bytes(&mut self, bytes: Vec<u8>, notifier: &impl Notifier) {
+		let len = bytes.len() as u64;
+		self.send_serialized(bytes, len, notifier)
+	}
+
+	fn send_serialized(&mut self, buffer: Vec<u8>, len: u64, notifier: &impl Notifier) {
+		match self {
+			&mut Inner::Connected(ref mut connected) => connected.send(buffer, len, notifier),
+			&mut Inner::RemoteClosed(ref mut remote_closed) => {
+				remote_closed.send(buffer, len, notifier)
+			}
 			_ => panic!(),
 		}
 	}
 
+	/// How many of the messages sent the remote is yet to acknowledge receiving, or `None` if no more acknowledgements can arrive.
+	pub fn unacked(&self) -> Option<u64> {
+		match self {
+			&Inner::Connected(ref connected) => Some(connected.unacked()),
+			_ => None,
+		}
+	}
+
 	pub fn closable(&self) -> bool {
 		match self {
 			&Inner::Connecting(_) | &Inner::Connected(_) | &Inner::RemoteClosed(_) => true,
@@ -145,7 +190,21 @@ impl Inner {
 			| &Inner::LocalClosed(_)
 			| &Inner::Closing(_)
 			| &Inner::Closed => true,
-			&Inner::Killed => false,
+			&Inner::Mismatched | &Inner::TlsFailed | &Inner::Killed => false,
+		}
+	}
+
+	pub fn mismatched(&self) -> bool {
+		match self {
+			&Inner::Mismatched => true,
+			_ => false,
+		}
+	}
+
+	pub fn tls_failed(&self) -> bool {
+		match self {
+			&Inner::TlsFailed => true,
+			_ => false,
 		}
 	}
 
@@ -158,6 +217,8 @@ impl Inner {
 			| Inner::LocalClosed(_)
 			| Inner::Closing(_)
 			| Inner::Closed
+			| Inner::Mismatched
+			| Inner::TlsFailed
 			| Inner::Killed => panic!(),
 		};
 	}
@@ -177,6 +238,8 @@ impl From<InnerConnectingPoll> for Inner {
 			InnerConnectingPoll::Connecting(connecting) => Inner::Connecting(connecting),
 			InnerConnectingPoll::Connected(connected) => Inner::Connected(connected),
 			InnerConnectingPoll::RemoteClosed(remote_closed) => Inner::RemoteClosed(remote_closed),
+			InnerConnectingPoll::Mismatched => Inner::Mismatched,
+			InnerConnectingPoll::TlsFailed => Inner::TlsFailed,
 			InnerConnectingPoll::Killed => Inner::Killed,
 		}
 	}
@@ -199,6 +262,8 @@ impl From<InnerConnectingLocalClosedPoll> for Inner {
 			}
 			InnerConnectingLocalClosedPoll::Closing(closing) => Inner::Closing(closing),
 			InnerConnectingLocalClosedPoll::Closed => Inner::Closed,
+			InnerConnectingLocalClosedPoll::Mismatched => Inner::Mismatched,
+			InnerConnectingLocalClosedPoll::TlsFailed => Inner::TlsFailed,
 			InnerConnectingLocalClosedPoll::Killed => Inner::Killed,
 		}
 	}
@@ -215,6 +280,8 @@ impl From<InnerConnectedPoll> for Inner {
 		match connected_poll {
 			InnerConnectedPoll::Connected(connected) => Inner::Connected(connected),
 			InnerConnectedPoll::RemoteClosed(remote_closed) => Inner::RemoteClosed(remote_closed),
+			InnerConnectedPoll::Mismatched => Inner::Mismatched,
+			InnerConnectedPoll::TlsFailed => Inner::TlsFailed,
 			InnerConnectedPoll::Killed => Inner::Killed,
 		}
 	}
@@ -249,6 +316,8 @@ impl From<InnerLocalClosedPoll> for Inner {
 			InnerLocalClosedPoll::LocalClosed(local_closed) => Inner::LocalClosed(local_closed),
 			InnerLocalClosedPoll::Closing(closing) => Inner::Closing(closing),
 			InnerLocalClosedPoll::Closed => Inner::Closed,
+			InnerLocalClosedPoll::Mismatched => Inner::Mismatched,
+			InnerLocalClosedPoll::TlsFailed => Inner::TlsFailed,
 			InnerLocalClosedPoll::Killed => Inner::Killed,
 		}
 	}
@@ -1,9 +1,225 @@
 use super::*;
+use constellation_internal::{
+	bincode_config, layout_hash, ChannelAuth, ProcessInputEvent, ProcessOutputEvent
+};
 use std::{
-	collections::hash_map::DefaultHasher, hash::{Hash, Hasher}
+	collections::hash_map::DefaultHasher, hash::{Hash, Hasher}, io
 };
 use tcp_typed::Notifier;
 
+lazy_static! {
+	/// The [`layout_hash()`] sent in each [Handshake], worked out once rather than per connection.
+	static ref LAYOUT: u64 = layout_hash::<(ProcessOutputEvent, ProcessInputEvent)>();
+}
+
+/// Sent first on every connection, so that processes built against a different version of this crate, or without the same `CONSTELLATION_CHANNEL_TOKEN`, fail with [`ChannelError::Mismatch`] rather than misparsing each other's messages.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Debug)]
+struct Handshake {
+	protocol: u32,
+	version: String,
+	/// The [`layout_hash()`] of the events exchanged with the bridge. The types of the user's [Sender]s and [Receiver]s aren't checked, so processes of different executables can exchange types that serialize alike.
+	layout: u64,
+	/// For the remote to prove it has the token with, if the connection is authenticated.
+	nonce: Option<[u8; 16]>,
+}
+impl Handshake {
+	fn new(nonce: Option<[u8; 16]>) -> Self {
+		Self {
+			protocol: PROTOCOL_VERSION,
+			version: String::from(env!("CARGO_PKG_VERSION")),
+			layout: *LAYOUT,
+			nonce,
+		}
+	}
+
+	fn compatible(&self, other: &Self) -> bool {
+		self.protocol == other.protocol
+			&& self.version == other.version
+			&& self.layout == other.layout
+			&& self.nonce.is_some() == other.nonce.is_some()
+	}
+}
+
+fn handshake(auth: &Option<Auth>) -> serde_pipe::Serializer {
+	let mut send_serializer = serde_pipe::Serializer::new();
+	send_serializer.push().unwrap()(Handshake::new(auth.as_ref().map(|auth| auth.0.nonce())));
+	send_serializer
+}
+
+/// Sent after the [Handshake] on an authenticated connection: an HMAC with the token of the sender's role and both nonces, which only a process with the token can produce.
+#[derive(Serialize, Deserialize, Debug)]
+struct Proof([u8; 32]);
+
+/// Authentication of the remote with `CONSTELLATION_CHANNEL_TOKEN`, per [ChannelAuth]. Nothing is sent or received other than the handshake and proofs until the remote has proven it has the token, and this process has queued its own proof.
+#[derive(Debug)]
+struct Auth(ChannelAuth);
+impl Auth {
+	fn new(token: Option<[u8; 16]>, outgoing: bool) -> Option<Self> {
+		token.map(|token| Auth(ChannelAuth::new(token, outgoing, rand::random())))
+	}
+
+	/// Queue this process's proof to be sent, once it's ready and there's room.
+	fn send_proof(&mut self, send_serializer: &mut serde_pipe::Serializer) -> bool {
+		if !send_serializer.push_avail() {
+			return false;
+		}
+		if let Some(proof) = self.0.take_proof() {
+			send_serializer.push().unwrap()(Proof(proof));
+			true
+		} else {
+			false
+		}
+	}
+
+	fn done(&self) -> bool {
+		self.0.done()
+	}
+}
+
+/// Receive the remote's [Handshake], and then its [Proof] if the connection is authenticated. Returns whether progress was made, or `None` if the remote is incompatible or fails to prove it has the token.
+fn recv_handshake(
+	recv_deserializer: &mut serde_pipe::Deserializer, send_serializer: &mut serde_pipe::Serializer,
+	handshaken: &mut bool, auth: &mut Option<Auth>,
+) -> Option<bool> {
+	let mut progress = false;
+	if !*handshaken {
+		if let Some(handshake) = recv_deserializer.pull::<Handshake>() {
+			let handshake = handshake();
+			if !handshake.compatible(&Handshake::new(auth.as_ref().map(|auth| auth.0.nonce()))) {
+				return None;
+			}
+			if let Some(ref mut auth) = *auth {
+				if !auth.0.set_remote_nonce(handshake.nonce.unwrap()) {
+					return None;
+				}
+			}
+			*handshaken = true;
+			progress = true;
+		}
+	}
+	if let Some(ref mut auth) = *auth {
+		// Checked before this process's proof is sent, as if it accepted the connection it only sends it to a remote that has proven itself
+		if *handshaken && !auth.0.proven() {
+			if let Some(proof) = recv_deserializer.pull::<Proof>() {
+				if !auth.0.check_proof(&proof().0) {
+					return None;
+				}
+				progress = true;
+			}
+		}
+		if auth.send_proof(send_serializer) {
+			progress = true;
+		}
+	}
+	Some(progress)
+}
+
+/// Precedes each value sent on a connection after the [Handshake] and any [Proof], so that acknowledgements can be picked out from among the remote's messages without knowing their type.
+#[derive(Serialize, Deserialize, Debug)]
+enum Frame {
+	/// Followed by this many messages, serialized one after another into a length-prefixed buffer, so that the [Receiver] can deserialize them with a seed of its choosing. A `u8` is serialized as itself, so a buffer of bytes is sent as a message per byte, which [`Receiver::try_recv_bytes()`](super::Receiver::try_recv_bytes) can take in one go.
+	Messages(u64),
+	/// This many more of the messages sent to the remote have been received by it.
+	Ack(u64),
+}
+
+/// Pull the remote's [Frame]s up to its next message, adding up the acknowledgements among them. Returns whether progress was made.
+fn recv_frames(
+	recv_deserializer: &mut serde_pipe::Deserializer, recv_pending: &mut u64, acked: &mut u64,
+) -> bool {
+	let mut progress = false;
+	while *recv_pending == 0 {
+		if let Some(frame) = recv_deserializer.pull::<Frame>() {
+			match frame() {
+				Frame::Messages(len) => *recv_pending = len,
+				Frame::Ack(len) => *acked += len,
+			}
+			progress = true;
+		} else {
+			break;
+		}
+	}
+	progress
+}
+
+/// Whether the next of the `recv_pending` messages can be received, pulling the buffer they were sent in if it hasn't been.
+fn recv_next_avail(
+	recv_deserializer: &mut serde_pipe::Deserializer, recv_pending: u64,
+	recv_buffer: &mut Option<io::Cursor<Vec<u8>>>,
+) -> bool {
+	if recv_pending == 0 {
+		return false;
+	}
+	if recv_buffer.is_none() {
+		*recv_buffer = recv_deserializer
+			.pull::<Vec<u8>>()
+			.map(|buffer| io::Cursor::new(buffer()));
+	}
+	recv_buffer.is_some()
+}
+
+/// Deserialize the next message with `seed`, once [`recv_next_avail()`] has returned true.
+fn recv_next<S: serde::de::DeserializeSeed<'static>>(
+	seed: S, recv_buffer: &mut Option<io::Cursor<Vec<u8>>>,
+) -> S::Value {
+	bincode_config()
+		.deserialize_from_seed(seed, recv_buffer.as_mut().unwrap())
+		.unwrap()
+}
+
+/// Receive into `buf` as many of the next `u8` messages as it has room for, once [`recv_next_avail()`] has returned true. Returns how many were received.
+fn recv_next_into(recv_buffer: &mut Option<io::Cursor<Vec<u8>>>, buf: &mut [u8]) -> usize {
+	io::Read::read(recv_buffer.as_mut().unwrap(), buf).unwrap()
+}
+
+/// Queue an acknowledgement of the messages received since the last one, if there are any and there's room. Returns whether it was queued.
+fn send_acks(send_serializer: &mut serde_pipe::Serializer, acks: &mut u64) -> bool {
+	if *acks == 0 || !send_serializer.push_avail() {
+		return false;
+	}
+	send_serializer.push().unwrap()(Frame::Ack(*acks));
+	*acks = 0;
+	true
+}
+
+/// Move what's been serialized onto the connection, as far as it has room, counting it in [`BYTES_SENT`]. Returns whether anything was moved.
+fn send_bytes(
+	connection: &mut Connection, send_serializer: &mut serde_pipe::Serializer,
+	notifier: &impl Notifier,
+) -> bool {
+	let mut sent = 0;
+	while connection.send_avail().unwrap() > 0 && send_serializer.pull_avail() {
+		connection.send(notifier).unwrap()(send_serializer.pull().unwrap()());
+		sent += 1;
+	}
+	let _ = BYTES_SENT.fetch_add(sent, sync::atomic::Ordering::Relaxed);
+	sent > 0
+}
+
+/// Move what's arrived on the connection into the deserializer, as far as it has room, counting it in [`BYTES_RECEIVED`]. Returns whether anything was moved.
+fn recv_bytes(
+	connection: &mut Connection, recv_deserializer: &mut serde_pipe::Deserializer,
+	notifier: &impl Notifier,
+) -> bool {
+	let mut received = 0;
+	while connection.recv_avail().unwrap() > 0 && recv_deserializer.push_avail() {
+		recv_deserializer.push().unwrap()(connection.recv(notifier).unwrap()());
+		received += 1;
+	}
+	let _ = BYTES_RECEIVED.fetch_add(received, sync::atomic::Ordering::Relaxed);
+	received > 0
+}
+
+/// Whether everything sent has been written to the connection's socket: none is left in the serializer, and the connection's buffer is back to the `send_capacity` it had before anything was sent.
+fn flushed(
+	connection: &Connection, send_serializer: &serde_pipe::Serializer, send_capacity: usize,
+) -> bool {
+	!send_serializer.pull_avail()
+		&& connection
+			.send_avail()
+			.map_or(true, |avail| avail >= send_capacity)
+}
+
 /// Used to determine which side should be connecter/client and which connectee/server/listener.
 fn ord(a: &net::SocketAddr, b: &net::SocketAddr) -> bool {
 	let a = (a.ip(), a.port());
@@ -27,29 +243,36 @@ pub enum InnerConnectingPoll {
 	Connecting(InnerConnecting),
 	Connected(InnerConnected),
 	RemoteClosed(InnerRemoteClosed),
+	Mismatched,
+	TlsFailed,
 	Killed,
 }
+/// Each with the token to authenticate the connection with, if any.
 #[derive(Debug)]
 pub enum InnerConnecting {
-	Outgoing(Option<Connection>),
-	Incoming(Option<Connection>),
+	Outgoing(Option<Connection>, Option<[u8; 16]>),
+	Incoming(Option<Connection>, Option<[u8; 16]>),
 }
 impl InnerConnecting {
 	pub fn new(
 		local: net::SocketAddr, remote: net::SocketAddr, incoming: Option<Connection>,
 		notifier: &impl Notifier,
 	) -> InnerConnectingPoll {
+		let token = token(&local, &remote);
 		if ord(&local, &remote) {
 			assert!(incoming.is_none());
-			InnerConnecting::Outgoing(Some(Connection::connect(local, remote, notifier)))
+			InnerConnecting::Outgoing(
+				Some(Connection::connect(local, remote, notifier)),
+				token,
+			)
 		} else {
-			InnerConnecting::Incoming(incoming)
+			InnerConnecting::Incoming(incoming, token)
 		}
 		.poll(notifier)
 	}
 
 	pub fn add_incoming(&mut self, incoming: Connection, notifier: &impl Notifier) {
-		if let InnerConnecting::Incoming(ref mut prev_incoming) = self {
+		if let InnerConnecting::Incoming(ref mut prev_incoming, _) = self {
 			if let Some(mut prev_incoming) = prev_incoming.take() {
 				prev_incoming.kill(notifier).unwrap()();
 			}
@@ -62,33 +285,51 @@ impl InnerConnecting {
 
 	pub fn poll(mut self, notifier: &impl Notifier) -> InnerConnectingPoll {
 		match self {
-			InnerConnecting::Incoming(ref mut incoming) => {
+			InnerConnecting::Incoming(ref mut incoming, token) => {
 				if incoming.is_some() {
 					incoming.as_mut().unwrap().poll(notifier);
 					if !incoming.as_ref().unwrap().connecting() {
-						return match InnerConnected::new(incoming.take().unwrap(), notifier) {
+						return match InnerConnected::new(
+							incoming.take().unwrap(),
+							token,
+							false,
+							notifier,
+						) {
 							InnerConnectedPoll::Connected(connected) => {
 								InnerConnectingPoll::Connected(connected)
 							}
 							InnerConnectedPoll::RemoteClosed(remote_closed) => {
 								InnerConnectingPoll::RemoteClosed(remote_closed)
 							}
+							InnerConnectedPoll::Mismatched => InnerConnectingPoll::Mismatched,
+							InnerConnectedPoll::TlsFailed => InnerConnectingPoll::TlsFailed,
 							InnerConnectedPoll::Killed => InnerConnectingPoll::Killed,
 						};
 					}
 				}
 			}
-			InnerConnecting::Outgoing(ref mut outgoing) => {
+			InnerConnecting::Outgoing(ref mut outgoing, token) => {
 				if outgoing.is_some() {
-					outgoing.as_mut().unwrap().poll(notifier);
+					// Reconnecting registers a new socket
+					outgoing
+						.as_mut()
+						.unwrap()
+						.poll(&KeepaliveNotifier(notifier));
 					if !outgoing.as_ref().unwrap().connecting() {
-						return match InnerConnected::new(outgoing.take().unwrap(), notifier) {
+						return match InnerConnected::new(
+							outgoing.take().unwrap(),
+							token,
+							true,
+							notifier,
+						) {
 							InnerConnectedPoll::Connected(connected) => {
 								InnerConnectingPoll::Connected(connected)
 							}
 							InnerConnectedPoll::RemoteClosed(remote_closed) => {
 								InnerConnectingPoll::RemoteClosed(remote_closed)
 							}
+							InnerConnectedPoll::Mismatched => InnerConnectingPoll::Mismatched,
+							InnerConnectedPoll::TlsFailed => InnerConnectingPoll::TlsFailed,
 							InnerConnectedPoll::Killed => InnerConnectingPoll::Killed,
 						};
 					}
@@ -99,13 +340,14 @@ impl InnerConnecting {
 	}
 
 	pub fn close(self, notifier: &impl Notifier) -> InnerConnectingLocalClosedPoll {
-		InnerConnectingLocalClosed::new(
-			match self {
-				InnerConnecting::Outgoing(outgoing) => Either::Left(outgoing),
-				InnerConnecting::Incoming(incoming) => Either::Left(incoming),
-			},
-			notifier,
-		)
+		match self {
+			InnerConnecting::Outgoing(outgoing, token) => {
+				InnerConnectingLocalClosed::new(Either::Left(outgoing), token, notifier)
+			}
+			InnerConnecting::Incoming(incoming, token) => {
+				InnerConnectingLocalClosed::new(Either::Right(incoming), token, notifier)
+			}
+		}
 	}
 }
 
@@ -115,26 +357,30 @@ pub enum InnerConnectingLocalClosedPoll {
 	LocalClosed(InnerLocalClosed),
 	Closing(InnerClosing),
 	Closed,
+	Mismatched,
+	TlsFailed,
 	Killed,
 }
+/// Each with the token to authenticate the connection with, if any.
 #[derive(Debug)]
 pub enum InnerConnectingLocalClosed {
-	Outgoing(Option<Connection>),
-	Incoming(Option<Connection>),
+	Outgoing(Option<Connection>, Option<[u8; 16]>),
+	Incoming(Option<Connection>, Option<[u8; 16]>),
 }
 impl InnerConnectingLocalClosed {
 	fn new(
-		connection: Either<Option<Connection>, Option<Connection>>, notifier: &impl Notifier,
+		connection: Either<Option<Connection>, Option<Connection>>, token: Option<[u8; 16]>,
+		notifier: &impl Notifier,
 	) -> InnerConnectingLocalClosedPoll {
 		match connection {
-			Either::Left(outgoing) => InnerConnectingLocalClosed::Outgoing(outgoing),
-			Either::Right(incoming) => InnerConnectingLocalClosed::Incoming(incoming),
+			Either::Left(outgoing) => InnerConnectingLocalClosed::Outgoing(outgoing, token),
+			Either::Right(incoming) => InnerConnectingLocalClosed::Incoming(incoming, token),
 		}
 		.poll(notifier)
 	}
 
 	pub fn add_incoming(&mut self, incoming: Connection, notifier: &impl Notifier) {
-		if let InnerConnectingLocalClosed::Incoming(ref mut prev_incoming) = self {
+		if let InnerConnectingLocalClosed::Incoming(ref mut prev_incoming, _) = self {
 			if let Some(mut prev_incoming) = prev_incoming.take() {
 				prev_incoming.kill(notifier).unwrap()();
 			}
@@ -147,18 +393,24 @@ impl InnerConnectingLocalClosed {
 
 	pub fn poll(mut self, notifier: &impl Notifier) -> InnerConnectingLocalClosedPoll {
 		match self {
-			InnerConnectingLocalClosed::Incoming(ref mut incoming) => {
+			InnerConnectingLocalClosed::Incoming(ref mut incoming, token) => {
 				if incoming.is_some() {
 					incoming.as_mut().unwrap().poll(notifier);
 					if incoming.as_ref().unwrap().closable() {
 						incoming.as_mut().unwrap().close(notifier).unwrap()();
 					}
 					if !incoming.as_ref().unwrap().connecting() {
+						let auth = Auth::new(token, false);
 						return match InnerLocalClosed::new(
 							incoming.take().unwrap(),
-							serde_pipe::Serializer::new(),
+							handshake(&auth),
 							serde_pipe::Deserializer::new(),
 							false,
+							false,
+							auth,
+							0,
+							None,
+							0,
 							notifier,
 						) {
 							InnerLocalClosedPoll::LocalClosed(local_closed) => {
@@ -168,6 +420,12 @@ impl InnerConnectingLocalClosed {
 								InnerConnectingLocalClosedPoll::Closing(closing)
 							}
 							InnerLocalClosedPoll::Closed => InnerConnectingLocalClosedPoll::Closed,
+							InnerLocalClosedPoll::Mismatched => {
+								InnerConnectingLocalClosedPoll::Mismatched
+							}
+							InnerLocalClosedPoll::TlsFailed => {
+								InnerConnectingLocalClosedPoll::TlsFailed
+							}
 							InnerLocalClosedPoll::Killed => InnerConnectingLocalClosedPoll::Killed,
 						};
 					}
@@ -176,18 +434,27 @@ impl InnerConnectingLocalClosed {
 					return InnerConnectingLocalClosedPoll::Closed;
 				}
 			}
-			InnerConnectingLocalClosed::Outgoing(ref mut outgoing) => {
+			InnerConnectingLocalClosed::Outgoing(ref mut outgoing, token) => {
 				if outgoing.is_some() {
-					outgoing.as_mut().unwrap().poll(notifier);
+					outgoing
+						.as_mut()
+						.unwrap()
+						.poll(&KeepaliveNotifier(notifier));
 					if outgoing.as_ref().unwrap().closable() {
 						outgoing.as_mut().unwrap().close(notifier).unwrap()();
 					}
 					if !outgoing.as_ref().unwrap().connecting() {
+						let auth = Auth::new(token, true);
 						return match InnerLocalClosed::new(
 							outgoing.take().unwrap(),
-							serde_pipe::Serializer::new(),
+							handshake(&auth),
 							serde_pipe::Deserializer::new(),
 							false,
+							false,
+							auth,
+							0,
+							None,
+							0,
 							notifier,
 						) {
 							InnerLocalClosedPoll::LocalClosed(local_closed) => {
@@ -197,6 +464,12 @@ impl InnerConnectingLocalClosed {
 								InnerConnectingLocalClosedPoll::Closing(closing)
 							}
 							InnerLocalClosedPoll::Closed => InnerConnectingLocalClosedPoll::Closed,
+							InnerLocalClosedPoll::Mismatched => {
+								InnerConnectingLocalClosedPoll::Mismatched
+							}
+							InnerLocalClosedPoll::TlsFailed => {
+								InnerConnectingLocalClosedPoll::TlsFailed
+							}
 							InnerLocalClosedPoll::Killed => InnerConnectingLocalClosedPoll::Killed,
 						};
 					}
@@ -214,6 +487,8 @@ impl InnerConnectingLocalClosed {
 pub enum InnerConnectedPoll {
 	Connected(InnerConnected),
 	RemoteClosed(InnerRemoteClosed),
+	Mismatched,
+	TlsFailed,
 	Killed,
 }
 #[derive(Debug)]
@@ -222,47 +497,101 @@ pub struct InnerConnected {
 	send_serializer: serde_pipe::Serializer,
 	recv_deserializer: serde_pipe::Deserializer,
 	recv_deserializer_given: bool,
+	handshaken: bool,
+	auth: Option<Auth>,
+	/// How much the connection can buffer, seen before anything is sent on it, to tell when it's been flushed.
+	send_capacity: usize,
+	/// Messages the remote has framed that are yet to be received, and the buffer they were sent in, once it's been pulled.
+	recv_pending: u64,
+	recv_buffer: Option<io::Cursor<Vec<u8>>>,
+	/// Messages received that the remote is yet to be sent an acknowledgement of.
+	acks: u64,
+	/// Messages sent, and of those how many the remote has acknowledged receiving.
+	sent: u64,
+	acked: u64,
 }
 impl InnerConnected {
-	fn new(connection: Connection, notifier: &impl Notifier) -> InnerConnectedPoll {
+	fn new(
+		connection: Connection, token: Option<[u8; 16]>, outgoing: bool, notifier: &impl Notifier,
+	) -> InnerConnectedPoll {
+		let auth = Auth::new(token, outgoing);
+		let send_capacity = connection.send_avail().unwrap_or(0);
 		Self {
 			connection,
-			send_serializer: serde_pipe::Serializer::new(),
+			send_serializer: handshake(&auth),
 			recv_deserializer: serde_pipe::Deserializer::new(),
 			recv_deserializer_given: false,
+			handshaken: false,
+			auth,
+			send_capacity,
+			recv_pending: 0,
+			recv_buffer: None,
+			acks: 0,
+			sent: 0,
+			acked: 0,
 		}
 		.poll(notifier)
 	}
 
 	pub fn poll(mut self, notifier: &impl Notifier) -> InnerConnectedPoll {
+		match self.connection.failed() {
+			Some(Failure::Mismatch) => return InnerConnectedPoll::Mismatched,
+			Some(Failure::Tls) => return InnerConnectedPoll::TlsFailed,
+			None => (),
+		}
 		assert!(!self.connection.connecting());
 		let mut progress = true;
 		loop {
 			if self.connection.sendable() {
-				while self.connection.send_avail().unwrap() > 0 && self.send_serializer.pull_avail()
-				{
-					self.connection.send(notifier).unwrap()(self.send_serializer.pull().unwrap()());
-					progress = true;
-				}
+				progress |= send_bytes(&mut self.connection, &mut self.send_serializer, notifier);
 			}
 			if self.connection.recvable() {
-				while self.connection.recv_avail().unwrap() > 0
-					&& self.recv_deserializer.push_avail()
-				{
-					self.recv_deserializer.push().unwrap()(
-						self.connection.recv(notifier).unwrap()(),
-					);
-					progress = true;
+				progress |= recv_bytes(&mut self.connection, &mut self.recv_deserializer, notifier);
+			}
+			match recv_handshake(
+				&mut self.recv_deserializer,
+				&mut self.send_serializer,
+				&mut self.handshaken,
+				&mut self.auth,
+			) {
+				Some(handshake_progress) => progress |= handshake_progress,
+				None => {
+					self.connection.kill(notifier).unwrap()();
+					return InnerConnectedPoll::Mismatched;
 				}
 			}
+			if self.handshaken && self.auth.as_ref().map_or(true, |auth| auth.0.proven()) {
+				progress |= recv_frames(
+					&mut self.recv_deserializer,
+					&mut self.recv_pending,
+					&mut self.acked,
+				);
+			}
+			if self.auth.as_ref().map_or(true, Auth::done) {
+				progress |= send_acks(&mut self.send_serializer, &mut self.acks);
+			}
 			if !progress {
 				break;
 			}
 			progress = false;
 			self.connection.poll(notifier);
 		}
-		if !self.connection.recvable() && self.recv_deserializer.empty().is_none() {
-			return match InnerRemoteClosed::new(self.connection, self.send_serializer, notifier) {
+		if !self.connection.recvable()
+			&& self.recv_deserializer.empty().is_none()
+			&& self.recv_buffer.is_none()
+		{
+			if self.auth.as_ref().map_or(false, |auth| !auth.0.proven()) {
+				// Closed without proving it has the token, so what's sent would go to an unauthenticated process
+				self.connection.kill(notifier).unwrap()();
+				return InnerConnectedPoll::Killed;
+			}
+			return match InnerRemoteClosed::new(
+				self.connection,
+				self.send_serializer,
+				self.auth,
+				self.send_capacity,
+				notifier,
+			) {
 				InnerRemoteClosedPoll::RemoteClosed(remote_closed) => {
 					InnerConnectedPoll::RemoteClosed(remote_closed)
 				}
@@ -276,39 +605,89 @@ impl InnerConnected {
 	}
 
 	pub fn send_avail(&self) -> bool {
-		self.send_serializer.push_avail()
+		self.send_serializer.push_avail() && self.auth.as_ref().map_or(true, Auth::done)
 	}
 
-	pub fn send<T: serde::ser::Serialize + 'static>(&mut self, t: T, notifier: &impl Notifier) {
-		self.send_serializer.push().unwrap()(t);
+	/// Send `buffer`, which holds `len` serialized messages.
+	pub fn send(&mut self, buffer: Vec<u8>, len: u64, notifier: &impl Notifier) {
+		self.send_serializer.push().unwrap()((Frame::Messages(len), buffer));
+		self.sent += len;
 		notifier.queue();
 	}
 
-	pub fn recv_avail<T: serde::de::DeserializeOwned + 'static, E: Notifier>(
-		&mut self, notifier: &E,
-	) -> bool {
+	/// How many of the messages sent the remote is yet to acknowledge receiving.
+	pub fn unacked(&self) -> u64 {
+		self.sent - self.acked
+	}
+
+	pub fn flushed(&self) -> bool {
+		flushed(&self.connection, &self.send_serializer, self.send_capacity)
+	}
+
+	pub fn recv_avail(&mut self, notifier: &impl Notifier) -> bool {
 		if !self.recv_deserializer_given {
 			self.recv_deserializer_given = true;
 			notifier.queue(); // TODO: we only actually need to do this if self.poll() is gonna return Either::Right
 		}
-		self.recv_deserializer.pull::<T>().is_some()
+		if !self.handshaken || !self.auth.as_ref().map_or(true, |auth| auth.0.proven()) {
+			return false;
+		}
+		if recv_frames(
+			&mut self.recv_deserializer,
+			&mut self.recv_pending,
+			&mut self.acked,
+		) {
+			notifier.queue();
+		}
+		recv_next_avail(
+			&mut self.recv_deserializer,
+			self.recv_pending,
+			&mut self.recv_buffer,
+		)
 	}
 
-	pub fn recv<T: serde::de::DeserializeOwned + 'static>(
-		&mut self, notifier: &impl Notifier,
-	) -> T {
+	/// Receive the next message, deserializing it with `seed`.
+	pub fn recv<S: serde::de::DeserializeSeed<'static>>(
+		&mut self, seed: S, notifier: &impl Notifier,
+	) -> S::Value {
 		self.recv_deserializer_given = false;
-		let ret = self.recv_deserializer.pull::<T>().unwrap()();
-		notifier.queue();
+		let ret = recv_next(seed, &mut self.recv_buffer);
+		self.received(1, notifier);
 		ret
 	}
 
+	/// Receive into `buf` as many bytes as are available in one go, acknowledging them together. Returns how many were received.
+	pub fn recv_into(&mut self, buf: &mut [u8], notifier: &impl Notifier) -> usize {
+		self.recv_deserializer_given = false;
+		let len = recv_next_into(&mut self.recv_buffer, buf);
+		self.received(len as u64, notifier);
+		len
+	}
+
+	fn received(&mut self, len: u64, notifier: &impl Notifier) {
+		self.recv_pending -= len;
+		if self.recv_pending == 0 {
+			self.recv_buffer = None;
+		}
+		self.acks += len;
+		// Queued now if there's room, so it's ahead of anything this process sends in reply
+		if self.auth.as_ref().map_or(true, Auth::done) {
+			let _ = send_acks(&mut self.send_serializer, &mut self.acks);
+		}
+		notifier.queue();
+	}
+
 	pub fn close(self, notifier: &impl Notifier) -> InnerLocalClosedPoll {
 		InnerLocalClosed::new(
 			self.connection,
 			self.send_serializer,
 			self.recv_deserializer,
 			self.recv_deserializer_given,
+			self.handshaken,
+			self.auth,
+			self.recv_pending,
+			self.recv_buffer,
+			self.acks,
 			notifier,
 		)
 	}
@@ -323,14 +702,19 @@ pub enum InnerRemoteClosedPoll {
 pub struct InnerRemoteClosed {
 	connection: Connection,
 	send_serializer: serde_pipe::Serializer,
+	auth: Option<Auth>,
+	send_capacity: usize,
 }
 impl InnerRemoteClosed {
 	fn new(
-		connection: Connection, send_serializer: serde_pipe::Serializer, notifier: &impl Notifier,
+		connection: Connection, send_serializer: serde_pipe::Serializer, auth: Option<Auth>,
+		send_capacity: usize, notifier: &impl Notifier,
 	) -> InnerRemoteClosedPoll {
 		Self {
 			connection,
 			send_serializer,
+			auth,
+			send_capacity,
 		}
 		.poll(notifier)
 	}
@@ -339,13 +723,14 @@ impl InnerRemoteClosed {
 		assert!(!self.connection.recvable());
 		let mut progress = true;
 		loop {
-			if self.connection.sendable() {
-				while self.connection.send_avail().unwrap() > 0 && self.send_serializer.pull_avail()
-				{
-					self.connection.send(notifier).unwrap()(self.send_serializer.pull().unwrap()());
+			if let Some(ref mut auth) = self.auth {
+				if auth.send_proof(&mut self.send_serializer) {
 					progress = true;
 				}
 			}
+			if self.connection.sendable() {
+				progress |= send_bytes(&mut self.connection, &mut self.send_serializer, notifier);
+			}
 			if !progress {
 				break;
 			}
@@ -359,16 +744,20 @@ impl InnerRemoteClosed {
 	}
 
 	pub fn send_avail(&self) -> bool {
-		self.send_serializer.push_avail()
+		self.send_serializer.push_avail() && self.auth.as_ref().map_or(true, Auth::done)
 	}
 
-	pub fn send<T: serde::ser::Serialize + 'static>(&mut self, t: T, notifier: &impl Notifier) {
-		self.send_serializer.push().unwrap()(t);
+	pub fn send(&mut self, buffer: Vec<u8>, len: u64, notifier: &impl Notifier) {
+		self.send_serializer.push().unwrap()((Frame::Messages(len), buffer));
 		notifier.queue();
 	}
 
+	pub fn flushed(&self) -> bool {
+		flushed(&self.connection, &self.send_serializer, self.send_capacity)
+	}
+
 	pub fn close(self, notifier: &impl Notifier) -> InnerClosingPoll {
-		InnerClosing::new(self.connection, self.send_serializer, notifier)
+		InnerClosing::new(self.connection, self.send_serializer, self.auth, notifier)
 	}
 }
 
@@ -377,6 +766,8 @@ pub enum InnerLocalClosedPoll {
 	LocalClosed(InnerLocalClosed),
 	Closing(InnerClosing),
 	Closed,
+	Mismatched,
+	TlsFailed,
 	Killed,
 }
 #[derive(Debug)]
@@ -385,59 +776,100 @@ pub struct InnerLocalClosed {
 	send_serializer: serde_pipe::Serializer,
 	recv_deserializer: serde_pipe::Deserializer,
 	recv_deserializer_given: bool,
+	handshaken: bool,
+	auth: Option<Auth>,
+	recv_pending: u64,
+	recv_buffer: Option<io::Cursor<Vec<u8>>>,
+	acks: u64,
 }
 impl InnerLocalClosed {
+	#[allow(clippy::too_many_arguments)]
 	fn new(
 		connection: Connection, send_serializer: serde_pipe::Serializer,
 		recv_deserializer: serde_pipe::Deserializer, recv_deserializer_given: bool,
-		notifier: &impl Notifier,
+		handshaken: bool, auth: Option<Auth>, recv_pending: u64,
+		recv_buffer: Option<io::Cursor<Vec<u8>>>, acks: u64, notifier: &impl Notifier,
 	) -> InnerLocalClosedPoll {
 		Self {
 			connection,
 			send_serializer,
 			recv_deserializer,
 			recv_deserializer_given,
+			handshaken,
+			auth,
+			recv_pending,
+			recv_buffer,
+			acks,
 		}
 		.poll(notifier)
 	}
 
 	pub fn poll(mut self, notifier: &impl Notifier) -> InnerLocalClosedPoll {
+		match self.connection.failed() {
+			Some(Failure::Mismatch) => return InnerLocalClosedPoll::Mismatched,
+			Some(Failure::Tls) => return InnerLocalClosedPoll::TlsFailed,
+			None => (),
+		}
 		assert!(!self.connection.connecting());
 		let mut progress = true;
 		loop {
 			if self.connection.sendable() {
-				while self.connection.send_avail().unwrap() > 0 && self.send_serializer.pull_avail()
-				{
-					self.connection.send(notifier).unwrap()(self.send_serializer.pull().unwrap()());
-					progress = true;
-				}
+				progress |= send_bytes(&mut self.connection, &mut self.send_serializer, notifier);
 			}
 			if self.connection.recvable() {
-				while self.connection.recv_avail().unwrap() > 0
-					&& self.recv_deserializer.push_avail()
-				{
-					self.recv_deserializer.push().unwrap()(
-						self.connection.recv(notifier).unwrap()(),
-					);
-					progress = true;
+				progress |= recv_bytes(&mut self.connection, &mut self.recv_deserializer, notifier);
+			}
+			match recv_handshake(
+				&mut self.recv_deserializer,
+				&mut self.send_serializer,
+				&mut self.handshaken,
+				&mut self.auth,
+			) {
+				Some(handshake_progress) => progress |= handshake_progress,
+				None => {
+					self.connection.kill(notifier).unwrap()();
+					return InnerLocalClosedPoll::Mismatched;
 				}
 			}
+			if self.handshaken && self.auth.as_ref().map_or(true, |auth| auth.0.proven()) {
+				// Acknowledgements of what was sent before closing are no longer waited on
+				progress |= recv_frames(
+					&mut self.recv_deserializer,
+					&mut self.recv_pending,
+					&mut 0,
+				);
+			}
+			// Until the connection is closed, after which what's received goes unacknowledged
+			if self.connection.sendable() && self.auth.as_ref().map_or(true, Auth::done) {
+				progress |= send_acks(&mut self.send_serializer, &mut self.acks);
+			}
 			if !progress {
 				break;
 			}
 			progress = false;
 			self.connection.poll(notifier);
 		}
-		if self.connection.sendable() && !self.send_serializer.pull_avail() {
+		if self.connection.sendable()
+			&& !self.send_serializer.pull_avail()
+			&& self.auth.as_ref().map_or(true, |auth| auth.0.proof_taken())
+		{
 			self.connection.close(notifier).unwrap()();
 		}
 		// if !self.connection.recvable() {
 		// 	assert!(!self.recv_deserializer.push_avail(), "{}: CLOSED WHILE PENDING RECV", ::internal::pid());
 		// }
-		if !self.connection.recvable() && self.recv_deserializer.empty().is_none() {
+		if !self.connection.recvable()
+			&& self.recv_deserializer.empty().is_none()
+			&& self.recv_buffer.is_none()
+		{
 			// self.recv_deserializer.pull_avail() {
 			// assert!(!self.recv_deserializer_given);
-			return match InnerClosing::new(self.connection, self.send_serializer, notifier) {
+			return match InnerClosing::new(
+				self.connection,
+				self.send_serializer,
+				self.auth,
+				notifier,
+			) {
 				InnerClosingPoll::Closing(closing) => InnerLocalClosedPoll::Closing(closing),
 				InnerClosingPoll::Closed => InnerLocalClosedPoll::Closed,
 				InnerClosingPoll::Killed => InnerLocalClosedPoll::Killed,
@@ -449,24 +881,54 @@ impl InnerLocalClosed {
 		InnerLocalClosedPoll::LocalClosed(self)
 	}
 
-	pub fn recv_avail<T: serde::de::DeserializeOwned + 'static, E: Notifier>(
-		&mut self, notifier: &E,
-	) -> bool {
+	pub fn recv_avail(&mut self, notifier: &impl Notifier) -> bool {
 		if !self.recv_deserializer_given {
 			self.recv_deserializer_given = true;
 			notifier.queue(); // TODO: we only actually need to do this if self.poll() is gonna return Either::Right
 		}
-		self.recv_deserializer.pull::<T>().is_some()
+		if !self.handshaken || !self.auth.as_ref().map_or(true, |auth| auth.0.proven()) {
+			return false;
+		}
+		let _ = recv_frames(
+			&mut self.recv_deserializer,
+			&mut self.recv_pending,
+			&mut 0,
+		);
+		recv_next_avail(
+			&mut self.recv_deserializer,
+			self.recv_pending,
+			&mut self.recv_buffer,
+		)
 	}
 
-	pub fn recv<T: serde::de::DeserializeOwned + 'static>(
-		&mut self, notifier: &impl Notifier,
-	) -> T {
+	/// Receive the next message, deserializing it with `seed`.
+	pub fn recv<S: serde::de::DeserializeSeed<'static>>(
+		&mut self, seed: S, notifier: &impl Notifier,
+	) -> S::Value {
 		self.recv_deserializer_given = false;
-		let ret = self.recv_deserializer.pull::<T>().unwrap()();
-		notifier.queue();
+		let ret = recv_next(seed, &mut self.recv_buffer);
+		self.received(1, notifier);
 		ret
 	}
+
+	pub fn recv_into(&mut self, buf: &mut [u8], notifier: &impl Notifier) -> usize {
+		self.recv_deserializer_given = false;
+		let len = recv_next_into(&mut self.recv_buffer, buf);
+		self.received(len as u64, notifier);
+		len
+	}
+
+	fn received(&mut self, len: u64, notifier: &impl Notifier) {
+		self.recv_pending -= len;
+		if self.recv_pending == 0 {
+			self.recv_buffer = None;
+		}
+		self.acks += len;
+		if self.connection.sendable() && self.auth.as_ref().map_or(true, Auth::done) {
+			let _ = send_acks(&mut self.send_serializer, &mut self.acks);
+		}
+		notifier.queue();
+	}
 }
 
 #[derive(Debug)]
@@ -479,14 +941,17 @@ pub enum InnerClosingPoll {
 pub struct InnerClosing {
 	connection: Connection,
 	send_serializer: serde_pipe::Serializer,
+	auth: Option<Auth>,
 }
 impl InnerClosing {
 	fn new(
-		connection: Connection, send_serializer: serde_pipe::Serializer, notifier: &impl Notifier,
+		connection: Connection, send_serializer: serde_pipe::Serializer, auth: Option<Auth>,
+		notifier: &impl Notifier,
 	) -> InnerClosingPoll {
 		Self {
 			connection,
 			send_serializer,
+			auth,
 		}
 		.poll(notifier)
 	}
@@ -495,20 +960,24 @@ impl InnerClosing {
 		assert!(!self.connection.recvable());
 		let mut progress = true;
 		loop {
-			if self.connection.sendable() {
-				while self.connection.send_avail().unwrap() > 0 && self.send_serializer.pull_avail()
-				{
-					self.connection.send(notifier).unwrap()(self.send_serializer.pull().unwrap()());
+			if let Some(ref mut auth) = self.auth {
+				if auth.send_proof(&mut self.send_serializer) {
 					progress = true;
 				}
 			}
+			if self.connection.sendable() {
+				progress |= send_bytes(&mut self.connection, &mut self.send_serializer, notifier);
+			}
 			if !progress {
 				break;
 			}
 			progress = false;
 			self.connection.poll(notifier);
 		}
-		if self.connection.sendable() && !self.send_serializer.pull_avail() {
+		if self.connection.sendable()
+			&& !self.send_serializer.pull_avail()
+			&& self.auth.as_ref().map_or(true, |auth| !auth.0.proof_ready())
+		{
 			self.connection.close(notifier).unwrap()();
 		}
 		if !self.connection.valid() {
@@ -0,0 +1,780 @@
+//! Channels between processes run as threads of this one, backed by in-memory queues rather than sockets, for the `test-transport` feature.
+//!
+//! [Sender], [Receiver] and [Accept] have the methods of their socket-backed counterparts in the parent module that `lib.rs` uses, so it uses whichever the feature selects. Messages are serialized on sending and deserialized on receiving, as they are between processes, so a type that doesn't round-trip fails here too.
+
+use super::{select, ChannelError, Reactor, Selectable};
+use constellation_internal::bincode_config;
+use serde;
+use std::{
+	borrow::Borrow, boxed::FnBox, cell, collections::{HashMap, VecDeque}, convert::TryInto, fmt, marker, net, sync::{self, Arc}, thread
+};
+
+lazy_static! {
+	/// The pipe between each pair of processes that have had a channel between them, under both orderings of their addresses.
+	static ref PIPES: sync::Mutex<HashMap<(net::SocketAddr, net::SocketAddr), Arc<sync::Mutex<Pipe>>>> =
+		sync::Mutex::new(HashMap::new());
+	static ref ACCEPTERS: sync::Mutex<HashMap<thread::ThreadId, thread::Thread>> =
+		sync::Mutex::new(HashMap::new());
+}
+thread_local! {
+	/// The address of the process the current thread is running the code of.
+	static LOCAL: cell::Cell<Option<net::SocketAddr>> = cell::Cell::new(None);
+}
+
+/// Make the current thread that of the process at `local`.
+pub fn set_local(local: net::SocketAddr) {
+	LOCAL.with(|local_| local_.set(Some(local)));
+}
+
+/// The address of the process the current thread is that of, if it's been set with [`set_local()`](set_local).
+pub fn local() -> Option<net::SocketAddr> {
+	LOCAL.with(cell::Cell::get)
+}
+
+fn local_or_panic() -> net::SocketAddr {
+	local().unwrap_or_else(|| {
+		panic!("Channels must be created on the thread that called init() or runs a spawned closure")
+	})
+}
+
+/// Close the ends of the process at `local`, as it has exited.
+pub fn exited(local: net::SocketAddr) {
+	let pipes = PIPES.lock().unwrap();
+	for (_, pipe) in pipes.iter().filter(|&(&(local_, _), _)| local_ == local) {
+		let mut pipe = pipe.lock().unwrap();
+		let end = pipe.end(local);
+		pipe.ends[end].closed = true;
+		pipe.changed();
+	}
+}
+
+/// The pipe between `local` and `remote`, and which of its ends is `local`'s.
+fn pipe(local: net::SocketAddr, remote: net::SocketAddr) -> (Arc<sync::Mutex<Pipe>>, usize) {
+	let mut pipes = PIPES.lock().unwrap();
+	let pipe = pipes
+		.entry((local, remote))
+		.or_insert_with(|| Arc::new(sync::Mutex::new(Pipe::new(local, remote))))
+		.clone();
+	let _ = pipes.entry((remote, local)).or_insert_with(|| pipe.clone());
+	let end = pipe.lock().unwrap().end(local);
+	(pipe, end)
+}
+
+/// The channel between two processes.
+struct Pipe {
+	addrs: [net::SocketAddr; 2],
+	ends: [End; 2],
+	senders: HashMap<thread::ThreadId, thread::Thread>,
+	receivers: HashMap<thread::ThreadId, thread::Thread>,
+}
+/// One process's end of a [Pipe].
+#[derive(Default)]
+struct End {
+	sender: bool,
+	receiver: bool,
+	/// Whether this end has stopped sending, as it was closed, its `Sender` and `Receiver` were dropped, or its process exited.
+	closed: bool,
+	/// What this end has sent, serialized, that the other end has yet to receive.
+	messages: VecDeque<Vec<u8>>,
+	/// How many messages this end has sent, and how many of those the other end has received.
+	sent: u64,
+	received: u64,
+}
+impl Pipe {
+	fn new(a: net::SocketAddr, b: net::SocketAddr) -> Self {
+		Self {
+			addrs: [a, b],
+			ends: [End::default(), End::default()],
+			senders: HashMap::new(),
+			receivers: HashMap::new(),
+		}
+	}
+
+	fn end(&self, local: net::SocketAddr) -> usize {
+		if self.addrs[0] == local {
+			0
+		} else {
+			1
+		}
+	}
+
+	/// Unpark the threads selecting on this pipe or accepting, as something about it has changed.
+	fn changed(&self) {
+		for thread in self.senders.values().chain(self.receivers.values()) {
+			thread.unpark();
+		}
+		for thread in ACCEPTERS.lock().unwrap().values() {
+			thread.unpark();
+		}
+	}
+
+	/// Take a `Sender` or `Receiver` for `end`, reopening it if it was closed by dropping both.
+	fn open(&mut self, end: usize) {
+		let end = &mut self.ends[end];
+		if !end.sender && !end.receiver {
+			end.closed = false;
+		}
+	}
+
+	/// Drop a `Sender` or `Receiver` for `end`, closing it if both have been.
+	fn release(&mut self, end: usize) {
+		{
+			let end = &mut self.ends[end];
+			if !end.sender && !end.receiver {
+				end.closed = true;
+			}
+		}
+		self.changed();
+	}
+
+	fn sendable(&self, end: usize) -> bool {
+		!self.ends[end].closed && !self.ends[1 - end].closed
+	}
+
+	/// How many of the messages `end` has sent are yet to be received.
+	fn unacked(&self, end: usize) -> u64 {
+		self.ends[end].sent - self.ends[end].received
+	}
+
+	fn send<T: serde::ser::Serialize>(&mut self, end: usize, t: &T) {
+		let message = bincode_config().serialize(t).unwrap();
+		self.ends[end].messages.push_back(message);
+		self.ends[end].sent += 1;
+		self.changed();
+	}
+
+	/// Deserialize, without receiving, the next message for `end`.
+	fn peek<T: serde::de::DeserializeOwned>(&self, end: usize) -> Option<T> {
+		self.ends[1 - end]
+			.messages
+			.front()
+			.map(|message| deserialize(marker::PhantomData::<T>, message))
+	}
+
+	/// Receive the next message for `end`, deserializing it with `seed`.
+	fn recv<S: serde::de::DeserializeSeed<'static>>(
+		&mut self, end: usize, seed: S,
+	) -> Option<S::Value> {
+		let message = self.ends[1 - end].messages.pop_front()?;
+		self.ends[1 - end].received += 1;
+		self.changed();
+		Some(deserialize(seed, &message))
+	}
+
+	/// Whether the other end has closed and everything it sent has been received by `end`.
+	fn drained(&self, end: usize) -> bool {
+		self.ends[1 - end].closed && self.ends[1 - end].messages.is_empty()
+	}
+
+	fn claimable(&self, end: usize) -> bool {
+		!self.ends[end].receiver && !self.drained(end)
+	}
+}
+fn deserialize<S: serde::de::DeserializeSeed<'static>>(seed: S, message: &[u8]) -> S::Value {
+	bincode_config()
+		.deserialize_from_seed(seed, message)
+		.expect("Message received didn't deserialize as the type of the Receiver")
+}
+
+pub struct Sender<T: serde::ser::Serialize> {
+	pipe: Arc<sync::Mutex<Pipe>>,
+	end: usize,
+	capacity: Option<usize>,
+	_marker: marker::PhantomData<fn(T)>,
+}
+impl<T: serde::ser::Serialize> Sender<T> {
+	pub fn new(remote: net::SocketAddr, context: &Reactor) -> Option<Self> {
+		Self::with_capacity(remote, None, context)
+	}
+
+	/// With a `capacity`, sending waits until no more than it of the messages sent are yet to be received by the remote.
+	pub fn with_capacity(
+		remote: net::SocketAddr, capacity: Option<usize>, _context: &Reactor,
+	) -> Option<Self> {
+		let (pipe, end) = pipe(local_or_panic(), remote);
+		{
+			let mut pipe = pipe.lock().unwrap();
+			if pipe.ends[end].sender {
+				return None;
+			}
+			pipe.open(end);
+			pipe.ends[end].sender = true;
+			pipe.changed();
+		}
+		Some(Self {
+			pipe,
+			end,
+			capacity,
+			_marker: marker::PhantomData,
+		})
+	}
+
+	pub fn send<F: FnMut() -> C, C: Borrow<Reactor>>(&self, t: T, context: &mut F)
+	where
+		T: 'static,
+	{
+		let x = cell::RefCell::new(None);
+		let _ = select(
+			vec![Box::new(self.selectable_send(|| {
+				*x.borrow_mut() = Some(());
+				t
+			}))],
+			context,
+		);
+		x.into_inner().unwrap();
+		if let Some(capacity) = self.capacity {
+			let _ = self.acked(capacity as u64, context);
+		}
+	}
+
+	pub fn try_send<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, t: T, context: &mut F,
+	) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		let x = cell::RefCell::new(None);
+		let _ = select(vec![Box::new(TrySend(self, Some(t), &x))], context);
+		let ret = x.into_inner().unwrap();
+		if let (&Ok(()), Some(capacity)) = (&ret, self.capacity) {
+			let _ = self.acked(capacity as u64, context);
+		}
+		ret
+	}
+
+	pub fn try_send_all<I: IntoIterator<Item = T>, F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, items: I, context: &mut F,
+	) -> Result<(), (usize, ChannelError)>
+	where
+		T: 'static,
+	{
+		for (sent, t) in items.into_iter().enumerate() {
+			self.try_send(t, context).map_err(|err| (sent, err))?;
+		}
+		Ok(())
+	}
+
+	/// Messages are queued for the remote as they're sent, so there's nothing to wait for.
+	pub fn flush<F: FnMut() -> C, C: Borrow<Reactor>>(&self, _context: &mut F)
+	where
+		T: 'static,
+	{
+	}
+
+	pub fn drain<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, _context: &mut F,
+	) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		if self.pipe.lock().unwrap().sendable(self.end) {
+			Ok(())
+		} else {
+			Err(ChannelError::Exited)
+		}
+	}
+
+	fn within_capacity(&self, pipe: &Pipe) -> bool {
+		self.capacity
+			.map_or(true, |capacity| pipe.unacked(self.end) <= capacity as u64)
+	}
+
+	/// Block until at most `unacked` of the messages sent are yet to be received by the remote. Returns an error if more are, as the remote can no longer receive.
+	pub fn acked<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, unacked: u64, context: &mut F,
+	) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		let _ = select(vec![Box::new(Acked(self, unacked))], context);
+		if self.pipe.lock().unwrap().unacked(self.end) <= unacked {
+			Ok(())
+		} else {
+			Err(ChannelError::Exited)
+		}
+	}
+
+	pub fn close(&self, _context: &Reactor) {
+		let mut pipe = self.pipe.lock().unwrap();
+		pipe.ends[self.end].closed = true;
+		pipe.changed();
+	}
+
+	pub fn selectable_send<'a, F: FnOnce() -> T + 'a>(&'a self, f: F) -> impl Selectable + 'a
+	where
+		T: 'static,
+	{
+		Send(self, Some(f))
+	}
+
+	pub fn selectable_try_send<'a>(
+		&'a self, t: T, result: &'a cell::RefCell<Option<Result<(), ChannelError>>>,
+	) -> impl Selectable + 'a
+	where
+		T: 'static,
+	{
+		TrySend(self, Some(t), result)
+	}
+
+	/// Released when dropped; this matches the socket-backed `Sender`, which needs the reactor to release it.
+	pub fn drop(self, _context: &Reactor) {}
+}
+impl<T: serde::ser::Serialize> Drop for Sender<T> {
+	fn drop(&mut self) {
+		let mut pipe = self.pipe.lock().unwrap();
+		pipe.ends[self.end].sender = false;
+		pipe.release(self.end);
+	}
+}
+impl Sender<u8> {
+	/// Send as many bytes of `buf` as the capacity allows. Returns how many were sent, or an error if the channel can no longer send.
+	pub fn try_send_bytes<C: Borrow<Reactor>>(
+		&self, buf: &[u8], _context: C,
+	) -> Result<usize, ChannelError> {
+		let mut pipe = self.pipe.lock().unwrap();
+		if !pipe.sendable(self.end) {
+			return Err(ChannelError::Exited);
+		}
+		let room = self.capacity.map_or(buf.len(), |capacity| {
+			let unacked: usize = pipe.unacked(self.end).try_into().unwrap();
+			(capacity + 1).saturating_sub(unacked)
+		});
+		let sent = buf.len().min(room);
+		for byte in &buf[..sent] {
+			pipe.send(self.end, byte);
+		}
+		Ok(sent)
+	}
+}
+impl<T: serde::ser::Serialize> fmt::Debug for Sender<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let pipe = self.pipe.lock().unwrap();
+		f.debug_struct("Sender")
+			.field("local", &pipe.addrs[self.end])
+			.field("remote", &pipe.addrs[1 - self.end])
+			.field("unacked", &pipe.unacked(self.end))
+			.finish()
+	}
+}
+struct Send<'a, T: serde::ser::Serialize + 'static, F: FnOnce() -> T>(&'a Sender<T>, Option<F>);
+impl<'a, T: serde::ser::Serialize + 'static, F: FnOnce() -> T> fmt::Debug for Send<'a, T, F> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Send").field("sender", &self.0).finish()
+	}
+}
+impl<'a, T: serde::ser::Serialize + 'static, F: FnOnce() -> T> Selectable for Send<'a, T, F> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self.0.pipe.lock().unwrap().senders.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, _context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let sender = self.0;
+		let available = {
+			let pipe = sender.pipe.lock().unwrap();
+			pipe.sendable(sender.end) && sender.within_capacity(&pipe)
+		};
+		if available {
+			Some(Box::new(move || {
+				let t = (self.1.take().unwrap())();
+				let mut pipe = sender.pipe.lock().unwrap();
+				let _ = pipe.senders.remove(&thread::current().id());
+				pipe.send(sender.end, &t);
+			}) as Box<FnBox() + 'b>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.pipe
+			.lock()
+			.unwrap()
+			.senders
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+struct TrySend<'a, T: serde::ser::Serialize + 'static>(
+	&'a Sender<T>,
+	Option<T>,
+	&'a cell::RefCell<Option<Result<(), ChannelError>>>,
+);
+impl<'a, T: serde::ser::Serialize + 'static> fmt::Debug for TrySend<'a, T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("TrySend").field("sender", &self.0).finish()
+	}
+}
+impl<'a, T: serde::ser::Serialize + 'static> Selectable for TrySend<'a, T> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self.0.pipe.lock().unwrap().senders.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, _context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let sender = self.0;
+		let result = {
+			let pipe = sender.pipe.lock().unwrap();
+			if !pipe.sendable(sender.end) {
+				Some(Err(ChannelError::Exited))
+			} else if sender.within_capacity(&pipe) {
+				Some(Ok(()))
+			} else {
+				None
+			}
+		};
+		result.map(|result| {
+			Box::new(move || {
+				let mut pipe = sender.pipe.lock().unwrap();
+				let _ = pipe.senders.remove(&thread::current().id());
+				if result.is_ok() {
+					pipe.send(sender.end, &self.1.take().unwrap());
+				}
+				*self.2.borrow_mut() = Some(result);
+			}) as Box<FnBox() + 'b>
+		})
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.pipe
+			.lock()
+			.unwrap()
+			.senders
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+/// Available once at most `.1` of the messages sent are yet to be received by the remote, or it can no longer receive.
+struct Acked<'a, T: serde::ser::Serialize + 'static>(&'a Sender<T>, u64);
+impl<'a, T: serde::ser::Serialize + 'static> fmt::Debug for Acked<'a, T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Acked")
+			.field("sender", &self.0)
+			.field("unacked", &self.1)
+			.finish()
+	}
+}
+impl<'a, T: serde::ser::Serialize + 'static> Selectable for Acked<'a, T> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self.0.pipe.lock().unwrap().senders.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, _context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let sender = self.0;
+		let acked = {
+			let pipe = sender.pipe.lock().unwrap();
+			pipe.unacked(sender.end) <= self.1 || !pipe.sendable(sender.end)
+		};
+		if acked {
+			Some(Box::new(move || {
+				let _ = sender
+					.pipe
+					.lock()
+					.unwrap()
+					.senders
+					.remove(&thread::current().id());
+			}) as Box<FnBox() + 'b>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.pipe
+			.lock()
+			.unwrap()
+			.senders
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+
+pub struct Receiver<T: serde::de::DeserializeOwned> {
+	pipe: Arc<sync::Mutex<Pipe>>,
+	end: usize,
+	filter: sync::Mutex<Option<Box<Fn(&T) -> bool + marker::Send>>>,
+	_marker: marker::PhantomData<fn() -> T>,
+}
+impl<T: serde::de::DeserializeOwned> Receiver<T> {
+	pub fn new(remote: net::SocketAddr, _context: &Reactor) -> Option<Self> {
+		let (pipe, end) = pipe(local_or_panic(), remote);
+		{
+			let mut pipe = pipe.lock().unwrap();
+			if pipe.ends[end].receiver {
+				return None;
+			}
+			pipe.open(end);
+			pipe.ends[end].receiver = true;
+			pipe.changed();
+		}
+		Some(Self::claimed(pipe, end))
+	}
+
+	fn claimed(pipe: Arc<sync::Mutex<Pipe>>, end: usize) -> Self {
+		Self {
+			pipe,
+			end,
+			filter: sync::Mutex::new(None),
+			_marker: marker::PhantomData,
+		}
+	}
+
+	/// Create a `Receiver<T>` for every remote that could be received from but doesn't yet have one.
+	pub fn new_unclaimed(_context: &Reactor) -> Vec<(net::SocketAddr, Self)> {
+		let local = local_or_panic();
+		let pipes = PIPES.lock().unwrap();
+		pipes
+			.iter()
+			.filter(|&(&(local_, _), _)| local_ == local)
+			.filter_map(|(&(_, remote), pipe_)| {
+				let end = {
+					let mut pipe = pipe_.lock().unwrap();
+					let end = pipe.end(local);
+					if !pipe.claimable(end) {
+						return None;
+					}
+					pipe.open(end);
+					pipe.ends[end].receiver = true;
+					end
+				};
+				Some((remote, Self::claimed(pipe_.clone(), end)))
+			})
+			.collect()
+	}
+
+	/// Receive and drop the messages at the front that the filter rejects.
+	fn skip_rejected(&self, pipe: &mut Pipe) {
+		if let Some(ref filter) = *self.filter.lock().unwrap() {
+			while pipe.peek(self.end).map_or(false, |t| !filter(&t)) {
+				let _ = pipe.recv(self.end, marker::PhantomData::<T>);
+			}
+		}
+	}
+
+	/// Whether the remote has closed and everything it sent has been received.
+	pub fn drained(&self) -> bool {
+		let mut pipe = self.pipe.lock().unwrap();
+		self.skip_rejected(&mut pipe);
+		pipe.drained(self.end)
+	}
+
+	/// Drop messages for which `pred` returns false. They're dropped as they're received, rather than as they arrive, as there's no reactor thread they arrive on.
+	pub fn set_filter<F: Fn(&T) -> bool + marker::Send + 'static>(&self, pred: F)
+	where
+		T: marker::Send + 'static,
+	{
+		*self.filter.lock().unwrap() = Some(Box::new(pred));
+	}
+
+	pub fn recv<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, context: &mut F,
+	) -> Result<T, ChannelError>
+	where
+		T: 'static,
+	{
+		let x = cell::RefCell::new(None);
+		let _ = select(
+			vec![Box::new(
+				self.selectable_recv(|t| *x.borrow_mut() = Some(t)),
+			)],
+			context,
+		);
+		x.into_inner().unwrap()
+	}
+
+	/// Panics if a filter is set, as the socket-backed `Receiver` does.
+	pub fn recv_deserialize_with<
+		S: serde::de::DeserializeSeed<'static>,
+		F: FnMut() -> C,
+		C: Borrow<Reactor>,
+	>(
+		&self, seed: S, context: &mut F,
+	) -> Result<S::Value, ChannelError> {
+		assert!(
+			self.filter.lock().unwrap().is_none(),
+			"recv_deserialize_with() called on a Receiver with a filter"
+		);
+		let x = cell::RefCell::new(None);
+		let _ = select(
+			vec![Box::new(Recv(self, Some((seed, |t| *x.borrow_mut() = Some(t)))))],
+			context,
+		);
+		x.into_inner().unwrap()
+	}
+
+	pub fn recv_all<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, n: usize, context: &mut F,
+	) -> Result<Vec<T>, (Vec<T>, ChannelError)>
+	where
+		T: 'static,
+	{
+		let mut received = Vec::with_capacity(n);
+		while received.len() < n {
+			match self.recv(context) {
+				Ok(t) => received.push(t),
+				Err(err) => return Err((received, err)),
+			}
+		}
+		Ok(received)
+	}
+
+	pub fn selectable_recv<'a, F: FnOnce(Result<T, ChannelError>) + 'a>(
+		&'a self, f: F,
+	) -> impl Selectable + 'a
+	where
+		T: 'static,
+	{
+		Recv(self, Some((marker::PhantomData::<T>, f)))
+	}
+
+	/// Released when dropped; this matches the socket-backed `Receiver`, which needs the reactor to release it.
+	pub fn drop(self, _context: &Reactor) {}
+}
+impl<T: serde::de::DeserializeOwned> Drop for Receiver<T> {
+	fn drop(&mut self) {
+		let mut pipe = self.pipe.lock().unwrap();
+		pipe.ends[self.end].receiver = false;
+		pipe.release(self.end);
+	}
+}
+impl Receiver<u8> {
+	/// Receive into `buf` as many bytes as have arrived. Returns how many were received, or an error if none had and none will, as the remote has closed.
+	pub fn try_recv_bytes<C: Borrow<Reactor>>(
+		&self, buf: &mut [u8], _context: C,
+	) -> Result<usize, ChannelError> {
+		let mut pipe = self.pipe.lock().unwrap();
+		let mut received = 0;
+		// with a filter, bytes go through recv() so as to be run through it
+		if self.filter.lock().unwrap().is_none() {
+			while received < buf.len() {
+				match pipe.recv(self.end, marker::PhantomData::<u8>) {
+					Some(byte) => buf[received] = byte,
+					None => break,
+				}
+				received += 1;
+			}
+		}
+		if received == 0 && pipe.drained(self.end) {
+			return Err(ChannelError::Exited);
+		}
+		Ok(received)
+	}
+}
+impl<T: serde::de::DeserializeOwned> fmt::Debug for Receiver<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let pipe = self.pipe.lock().unwrap();
+		f.debug_struct("Receiver")
+			.field("local", &pipe.addrs[self.end])
+			.field("remote", &pipe.addrs[1 - self.end])
+			.field("queued", &pipe.ends[1 - self.end].messages.len())
+			.finish()
+	}
+}
+struct Recv<
+	'a,
+	T: serde::de::DeserializeOwned + 'static,
+	S: serde::de::DeserializeSeed<'static>,
+	F: FnOnce(Result<S::Value, ChannelError>),
+>(&'a Receiver<T>, Option<(S, F)>);
+impl<
+		'a,
+		T: serde::de::DeserializeOwned + 'static,
+		S: serde::de::DeserializeSeed<'static>,
+		F: FnOnce(Result<S::Value, ChannelError>),
+	> fmt::Debug for Recv<'a, T, S, F>
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Recv").field("receiver", &self.0).finish()
+	}
+}
+impl<
+		'a,
+		T: serde::de::DeserializeOwned + 'static,
+		S: serde::de::DeserializeSeed<'static>,
+		F: FnOnce(Result<S::Value, ChannelError>),
+	> Selectable for Recv<'a, T, S, F>
+{
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self.0.pipe.lock().unwrap().receivers.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, _context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let receiver = self.0;
+		let available = {
+			let mut pipe = receiver.pipe.lock().unwrap();
+			receiver.skip_rejected(&mut pipe);
+			!pipe.ends[1 - receiver.end].messages.is_empty() || pipe.drained(receiver.end)
+		};
+		if available {
+			Some(Box::new(move || {
+				let (seed, f) = self.1.take().unwrap();
+				let t = {
+					let mut pipe = receiver.pipe.lock().unwrap();
+					let _ = pipe.receivers.remove(&thread::current().id());
+					pipe.recv(receiver.end, seed).ok_or(ChannelError::Exited)
+				};
+				f(t)
+			}) as Box<FnBox() + 'b>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.pipe
+			.lock()
+			.unwrap()
+			.receivers
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+
+/// [Selectable] that becomes available when a remote has a channel to this process that doesn't have a [Receiver].
+#[derive(Clone, Debug)]
+pub struct Accept(());
+impl Accept {
+	pub fn new(_context: &Reactor) -> Self {
+		Accept(())
+	}
+}
+impl Selectable for Accept {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = ACCEPTERS.lock().unwrap().insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'a>(&'a mut self, _context: &'a Reactor) -> Option<Box<FnBox() + 'a>> {
+		let local = local_or_panic();
+		let claimable = PIPES
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|&(&(local_, _), _)| local_ == local)
+			.any(|(_, pipe)| {
+				let pipe = pipe.lock().unwrap();
+				pipe.claimable(pipe.end(local))
+			});
+		if claimable {
+			Some(Box::new(|| {
+				let _ = ACCEPTERS.lock().unwrap().remove(&thread::current().id());
+			}) as Box<FnBox() + 'a>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = ACCEPTERS.lock().unwrap().remove(&thread.id()).unwrap();
+	}
+}
@@ -1,29 +1,105 @@
+mod connection;
 mod inner;
 mod inner_states;
+#[cfg(feature = "tls")]
+mod tls;
+mod unix;
+#[cfg(feature = "test-transport")]
+pub mod memory;
 
-use constellation_internal::Rand;
+use constellation_internal::{seeded_rng, Rand};
 use either::Either;
 // use futures;
-use nix::sys::socket;
 use notifier::{Notifier, Triggerer};
 use palaver::spawn;
-use rand;
+use platform::{Native, Platform};
+use rand::{self, SeedableRng};
 use serde;
 use serde_pipe;
 use std::{
-	borrow::Borrow, boxed::FnBox, cell, collections::{hash_map, HashMap}, error, fmt, marker, mem, net, os, ptr, sync::{self, Arc}, thread
+	any, borrow::Borrow, boxed::FnBox, cell, cmp, collections::{hash_map, HashMap}, error, fmt, marker, mem, net, ops, os, ptr, sync::{self, mpsc, Arc}, thread, time
 };
-use tcp_typed::{Connection, Listener};
+use tcp_typed::{self, Listener};
 
 #[cfg(target_family = "unix")]
 type Fd = os::unix::io::RawFd;
 #[cfg(target_family = "windows")]
 type Fd = os::windows::io::RawHandle;
 
-pub use self::{inner::*, inner_states::*};
+pub use self::{connection::*, inner::*, inner_states::*};
+#[cfg(feature = "tls")]
+pub use self::tls::{set_tls, Config as TlsConfig};
 pub use tcp_typed::{socket_forwarder, SocketForwardee, SocketForwarder};
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+/// The version of the protocol spoken between processes. Bumped whenever a change means processes built against different versions of this crate can't talk to each other.
+pub const PROTOCOL_VERSION: u32 = 10;
+
+/// The number of bytes written to connections, including handshakes.
+pub static BYTES_SENT: sync::atomic::AtomicUsize = sync::atomic::AtomicUsize::new(0);
+/// The number of bytes read from connections, including handshakes.
+pub static BYTES_RECEIVED: sync::atomic::AtomicUsize = sync::atomic::AtomicUsize::new(0);
+
+lazy_static! {
+	static ref SELECT_SEED: sync::RwLock<Option<u64>> = sync::RwLock::new(None);
+	static ref TOKEN: sync::RwLock<Option<([u8; 16], net::SocketAddr)>> = sync::RwLock::new(None);
+	static ref KEEPALIVE: sync::RwLock<Option<(u16, u16, u16)>> = sync::RwLock::new(None);
+}
+thread_local! {
+	// Seeded on each thread's first select, so a thread's choices depend only on its own selects
+	static SELECT_RNG: cell::RefCell<Option<rand::XorShiftRng>> =
+		cell::RefCell::new(SELECT_SEED.read().unwrap().map(seeded_rng));
+}
+
+/// Seed the RNG that [`select()`](select) picks among progressable [Selectable]s with, rather than using the thread's random RNG. Must be called before any thread selects.
+pub fn seed_select(seed: u64) {
+	*SELECT_SEED.write().unwrap() = Some(seed);
+}
+
+/// Require the remote end of each connection to prove it has `token` before anything is sent or received on it, other than connections to or from `exempt`, the bridge, which isn't given it. Must be called before any connections are made.
+pub fn set_token(token: [u8; 16], exempt: net::SocketAddr) {
+	*TOKEN.write().unwrap() = Some((token, exempt));
+}
+
+/// The token the connection between `local` and `remote` is authenticated with, if any.
+fn token(local: &net::SocketAddr, remote: &net::SocketAddr) -> Option<[u8; 16]> {
+	let token = *TOKEN.read().unwrap();
+	token
+		.filter(|&(_, exempt)| *local != exempt && *remote != exempt)
+		.map(|(token, _)| token)
+}
+
+/// Enable TCP keepalive, as per [`Platform::channel_keepalive()`](Platform::channel_keepalive), on the connections this process makes. Must be called before any connections are made.
+pub fn set_keepalive(keepalive: (u16, u16, u16)) {
+	*KEEPALIVE.write().unwrap() = Some(keepalive);
+}
+
+/// Wraps the [Notifier](tcp_typed::Notifier) an outgoing [`tcp_typed::Connection`] is made and polled with, to enable keepalive on its socket as it's registered, as it doesn't otherwise expose it.
+struct KeepaliveNotifier<'a, N: tcp_typed::Notifier + 'a>(&'a N);
+impl<'a, N: tcp_typed::Notifier> tcp_typed::Notifier for KeepaliveNotifier<'a, N> {
+	type InstantSlot = N::InstantSlot;
+
+	fn queue(&self) {
+		self.0.queue()
+	}
+	fn add_fd(&self, fd: Fd) {
+		if let Some(keepalive) = *KEEPALIVE.read().unwrap() {
+			// Ignore failure, as the connection may already have been refused
+			let _ = Native::channel_keepalive(fd, keepalive);
+		}
+		self.0.add_fd(fd)
+	}
+	fn remove_fd(&self, fd: Fd) {
+		self.0.remove_fd(fd)
+	}
+	fn add_instant(&self, instant: time::Instant) -> Self::InstantSlot {
+		self.0.add_instant(instant)
+	}
+	fn remove_instant(&self, slot: Self::InstantSlot) {
+		self.0.remove_instant(slot)
+	}
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 struct Key(*const ());
 /// Because `*const ()`. Pointers aren't really not Send+Sync, it's more of a lint.
 unsafe impl marker::Send for Key {}
@@ -42,17 +118,40 @@ impl From<Key> for usize {
 pub struct Handle {
 	triggerer: Option<Triggerer>,
 	tcp_thread: Option<thread::JoinHandle<()>>,
+	/// Disconnected once the tcp thread has finished.
+	finished: mpsc::Receiver<()>,
+}
+impl Handle {
+	/// Close all channels, and wait up to `timeout` for what's been sent on them to be delivered and the tcp thread to finish. Returns whether it finished; if not, the thread is left running and still borrows the [Reactor].
+	pub fn shutdown(mut self, timeout: time::Duration) -> bool {
+		drop(self.triggerer.take().unwrap());
+		let tcp_thread = self.tcp_thread.take().unwrap();
+		match self.finished.recv_timeout(timeout) {
+			Err(mpsc::RecvTimeoutError::Disconnected) => {
+				tcp_thread.join().unwrap();
+				true
+			}
+			Ok(()) | Err(mpsc::RecvTimeoutError::Timeout) => false,
+		}
+	}
 }
 impl Drop for Handle {
 	fn drop(&mut self) {
-		drop(self.triggerer.take().unwrap());
-		self.tcp_thread.take().unwrap().join().unwrap();
+		if let Some(triggerer) = self.triggerer.take() {
+			drop(triggerer);
+			self.tcp_thread.take().unwrap().join().unwrap();
+		}
 	}
 }
 pub struct Reactor {
 	notifier: Notifier<Key>,
 	listener: sync::RwLock<Option<Listener>>,
+	/// Listens for connections from processes on the same node, if this is a user process.
+	unix_listener: sync::RwLock<Option<unix::Listener>>,
 	sockets: sync::RwLock<HashMap<net::SocketAddr, Arc<sync::RwLock<Option<Channel>>>>>,
+	accepters: Arc<sync::Mutex<HashMap<thread::ThreadId, thread::Thread>>>,
+	/// Threads to unpark on events for their key, registered with [`add_fd_waiter()`](Reactor::add_fd_waiter).
+	fd_waiters: sync::Mutex<HashMap<Key, thread::Thread>>,
 	local: net::SocketAddr,
 }
 impl Reactor {
@@ -65,7 +164,10 @@ impl Reactor {
 			Self {
 				notifier,
 				listener: sync::RwLock::new(Some(listener)),
+				unix_listener: sync::RwLock::new(None),
 				sockets,
+				accepters: Arc::new(sync::Mutex::new(HashMap::new())),
+				fd_waiters: sync::Mutex::new(HashMap::new()),
 				local,
 			},
 			port,
@@ -75,33 +177,55 @@ impl Reactor {
 	pub fn with_fd(fd: Fd) -> Self {
 		let notifier = Notifier::new();
 		let listener = Listener::with_fd(fd, &notifier.context(Key(ptr::null())));
+		// Unset in case it was inherited, as a monitor is forked from the user process that spawned it
+		UNIX.store(false, sync::atomic::Ordering::Relaxed);
 		let sockets = sync::RwLock::new(HashMap::new());
-		let local = if let socket::SockAddr::Inet(inet) = socket::getsockname(fd).unwrap() {
-			inet.to_std()
-		} else {
-			panic!()
-		};
+		let local = Native::channel_local_addr(fd).unwrap();
 		Self {
 			notifier,
 			listener: sync::RwLock::new(Some(listener)),
+			unix_listener: sync::RwLock::new(None),
 			sockets,
+			accepters: Arc::new(sync::Mutex::new(HashMap::new())),
+			fd_waiters: sync::Mutex::new(HashMap::new()),
 			local,
 		}
 	}
 
-	pub fn with_forwardee(socket_forwardee: SocketForwardee, local: net::SocketAddr) -> Self {
+	/// For a user process, which also listens for connections from processes on the same node on a unix domain socket, queueing up to `backlog`.
+	pub fn with_forwardee(
+		socket_forwardee: SocketForwardee, local: net::SocketAddr, backlog: usize,
+	) -> Self {
 		let notifier = Notifier::new();
 		let listener =
 			Listener::with_socket_forwardee(socket_forwardee, &notifier.context(Key(ptr::null())));
+		let unix_listener =
+			unix::Listener::new(local, backlog, &notifier.context(Key(2 as *const ())));
+		UNIX.store(unix_listener.is_some(), sync::atomic::Ordering::Relaxed);
 		let sockets = sync::RwLock::new(HashMap::new());
 		Self {
 			notifier,
 			listener: sync::RwLock::new(Some(listener)),
+			unix_listener: sync::RwLock::new(unix_listener),
 			sockets,
+			accepters: Arc::new(sync::Mutex::new(HashMap::new())),
+			fd_waiters: sync::Mutex::new(HashMap::new()),
 			local,
 		}
 	}
 
+	/// Unpark `thread` on each change in the readiness of `fd`, until [`remove_fd_waiter()`](Reactor::remove_fd_waiter) is called with the same `key`. `key` must be the address of something that lives until then, so that it's distinct from the keys of channels.
+	pub fn add_fd_waiter(&self, key: *const (), fd: Fd, thread: thread::Thread) {
+		let x = self.fd_waiters.lock().unwrap().insert(Key(key), thread);
+		assert!(x.is_none());
+		tcp_typed::Notifier::add_fd(&self.notifier.context(Key(key)), fd);
+	}
+
+	pub fn remove_fd_waiter(&self, key: *const (), fd: Fd) {
+		tcp_typed::Notifier::remove_fd(&self.notifier.context(Key(key)), fd);
+		let _ = self.fd_waiters.lock().unwrap().remove(&Key(key)).unwrap();
+	}
+
 	pub fn run<
 		F: FnMut() -> C + marker::Send + 'static,
 		C: Borrow<Self>,
@@ -118,14 +242,19 @@ impl Reactor {
 				.add_trigger()
 		};
 		let mut triggeree = Some(triggeree);
+		let (finished_sender, finished) = mpsc::channel::<()>();
 		let tcp_thread = spawn(String::from("tcp-thread"), move || {
+			let _finished_sender = finished_sender;
 			let context = context();
 			let context = context.borrow();
 			let mut listener = context.listener.try_write().unwrap();
-			let (notifier, listener, sockets, local) = (
+			let mut unix_listener = context.unix_listener.try_write().unwrap();
+			let (notifier, listener, sockets, accepters, fd_waiters, local) = (
 				&context.notifier,
 				listener.as_mut().unwrap(),
 				&context.sockets,
+				&context.accepters,
+				&context.fd_waiters,
 				&context.local,
 			);
 			let mut done: Option<
@@ -147,10 +276,34 @@ impl Reactor {
 				// }
 				#[allow(clippy::cyclomatic_complexity)]
 				notifier.wait(|_events, data| {
-					if data == Key(ptr::null()) {
-						for (remote, connection) in
-							listener.poll(&notifier.context(Key(ptr::null())), &mut accept_hook)
-						{
+					if data == Key(ptr::null()) || data == Key(2 as *const ()) {
+						let accepted: Vec<_> = if data == Key(ptr::null()) {
+							listener
+								.poll(&notifier.context(Key(ptr::null())), &mut accept_hook)
+								.into_iter()
+								.map(|(remote, connection)| (remote, Either::Left(connection)))
+								.collect()
+						} else {
+							unix_listener
+								.as_mut()
+								.unwrap()
+								.poll(&notifier.context(Key(2 as *const ())))
+								.into_iter()
+								.map(|(remote, connection)| (remote, Either::Right(connection)))
+								.collect()
+						};
+						for (remote, connection) in accepted {
+							let connectee = move |notifier: &_| {
+								let transport = match connection {
+									Either::Left(connection) => {
+										Transport::Tcp(connection(notifier).into())
+									}
+									Either::Right(connection) => {
+										Transport::Unix(connection.connection(notifier))
+									}
+								};
+								Connection::accepted(transport, *local, remote)
+							};
 							let is_done = done.is_some();
 							let mut sockets_ = if done.is_none() {
 								Some(sockets.write().unwrap())
@@ -172,7 +325,7 @@ impl Reactor {
 									let notifier_key: *const sync::RwLock<Option<Channel>> = channel_;
 									let notifier =
 										&notifier.context(Key(notifier_key as *const ()));
-									let connectee: Connection = connection(notifier).into();
+									let connectee = connectee(notifier);
 									let mut channel = channel_.write().unwrap();
 									let channel = channel.as_mut().unwrap();
 									if channel.inner.add_incoming(notifier).is_some() {
@@ -216,7 +369,7 @@ impl Reactor {
 									let notifier_key: *const sync::RwLock<Option<Channel>> = &*channel;
 									let notifier =
 										&notifier.context(Key(notifier_key as *const ()));
-									let connectee: Connection = connection(notifier).into();
+									let connectee = connectee(notifier);
 									let mut inner =
 										Inner::connect(*local, remote, Some(connectee), notifier);
 									if is_done && inner.closable() {
@@ -225,12 +378,20 @@ impl Reactor {
 									if !inner.closed() {
 										*channel.try_write().unwrap() = Some(Channel::new(inner));
 										let _ = vacant.insert(channel);
+										if !is_done {
+											for accepter in accepters.lock().unwrap().values() {
+												accepter.unpark();
+											}
+										}
 									}
 								}
 							}
 						}
 					} else if data != Key(1 as *const ()) {
-						if done.is_none() {
+						let waiter = fd_waiters.lock().unwrap().get(&data).cloned();
+						if let Some(waiter) = waiter {
+							waiter.unpark();
+						} else if done.is_none() {
 							let mut sockets = sockets.write().unwrap();
 							let notifier_key: *const sync::RwLock<
 								Option<Channel>,
@@ -256,6 +417,9 @@ impl Reactor {
 									let channel: &mut Channel = channel.as_mut().unwrap();
 									let inner: &mut Inner = &mut channel.inner;
 									inner.poll(notifier);
+									if let Some(ref mut filter) = channel.filter {
+										let _ = filter.poll(inner, &context.notifier, Key(notifier_key as *const ()));
+									}
 									for sender in channel.senders.values() {
 										sender.unpark(); // TODO: don't do unless actual progress
 									}
@@ -373,6 +537,7 @@ impl Reactor {
 		Handle {
 			triggerer: Some(triggerer),
 			tcp_thread: Some(tcp_thread),
+			finished,
 		}
 	}
 }
@@ -385,6 +550,9 @@ impl Drop for Reactor {
 			.take()
 			.unwrap()
 			.close(&self.notifier.context(Key(ptr::null())));
+		if let Some(unix_listener) = self.unix_listener.get_mut().unwrap().take() {
+			unix_listener.close(&self.notifier.context(Key(2 as *const ())));
+		}
 	}
 }
 
@@ -397,6 +565,7 @@ pub struct Channel {
 	// senders_futures: Vec<futures::task::Waker>,
 	receivers: HashMap<thread::ThreadId, thread::Thread>,
 	// receivers_futures: Vec<futures::task::Waker>,
+	filter: Option<Filter>,
 }
 impl Channel {
 	fn new(inner: Inner) -> Self {
@@ -408,10 +577,67 @@ impl Channel {
 			// senders_futures: Vec::new(),
 			receivers: HashMap::new(),
 			// receivers_futures: Vec::new(),
+			filter: None,
 		}
 	}
 }
 
+/// A predicate set by [`Receiver::set_filter()`]. The reactor runs it on messages as they arrive, dropping those it rejects without waking the receiving thread. A deserialized message can't be put back, so the first accepted is held until it's received.
+struct Filter {
+	next: Box<
+		FnMut(&mut Inner, &Notifier<Key>, Key) -> Option<Box<any::Any + marker::Send>>
+			+ marker::Send,
+	>,
+	accepted: Option<Box<any::Any + marker::Send>>,
+}
+// Only accepted() takes &self, and it doesn't look inside the message, so sharing a &Filter between threads is safe.
+unsafe impl Sync for Filter {}
+impl Filter {
+	fn new<
+		T: serde::de::DeserializeOwned + marker::Send + 'static,
+		F: Fn(&T) -> bool + marker::Send + 'static,
+	>(
+		pred: F,
+	) -> Self {
+		Self {
+			next: Box::new(move |inner: &mut Inner, notifier: &Notifier<Key>, key: Key| {
+				let notifier = &notifier.context(key);
+				while inner.recv_avail(notifier).unwrap_or(false) {
+					let t = inner.recv(marker::PhantomData::<T>, notifier);
+					if pred(&t) {
+						return Some(Box::new(t) as Box<any::Any + marker::Send>);
+					}
+				}
+				None
+			}),
+			accepted: None,
+		}
+	}
+
+	/// Run the predicate over what's arrived until a message is accepted, returning whether one is waiting to be received.
+	fn poll(&mut self, inner: &mut Inner, notifier: &Notifier<Key>, key: Key) -> bool {
+		if self.accepted.is_none() {
+			self.accepted = (self.next)(inner, notifier, key);
+		}
+		self.accepted.is_some()
+	}
+
+	fn accepted(&self) -> bool {
+		self.accepted.is_some()
+	}
+
+	fn take<T: 'static>(&mut self) -> Option<T> {
+		self.accepted.take().map(|t| *t.downcast::<T>().unwrap())
+	}
+}
+impl fmt::Debug for Filter {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Filter")
+			.field("accepted", &self.accepted())
+			.finish()
+	}
+}
+
 /// Channel operation error modes.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ChannelError {
@@ -419,6 +645,10 @@ pub enum ChannelError {
 	Exited,
 	/// The remote process terminated abruptly, or the channel was killed by the OS or hardware.
 	Error,
+	/// The remote process was built against a different version of this crate, or failed to prove it has the same `CONSTELLATION_CHANNEL_TOKEN`, so couldn't be communicated with.
+	Mismatch,
+	/// The connection to the remote process, on another node, couldn't be encrypted: only one of the two processes wanted TLS, or its handshake failed, for example on a certificate that isn't trusted. The cause is logged, as this is `Copy` and can't carry it.
+	Tls,
 }
 impl fmt::Display for ChannelError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -427,6 +657,10 @@ impl fmt::Display for ChannelError {
 				write!(f, "Remote process died or channel killed by OS/hardware")
 			} //(ref err) => err.fmt(f),
 			ChannelError::Exited => write!(f, "Remote process already exited"),
+			ChannelError::Mismatch => {
+				write!(f, "Remote process built against a different version or token")
+			}
+			ChannelError::Tls => write!(f, "TLS with remote process failed"),
 		}
 	}
 }
@@ -435,23 +669,35 @@ impl error::Error for ChannelError {
 		match *self {
 			ChannelError::Error => "remote process died or channel killed by OS/hardware", //(ref err) => err.description(),
 			ChannelError::Exited => "remote process already exited",
+			ChannelError::Mismatch => "remote process built against a different version or token",
+			ChannelError::Tls => "TLS with remote process failed",
 		}
 	}
 
 	fn cause(&self) -> Option<&error::Error> {
 		match *self {
 			ChannelError::Error /*(ref err) => Some(err),*/ |
-			ChannelError::Exited => None,
+			ChannelError::Exited |
+			ChannelError::Mismatch |
+			ChannelError::Tls => None,
 		}
 	}
 }
 
 pub struct Sender<T: serde::ser::Serialize> {
 	channel: Option<Arc<sync::RwLock<Option<Channel>>>>,
+	capacity: Option<usize>,
 	_marker: marker::PhantomData<fn(T)>,
 }
 impl<T: serde::ser::Serialize> Sender<T> {
 	pub fn new(remote: net::SocketAddr, context: &Reactor) -> Option<Self> {
+		Self::with_capacity(remote, None, context)
+	}
+
+	/// With a `capacity`, sending waits until no more than it of the messages sent are yet to be acknowledged by the remote.
+	pub fn with_capacity(
+		remote: net::SocketAddr, capacity: Option<usize>, context: &Reactor,
+	) -> Option<Self> {
 		let (notifier, sockets, local) = (&context.notifier, &context.sockets, &context.local);
 		let sockets = &mut *sockets.write().unwrap();
 		let channel = match sockets.entry(remote) {
@@ -486,6 +732,7 @@ impl<T: serde::ser::Serialize> Sender<T> {
 		);
 		Some(Self {
 			channel: Some(channel),
+			capacity,
 			_marker: marker::PhantomData,
 		})
 	}
@@ -505,7 +752,9 @@ impl<T: serde::ser::Serialize> Sender<T> {
 			// let notifier = &notifier.context(Key(notifier_key as *const ()));
 			// assert_eq!(sync::Arc::strong_count(&self.channel.as_ref().unwrap()), 1+channel.as_ref().unwrap().senders_count+channel.as_ref().unwrap().receivers_count);
 			let inner = &mut channel.as_mut().unwrap().inner;
-			inner.send_avail().unwrap_or(!inner.valid()) // || inner.closed()
+			inner.send_avail().map_or(!inner.valid(), |avail| {
+				avail && self.within_capacity(inner)
+			}) // || inner.closed()
 		};
 		if unblocked {
 			Some(move |t| {
@@ -526,7 +775,7 @@ impl<T: serde::ser::Serialize> Sender<T> {
 				if !inner.sendable() {
 					panic!(".send() called on a closed Sender");
 				}
-				inner.send(t, notifier);
+				inner.send(t, 1, notifier);
 				// TODO: unpark queue?
 			})
 		} else {
@@ -534,6 +783,46 @@ impl<T: serde::ser::Serialize> Sender<T> {
 		}
 	}
 
+	pub fn try_async_send<'a, C: Borrow<Reactor> + 'a>(
+		&'a self, context_: C,
+	) -> Result<Option<impl FnOnce(T) + 'a>, ChannelError>
+	where
+		T: 'static,
+	{
+		let mut channel = self.channel.as_ref().unwrap().write().unwrap();
+		{
+			let inner = &channel.as_ref().unwrap().inner;
+			if inner.mismatched() {
+				return Err(ChannelError::Mismatch);
+			}
+			if inner.tls_failed() {
+				return Err(ChannelError::Tls);
+			}
+			if !inner.valid() {
+				return Err(ChannelError::Error);
+			}
+			if !inner.sendable() && !inner.connecting() {
+				return Err(ChannelError::Exited);
+			}
+			if !inner.send_avail().unwrap_or(false) || !self.within_capacity(inner) {
+				return Ok(None);
+			}
+		}
+		Ok(Some(move |t| {
+			let _ = channel
+				.as_mut()
+				.unwrap()
+				.senders
+				.remove(&thread::current().id());
+			let context = context_.borrow();
+			let notifier = &context.notifier;
+			let notifier_key: *const sync::RwLock<Option<Channel>> =
+				&**self.channel.as_ref().unwrap();
+			let notifier = &notifier.context(Key(notifier_key as *const ()));
+			channel.as_mut().unwrap().inner.send(t, 1, notifier);
+		}))
+	}
+
 	pub fn send<F: FnMut() -> C, C: Borrow<Reactor>>(&self, t: T, context: &mut F)
 	where
 		T: 'static,
@@ -546,7 +835,126 @@ impl<T: serde::ser::Serialize> Sender<T> {
 			}))],
 			context,
 		);
-		x.into_inner().unwrap()
+		x.into_inner().unwrap();
+		if let Some(capacity) = self.capacity {
+			let _ = self.acked(capacity as u64, context);
+		}
+	}
+
+	pub fn try_send<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, t: T, context: &mut F,
+	) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		let x = cell::RefCell::new(None);
+		let _ = select(vec![Box::new(TrySend(self, Some(t), &x))], context);
+		let ret = x.into_inner().unwrap();
+		if let (&Ok(()), Some(capacity)) = (&ret, self.capacity) {
+			// It was sent, so whether it's acknowledged doesn't change the result
+			let _ = self.acked(capacity as u64, context);
+		}
+		ret
+	}
+
+	/// [`try_send()`](Sender::try_send) each of `items` in turn, skipping the select machinery for those that can be sent immediately. On error, returns it with how many were sent.
+	pub fn try_send_all<I: IntoIterator<Item = T>, F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, items: I, context: &mut F,
+	) -> Result<(), (usize, ChannelError)>
+	where
+		T: 'static,
+	{
+		for (sent, t) in items.into_iter().enumerate() {
+			// A capacity needs each message's acknowledgement waited on, which try_send() does
+			let t = if self.capacity.is_none() {
+				match self.try_async_send(context()) {
+					Ok(Some(send)) => {
+						send(t);
+						continue;
+					}
+					Ok(None) => t,
+					Err(err) => return Err((sent, err)),
+				}
+			} else {
+				t
+			};
+			self.try_send(t, context).map_err(|err| (sent, err))?;
+		}
+		Ok(())
+	}
+
+	/// Block until messages already sent have been written to the connection's socket, or it can no longer send.
+	pub fn flush<F: FnMut() -> C, C: Borrow<Reactor>>(&self, context: &mut F)
+	where
+		T: 'static,
+	{
+		let _ = select(vec![Box::new(Flush(self))], context);
+	}
+
+	/// [`flush()`](Sender::flush), then return an error if the channel can no longer send.
+	pub fn drain<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, context: &mut F,
+	) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		self.flush(context);
+		let channel = self.channel.as_ref().unwrap().read().unwrap();
+		let inner = &channel.as_ref().unwrap().inner;
+		if inner.mismatched() {
+			Err(ChannelError::Mismatch)
+		} else if inner.tls_failed() {
+			Err(ChannelError::Tls)
+		} else if !inner.valid() {
+			Err(ChannelError::Error)
+		} else if !inner.sendable() && !inner.connecting() {
+			Err(ChannelError::Exited)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Whether few enough of the messages sent are yet to be acknowledged by the remote for another to be sent without exceeding the capacity.
+	fn within_capacity(&self, inner: &Inner) -> bool {
+		self.capacity.map_or(true, |capacity| {
+			inner
+				.unacked()
+				.map_or(true, |unacked| unacked <= capacity as u64)
+		})
+	}
+
+	/// Block until at most `unacked` of the messages sent are yet to be acknowledged by the remote. Returns an error if more are, as no more acknowledgements can arrive.
+	pub fn acked<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, unacked: u64, context: &mut F,
+	) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		let _ = select(vec![Box::new(Acked(self, unacked))], context);
+		let channel = self.channel.as_ref().unwrap().read().unwrap();
+		let inner = &channel.as_ref().unwrap().inner;
+		if inner.unacked().map_or(false, |unacked_| unacked_ <= unacked) {
+			Ok(())
+		} else if inner.mismatched() {
+			Err(ChannelError::Mismatch)
+		} else if inner.tls_failed() {
+			Err(ChannelError::Tls)
+		} else if !inner.valid() {
+			Err(ChannelError::Error)
+		} else {
+			Err(ChannelError::Exited)
+		}
+	}
+
+	/// Close the sending half of the connection, so the remote receives [`ChannelError::Exited`] once it has received what was already sent.
+	pub fn close(&self, context: &Reactor) {
+		let notifier_key: *const sync::RwLock<Option<Channel>> = &**self.channel.as_ref().unwrap();
+		let notifier = &context.notifier.context(Key(notifier_key as *const ()));
+		let mut channel = self.channel.as_ref().unwrap().write().unwrap();
+		let inner = &mut channel.as_mut().unwrap().inner;
+		if inner.closable() {
+			inner.close(notifier);
+		}
 	}
 
 	pub fn selectable_send<'a, F: FnOnce() -> T + 'a>(&'a self, f: F) -> impl Selectable + 'a
@@ -556,6 +964,16 @@ impl<T: serde::ser::Serialize> Sender<T> {
 		Send(self, Some(f))
 	}
 
+	/// Like [`selectable_send()`](Sender::selectable_send), but executable with an error, rather than panicking, if the channel can no longer send. `result` is set once it's executed.
+	pub fn selectable_try_send<'a>(
+		&'a self, t: T, result: &'a cell::RefCell<Option<Result<(), ChannelError>>>,
+	) -> impl Selectable + 'a
+	where
+		T: 'static,
+	{
+		TrySend(self, Some(t), result)
+	}
+
 	pub fn drop(mut self, context: &Reactor) {
 		let mut sockets = context.sockets.write().unwrap();
 		let channel_arc = self.channel.take().unwrap();
@@ -703,10 +1121,242 @@ impl<'a, T: serde::ser::Serialize + 'static, F: FnOnce() -> T> Selectable for Se
 			.unwrap();
 	}
 }
+struct TrySend<'a, T: serde::ser::Serialize + 'static>(
+	&'a Sender<T>,
+	Option<T>,
+	&'a cell::RefCell<Option<Result<(), ChannelError>>>,
+);
+impl<'a, T: serde::ser::Serialize + 'static> fmt::Debug for TrySend<'a, T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("TrySend").field("sender", &self.0).finish()
+	}
+}
+impl<'a, T: serde::ser::Serialize + 'static> Selectable for TrySend<'a, T> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self
+			.0
+			.channel
+			.as_ref()
+			.unwrap()
+			.write()
+			.unwrap()
+			.as_mut()
+			.unwrap()
+			.senders
+			.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let sender = self.0;
+		match sender.try_async_send(context) {
+			Ok(Some(send)) => Some(Box::new(move || {
+				send(self.1.take().unwrap());
+				*self.2.borrow_mut() = Some(Ok(()));
+			}) as Box<FnBox() + 'b>),
+			Ok(None) => None,
+			Err(err) => {
+				let result = self.2;
+				Some(Box::new(move || *result.borrow_mut() = Some(Err(err))) as Box<FnBox() + 'b>)
+			}
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.channel
+			.as_ref()
+			.unwrap()
+			.write()
+			.unwrap()
+			.as_mut()
+			.unwrap()
+			.senders
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+struct Flush<'a, T: serde::ser::Serialize + 'static>(&'a Sender<T>);
+impl<'a, T: serde::ser::Serialize + 'static> fmt::Debug for Flush<'a, T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Flush").field("sender", &self.0).finish()
+	}
+}
+impl<'a, T: serde::ser::Serialize + 'static> Selectable for Flush<'a, T> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self
+			.0
+			.channel
+			.as_ref()
+			.unwrap()
+			.write()
+			.unwrap()
+			.as_mut()
+			.unwrap()
+			.senders
+			.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, _context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let flushed = {
+			let channel = self.0.channel.as_ref().unwrap().read().unwrap();
+			channel.as_ref().unwrap().inner.flushed()
+		};
+		if flushed {
+			let sender = self.0;
+			Some(Box::new(move || {
+				let _ = sender
+					.channel
+					.as_ref()
+					.unwrap()
+					.write()
+					.unwrap()
+					.as_mut()
+					.unwrap()
+					.senders
+					.remove(&thread::current().id());
+			}) as Box<FnBox() + 'b>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.channel
+			.as_ref()
+			.unwrap()
+			.write()
+			.unwrap()
+			.as_mut()
+			.unwrap()
+			.senders
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+/// Available once at most `.1` of the messages sent are yet to be acknowledged by the remote, or no more acknowledgements can arrive.
+struct Acked<'a, T: serde::ser::Serialize + 'static>(&'a Sender<T>, u64);
+impl<'a, T: serde::ser::Serialize + 'static> fmt::Debug for Acked<'a, T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Acked")
+			.field("sender", &self.0)
+			.field("unacked", &self.1)
+			.finish()
+	}
+}
+impl<'a, T: serde::ser::Serialize + 'static> Selectable for Acked<'a, T> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self
+			.0
+			.channel
+			.as_ref()
+			.unwrap()
+			.write()
+			.unwrap()
+			.as_mut()
+			.unwrap()
+			.senders
+			.insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'b>(&'b mut self, _context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
+		let acked = {
+			let channel = self.0.channel.as_ref().unwrap().read().unwrap();
+			channel
+				.as_ref()
+				.unwrap()
+				.inner
+				.unacked()
+				.map_or(true, |unacked| unacked <= self.1)
+		};
+		if acked {
+			let sender = self.0;
+			Some(Box::new(move || {
+				let _ = sender
+					.channel
+					.as_ref()
+					.unwrap()
+					.write()
+					.unwrap()
+					.as_mut()
+					.unwrap()
+					.senders
+					.remove(&thread::current().id());
+			}) as Box<FnBox() + 'b>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self
+			.0
+			.channel
+			.as_ref()
+			.unwrap()
+			.write()
+			.unwrap()
+			.as_mut()
+			.unwrap()
+			.senders
+			.remove(&thread.id())
+			.unwrap();
+	}
+}
+/// The most bytes [`Sender::try_send_bytes()`](Sender::try_send_bytes) sends at once, bounding the buffer the serializer holds.
+const SEND_BYTES_MAX: usize = 64 * 1024;
+
+impl Sender<u8> {
+	/// Send as many of `buf` as can be sent without blocking, as a single message rather than one per byte, up to 64 KiB at a time, and with a capacity, up to one more than it unacknowledged. Returns how many were sent, or an error if none could be as the channel can no longer send.
+	pub fn try_send_bytes<C: Borrow<Reactor>>(
+		&self, buf: &[u8], context: C,
+	) -> Result<usize, ChannelError> {
+		let mut channel = self.channel.as_ref().unwrap().write().unwrap();
+		{
+			let inner = &channel.as_ref().unwrap().inner;
+			if inner.mismatched() {
+				return Err(ChannelError::Mismatch);
+			}
+			if inner.tls_failed() {
+				return Err(ChannelError::Tls);
+			}
+			if !inner.valid() {
+				return Err(ChannelError::Error);
+			}
+			if !inner.sendable() && !inner.connecting() {
+				return Err(ChannelError::Exited);
+			}
+		}
+		let notifier = &context.borrow().notifier;
+		let notifier_key: *const sync::RwLock<Option<Channel>> = &**self.channel.as_ref().unwrap();
+		let notifier = &notifier.context(Key(notifier_key as *const ()));
+		let channel = channel.as_mut().unwrap();
+		if buf.is_empty()
+			|| !channel.inner.send_avail().unwrap_or(false)
+			|| !self.within_capacity(&channel.inner)
+		{
+			return Ok(0);
+		}
+		let mut sent = cmp::min(buf.len(), SEND_BYTES_MAX);
+		if let (Some(capacity), Some(unacked)) = (self.capacity, channel.inner.unacked()) {
+			// Each byte is a message, so no more than there's capacity for
+			sent = cmp::min(sent, capacity + 1 - unacked as usize);
+		}
+		channel.inner.send_bytes(buf[..sent].to_vec(), notifier);
+		let _ = channel.senders.remove(&thread::current().id());
+		Ok(sent)
+	}
+}
 impl<T: serde::ser::Serialize> fmt::Debug for Sender<T> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		f.debug_struct("Sender")
 			.field("inner", &self.channel)
+			.field("capacity", &self.capacity)
 			.finish()
 	}
 }
@@ -755,12 +1405,36 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 		})
 	}
 
+	/// Whether the remote has closed and everything it sent has been received.
+	pub fn drained(&self) -> bool {
+		let channel = self.channel.as_ref().unwrap().read().unwrap();
+		let channel = channel.as_ref().unwrap();
+		!channel.inner.recvable() && !channel.filter.as_ref().map_or(false, Filter::accepted)
+	}
+
+	/// Drop messages for which `pred` returns false, running it in the reactor as they arrive.
+	pub fn set_filter<F: Fn(&T) -> bool + marker::Send + 'static>(&self, pred: F)
+	where
+		T: marker::Send + 'static,
+	{
+		let mut channel = self.channel.as_ref().unwrap().write().unwrap();
+		channel.as_mut().unwrap().filter = Some(Filter::new(pred));
+	}
+
 	pub fn async_recv<'a, C: Borrow<Reactor> + 'a>(
 		&'a self, context: C,
 	) -> Option<impl FnOnce() -> Result<T, ChannelError> + 'a>
 	where
 		T: 'static,
 	{
+		self.async_recv_seed::<_, marker::PhantomData<T>>(Filter::take, context)
+			.map(|recv| move || recv(marker::PhantomData))
+	}
+
+	/// Like [`async_recv()`](Receiver::async_recv), but deserializing the message with the seed the returned closure is given. `take_accepted` takes the message the [Filter] has accepted, if there's one.
+	fn async_recv_seed<'a, C: Borrow<Reactor> + 'a, S: serde::de::DeserializeSeed<'static> + 'a>(
+		&'a self, take_accepted: fn(&mut Filter) -> Option<S::Value>, context: C,
+	) -> Option<impl FnOnce(S) -> Result<S::Value, ChannelError> + 'a> {
 		let mut channel = self.channel.as_ref().unwrap().write().unwrap();
 		let unblocked = {
 			let notifier = &context.borrow().notifier;
@@ -768,11 +1442,21 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 				&**self.channel.as_ref().unwrap();
 			let notifier = &notifier.context(Key(notifier_key as *const ()));
 			// assert_eq!(sync::Arc::strong_count(&self.channel.as_ref().unwrap()), 1+channel.as_ref().unwrap().senders_count+channel.as_ref().unwrap().receivers_count);
-			let inner = &mut channel.as_mut().unwrap().inner;
-			inner.recv_avail::<T, _>(notifier).unwrap_or(!inner.valid()) // || inner.closed()
+			let channel = channel.as_mut().unwrap();
+			let inner = &mut channel.inner;
+			let accepted = match channel.filter {
+				Some(ref mut filter) => filter.poll(
+					inner,
+					&context.borrow().notifier,
+					Key(notifier_key as *const ()),
+				),
+				None => false,
+			};
+			// after a filter's run this is Some(false), unless the channel can no longer receive
+			accepted || inner.recv_avail(notifier).unwrap_or(!inner.valid()) // || inner.closed()
 		};
 		if unblocked {
-			Some(move || {
+			Some(move |seed: S| {
 				let _ = channel
 					.as_mut()
 					.unwrap()
@@ -784,14 +1468,24 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 				let notifier = &notifier.context(Key(notifier_key as *const ()));
 				// let mut channel = self.channel.as_ref().unwrap().write().unwrap();
 				// assert_eq!(sync::Arc::strong_count(&self.channel.as_ref().unwrap()), 1+channel.as_ref().unwrap().senders_count+channel.as_ref().unwrap().receivers_count);
-				let inner = &mut channel.as_mut().unwrap().inner;
+				let channel = channel.as_mut().unwrap();
+				if let Some(t) = channel.filter.as_mut().and_then(take_accepted) {
+					return Ok(t);
+				}
+				let inner = &mut channel.inner;
+				if inner.mismatched() {
+					return Err(ChannelError::Mismatch);
+				}
+				if inner.tls_failed() {
+					return Err(ChannelError::Tls);
+				}
 				if !inner.valid() {
 					return Err(ChannelError::Error);
 				}
 				if !inner.recvable() {
 					return Err(ChannelError::Exited);
 				}
-				Ok(inner.recv(notifier))
+				Ok(inner.recv(seed, notifier))
 				// TODO: unpark queue?
 			})
 		} else {
@@ -815,13 +1509,58 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 		x.into_inner().unwrap()
 	}
 
+	/// [`recv()`](Receiver::recv), but deserializing the message with `seed` rather than as a `T`. Panics if a filter is set, as it's run on messages deserialized as `T`s.
+	pub fn recv_deserialize_with<
+		S: serde::de::DeserializeSeed<'static>,
+		F: FnMut() -> C,
+		C: Borrow<Reactor>,
+	>(
+		&self, seed: S, context: &mut F,
+	) -> Result<S::Value, ChannelError> {
+		assert!(
+			self.channel.as_ref().unwrap().read().unwrap().as_ref().unwrap().filter.is_none(),
+			"recv_deserialize_with() called on a Receiver with a filter"
+		);
+		let x = cell::RefCell::new(None);
+		let _ = select(
+			vec![Box::new(Recv(
+				self,
+				Some((seed, |t| *x.borrow_mut() = Some(t))),
+				|_| None,
+			))],
+			context,
+		);
+		x.into_inner().unwrap()
+	}
+
+	/// [`recv()`](Receiver::recv) `n` messages, skipping the select machinery for those already available. On error, returns it with those received.
+	pub fn recv_all<F: FnMut() -> C, C: Borrow<Reactor>>(
+		&self, n: usize, context: &mut F,
+	) -> Result<Vec<T>, (Vec<T>, ChannelError)>
+	where
+		T: 'static,
+	{
+		let mut received = Vec::with_capacity(n);
+		while received.len() < n {
+			let t = match self.async_recv(context()) {
+				Some(recv) => recv(),
+				None => self.recv(context),
+			};
+			match t {
+				Ok(t) => received.push(t),
+				Err(err) => return Err((received, err)),
+			}
+		}
+		Ok(received)
+	}
+
 	pub fn selectable_recv<'a, F: FnOnce(Result<T, ChannelError>) + 'a>(
 		&'a self, f: F,
 	) -> impl Selectable + 'a
 	where
 		T: 'static,
 	{
-		Recv(self, Some(f))
+		Recv(self, Some((marker::PhantomData::<T>, f)), Filter::take)
 	}
 
 	pub fn drop(mut self, context: &Reactor) {
@@ -838,6 +1577,7 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 			let channel = channel.as_mut().unwrap();
 			channel.receivers_count -= 1;
 			assert_eq!(channel.receivers_count, 0);
+			channel.filter = None;
 			trace!("release receiver {:?}", notifier_key);
 			channel.senders_count == 0 && channel.receivers_count == 0 && channel.inner.closed()
 		};
@@ -895,19 +1635,34 @@ impl<T: serde::de::DeserializeOwned> Drop for Receiver<T> {
 		panic!("call .drop(context) rather than dropping a Receiver<T>");
 	}
 }
-struct Recv<'a, T: serde::de::DeserializeOwned + 'static, F: FnOnce(Result<T, ChannelError>)>(
+/// [Selectable] receiving a message from a [Receiver], deserialized with a seed of type `S`.
+struct Recv<
+	'a,
+	T: serde::de::DeserializeOwned + 'static,
+	S: serde::de::DeserializeSeed<'static>,
+	F: FnOnce(Result<S::Value, ChannelError>),
+>(
 	&'a Receiver<T>,
-	Option<F>,
+	Option<(S, F)>,
+	fn(&mut Filter) -> Option<S::Value>,
 );
-impl<'a, T: serde::de::DeserializeOwned + 'static, F: FnOnce(Result<T, ChannelError>)> fmt::Debug
-	for Recv<'a, T, F>
+impl<
+		'a,
+		T: serde::de::DeserializeOwned + 'static,
+		S: serde::de::DeserializeSeed<'static>,
+		F: FnOnce(Result<S::Value, ChannelError>),
+	> fmt::Debug for Recv<'a, T, S, F>
 {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		f.debug_struct("Recv").field("receiver", &self.0).finish()
 	}
 }
-impl<'a, T: serde::de::DeserializeOwned + 'static, F: FnOnce(Result<T, ChannelError>)> Selectable
-	for Recv<'a, T, F>
+impl<
+		'a,
+		T: serde::de::DeserializeOwned + 'static,
+		S: serde::de::DeserializeSeed<'static>,
+		F: FnOnce(Result<S::Value, ChannelError>),
+	> Selectable for Recv<'a, T, S, F>
 {
 	fn subscribe(&self, thread: thread::Thread) {
 		let x = self
@@ -925,10 +1680,11 @@ impl<'a, T: serde::de::DeserializeOwned + 'static, F: FnOnce(Result<T, ChannelEr
 	}
 
 	fn available<'b>(&'b mut self, context: &'b Reactor) -> Option<Box<FnBox() + 'b>> {
-		self.0.async_recv(context).map(|t| {
+		let (receiver, take_accepted, recv) = (self.0, self.2, &mut self.1);
+		receiver.async_recv_seed(take_accepted, context).map(|t| {
 			Box::new(move || {
-				let f = self.1.take().unwrap();
-				f(t())
+				let (seed, f) = recv.take().unwrap();
+				f(t(seed))
 			}) as Box<FnBox() + 'b>
 		})
 	}
@@ -956,6 +1712,108 @@ impl<T: serde::de::DeserializeOwned> fmt::Debug for Receiver<T> {
 	}
 }
 
+impl Receiver<u8> {
+	/// Receive into `buf` as many bytes as have arrived, taking the channel's lock once rather than per byte, and those sent together by [`Sender::try_send_bytes()`](Sender::try_send_bytes) in one deserialize. Returns how many were received, or an error if none had and none will, as the channel can no longer receive.
+	pub fn try_recv_bytes<C: Borrow<Reactor>>(
+		&self, buf: &mut [u8], context: C,
+	) -> Result<usize, ChannelError> {
+		let mut channel = self.channel.as_ref().unwrap().write().unwrap();
+		let notifier = &context.borrow().notifier;
+		let notifier_key: *const sync::RwLock<Option<Channel>> = &**self.channel.as_ref().unwrap();
+		let notifier = &notifier.context(Key(notifier_key as *const ()));
+		let channel = channel.as_mut().unwrap();
+		let mut received = 0;
+		// with a filter, bytes go through recv() so as to be run through it
+		while received < buf.len()
+			&& channel.filter.is_none()
+			&& channel.inner.recv_avail(notifier).unwrap_or(false)
+		{
+			received += channel.inner.recv_into(&mut buf[received..], notifier);
+		}
+		if received > 0 {
+			let _ = channel.receivers.remove(&thread::current().id());
+			return Ok(received);
+		}
+		if channel.inner.mismatched() {
+			return Err(ChannelError::Mismatch);
+		}
+		if channel.inner.tls_failed() {
+			return Err(ChannelError::Tls);
+		}
+		if !channel.inner.valid() {
+			return Err(ChannelError::Error);
+		}
+		if !channel.inner.recvable() && !channel.inner.connecting() {
+			return Err(ChannelError::Exited);
+		}
+		Ok(0)
+	}
+}
+
+fn claimable(channel: &Option<Channel>) -> bool {
+	channel.as_ref().map_or(false, |channel| {
+		channel.receivers_count == 0 && (channel.inner.connecting() || channel.inner.recvable())
+	})
+}
+impl<T: serde::de::DeserializeOwned> Receiver<T> {
+	/// Create a `Receiver<T>` for every remote that could be received from but doesn't yet have one.
+	pub fn new_unclaimed(context: &Reactor) -> Vec<(net::SocketAddr, Self)> {
+		let sockets = context.sockets.read().unwrap();
+		sockets
+			.iter()
+			.filter_map(|(&remote, channel)| {
+				{
+					let mut channel = channel.write().unwrap();
+					if !claimable(&channel) {
+						return None;
+					}
+					channel.as_mut().unwrap().receivers_count += 1;
+				}
+				let notifier_key: *const sync::RwLock<Option<Channel>> = &**channel;
+				trace!("retain receiver {:?}", notifier_key);
+				Some((
+					remote,
+					Self {
+						channel: Some(channel.clone()),
+						_marker: marker::PhantomData,
+					},
+				))
+			})
+			.collect()
+	}
+}
+
+/// [Selectable] that becomes available when a remote connects that doesn't have a [Receiver].
+#[derive(Clone, Debug)]
+pub struct Accept(Arc<sync::Mutex<HashMap<thread::ThreadId, thread::Thread>>>);
+impl Accept {
+	pub fn new(context: &Reactor) -> Self {
+		Accept(context.accepters.clone())
+	}
+}
+impl Selectable for Accept {
+	fn subscribe(&self, thread: thread::Thread) {
+		let x = self.0.lock().unwrap().insert(thread.id(), thread);
+		assert!(x.is_none());
+	}
+
+	fn available<'a>(&'a mut self, context: &'a Reactor) -> Option<Box<FnBox() + 'a>> {
+		let sockets = context.sockets.read().unwrap();
+		if sockets
+			.values()
+			.any(|channel| claimable(&channel.read().unwrap()))
+		{
+			Some(Box::new(|| ()) as Box<FnBox() + 'a>)
+		} else {
+			None
+		}
+	}
+
+	fn unsubscribe(&self, thread: thread::Thread) {
+		let _ = self.0.lock().unwrap().remove(&thread.id()).unwrap();
+	}
+}
+
 /// Types that can be [`select()`](select)ed upon.
 ///
 /// [`select()`](select) lets you block on multiple blocking operations until progress can be made on at least one.
@@ -974,6 +1832,10 @@ pub trait Selectable: fmt::Debug {
 	// fn run(&mut self, state: Self::State); // get rid once impl trait works in trait method return vals
 	#[doc(hidden)]
 	fn unsubscribe(&self, thread::Thread);
+	#[doc(hidden)]
+	fn weight(&self) -> u32 {
+		1
+	}
 }
 // struct SelectableRun<'a,T:Selectable+?Sized+'a>(&'a mut T,<T as Selectable>::State);
 // impl<'a,T:Selectable+?Sized+'a> ops::FnOnce<()> for SelectableRun<'a,T> {
@@ -985,41 +1847,121 @@ pub trait Selectable: fmt::Debug {
 pub fn select<'a, F: FnMut() -> C, C: Borrow<Reactor>>(
 	mut select: Vec<Box<Selectable + 'a>>, context: &mut F,
 ) -> impl Iterator<Item = Box<Selectable + 'a>> + 'a {
-	for selectable in &select {
-		selectable.subscribe(thread::current());
+	let i_ = select_mut(&mut select, context);
+	let mut rem = Vec::with_capacity(select.len() - 1);
+	for (i, select) in select.into_iter().enumerate() {
+		if i != i_ {
+			rem.push(select);
+			// } else {
+			// ret.1();
+			// select.run(&*context());
+		}
+	}
+	rem.into_iter()
+}
+/// Like [`select()`](select) but without taking ownership, returning the index of the executed [Selectable].
+pub fn select_mut<
+	'a,
+	S: ops::DerefMut<Target = Selectable + 'a>,
+	F: FnMut() -> C,
+	C: Borrow<Reactor>,
+>(
+	select: &mut [S], context: &mut F,
+) -> usize {
+	select_mut_inner(select, None, true, context).unwrap()
+}
+/// Like [`select_mut()`](select_mut), but returning `None` rather than blocking if none of the [Selectable]s are immediately progressable.
+pub fn try_select_mut<
+	'a,
+	S: ops::DerefMut<Target = Selectable + 'a>,
+	F: FnMut() -> C,
+	C: Borrow<Reactor>,
+>(
+	select: &mut [S], context: &mut F,
+) -> Option<usize> {
+	select_mut_inner(select, None, false, context)
+}
+/// Like [`select_mut()`](select_mut), but picking the first progressable [Selectable] at or after index `*next`, wrapping around, rather than one at random. `*next` is then set to the index after the executed one.
+pub fn select_mut_fair<
+	'a,
+	S: ops::DerefMut<Target = Selectable + 'a>,
+	F: FnMut() -> C,
+	C: Borrow<Reactor>,
+>(
+	select: &mut [S], next: &mut usize, context: &mut F,
+) -> usize {
+	let i = select_mut_inner(select, Some(*next), true, context).unwrap();
+	*next = i + 1;
+	i
+}
+fn select_mut_inner<
+	'a,
+	S: ops::DerefMut<Target = Selectable + 'a>,
+	F: FnMut() -> C,
+	C: Borrow<Reactor>,
+>(
+	select: &mut [S], start: Option<usize>, block: bool, context: &mut F,
+) -> Option<usize> {
+	if block {
+		for selectable in &*select {
+			selectable.subscribe(thread::current());
+		}
 	}
 	let mut context_lock;
 	let ret = loop {
-		let mut rand = Rand::new();
 		context_lock = Some(context());
-		for (i, selectable) in select.iter_mut().enumerate() {
-			if let Some(run) = selectable.available(context_lock.as_ref().unwrap().borrow()) {
-				rand.push((i, run), &mut rand::thread_rng());
+		let ret = if let Some(start) = start {
+			let start = start.min(select.len());
+			let reactor = context_lock.as_ref().unwrap().borrow();
+			let (before, after) = select.split_at_mut(start);
+			after
+				.iter_mut()
+				.enumerate()
+				.map(|(i, selectable)| (start + i, selectable))
+				.chain(before.iter_mut().enumerate())
+				.filter_map(|(i, selectable)| selectable.available(reactor).map(|run| (i, run)))
+				.next()
+		} else {
+			// Forked off the thread's seeded RNG, if any, as it can't be borrowed out of the thread local
+			let mut seeded = SELECT_RNG.with(|rng| {
+				rng.borrow_mut()
+					.as_mut()
+					.map(|rng| rand::XorShiftRng::from_rng(rng).unwrap())
+			});
+			let mut thread_rng = rand::thread_rng();
+			let mut rng: &mut rand::RngCore = match seeded {
+				Some(ref mut rng) => rng,
+				None => &mut thread_rng,
+			};
+			let mut rand = Rand::new();
+			for (i, selectable) in select.iter_mut().enumerate() {
+				let weight = selectable.weight().into();
+				if let Some(run) = selectable.available(context_lock.as_ref().unwrap().borrow()) {
+					rand.push_weighted((i, run), weight, &mut rng);
+				}
 			}
-		}
-		if let Some((i, run)) = rand.get() {
+			rand.get()
+		};
+		if let Some((i, run)) = ret {
 			break (i, run);
 		}
 		drop(context_lock.take().unwrap());
+		if !block {
+			return None;
+		}
 		thread::park();
 	};
 	let i_ = ret.0;
 	{ ret }.1();
-	for (i, selectable) in select.iter().enumerate() {
-		// TODO: unsub should be before run
-		if i != i_ {
-			selectable.unsubscribe(thread::current());
-		}
-	}
+	// Released first, so unsubscribing can take it
 	drop(context_lock.take().unwrap());
-	let mut rem = Vec::with_capacity(select.len() - 1);
-	for (i, select) in select.into_iter().enumerate() {
-		if i != i_ {
-			rem.push(select);
-			// } else {
-			// ret.1();
-			// select.run(&*context());
+	if block {
+		for (i, selectable) in select.iter().enumerate() {
+			// TODO: unsub should be before run
+			if i != i_ {
+				selectable.unsubscribe(thread::current());
+			}
 		}
 	}
-	rem.into_iter()
+	Some(i_)
 }
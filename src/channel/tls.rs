@@ -0,0 +1,274 @@
+//! TLS for channel connections to processes on other nodes, enabled with [`set_tls()`].
+//!
+//! Whether a connection is encrypted is agreed by [Connection](super::Connection) before anything else is sent on it; this runs a rustls session over it once it has been. Peers are known only by their IP address, which certificates can't be issued for, so instead every process's certificate is issued for the same DNS name, and signed by an authority the deployment trusts.
+
+use super::Transport;
+use rustls::{self, internal::pemfile, Session};
+use std::{
+	cmp, collections::VecDeque, fmt, fs, io::{self, Read, Write}, net, path, sync::{self, Arc}
+};
+use tcp_typed::Notifier;
+use webpki;
+
+/// How many bytes of plaintext are buffered each way, and of TLS records waiting to be written.
+pub const BUFFER: usize = 64 * 1024;
+
+lazy_static! {
+	static ref CONFIG: sync::RwLock<Option<(Config, net::SocketAddr)>> = sync::RwLock::new(None);
+}
+
+/// The certificates and key connections are encrypted with, loaded by [`Config::from_files()`].
+pub struct Config {
+	client: Arc<rustls::ClientConfig>,
+	server: Arc<rustls::ServerConfig>,
+	name: webpki::DNSName,
+}
+impl Config {
+	/// Load this process's certificate chain from the PEM file `cert`, and its private key (PKCS#8 or RSA) from `key`. The certificates of remotes are checked against the authorities in the PEM file `ca`, and must be issued for the DNS name `name`, as this process's must be.
+	pub fn from_files(
+		cert: &path::Path, key: &path::Path, ca: &path::Path, name: &str,
+	) -> Result<Self, io::Error> {
+		fn invalid(what: &path::Path) -> io::Error {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("{} isn't valid PEM", what.display()),
+			)
+		}
+		let read = |path: &path::Path| fs::File::open(path).map(io::BufReader::new);
+		let certs = pemfile::certs(&mut read(cert)?).map_err(|()| invalid(cert))?;
+		let mut keys = pemfile::pkcs8_private_keys(&mut read(key)?).map_err(|()| invalid(key))?;
+		if keys.is_empty() {
+			keys = pemfile::rsa_private_keys(&mut read(key)?).map_err(|()| invalid(key))?;
+		}
+		if certs.is_empty() || keys.is_empty() {
+			return Err(invalid(if certs.is_empty() { cert } else { key }));
+		}
+		let mut roots = rustls::RootCertStore::empty();
+		match roots.add_pem_file(&mut read(ca)?) {
+			Ok((valid, _)) if valid > 0 => (),
+			_ => return Err(invalid(ca)),
+		}
+		let name = webpki::DNSNameRef::try_from_ascii_str(name)
+			.map_err(|()| {
+				io::Error::new(
+					io::ErrorKind::InvalidInput,
+					format!("{:?} isn't a valid DNS name", name),
+				)
+			})?
+			.to_owned();
+		let mut client = rustls::ClientConfig::new();
+		client.root_store = roots.clone();
+		client.set_single_client_cert(certs.clone(), keys[0].clone());
+		let mut server = rustls::ServerConfig::new(rustls::AllowAnyAuthenticatedClient::new(roots));
+		server
+			.set_single_cert(certs, keys.swap_remove(0))
+			.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+		Ok(Self {
+			client: Arc::new(client),
+			server: Arc::new(server),
+			name,
+		})
+	}
+}
+
+/// Encrypt connections to and from processes on other nodes with `config`, other than connections to or from `exempt`, the bridge, which isn't given it. Must be called before any connections are made.
+pub fn set_tls(config: Config, exempt: net::SocketAddr) {
+	*CONFIG.write().unwrap() = Some((config, exempt));
+}
+
+/// Whether this process wants the connection between `local` and `remote` encrypted: if it's been given a [Config], and `remote` is on another node.
+pub fn wanted(local: &net::SocketAddr, remote: &net::SocketAddr) -> bool {
+	CONFIG.read().unwrap().as_ref().map_or(false, |&(_, exempt)| {
+		local.ip() != remote.ip() && *local != exempt && *remote != exempt
+	})
+}
+
+/// A rustls session run over a [Transport]. The plaintext sent and received on it is buffered by the [Connection](super::Connection).
+pub struct Tls {
+	session: Box<Session>,
+	/// How much the transport could buffer when the session started, to tell when what it's been given has been written.
+	capacity: usize,
+	/// Whether this process has closed the connection, whereupon a close_notify is sent after what's buffered.
+	local_closed: bool,
+	close_notified: bool,
+	/// Whether the remote has sent its close_notify.
+	remote_closed: bool,
+}
+impl Tls {
+	/// Start a session over `transport`, as the client if this process made the connection.
+	pub fn new(outgoing: bool, transport: &Transport) -> Self {
+		let config = CONFIG.read().unwrap();
+		let &(ref config, _) = config.as_ref().unwrap();
+		let mut session: Box<Session> = if outgoing {
+			Box::new(rustls::ClientSession::new(
+				&config.client,
+				config.name.as_ref(),
+			))
+		} else {
+			Box::new(rustls::ServerSession::new(&config.server))
+		};
+		session.set_buffer_limit(BUFFER);
+		Self {
+			session,
+			capacity: transport.send_avail().unwrap_or(0),
+			local_closed: false,
+			close_notified: false,
+			remote_closed: false,
+		}
+	}
+
+	/// Whether the TLS handshake is still under way, or what it sent is yet to be written.
+	pub fn handshaking(&self, transport: &Transport) -> bool {
+		self.session.is_handshaking() || self.unflushed(transport) > 0
+	}
+
+	/// How much of what's been encrypted is yet to be written to the transport's socket.
+	pub fn unflushed(&self, transport: &Transport) -> usize {
+		let unwritten = self
+			.capacity
+			.saturating_sub(transport.send_avail().unwrap_or(self.capacity));
+		unwritten + if self.session.wants_write() { 1 } else { 0 }
+	}
+
+	pub fn sendable(&self, transport: &Transport) -> bool {
+		transport.sendable() && !self.local_closed
+	}
+
+	/// Whether the remote has sent its close_notify.
+	pub fn remote_closed(&self) -> bool {
+		self.remote_closed
+	}
+
+	pub fn closable(&self, transport: &Transport) -> bool {
+		self.sendable(transport)
+	}
+
+	/// Close once what's been sent has been encrypted, sending a close_notify and then closing the transport.
+	pub fn close(&mut self) {
+		self.local_closed = true;
+	}
+
+	/// Move plaintext into the session, records between it and the transport, and plaintext out of it, until no more progress can be made. Returns the error if the session fails, after which the transport should be killed.
+	pub fn poll(
+		&mut self, transport: &mut Transport, send: &mut VecDeque<u8>, recv: &mut VecDeque<u8>,
+		notifier: &impl Notifier,
+	) -> Result<(), rustls::TLSError> {
+		loop {
+			if !transport.valid() {
+				// Failed or killed, which isn't the session's doing
+				break Ok(());
+			}
+			let mut progress = false;
+			if !self.session.is_handshaking() && !send.is_empty() {
+				let written = self.session.write(send.as_slices().0).unwrap();
+				let _ = send.drain(..written);
+				progress |= written > 0;
+			}
+			if self.local_closed
+				&& send.is_empty()
+				&& !self.session.is_handshaking()
+				&& !self.close_notified
+			{
+				self.session.send_close_notify();
+				self.close_notified = true;
+			}
+			while self.session.wants_write() {
+				match self.session.write_tls(&mut Writer(transport, notifier)) {
+					Ok(0) => break,
+					Ok(_) => progress = true,
+					Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+					Err(err) => panic!("{:?}", err),
+				}
+			}
+			if self.close_notified && !self.session.wants_write() && transport.closable() {
+				transport.close(notifier).unwrap()();
+			}
+			let mut eof = false;
+			while self.session.wants_read() {
+				match self.session.read_tls(&mut Reader(transport, notifier)) {
+					Ok(0) => {
+						eof = true;
+						break;
+					}
+					Ok(_) => progress = true,
+					Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+					Err(err) => panic!("{:?}", err),
+				}
+				self.session.process_new_packets()?;
+			}
+			let mut buf = [0; 4096];
+			while !self.remote_closed && recv.len() < BUFFER {
+				let len = cmp::min(buf.len(), BUFFER - recv.len());
+				match self.session.read(&mut buf[..len]) {
+					Ok(0) => {
+						if eof {
+							// Closed without a close_notify, so what was received may have been truncated
+							return Err(rustls::TLSError::General(String::from(
+								"connection closed without a close_notify",
+							)));
+						}
+						break;
+					}
+					Ok(read) => {
+						recv.extend(&buf[..read]);
+						progress = true;
+					}
+					Err(ref err) if err.kind() == io::ErrorKind::ConnectionAborted => {
+						self.remote_closed = true;
+						progress = true;
+					}
+					Err(err) => panic!("{:?}", err),
+				}
+			}
+			if !progress {
+				break Ok(());
+			}
+			transport.poll(notifier);
+		}
+	}
+}
+impl fmt::Debug for Tls {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Tls")
+			.field("handshaking", &self.session.is_handshaking())
+			.field("local_closed", &self.local_closed)
+			.field("remote_closed", &self.remote_closed)
+			.finish()
+	}
+}
+
+/// Writes records onto a [Transport], as far as it has room.
+struct Writer<'a, N: Notifier + 'a>(&'a mut Transport, &'a N);
+impl<'a, N: Notifier> Write for Writer<'a, N> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let avail = cmp::min(self.0.send_avail().unwrap_or(0), buf.len());
+		if avail == 0 && !buf.is_empty() {
+			return Err(io::ErrorKind::WouldBlock.into());
+		}
+		for &byte in &buf[..avail] {
+			self.0.send(self.1).unwrap()(byte);
+		}
+		Ok(avail)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Reads records from a [Transport], as far as they've arrived.
+struct Reader<'a, N: Notifier + 'a>(&'a mut Transport, &'a N);
+impl<'a, N: Notifier> Read for Reader<'a, N> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let avail = match self.0.recv_avail() {
+			Some(avail) => cmp::min(avail, buf.len()),
+			None => return Ok(0),
+		};
+		if avail == 0 && !buf.is_empty() {
+			return Err(io::ErrorKind::WouldBlock.into());
+		}
+		for byte in &mut buf[..avail] {
+			*byte = self.0.recv(self.1).unwrap()();
+		}
+		Ok(avail)
+	}
+}
@@ -0,0 +1,307 @@
+//! Connections between processes on the same node over unix domain sockets, rather than through the TCP stack over loopback.
+//!
+//! They're made transparently by [Connection](super::Connection) where the remote has the same IP as this process, and fall back to TCP where the remote isn't listening on a [Listener]. Channels are keyed by the address of each process's TCP listener, which a unix domain socket has nothing to stand in for, so the connecting process sends it first.
+
+use platform::{Native, Platform};
+use std::{
+	cmp, collections::VecDeque, io::{self, Read, Write}, mem, net, os::unix::{
+		io::{AsRawFd, FromRawFd}, net::{UnixListener, UnixStream}
+	}
+};
+use tcp_typed::Notifier;
+
+/// How many bytes are buffered each way, on top of the socket's own buffers.
+const BUFFER: usize = 64 * 1024;
+
+/// The length of an address sent by [`encode_addr()`].
+const ADDR_LEN: usize = 19;
+
+fn encode_addr(addr: &net::SocketAddr) -> [u8; ADDR_LEN] {
+	let mut bytes = [0; ADDR_LEN];
+	match addr.ip() {
+		net::IpAddr::V4(ip) => {
+			bytes[0] = 4;
+			bytes[1..5].copy_from_slice(&ip.octets());
+		}
+		net::IpAddr::V6(ip) => {
+			bytes[0] = 6;
+			bytes[1..17].copy_from_slice(&ip.octets());
+		}
+	}
+	bytes[17] = (addr.port() >> 8) as u8;
+	bytes[18] = addr.port() as u8;
+	bytes
+}
+
+fn decode_addr(bytes: &[u8; ADDR_LEN]) -> Option<net::SocketAddr> {
+	let ip = match bytes[0] {
+		4 => net::IpAddr::V4(net::Ipv4Addr::new(bytes[1], bytes[2], bytes[3], bytes[4])),
+		6 => {
+			let mut octets = [0; 16];
+			octets.copy_from_slice(&bytes[1..17]);
+			net::IpAddr::V6(net::Ipv6Addr::from(octets))
+		}
+		_ => return None,
+	};
+	let port = u16::from(bytes[17]) << 8 | u16::from(bytes[18]);
+	Some(net::SocketAddr::new(ip, port))
+}
+
+/// Listens for connections from processes on the same node, alongside the TCP listener.
+pub struct Listener {
+	listener: UnixListener,
+	/// Connections accepted that are yet to send the address of their TCP listener, with what of it has arrived.
+	pending: Vec<(UnixStream, [u8; ADDR_LEN], usize)>,
+}
+impl Listener {
+	/// Listen for connections to `local`, the address of this process's TCP listener. Returns `None` if that fails, in which case processes on the same node connect over TCP.
+	pub fn new(local: net::SocketAddr, backlog: usize, notifier: &impl Notifier) -> Option<Self> {
+		let fd = Native::channel_unix_listener(local, backlog)
+			.map_err(|err| warn!("Failed to listen on a unix domain socket, so channels from this node will use TCP: {}", err))
+			.ok()?;
+		notifier.add_fd(fd);
+		Some(Self {
+			listener: unsafe { UnixListener::from_raw_fd(fd) },
+			pending: Vec::new(),
+		})
+	}
+
+	/// Accept what connections have arrived, returning those whose remote has sent its address.
+	pub fn poll(&mut self, notifier: &impl Notifier) -> Vec<(net::SocketAddr, Accepted)> {
+		loop {
+			match Native::channel_unix_accept(self.listener.as_raw_fd()) {
+				Ok(fd) => {
+					notifier.add_fd(fd);
+					self.pending
+						.push((unsafe { UnixStream::from_raw_fd(fd) }, [0; ADDR_LEN], 0));
+				}
+				Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+					warn!("Refused a channel connection from a process of another user")
+				}
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+				Err(err) => panic!("{:?}", err),
+			}
+		}
+		let mut accepted = Vec::new();
+		for (mut stream, mut addr, mut len) in mem::replace(&mut self.pending, Vec::new()) {
+			let done = loop {
+				match stream.read(&mut addr[len..]) {
+					Ok(0) => break Some(None),
+					Ok(read) => {
+						len += read;
+						if len == ADDR_LEN {
+							break Some(decode_addr(&addr));
+						}
+					}
+					Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+					Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break None,
+					Err(_) => break Some(None),
+				}
+			};
+			match done {
+				None => self.pending.push((stream, addr, len)),
+				Some(remote) => {
+					notifier.remove_fd(stream.as_raw_fd());
+					// Closed, or what was sent isn't an address, so it's dropped
+					if let Some(remote) = remote {
+						accepted.push((remote, Accepted(stream)));
+					}
+				}
+			}
+		}
+		accepted
+	}
+
+	pub fn close(self, notifier: &impl Notifier) {
+		for (stream, _, _) in &self.pending {
+			notifier.remove_fd(stream.as_raw_fd());
+		}
+		notifier.remove_fd(self.listener.as_raw_fd());
+	}
+}
+
+/// A connection accepted by a [Listener], to be registered under the channel it's for.
+pub struct Accepted(UnixStream);
+impl Accepted {
+	pub fn connection(self, notifier: &impl Notifier) -> Connection {
+		Connection::new(self.0, Vec::new(), notifier)
+	}
+}
+
+/// A connection to a process on the same node, with the methods of [`tcp_typed::Connection`] that channels use.
+#[derive(Debug)]
+pub struct Connection {
+	/// `None` once the connection has been killed, failed, or finished closing.
+	stream: Option<UnixStream>,
+	send: VecDeque<u8>,
+	recv: VecDeque<u8>,
+	/// Whether the address sent first by the connecting process is yet to be written, so that what's sent after can be told to have been flushed.
+	connecting: bool,
+	/// Whether this process has closed the connection, whereupon its sending half is shut down once `send` has been written.
+	local_closed: bool,
+	shut_down: bool,
+	/// Whether the remote has shut down its sending half.
+	remote_closed: bool,
+	killed: bool,
+}
+impl Connection {
+	/// Connect to `remote`'s [Listener], or `None` if it isn't listening on one.
+	pub fn connect(
+		local: net::SocketAddr, remote: net::SocketAddr, notifier: &impl Notifier,
+	) -> Option<Self> {
+		let fd = Native::channel_unix_connect(remote).ok()?;
+		let stream = unsafe { UnixStream::from_raw_fd(fd) };
+		Some(Self::new(stream, encode_addr(&local).to_vec(), notifier))
+	}
+
+	fn new(stream: UnixStream, send: Vec<u8>, notifier: &impl Notifier) -> Self {
+		notifier.add_fd(stream.as_raw_fd());
+		let mut connection = Self {
+			stream: Some(stream),
+			connecting: !send.is_empty(),
+			send: send.into(),
+			recv: VecDeque::new(),
+			local_closed: false,
+			shut_down: false,
+			remote_closed: false,
+			killed: false,
+		};
+		connection.poll(notifier);
+		connection
+	}
+
+	/// Whether the address is still being sent: connecting a unix domain socket otherwise succeeds or fails immediately.
+	pub fn connecting(&self) -> bool {
+		self.connecting && !self.killed
+	}
+
+	pub fn valid(&self) -> bool {
+		!self.killed
+	}
+
+	pub fn sendable(&self) -> bool {
+		!self.killed && !self.local_closed
+	}
+
+	pub fn send_avail(&self) -> Option<usize> {
+		if self.sendable() {
+			Some(BUFFER.saturating_sub(self.send.len()))
+		} else {
+			None
+		}
+	}
+
+	pub fn send<'a>(&'a mut self, _notifier: &'a impl Notifier) -> Option<impl FnOnce(u8) + 'a> {
+		if self.send_avail().map_or(false, |avail| avail > 0) {
+			Some(move |byte| self.send.push_back(byte))
+		} else {
+			None
+		}
+	}
+
+	/// Whether there may be more to receive: until the remote has closed and all it sent has been received.
+	pub fn recvable(&self) -> bool {
+		!self.killed && !(self.remote_closed && self.recv.is_empty())
+	}
+
+	pub fn recv_avail(&self) -> Option<usize> {
+		if self.recvable() {
+			Some(self.recv.len())
+		} else {
+			None
+		}
+	}
+
+	pub fn recv<'a>(&'a mut self, _notifier: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
+		if self.recv_avail().map_or(false, |avail| avail > 0) {
+			Some(move || self.recv.pop_front().unwrap())
+		} else {
+			None
+		}
+	}
+
+	pub fn closable(&self) -> bool {
+		self.sendable()
+	}
+
+	/// Close the sending half, once what's been sent has been written.
+	pub fn close<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		if self.closable() {
+			Some(move || {
+				self.local_closed = true;
+				self.poll(notifier)
+			})
+		} else {
+			None
+		}
+	}
+
+	/// Whether both halves have been closed, and all that was sent written.
+	pub fn closed(&self) -> bool {
+		!self.killed && self.stream.is_none()
+	}
+
+	pub fn kill<'a>(&'a mut self, notifier: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		if !self.killed {
+			Some(move || self.finish(notifier, true))
+		} else {
+			None
+		}
+	}
+
+	/// Write what's been sent and read what's arrived, as far as there's room.
+	pub fn poll(&mut self, notifier: &impl Notifier) {
+		if self.stream.is_none() {
+			return;
+		}
+		if let Err(_) = self.write().and_then(|()| self.read()) {
+			return self.finish(notifier, true);
+		}
+		if self.shut_down && self.remote_closed {
+			self.finish(notifier, false);
+		}
+	}
+
+	fn write(&mut self) -> Result<(), io::Error> {
+		let stream = self.stream.as_mut().unwrap();
+		while !self.send.is_empty() {
+			let written = match stream.write(self.send.as_slices().0) {
+				Ok(written) => written,
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+				Err(err) => return Err(err),
+			};
+			let _ = self.send.drain(..written);
+		}
+		self.connecting = false;
+		if self.local_closed && !self.shut_down {
+			stream.shutdown(net::Shutdown::Write)?;
+			self.shut_down = true;
+		}
+		Ok(())
+	}
+
+	fn read(&mut self) -> Result<(), io::Error> {
+		let stream = self.stream.as_mut().unwrap();
+		let mut buf = [0; 4096];
+		while !self.remote_closed && self.recv.len() < BUFFER {
+			let len = cmp::min(buf.len(), BUFFER - self.recv.len());
+			match stream.read(&mut buf[..len]) {
+				Ok(0) => self.remote_closed = true,
+				Ok(read) => self.recv.extend(&buf[..read]),
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+				Err(err) => return Err(err),
+			}
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self, notifier: &impl Notifier, killed: bool) {
+		if let Some(stream) = self.stream.take() {
+			notifier.remove_fd(stream.as_raw_fd());
+		}
+		self.killed = killed;
+	}
+}
+
@@ -8,6 +8,60 @@
 //!  * **Channels:** [Sender]s and [Receiver]s can be used for synchronous or asynchronous inter-process communication.
 //!
 //! The only requirement to use is that [`init()`](init) must be called immediately inside your application's `main()` function.
+//!
+//! Every process reports its output, spawns and exit to a bridge, which passes them on to the terminal. A program that spawns a great many processes can spread this work with [`spawn_relay()`](spawn_relay): the processes spawned through the [Relay] it returns report to it instead, and it passes their events on in turn.
+//!
+//! # Configuration
+//!
+//! The runtime is configured by env vars, read by [`init()`](init). Spawned processes inherit them along with the rest of the environment.
+//!
+//! Setting the env var `CONSTELLATION_DEADLOCK_TIMEOUT` to a number of seconds enables a watchdog that warns, listing the pids still running, when no process has spawned, output or exited for that long.
+//!
+//! Setting the env var `CONSTELLATION_LIVENESS_PORT` to a port number has the bridge listen on it and answer each connection with a one-line JSON summary of the processes of the deployment: `{"alive":2,"exited":5,"failed":1}`. This suits readiness and liveness probes. It's off by default. It listens on the loopback address, so only local processes can query it, unless the env var `CONSTELLATION_LIVENESS_HOST` is set to another address to listen on, like `0.0.0.0` for all interfaces.
+//!
+//! A spawned process that panics reports the panic message and a backtrace, which is shown attributed to its pid. The usual panic output to stderr is suppressed if the env var `CONSTELLATION_PANIC_CAPTURE` is set to `1`.
+//!
+//! Setting the env var `CONSTELLATION_SOCKET_BUFFER` to a number of bytes sets the kernel's send and receive buffer sizes (`SO_SNDBUF` and `SO_RCVBUF`) for the connections each process accepts, which can improve throughput of channels carrying a lot of data. It's limited by `net.core.rmem_max` and `net.core.wmem_max`; a warning is logged if the kernel clamps it. Connections a process makes, rather than accepts, keep the default sizes.
+//!
+//! The connections each process accepts have `TCP_NODELAY` set, so small messages are sent immediately rather than delayed to be batched. Setting the env var `CONSTELLATION_NODELAY` to `0` opts back into batching (Nagle's algorithm), which can suit bulk transfers.
+//!
+//! Channel connections have TCP keepalive enabled, so that if the machine of the process at the other end dies without closing them, channel operations fail with [`ChannelError::Error`], as when the process itself is killed, rather than blocking indefinitely. ([`ChannelError::Exited`] is reserved for processes that exited cleanly, which a dead machine can't be told apart from.) Probes are sent after a connection has been idle for 60 seconds, then every 10 seconds, and it fails after 6 go unanswered, so a dead peer is noticed within 2 minutes. The env vars `CONSTELLATION_KEEPALIVE_IDLE`, `CONSTELLATION_KEEPALIVE_INTERVAL` (both in seconds) and `CONSTELLATION_KEEPALIVE_COUNT` tune these; setting `CONSTELLATION_KEEPALIVE_IDLE` to `0` disables keepalive. The idle time and interval can be at most 32767 seconds, and the count at most 127.
+//!
+//! Setting the env var `CONSTELLATION_RECCE_TREE` to `1` records the process tree rather than running it, so a scheduler can work out the capacity it needs before launching. [`spawn()`](spawn) and its variants record the requested [Resources] and return a placeholder [Pid] without creating a process. Instead what the process would have run is run on a thread of the initial process, so that what it would spawn is recorded too, up until it finishes or creates its first [Sender] or [Receiver], as there's no process to communicate with. [`pid()`](pid) there returns the initial process's. The initial process's code likewise runs until it exits or creates its first channel, whereupon, once the others have also got that far, its [Resources] and those recorded are written as a bincode-serialized [RecceManifest] to fd 3. [`RecceManifest::total()`](RecceManifest::total) gives the aggregate footprint. Processes spawned with [`spawn_exec()`](spawn_exec) are recorded, but not what they would spawn.
+//!
+//! Each process listens for connections from the processes that want to communicate with it, and the bridge for those from `deploy`. Setting the env var `CONSTELLATION_LISTEN_BACKLOG` to a number sets how many not-yet-accepted connections each listener queues before refusing more; it defaults to 100. It should be at least the number of peers expected to connect to a single process at once, for example when many spawned processes all open a channel to their parent as they start. It's capped by `net.core.somaxconn`.
+//!
+//! Channels between processes on the same node are carried over unix domain sockets rather than TCP over loopback, which saves the work of the TCP stack. This is transparent: each process also listens on one, with the same backlog, and a process connecting to another with the same IP falls back to TCP if it isn't listening there. Connections with the bridge are always over TCP. The socket options above don't apply to them.
+//!
+//! [`select()`](select) picks at random among the progressable [Selectable] objects, so a program's behaviour can differ between runs. Setting the env var `CONSTELLATION_SELECT_SEED` to a number seeds the random choices of each thread of each process, so that given the same progressable objects in the same order, the same choices are made and a run can be replayed. This only makes each select deterministic: which objects are progressable still depends on the timing of processes and the network, so it doesn't make a distributed program as a whole deterministic.
+//!
+//! Any process that can reach a process's listener can open a channel to it. Setting the env var `CONSTELLATION_CHANNEL_TOKEN` to 32 hex digits (a 128-bit key) requires the process at the other end of each connection to prove it was given the same token before anything is sent or received on it; otherwise channels fail with [`ChannelError::Mismatch`]. Spawned processes inherit it along with the rest of the environment. The token itself is never sent, but the messages after the handshake aren't encrypted, so it protects against processes that shouldn't be talking to the application rather than against eavesdropping. Connections with the bridge aren't authenticated.
+//!
+//! With the `tls` feature, setting the env vars `CONSTELLATION_TLS_CERT`, `CONSTELLATION_TLS_KEY` and `CONSTELLATION_TLS_CA` to the paths of PEM files encrypts channels to processes on other nodes with TLS: each end presents the certificate chain and private key it was given, and checks the other's against the authorities in the CA file. Processes are known by address rather than by name, so every process's certificate must be issued for the same DNS name, `constellation` unless `CONSTELLATION_TLS_NAME` says otherwise. Both ends of each connection agree whether to encrypt it before anything is sent on it; if only one was given a certificate, channels over it fail with [`ChannelError::Tls`]. Channels between processes on the same node, and with the bridge, aren't encrypted.
+//!
+//! Spawns from a process are made one at a time. When deployed, setting the env var `CONSTELLATION_SPAWN_CONCURRENCY` to a number allows up to that many at once, which lets the copies of the binary to the nodes overlap. Each uses its own connection to the scheduler. When run natively spawns are always made one at a time, as each forks this process. See `examples/spawn_throughput.rs` to measure the difference it makes.
+//!
+//! Output to stdout and stderr is forwarded in chunks of up to 16 KiB, coalescing what's written within 10ms of the first write. The env var `CONSTELLATION_OUTPUT_BUFFER` sets the chunk size in bytes.
+//!
+//! The `mem` in [Resources] isn't enforced by default when run natively. Setting the env var `CONSTELLATION_MEM_LIMIT` to `1` limits the data segment (`RLIMIT_DATA`) of each spawned process to it, so that a runaway process fails to allocate rather than exhausting the machine. A process that fails to allocate this way exits with [`ExitStatus`] `MemoryLimit`. It's opt-in as some processes legitimately map more than they use.
+//!
+//! When deployed, each process's binary is removed once it exits (on Linux and the BSDs it's only ever in memory). Setting the env var `CONSTELLATION_KEEP_BINARY` to `1` keeps it for post-mortem debugging, for example alongside a core dump: where it's in memory it's copied to `constellation-<os pid>` in the node's temporary directory. Kept binaries are never cleaned up, so this leaks a copy per process to disk; it's meant for debugging rather than production.
+//!
+//! The human output format is colored when stderr is a terminal, unless the env var `NO_COLOR` is set. The env var `CONSTELLATION_COLOR` overrides this: `always`, `never`, `auto`, or `256` or `truecolor` to force a palette.
+//!
+//! Output of processes that isn't valid UTF-8 is written by the human output format with the invalid bytes replaced with U+FFFD, so binary output doesn't garble the terminal. The env var `CONSTELLATION_OUTPUT_ENCODING` can instead be set to `escape`, which writes the invalid bytes and control characters other than newline and tab as `\xNN` hex escapes, or `raw`, which writes output as it is.
+//!
+//! When deployed, connecting to the scheduler times out after 10 seconds, so that an unresponsive scheduler doesn't hang startup. The env var `CONSTELLATION_SCHEDULER_TIMEOUT` sets this in seconds. It also applies to the further connections made for `CONSTELLATION_SPAWN_CONCURRENCY`.
+//!
+//! # Teardown
+//!
+//! The runtime is torn down by an `atexit` handler. [`init_with_shutdown()`](init_with_shutdown) instead tears it down when a guard is dropped. Tearing down closes all channels and waits for what has been sent on them to be delivered, so a process can [`send()`](Sender::send) and then return without the message being lost. As this also waits for the remote processes to close their ends, it gives up after 10 seconds.
+//!
+//! # Testing
+//!
+//! The `test-transport` feature runs everything within one OS process, for unit tests of code built on this crate. [`spawn()`](spawn) runs the closure on a new thread with its own [Pid] rather than creating a process, and [Sender]s and [Receiver]s pass messages through in-memory queues rather than sockets. Messages are still serialized, so types that wouldn't survive being sent between processes fail here too. A spawned closure that panics exits as a process whose main thread panicked would.
+//!
+//! As a process is a thread here, [`pid()`](pid) and channels only work on the thread that called [`init()`](init) or that runs a spawned closure. [`wait()`](wait) and [`children()`](children) are shared by all of them. [`set_child_init()`](set_child_init) isn't run, nothing is forwarded to a bridge, and spawning a different executable, placing a process on a node, and passing fds aren't supported.
 
 #![doc(html_root_url = "https://docs.rs/constellation-rs/0.1.4")]
 #![feature(
@@ -19,6 +73,7 @@
 	pin,
 	unboxed_closures,
 	fnbox,
+	alloc_error_hook,
 	try_from,
 	never_type
 )]
@@ -48,6 +103,7 @@
 )]
 
 extern crate atty;
+extern crate backtrace;
 extern crate bincode;
 extern crate constellation_internal;
 extern crate either;
@@ -62,6 +118,12 @@ extern crate serde;
 extern crate serde_json;
 extern crate serde_pipe;
 extern crate tcp_typed;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate webpki;
+#[cfg(feature = "compression")]
+extern crate zstd;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
@@ -72,26 +134,33 @@ extern crate lazy_static;
 extern crate log;
 
 mod channel;
+mod platform;
+
+#[cfg(not(feature = "test-transport"))]
+use channel as transport;
+#[cfg(feature = "test-transport")]
+use channel::memory as transport;
 
 use constellation_internal::{
-	map_bincode_err, BufferedStream, Deploy, DeployOutputEvent, Envs, ExitStatus, Format, Formatter, PidInternal, ProcessInputEvent, ProcessOutputEvent, StyleSupport
+	bincode_config, bytes_hash, file_hash, is_descendant, map_bincode_err, retry_eintr, BufferedStream, Color, Deploy, DeployOutputEvent, Envs, ExitStatusError, Format, Formatter, Liveness, NodeIdInternal, OutputEncoding, PidInternal, ProcessInputEvent, ProcessOutputEvent, ResourceUsage, SpawnFailure, StyleSupport, Watchdog, EXIT_MEM_LIMIT, RESOURCES_QUERY
 };
 use either::Either;
 use nix::{
-	errno, fcntl, libc, sys::{
-		signal, socket::{self, sockopt}, stat, wait
+	errno, fcntl, libc, poll, sys::{
+		signal, stat, statvfs, wait
 	}, unistd
 };
 use palaver::{
-	copy_sendfile, fexecve, is_valgrind, memfd_create, socket, spawn as thread_spawn, valgrind_start_fd, SockFlag
+	copy_sendfile, is_valgrind, memfd_create, spawn as thread_spawn, valgrind_start_fd
 };
-use proc_self::{exe, exe_path, fd_path, FdIter};
+use platform::Platform;
+use proc_self::{exe, exe_path, fd_path};
 use std::{
-	alloc, borrow, cell, convert::TryInto, ffi::{CString, OsString}, fmt, fs, intrinsics, io::{self, Read, Write}, iter, marker, mem, net, ops, os::{
+	alloc, borrow, boxed::FnBox, cell, collections::{HashMap, VecDeque}, convert::TryInto, error, ffi::{CString, OsString}, fmt, fs, intrinsics, io::{self, Read, Write}, iter, marker, mem, net, ops, os::{
 		self, unix::{
 			ffi::OsStringExt, io::{AsRawFd, FromRawFd, IntoRawFd}
 		}
-	}, path, process, str, sync::{self, mpsc}, thread
+	}, panic, path, process, rc, str, sync::{self, mpsc}, thread, time
 };
 
 #[cfg(target_family = "unix")]
@@ -100,7 +169,66 @@ type Fd = os::unix::io::RawFd;
 type Fd = os::windows::io::RawHandle;
 
 pub use channel::{ChannelError, Selectable};
-pub use constellation_internal::{Pid, Resources, RESOURCES_DEFAULT};
+pub use constellation_internal::{
+	ExitStatus, NodeId, ParsePidError, ParseResourcesError, Pid, RecceManifest, Resources, RESOURCES_DEFAULT
+};
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Block on multiple channel operations until one can proceed, without boxing.
+///
+/// Each arm is either `recv(receiver) -> pattern => body`, where the pattern is matched against the `Result<T, ChannelError>` received, or `send(sender, value) => body`, where `value` is only evaluated if this arm is the one picked. As with [`select()`](select), of those that can proceed, one is picked at random. The value of the `select!` is that of the body of the arm executed.
+///
+/// ```ignore
+/// select! {
+/// 	recv(receiver) -> x => println!("received {:?}", x.unwrap()),
+/// 	send(sender, 1234) => println!("sent"),
+/// }
+/// ```
+#[macro_export]
+macro_rules! select {
+	(@parse [$($acc:tt)*] recv($r:expr) -> $x:pat => $body:block , $($rest:tt)*) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$r.selectable_recv(|t| *slot.borrow_mut() = ::std::option::Option::Some(t))} ($x) $body]] $($rest)*)
+	};
+	(@parse [$($acc:tt)*] recv($r:expr) -> $x:pat => $body:block $($rest:tt)*) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$r.selectable_recv(|t| *slot.borrow_mut() = ::std::option::Option::Some(t))} ($x) $body]] $($rest)*)
+	};
+	(@parse [$($acc:tt)*] recv($r:expr) -> $x:pat => $body:expr , $($rest:tt)*) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$r.selectable_recv(|t| *slot.borrow_mut() = ::std::option::Option::Some(t))} ($x) {$body}]] $($rest)*)
+	};
+	(@parse [$($acc:tt)*] recv($r:expr) -> $x:pat => $body:expr) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$r.selectable_recv(|t| *slot.borrow_mut() = ::std::option::Option::Some(t))} ($x) {$body}]])
+	};
+	(@parse [$($acc:tt)*] send($s:expr, $v:expr) => $body:block , $($rest:tt)*) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$s.selectable_send(|| { *slot.borrow_mut() = ::std::option::Option::Some(()); $v })} (()) $body]] $($rest)*)
+	};
+	(@parse [$($acc:tt)*] send($s:expr, $v:expr) => $body:block $($rest:tt)*) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$s.selectable_send(|| { *slot.borrow_mut() = ::std::option::Option::Some(()); $v })} (()) $body]] $($rest)*)
+	};
+	(@parse [$($acc:tt)*] send($s:expr, $v:expr) => $body:expr , $($rest:tt)*) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$s.selectable_send(|| { *slot.borrow_mut() = ::std::option::Option::Some(()); $v })} (()) {$body}]] $($rest)*)
+	};
+	(@parse [$($acc:tt)*] send($s:expr, $v:expr) => $body:expr) => {
+		$crate::select!(@parse [$($acc)* [slot selectable {$s.selectable_send(|| { *slot.borrow_mut() = ::std::option::Option::Some(()); $v })} (()) {$body}]])
+	};
+	// Each `slot` and `selectable` above is from a different expansion, so hygiene keeps them distinct
+	(@parse [$([$slot:ident $selectable:ident $make:tt ($pat:pat) $body:block])*]) => {{
+		$(let $slot = ::std::cell::RefCell::new(::std::option::Option::None);)*
+		{
+			$(let mut $selectable = $make;)*
+			let _ = $crate::select_mut(&mut [$(&mut $selectable as &mut $crate::Selectable),*]);
+		}
+		$(if let ::std::option::Option::Some($pat) = $slot.into_inner() $body else)* {
+			unreachable!()
+		}
+	}};
+	(@parse $($invalid:tt)*) => {
+		compile_error!("select! arms must be `recv(receiver) -> pattern => body` or `send(sender, value) => body`")
+	};
+	($($arms:tt)*) => {
+		$crate::select!(@parse [] $($arms)*)
+	};
+}
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
@@ -108,43 +236,288 @@ const LISTENER_FD: Fd = 3; // from fabric
 const ARG_FD: Fd = 4; // from fabric
 const SCHEDULER_FD: Fd = 4;
 const MONITOR_FD: Fd = 5;
+const OUTPUT_BUFFER: usize = 16 * 1024;
+const OUTPUT_FLUSH_MS: u64 = 10;
+const SCHEDULER_BUFFER: usize = 256 * 1024;
+const MONITOR_FRAME_MAX: u64 = 64 * 1024; // panics are truncated to fit
+const SCHEDULER_TIMEOUT_SECS: u64 = 10;
+const KEEPALIVE_IDLE_SECS: u16 = 60;
+const KEEPALIVE_INTERVAL_SECS: u16 = 10;
+const KEEPALIVE_COUNT: u16 = 6;
+const KEEPALIVE_SECS_MAX: u16 = 32767; // Linux's limit on TCP_KEEPIDLE and TCP_KEEPINTVL
+const KEEPALIVE_COUNT_MAX: u16 = 127; // and on TCP_KEEPCNT
+const LISTEN_BACKLOG: usize = 100;
+const SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+const EXITED_CHILDREN: usize = 4096;
+#[cfg(feature = "compression")]
+const COMPRESS_THRESHOLD: usize = 4 * 1024;
 
 #[derive(Clone, Deserialize, Debug)]
 struct SchedulerArg {
 	scheduler: net::SocketAddr,
 }
 
+/// Connections to the scheduler. Requests on a connection are answered in order, so there's one per concurrent spawn, up to `limit`. When run natively there's no scheduler, and each is just `SCHEDULER_FD` as a placeholder.
+struct SchedulerPool {
+	idle: Vec<Fd>,
+	open: usize,
+	limit: usize,
+	addr: Option<net::SocketAddr>,
+	timeout: time::Duration,
+}
+/// A connection taken from the [SchedulerPool], that's returned to it on drop.
+struct SchedulerConnection(Fd);
+impl SchedulerConnection {
+	fn take() -> Result<Self, io::Error> {
+		let mut pool = SCHEDULER.0.lock().unwrap();
+		loop {
+			if let Some(fd) = pool.idle.pop() {
+				return Ok(SchedulerConnection(fd));
+			}
+			if pool.open < pool.limit {
+				pool.open += 1;
+				let (addr, timeout) = (pool.addr, pool.timeout);
+				drop(pool);
+				let fd = addr.map_or(Ok(SCHEDULER_FD), |addr| scheduler_connect(addr, timeout));
+				if fd.is_err() {
+					SCHEDULER.0.lock().unwrap().open -= 1;
+					SCHEDULER.1.notify_one();
+				}
+				return fd.map(SchedulerConnection);
+			}
+			pool = SCHEDULER.1.wait(pool).unwrap();
+		}
+	}
+}
+fn scheduler_connect(addr: net::SocketAddr, timeout: time::Duration) -> Result<Fd, io::Error> {
+	net::TcpStream::connect_timeout(&addr, timeout).map(IntoRawFd::into_raw_fd)
+}
+impl Drop for SchedulerConnection {
+	fn drop(&mut self) {
+		SCHEDULER.0.lock().unwrap().idle.push(self.0);
+		SCHEDULER.1.notify_one();
+	}
+}
+
+/// The processes spawned by this process, with the exit status of those that have exited but not been waited on. Only the most recent `EXITED_CHILDREN` exits are kept, so those never waited on don't accumulate.
+#[derive(Default)]
+struct Children {
+	children: HashMap<Pid, Option<ExitStatus>>,
+	/// Those that have exited, oldest first.
+	exited: VecDeque<Pid>,
+}
+impl Children {
+	fn get(&self, pid: &Pid) -> Option<&Option<ExitStatus>> {
+		self.children.get(pid)
+	}
+
+	fn spawned(&mut self, pid: Pid) {
+		// It may already have exited
+		let _ = self.children.entry(pid).or_insert(None);
+	}
+
+	fn exited(&mut self, pid: Pid, exit_code: ExitStatus) {
+		let _ = self.children.insert(pid, Some(exit_code));
+		self.exited.push_back(pid);
+		while self.exited.len() > EXITED_CHILDREN {
+			let pid = self.exited.pop_front().unwrap();
+			// Unless its pid has since been reused
+			if let Some(&Some(_)) = self.children.get(&pid) {
+				let _ = self.children.remove(&pid).unwrap();
+			}
+		}
+	}
+
+	fn remove(&mut self, pid: &Pid) {
+		let _ = self.children.remove(pid).unwrap();
+		self.exited.retain(|pid_| pid_ != pid);
+	}
+
+	fn running<'a>(&'a self) -> impl Iterator<Item = Pid> + 'a {
+		self.children
+			.iter()
+			.filter(|&(_, exit_code)| exit_code.is_none())
+			.map(|(&pid, _)| pid)
+	}
+}
+
 lazy_static! {
 	static ref BRIDGE: sync::RwLock<Option<Pid>> = sync::RwLock::new(None);
-	static ref SCHEDULER: sync::Mutex<()> = sync::Mutex::new(());
+	static ref SCHEDULER: (sync::Mutex<SchedulerPool>, sync::Condvar) = (
+		sync::Mutex::new(SchedulerPool {
+			idle: vec![SCHEDULER_FD],
+			open: 1,
+			limit: 1,
+			addr: None,
+			timeout: time::Duration::from_secs(SCHEDULER_TIMEOUT_SECS),
+		}),
+		sync::Condvar::new()
+	);
 	static ref DEPLOYED: sync::RwLock<Option<bool>> = sync::RwLock::new(None);
+	static ref MEM_LIMIT: sync::RwLock<bool> = sync::RwLock::new(false);
 	static ref REACTOR: sync::RwLock<Option<channel::Reactor>> = sync::RwLock::new(None);
 	static ref RESOURCES: sync::RwLock<Option<Resources>> = sync::RwLock::new(None);
 	static ref HANDLE: sync::RwLock<Option<channel::Handle>> = sync::RwLock::new(None);
+	static ref LOOPBACK: sync::RwLock<Option<net::IpAddr>> = sync::RwLock::new(None);
+	static ref CHILDREN: (sync::Mutex<Children>, sync::Condvar) =
+		(sync::Mutex::new(Children::default()), sync::Condvar::new());
+	static ref ON_EXIT: sync::Mutex<HashMap<Pid, Vec<Box<FnBox(ExitStatus) + Send>>>> =
+		sync::Mutex::new(HashMap::new());
+	static ref MONITOR: sync::Mutex<()> = sync::Mutex::new(());
+	static ref EXEC_PAYLOAD: sync::RwLock<Option<(Pid, Vec<u8>)>> = sync::RwLock::new(None);
+	static ref CHILD_INIT: sync::RwLock<Option<fn()>> = sync::RwLock::new(None);
+	/// Where [`recv_fd()`](recv_fd) accepts fds, or why listening for them failed.
+	static ref FD_LISTENER: sync::RwLock<Option<Result<Fd, io::ErrorKind>>> = sync::RwLock::new(None);
+	static ref RECCE: (sync::Mutex<Option<Recce>>, sync::Condvar) =
+		(sync::Mutex::new(None), sync::Condvar::new());
+	static ref PASSED_FDS: (sync::Mutex<PassedFds>, sync::Condvar) = (
+		sync::Mutex::new(PassedFds {
+			fds: HashMap::new(),
+			accepting: false
+		}),
+		sync::Condvar::new()
+	);
 }
+static METRICS: Counters = Counters {
+	spawned: sync::atomic::AtomicUsize::new(0),
+	exited: sync::atomic::AtomicUsize::new(0),
+	messages_sent: sync::atomic::AtomicUsize::new(0),
+	messages_received: sync::atomic::AtomicUsize::new(0),
+};
+/// The pipe to the monitor that [`mem_limit_hook()`](mem_limit_hook) writes to, or `-1` if this process isn't limited.
+static MEM_LIMIT_FD: sync::atomic::AtomicIsize = sync::atomic::AtomicIsize::new(-1);
 
+#[cfg(not(feature = "alloc-stats"))]
 #[global_allocator]
 static GLOBAL_ALLOCATOR: alloc::System = alloc::System;
+#[cfg(feature = "alloc-stats")]
+#[global_allocator]
+static GLOBAL_ALLOCATOR: TrackingAllocator = TrackingAllocator {
+	allocated: sync::atomic::AtomicUsize::new(0),
+	peak: sync::atomic::AtomicUsize::new(0),
+};
+
+/// Wraps the system allocator, counting the bytes currently allocated and the most there have been.
+#[cfg(feature = "alloc-stats")]
+struct TrackingAllocator {
+	allocated: sync::atomic::AtomicUsize,
+	peak: sync::atomic::AtomicUsize,
+}
+#[cfg(feature = "alloc-stats")]
+impl TrackingAllocator {
+	fn add(&self, size: usize) {
+		let allocated = self
+			.allocated
+			.fetch_add(size, sync::atomic::Ordering::Relaxed)
+			+ size;
+		let mut peak = self.peak.load(sync::atomic::Ordering::Relaxed);
+		while allocated > peak {
+			match self.peak.compare_exchange_weak(
+				peak,
+				allocated,
+				sync::atomic::Ordering::Relaxed,
+				sync::atomic::Ordering::Relaxed,
+			) {
+				Ok(_) => break,
+				Err(peak_) => peak = peak_,
+			}
+		}
+	}
+
+	fn sub(&self, size: usize) {
+		let _ = self
+			.allocated
+			.fetch_sub(size, sync::atomic::Ordering::Relaxed);
+	}
+}
+#[cfg(feature = "alloc-stats")]
+unsafe impl alloc::GlobalAlloc for TrackingAllocator {
+	unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+		let ptr = alloc::GlobalAlloc::alloc(&alloc::System, layout);
+		if !ptr.is_null() {
+			self.add(layout.size());
+		}
+		ptr
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: alloc::Layout) -> *mut u8 {
+		let ptr = alloc::GlobalAlloc::alloc_zeroed(&alloc::System, layout);
+		if !ptr.is_null() {
+			self.add(layout.size());
+		}
+		ptr
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
+		alloc::GlobalAlloc::dealloc(&alloc::System, ptr, layout);
+		self.sub(layout.size());
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: alloc::Layout, new_size: usize) -> *mut u8 {
+		let new_ptr = alloc::GlobalAlloc::realloc(&alloc::System, ptr, layout, new_size);
+		if !new_ptr.is_null() {
+			self.sub(layout.size());
+			self.add(new_size);
+		}
+		new_ptr
+	}
+}
+
+/// The bytes currently allocated on the heap by this process. Comparing it to the `mem` requested in [Resources] shows how close the process is to its limit.
+///
+/// Requires the `alloc-stats` feature, which counts allocations in the global allocator.
+#[cfg(feature = "alloc-stats")]
+pub fn allocated_bytes() -> usize {
+	GLOBAL_ALLOCATOR
+		.allocated
+		.load(sync::atomic::Ordering::Relaxed)
+}
+
+/// The most bytes that have been allocated on the heap by this process at any one time. See [`allocated_bytes()`](allocated_bytes).
+///
+/// Requires the `alloc-stats` feature.
+#[cfg(feature = "alloc-stats")]
+pub fn peak_allocated_bytes() -> usize {
+	GLOBAL_ALLOCATOR.peak.load(sync::atomic::Ordering::Relaxed)
+}
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 /// The sending half of a channel.
 ///
 /// It has a synchronous blocking method [`send()`](Sender::send) and an asynchronous nonblocking method [`selectable_send()`](Sender::selectable_send).
-pub struct Sender<T: serde::ser::Serialize>(Option<channel::Sender<T>>, Pid);
+///
+/// Messages are streamed: a sent value is held as it is and serialized a piece at a time as the connection can take it, and the [Receiver] deserializes it as the bytes arrive. So a large value is never buffered whole in serialized form, and sending one needs little more memory than the value itself.
+pub struct Sender<T: serde::ser::Serialize>(Option<transport::Sender<T>>, Pid);
 impl<T: serde::ser::Serialize> Sender<T> {
 	/// Create a new `Sender<T>` with a remote [Pid]. This method returns instantly.
 	pub fn new(remote: Pid) -> Self {
+		Self::new_(remote, None)
+	}
+
+	/// Create a new `Sender<T>` with a remote [Pid] whose [`send()`](Sender::send) applies backpressure at a fixed depth. This method returns instantly.
+	///
+	/// `send()` returns only once no more than `capacity` of the messages sent are yet to be received by the remote process with its [Receiver], so at most that many are ever in flight, however slowly they're received. A `capacity` of 0 is a rendezvous: `send()` returns once its message has been received. This is tracked with acknowledgements from the remote process, as with [`send_confirmed()`](Sender::send_confirmed).
+	///
+	/// The capacity applies to [`send()`](Sender::send), [`try_send()`](Sender::try_send) and [`send_all()`](Sender::send_all). [`selectable_send()`](Sender::selectable_send) doesn't wait for its message to be received, but isn't executable while more than `capacity` are in flight, so with it at most `capacity + 1` are. Writing to a `Sender<u8>` is bounded likewise.
+	pub fn with_capacity(remote: Pid, capacity: usize) -> Self {
+		Self::new_(remote, Some(capacity))
+	}
+
+	fn new_(remote: Pid, capacity: Option<usize>) -> Self {
+		recce_end();
 		if remote == pid() {
 			panic!("Sender::<{}>::new() called with process's own pid. A process cannot create a channel to itself.", unsafe{intrinsics::type_name::<T>()});
 		}
 		let context = REACTOR.read().unwrap();
-		if let Some(sender) = channel::Sender::new(
+		if let Some(sender) = transport::Sender::with_capacity(
 			remote.addr(),
+			capacity,
 			context.as_ref().unwrap_or_else(|| {
 				panic!("You must call init() immediately inside your application's main() function")
 			}),
 		) {
+			debug!(target: "constellation::channel", "new Sender<{}> to {}", unsafe { intrinsics::type_name::<T>() }, remote);
 			Sender(Some(sender), remote)
 		} else {
 			panic!(
@@ -160,24 +533,116 @@ impl<T: serde::ser::Serialize> Sender<T> {
 		self.1
 	}
 
-	fn async_send<'a>(&'a self) -> Option<impl FnOnce(T) + 'a>
+	/// Convert this into a [SharedSender], that can be cloned and sent from multiple threads at once.
+	pub fn into_shared(self) -> SharedSender<T> {
+		let remote = self.1;
+		SharedSender(sync::Arc::new(sync::Mutex::new(self)), remote)
+	}
+
+	/// Blocking send.
+	///
+	/// This returns once `t` has been queued to be sent, not once the remote process has received it: if the remote exits or the connection fails first, it's lost without an error here. [`flush()`](Sender::flush) waits only until it has been handed to the connection. Where delivery must be confirmed, use [`send_confirmed()`](Sender::send_confirmed).
+	pub fn send(&self, t: T)
+	where
+		T: 'static,
+	{
+		self.0.as_ref().unwrap().send(t, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		log_send(self.1);
+	}
+
+	/// Block until messages already sent have been written to the socket of the connection to the remote process, so that they aren't lost if this process then exits, or until the channel can no longer send.
+	pub fn flush(&self)
 	where
 		T: 'static,
 	{
-		let context = REACTOR.read().unwrap();
 		self.0
 			.as_ref()
 			.unwrap()
-			.async_send(BorrowMap::new(context, borrow_unwrap_option))
+			.flush(&mut || BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option));
 	}
 
-	/// Blocking send.
-	pub fn send(&self, t: T)
+	/// Block until messages already sent have been written to the socket of the connection to the remote process, then drop it. Returns an error if the remote process has exited or the channel has been killed.
+	///
+	/// This doesn't mean the remote process has received them, only that they've been handed to the OS, which delivers them even if this process then exits.
+	pub fn drain(self) -> Result<(), ChannelError>
 	where
 		T: 'static,
 	{
-		self.0.as_ref().unwrap().send(t, &mut || {
+		self.0
+			.as_ref()
+			.unwrap()
+			.drain(&mut || BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option))
+	}
+
+	/// Signal end-of-stream to the remote process without dropping this `Sender`, like a TCP half-close. Once the remote process's [Receiver] has received what was already sent, it returns [`ChannelError::Exited`], and reading from it fails with [`io::ErrorKind::UnexpectedEof`](std::io::ErrorKind::UnexpectedEof).
+	///
+	/// After closing, [`try_send()`](Sender::try_send) returns [`ChannelError::Exited`], and [`send()`](Sender::send) blocks forever. Receiving from the remote process is unaffected.
+	pub fn close(&self) {
+		let context = REACTOR.read().unwrap();
+		self.0.as_ref().unwrap().close(context.as_ref().unwrap());
+	}
+
+	/// Blocking send that returns an error, rather than blocking forever, if the remote process has exited or the channel has been killed.
+	pub fn try_send(&self, t: T) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		let ret = self.0.as_ref().unwrap().try_send(t, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		match ret {
+			Ok(()) => log_send(self.1),
+			Err(err) => {
+				debug!(target: "constellation::channel", "send to {} failed: {}", self.1, err)
+			}
+		}
+		ret
+	}
+
+	/// Blocking send that, unlike [`send()`](Sender::send), returns only once the remote process has received `t` with its [Receiver], and everything sent before it.
+	///
+	/// This takes a round trip to the remote process per message, so is for the occasional message that must be known to have arrived, like a control message, rather than for bulk data. The remote process's acknowledgement follows what it has already sent to this process, so for it to arrive this process's [Receiver] from it mustn't be left full.
+	///
+	/// Returns an error if the remote process has exited or closed its end, or the channel has been killed, before acknowledging `t`, in which case it may or may not have been received.
+	pub fn send_confirmed(&self, t: T) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		self.try_send(t)?;
+		let ret = self.0.as_ref().unwrap().acked(0, &mut || {
 			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		if let Err(err) = ret {
+			debug!(target: "constellation::channel", "send to {} unconfirmed: {}", self.1, err);
+		}
+		ret
+	}
+
+	/// Blocking send of each of `items` in turn, returning an error rather than blocking forever if the remote process has exited or the channel has been killed.
+	///
+	/// This is like calling [`try_send()`](Sender::try_send) on each item, but with less overhead per message. On error, [`SendAllError::sent`] says how many items were sent before it.
+	pub fn send_all<I: IntoIterator<Item = T>>(&self, items: I) -> Result<(), SendAllError>
+	where
+		T: 'static,
+	{
+		let taken = cell::Cell::new(0);
+		let ret = self.0.as_ref().unwrap().try_send_all(
+			items.into_iter().inspect(|_| taken.set(taken.get() + 1)),
+			&mut || BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option),
+		);
+		let sent = match ret {
+			Ok(()) => taken.get(),
+			Err((sent, _)) => sent,
+		};
+		let _ = METRICS
+			.messages_sent
+			.fetch_add(sent, sync::atomic::Ordering::Relaxed);
+		trace!(target: "constellation::channel", "sent {} to {}", sent, self.1);
+		ret.map_err(|(sent, error)| {
+			debug!(target: "constellation::channel", "send to {} failed after {}: {}", self.1, sent, error);
+			SendAllError { sent, error }
 		})
 	}
 
@@ -188,48 +653,79 @@ impl<T: serde::ser::Serialize> Sender<T> {
 	where
 		T: 'static,
 	{
-		self.0.as_ref().unwrap().selectable_send(send)
+		let remote = self.1;
+		self.0.as_ref().unwrap().selectable_send(move || {
+			let t = send();
+			log_send(remote);
+			t
+		})
+	}
+
+	/// [Selectable] send that's executed with an error, rather than blocking forever, if the remote process has exited or the channel has been killed. `result` is set once it's executed.
+	fn selectable_try_send<'a>(
+		&'a self, t: T, result: &'a cell::RefCell<Option<Result<(), ChannelError>>>,
+	) -> impl Selectable + 'a
+	where
+		T: 'static,
+	{
+		self.0.as_ref().unwrap().selectable_try_send(t, result)
 	}
 }
 
 #[doc(hidden)] // noise
 impl<T: serde::ser::Serialize> Drop for Sender<T> {
 	fn drop(&mut self) {
+		debug!(target: "constellation::channel", "drop Sender<{}> to {}", unsafe { intrinsics::type_name::<T>() }, self.1);
 		let context = REACTOR.read().unwrap();
 		self.0.take().unwrap().drop(context.as_ref().unwrap())
 	}
 }
+/// Nonblocking: `write()` sends as many bytes as it can without blocking, and returns [`WouldBlock`](io::ErrorKind::WouldBlock) if it can't send any. `write_all()`, and so `write!` and [`io::copy()`](std::io::copy), block until everything has been sent. `flush()` blocks as [`Sender::flush()`](Sender::flush) does. The remote process having exited is reported as [`BrokenPipe`](io::ErrorKind::BrokenPipe).
 impl<'a> Write for &'a Sender<u8> {
 	#[inline(always)]
 	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
 		if buf.is_empty() {
 			return Ok(0);
 		}
-		self.send(buf[0]);
-		if buf.len() == 1 {
-			return Ok(1);
-		}
-		for (i, buf) in (1..buf.len()).zip(buf[1..].iter().cloned()) {
-			if let Some(send) = self.async_send() {
-				send(buf);
-			} else {
-				return Ok(i);
+		let context = REACTOR.read().unwrap();
+		// As one message, rather than the message per byte that sending each would be
+		match self
+			.0
+			.as_ref()
+			.unwrap()
+			.try_send_bytes(buf, BorrowMap::new(context, borrow_unwrap_option))
+		{
+			Ok(0) => Err(io::ErrorKind::WouldBlock.into()),
+			Ok(sent) => {
+				count_send();
+				Ok(sent)
 			}
+			Err(e) => Err(write_error(e)),
 		}
-		Ok(buf.len())
 	}
 
-	#[inline(always)]
-	fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-		for &byte in buf {
-			self.send(byte);
+	fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+		while !buf.is_empty() {
+			let sent = match self.write(buf) {
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+					// Block until there's room, sending the first byte the slow way
+					self.try_send(buf[0]).map_err(write_error)?;
+					1
+				}
+				sent => sent?,
+			};
+			buf = &buf[sent..];
 		}
 		Ok(())
 	}
 
-	#[inline(always)]
 	fn flush(&mut self) -> io::Result<()> {
-		Ok(())
+		// Sender::flush(), but reporting why if the channel can no longer send
+		self.0
+			.as_ref()
+			.unwrap()
+			.drain(&mut || BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option))
+			.map_err(write_error)
 	}
 }
 impl Write for Sender<u8> {
@@ -248,11 +744,57 @@ impl Write for Sender<u8> {
 		(&*self).flush()
 	}
 }
+fn write_error(e: ChannelError) -> io::Error {
+	match e {
+		ChannelError::Exited => io::ErrorKind::BrokenPipe,
+		ChannelError::Error => io::ErrorKind::ConnectionReset,
+		ChannelError::Mismatch => io::ErrorKind::InvalidData,
+		ChannelError::Tls => io::ErrorKind::PermissionDenied,
+	}
+	.into()
+}
 impl<T: serde::ser::Serialize> fmt::Debug for Sender<T> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		self.0.fmt(f)
 	}
 }
+
+/// A [Sender] shared between threads, created by [`Sender::into_shared()`](Sender::into_shared).
+///
+/// Clones refer to the same underlying [Sender], which is dropped once they all have been. Sends from different threads are serialized by a mutex, so each message is sent whole, but in an arbitrary order relative to those of other threads. A thread blocked in [`send()`](SharedSender::send) blocks the others' sends until it completes.
+pub struct SharedSender<T: serde::ser::Serialize>(sync::Arc<sync::Mutex<Sender<T>>>, Pid);
+impl<T: serde::ser::Serialize> SharedSender<T> {
+	/// Get the pid of the remote end of this SharedSender
+	pub fn remote_pid(&self) -> Pid {
+		self.1
+	}
+
+	/// Blocking send. See [`Sender::send()`](Sender::send).
+	pub fn send(&self, t: T)
+	where
+		T: 'static,
+	{
+		self.0.lock().unwrap().send(t)
+	}
+
+	/// Blocking send that returns an error, rather than blocking forever, if the remote process has exited or the channel has been killed. See [`Sender::try_send()`](Sender::try_send).
+	pub fn try_send(&self, t: T) -> Result<(), ChannelError>
+	where
+		T: 'static,
+	{
+		self.0.lock().unwrap().try_send(t)
+	}
+}
+impl<T: serde::ser::Serialize> Clone for SharedSender<T> {
+	fn clone(&self) -> Self {
+		SharedSender(self.0.clone(), self.1)
+	}
+}
+impl<T: serde::ser::Serialize> fmt::Debug for SharedSender<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
 // impl<T: 'static + serde::ser::Serialize> futures::sink::Sink for Sender<Option<T>> {
 // 	type SinkError = !;
 // 	type SinkItem = T;
@@ -292,23 +834,69 @@ impl<T: serde::ser::Serialize> fmt::Debug for Sender<T> {
 // 	}
 // }
 
+/// Error returned by [`Sender::send_all()`](Sender::send_all).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SendAllError {
+	/// How many items were sent before the error.
+	pub sent: usize,
+	/// Why the next item couldn't be sent.
+	pub error: ChannelError,
+}
+impl fmt::Display for SendAllError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} after sending {} items", self.error, self.sent)
+	}
+}
+impl error::Error for SendAllError {
+	fn description(&self) -> &str {
+		error::Error::description(&self.error)
+	}
+
+	fn cause(&self) -> Option<&error::Error> {
+		Some(&self.error)
+	}
+}
+
+/// The outcome of [`Receiver::recv_event()`](Receiver::recv_event), distinguishing the remote having finished from the channel failing.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RecvEvent<T> {
+	/// A message was received.
+	Message(T),
+	/// The remote process has exited, or closed its end, having sent everything there is to receive.
+	Disconnected,
+	/// The channel failed: [`ChannelError::Error`], [`ChannelError::Mismatch`] or [`ChannelError::Tls`], never [`ChannelError::Exited`].
+	Failed(ChannelError),
+}
+impl<T> RecvEvent<T> {
+	/// Convert into the `Result` that [`Receiver::recv()`](Receiver::recv) returns.
+	pub fn into_result(self) -> Result<T, ChannelError> {
+		match self {
+			RecvEvent::Message(t) => Ok(t),
+			RecvEvent::Disconnected => Err(ChannelError::Exited),
+			RecvEvent::Failed(err) => Err(err),
+		}
+	}
+}
+
 /// The receiving half of a channel.
 ///
 /// It has a synchronous blocking method [`recv()`](Receiver::recv) and an asynchronous nonblocking method [`selectable_recv()`](Receiver::selectable_recv).
-pub struct Receiver<T: serde::de::DeserializeOwned>(Option<channel::Receiver<T>>, Pid);
+pub struct Receiver<T: serde::de::DeserializeOwned>(Option<transport::Receiver<T>>, Pid);
 impl<T: serde::de::DeserializeOwned> Receiver<T> {
 	/// Create a new `Receiver<T>` with a remote [Pid]. This method returns instantly.
 	pub fn new(remote: Pid) -> Self {
+		recce_end();
 		if remote == pid() {
 			panic!("Receiver::<{}>::new() called with process's own pid. A process cannot create a channel to itself.", unsafe{intrinsics::type_name::<T>()});
 		}
 		let context = REACTOR.read().unwrap();
-		if let Some(receiver) = channel::Receiver::new(
+		if let Some(receiver) = transport::Receiver::new(
 			remote.addr(),
 			context.as_ref().unwrap_or_else(|| {
 				panic!("You must call init() immediately inside your application's main() function")
 			}),
 		) {
+			debug!(target: "constellation::channel", "new Receiver<{}> from {}", unsafe { intrinsics::type_name::<T>() }, remote);
 			Receiver(Some(receiver), remote)
 		} else {
 			panic!(
@@ -324,26 +912,80 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 		self.1
 	}
 
-	fn async_recv<'a>(&'a self) -> Option<impl FnOnce() -> Result<T, ChannelError> + 'a>
+	fn drained(&self) -> bool {
+		self.0.as_ref().unwrap().drained()
+	}
+
+	/// Blocking receive.
+	pub fn recv(&self) -> Result<T, ChannelError>
 	where
 		T: 'static,
 	{
-		let context = REACTOR.read().unwrap();
-		self.0
+		self.recv_event().into_result()
+	}
+
+	/// Blocking receive, like [`recv()`](Receiver::recv), but returning a [RecvEvent] so that the remote being done can be matched apart from a failure:
+	///
+	/// ```ignore
+	/// loop {
+	/// 	match receiver.recv_event() {
+	/// 		RecvEvent::Message(t) => process(t),
+	/// 		RecvEvent::Disconnected => break,
+	/// 		RecvEvent::Failed(err) => panic!("{}", err),
+	/// 	}
+	/// }
+	/// ```
+	pub fn recv_event(&self) -> RecvEvent<T>
+	where
+		T: 'static,
+	{
+		let ret = self
+			.0
 			.as_ref()
 			.unwrap()
-			.async_recv(BorrowMap::new(context, borrow_unwrap_option))
+			.recv(&mut || BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option));
+		log_recv(&ret, self.1);
+		match ret {
+			Ok(t) => RecvEvent::Message(t),
+			Err(ChannelError::Exited) => RecvEvent::Disconnected,
+			Err(err) => RecvEvent::Failed(err),
+		}
 	}
 
-	/// Blocking receive.
-	pub fn recv(&self) -> Result<T, ChannelError>
+	/// Blocking receive, like [`recv()`](Receiver::recv), but deserializing the message with `seed` rather than as a `T`, for example to deserialize into an arena or through an interning table.
+	///
+	/// Each message is sent in a length-prefixed buffer, so `seed` is given only the message's bytes, and must consume them as `T`'s [`Deserialize`](serde::Deserialize) would. It must not be used on a receiver [`with_filter()`](Receiver::with_filter), as the filter is run on messages deserialized as `T`s.
+	pub fn recv_deserialize_with<S: serde::de::DeserializeSeed<'static>>(
+		&self, seed: S,
+	) -> Result<S::Value, ChannelError> {
+		let ret = self.0.as_ref().unwrap().recv_deserialize_with(seed, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		log_recv(&ret, self.1);
+		ret
+	}
+
+	/// Blocking receive of `n` messages.
+	///
+	/// This is like calling [`recv()`](Receiver::recv) `n` times, but with less overhead per message. If a receive fails, for example as the remote process exited early, the messages already received are returned with the error, so they aren't lost.
+	pub fn recv_all(&self, n: usize) -> Result<Vec<T>, (Vec<T>, ChannelError)>
 	where
 		T: 'static,
 	{
-		self.0
+		let ret = self.0.as_ref().unwrap().recv_all(n, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		let received = ret
 			.as_ref()
-			.unwrap()
-			.recv(&mut || BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option))
+			.map_or_else(|&(ref received, _)| received.len(), Vec::len);
+		let _ = METRICS
+			.messages_received
+			.fetch_add(received, sync::atomic::Ordering::Relaxed);
+		trace!(target: "constellation::channel", "received {} from {}", received, self.1);
+		if let Err((_, err)) = &ret {
+			debug!(target: "constellation::channel", "receive from {} failed after {}: {}", self.1, received, err);
+		}
+		ret
 	}
 
 	/// [Selectable] receive.
@@ -355,16 +997,52 @@ impl<T: serde::de::DeserializeOwned> Receiver<T> {
 	where
 		T: 'static,
 	{
-		self.0.as_ref().unwrap().selectable_recv(recv)
+		let remote = self.1;
+		self.0.as_ref().unwrap().selectable_recv(move |t| {
+			log_recv(&t, remote);
+			recv(t)
+		})
+	}
+
+	/// Adapt the values received with `f`, without an intermediate process. [ChannelError]s pass through unmapped.
+	pub fn map<U, F: Fn(T) -> U>(self, f: F) -> MappedReceiver<T, F> {
+		MappedReceiver(self, f)
 	}
 }
-#[doc(hidden)] // noise
-impl<T: serde::de::DeserializeOwned> Drop for Receiver<T> {
-	fn drop(&mut self) {
+fn log_send(remote: Pid) {
+	count_send();
+	trace!(target: "constellation::channel", "sent to {}", remote);
+}
+fn count_send() {
+	let _ = METRICS
+		.messages_sent
+		.fetch_add(1, sync::atomic::Ordering::Relaxed);
+}
+fn count_recv() {
+	let _ = METRICS
+		.messages_received
+		.fetch_add(1, sync::atomic::Ordering::Relaxed);
+}
+fn log_recv<T>(t: &Result<T, ChannelError>, remote: Pid) {
+	match *t {
+		Ok(_) => {
+			count_recv();
+			trace!(target: "constellation::channel", "received from {}", remote)
+		}
+		Err(err) => {
+			debug!(target: "constellation::channel", "receive from {} failed: {}", remote, err)
+		}
+	}
+}
+#[doc(hidden)] // noise
+impl<T: serde::de::DeserializeOwned> Drop for Receiver<T> {
+	fn drop(&mut self) {
+		debug!(target: "constellation::channel", "drop Receiver<{}> from {}", unsafe { intrinsics::type_name::<T>() }, self.1);
 		let context = REACTOR.read().unwrap();
 		self.0.take().unwrap().drop(context.as_ref().unwrap())
 	}
 }
+/// Blocking: `read()` blocks until at least one byte has arrived, then fills as much of the buffer as it can from what else has already arrived, in bulk. The remote process having exited is reported as [`UnexpectedEof`](io::ErrorKind::UnexpectedEof).
 impl<'a> Read for &'a Receiver<u8> {
 	#[inline(always)]
 	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -374,31 +1052,25 @@ impl<'a> Read for &'a Receiver<u8> {
 		buf[0] = self.recv().map_err(|e| match e {
 			ChannelError::Exited => io::ErrorKind::UnexpectedEof,
 			ChannelError::Error => io::ErrorKind::ConnectionReset,
+			ChannelError::Mismatch => io::ErrorKind::InvalidData,
+			ChannelError::Tls => io::ErrorKind::PermissionDenied,
 		})?;
-		if buf.len() == 1 {
-			return Ok(1);
-		}
-		for (i, buf) in (1..buf.len()).zip(buf[1..].iter_mut()) {
-			if let Some(recv) = self.async_recv() {
-				if let Ok(t) = recv() {
-					*buf = t;
-				} else {
-					return Ok(i);
-				}
-			} else {
-				return Ok(i);
-			}
-		}
-		Ok(buf.len())
+		// Then in bulk whatever else has already arrived
+		let context = REACTOR.read().unwrap();
+		let received = self
+			.0
+			.as_ref()
+			.unwrap()
+			.try_recv_bytes(&mut buf[1..], BorrowMap::new(context, borrow_unwrap_option))
+			.unwrap_or(0);
+		Ok(1 + received)
 	}
 
 	#[inline(always)]
-	fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
-		for byte in buf {
-			*byte = self.recv().map_err(|e| match e {
-				ChannelError::Exited => io::ErrorKind::UnexpectedEof,
-				ChannelError::Error => io::ErrorKind::ConnectionReset,
-			})?;
+	fn read_exact(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+		while !buf.is_empty() {
+			let read = self.read(buf)?;
+			buf = &mut { buf }[read..];
 		}
 		Ok(())
 	}
@@ -429,6 +1101,67 @@ impl<T: serde::de::DeserializeOwned> fmt::Debug for Receiver<T> {
 		self.0.fmt(f)
 	}
 }
+
+/// A [Receiver] whose values are passed through a function as they're received. Created by [`Receiver::map()`](Receiver::map).
+pub struct MappedReceiver<T: serde::de::DeserializeOwned, F>(Receiver<T>, F);
+impl<T: serde::de::DeserializeOwned, U, F: Fn(T) -> U> MappedReceiver<T, F> {
+	/// Get the pid of the remote end of this MappedReceiver
+	pub fn remote_pid(&self) -> Pid {
+		self.0.remote_pid()
+	}
+
+	/// Blocking receive.
+	pub fn recv(&self) -> Result<U, ChannelError>
+	where
+		T: 'static,
+	{
+		self.0.recv().map(&self.1)
+	}
+
+	/// [Selectable] receive.
+	///
+	/// This needs to be passed to [`select()`](select) to be executed.
+	pub fn selectable_recv<'a, G: FnOnce(Result<U, ChannelError>) + 'a>(
+		&'a self, recv: G,
+	) -> impl Selectable + 'a
+	where
+		T: 'static,
+	{
+		let f = &self.1;
+		self.0.selectable_recv(move |t| recv(t.map(f)))
+	}
+
+	/// Further adapt the values received with `g`.
+	pub fn map<V, G: Fn(U) -> V>(self, g: G) -> MappedReceiver<T, impl Fn(T) -> V> {
+		let f = self.1;
+		MappedReceiver(self.0, move |t| g(f(t)))
+	}
+
+	/// Unwrap the underlying [Receiver], discarding the function.
+	pub fn into_inner(self) -> Receiver<T> {
+		self.0
+	}
+}
+impl<T: serde::de::DeserializeOwned, F> fmt::Debug for MappedReceiver<T, F> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_tuple("MappedReceiver").field(&self.0).finish()
+	}
+}
+
+impl<T: serde::de::DeserializeOwned> Receiver<T> {
+	/// Drop the messages received that don't satisfy `pred`, so that [`recv()`](Receiver::recv) and [`selectable_recv()`](Receiver::selectable_recv) only yield those that do.
+	///
+	/// `pred` is run by the reactor on each message as it arrives, so a message it rejects never wakes the receiving thread. It holds up every channel while it runs, so should be cheap and non-blocking. What a message is can't be told from its bytes without deserializing them, so `pred` is given each one deserialized; to filter a channel carrying various types without deserializing those rejected, receive [AnyMessage]s and test [`is()`](AnyMessage::is).
+	///
+	/// As the reactor deserializes each message as a `T` as soon as it arrives, the remote mustn't go on to send this process anything but `T`s.
+	pub fn with_filter<F: Fn(&T) -> bool + Send + 'static>(self, pred: F) -> Self
+	where
+		T: Send + 'static,
+	{
+		self.0.as_ref().unwrap().set_filter(pred);
+		self
+	}
+}
 // impl<T: 'static + serde::de::DeserializeOwned> futures::stream::Stream for Receiver<Option<T>> {
 // 	type Item = Result<T, ChannelError>;
 
@@ -443,241 +1176,1494 @@ impl<T: serde::de::DeserializeOwned> fmt::Debug for Receiver<T> {
 // 	}
 // }
 
-//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
-
-/// `select()` lets you block on multiple blocking operations until progress can be made on at least one.
+/// The receiving half of channels from any number of remote processes.
 ///
-/// [`Receiver::selectable_recv()`](Receiver::selectable_recv) and [`Sender::selectable_send()`](Sender::selectable_send) let one create [Selectable] objects, any number of which can be passed to `select()`. `select()` then blocks until at least one is progressable, and then from any that are progressable picks one at random and executes it.
+/// It receives from every remote process that connects to this one and doesn't have a [Receiver], including processes this one has only a [Sender] to. Those it has claimed can't subsequently have a [Receiver] created – [`Receiver::new()`](Receiver::new) panics – so create any before receiving with an `AnyReceiver`.
 ///
-/// It returns an iterator of all the [Selectable] objects bar the one that has been executed.
-///
-/// It is inspired by the `select()` of go, which itself draws from David May's language [occam](https://en.wikipedia.org/wiki/Occam_(programming_language)) and Tony Hoare’s formalisation of [Communicating Sequential Processes](https://en.wikipedia.org/wiki/Communicating_sequential_processes).
-pub fn select<'a>(
-	select: Vec<Box<Selectable + 'a>>,
-) -> impl Iterator<Item = Box<Selectable + 'a>> + 'a {
-	channel::select(select, &mut || {
-		BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
-	})
+/// The ordering of messages between different remote processes is arbitrary.
+pub struct AnyReceiver<T: serde::de::DeserializeOwned>(
+	cell::RefCell<HashMap<Pid, transport::Receiver<T>>>,
+	transport::Accept,
+);
+impl<T: serde::de::DeserializeOwned> AnyReceiver<T> {
+	/// Create a new `AnyReceiver<T>`. This method returns instantly.
+	pub fn new() -> Self {
+		let context = REACTOR.read().unwrap();
+		let accept = transport::Accept::new(context.as_ref().unwrap_or_else(|| {
+			panic!("You must call init() immediately inside your application's main() function")
+		}));
+		debug!(target: "constellation::channel", "new AnyReceiver<{}>", unsafe { intrinsics::type_name::<T>() });
+		AnyReceiver(cell::RefCell::new(HashMap::new()), accept)
+	}
+
+	/// Blocking receive from any remote process.
+	///
+	/// Returns the [Pid] of the remote process alongside what was received from it. A remote process that errors or exits is returned once with the [ChannelError], and then not received from again.
+	pub fn recv_any(&self) -> (Pid, Result<T, ChannelError>)
+	where
+		T: 'static,
+	{
+		loop {
+			{
+				let context = REACTOR.read().unwrap();
+				let mut receivers = self.0.borrow_mut();
+				for (remote, receiver) in
+					transport::Receiver::new_unclaimed(context.as_ref().unwrap())
+				{
+					let remote = Pid::new(remote.ip(), remote.port());
+					debug!(target: "constellation::channel", "AnyReceiver<{}> from {}", unsafe { intrinsics::type_name::<T>() }, remote);
+					let x = receivers.insert(remote, receiver);
+					assert!(x.is_none());
+				}
+			}
+			let event = cell::RefCell::new(None);
+			{
+				let receivers = self.0.borrow();
+				let event = &event;
+				let mut selectables = receivers
+					.iter()
+					.map(|(&remote, receiver)| {
+						Box::new(receiver.selectable_recv(move |t| {
+							log_recv(&t, remote);
+							*event.borrow_mut() = Some((remote, t));
+						})) as Box<Selectable>
+					})
+					.collect::<Vec<_>>();
+				selectables.push(Box::new(self.1.clone()));
+				let _ = select(selectables);
+			}
+			if let Some((remote, t)) = event.into_inner() {
+				if t.is_err() {
+					let receiver = self.0.borrow_mut().remove(&remote).unwrap();
+					let context = REACTOR.read().unwrap();
+					receiver.drop(context.as_ref().unwrap());
+				}
+				return (remote, t);
+			}
+		}
+	}
 }
-/// A thin wrapper around [`select()`](select) that loops until all [Selectable] objects have been executed.
-pub fn run<'a>(mut select: Vec<Box<Selectable + 'a>>) {
-	while !select.is_empty() {
-		select = self::select(select).collect();
+impl<T: serde::de::DeserializeOwned> Default for AnyReceiver<T> {
+	fn default() -> Self {
+		Self::new()
 	}
 }
-
-//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
-
-/// Get the [Pid] of the current process
-#[inline(always)]
-pub fn pid() -> Pid {
-	// TODO: panic!("You must call init() immediately inside your application's main() function")
-	// TODO: cache
-	let listener = unsafe { net::TcpListener::from_raw_fd(LISTENER_FD) };
-	let local_addr = listener.local_addr().unwrap();
-	let _ = listener.into_raw_fd();
-	Pid::new(local_addr.ip(), local_addr.port())
+#[doc(hidden)] // noise
+impl<T: serde::de::DeserializeOwned> Drop for AnyReceiver<T> {
+	fn drop(&mut self) {
+		debug!(target: "constellation::channel", "drop AnyReceiver<{}>", unsafe { intrinsics::type_name::<T>() });
+		let context = REACTOR.read().unwrap();
+		for (_, receiver) in self.0.get_mut().drain() {
+			receiver.drop(context.as_ref().unwrap())
+		}
+	}
+}
+impl<T: serde::de::DeserializeOwned> fmt::Debug for AnyReceiver<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
 }
 
-/// Get the memory and CPU requirements configured at initialisation of the current process
-pub fn resources() -> Resources {
-	RESOURCES.read().unwrap().unwrap_or_else(|| {
-		panic!("You must call init() immediately inside your application's main() function")
-	})
+/// Create both a [Sender] and a [Receiver] to a remote [Pid]. This method returns instantly.
+///
+/// This is equivalent to calling [`Sender::new()`](Sender::new) and [`Receiver::new()`](Receiver::new) with the same `remote`, and so likewise panics if a `Sender` or `Receiver` to `remote` already exists – regardless of its type. There is at most one of each per remote process.
+pub fn duplex<S: serde::ser::Serialize, R: serde::de::DeserializeOwned>(
+	remote: Pid,
+) -> (Sender<S>, Receiver<R>) {
+	(Sender::new(remote), Receiver::new(remote))
 }
 
-//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+/// A [Sender] and [Receiver] to each of a number of remote processes, keyed by [Pid]. Useful for a coordinator to keep track of its workers.
+///
+/// As there is at most one `Sender` and one `Receiver` per remote process, a remote process can only be in one `PeerMap`, and can't also have a `Sender` or `Receiver` created separately while it's in one.
+pub struct PeerMap<S: serde::ser::Serialize, R: serde::de::DeserializeOwned>(
+	HashMap<Pid, (Sender<S>, Receiver<R>)>,
+);
+impl<S: serde::ser::Serialize, R: serde::de::DeserializeOwned> PeerMap<S, R> {
+	/// Create a new, empty, `PeerMap<S, R>`.
+	pub fn new() -> Self {
+		PeerMap(HashMap::new())
+	}
 
-fn spawn_native(
-	resources: Resources, f: serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid,))>,
-) -> Option<Pid> {
-	trace!("spawn_native");
-	let argv: Vec<CString> = get_env::args_os()
-		.expect("Couldn't get argv")
-		.iter()
-		.map(|x| CString::new(OsStringExt::into_vec(x.clone())).unwrap())
-		.collect(); // argv.split('\0').map(|x|CString::new(x).unwrap()).collect();
-	let envp: Vec<(CString, CString)> = get_env::vars_os()
-		.expect("Couldn't get envp")
-		.iter()
-		.map(|&(ref x, ref y)| {
-			(
-				CString::new(OsStringExt::into_vec(x.clone())).unwrap(),
-				CString::new(OsStringExt::into_vec(y.clone())).unwrap(),
-			)
-		})
-		.chain(iter::once((
-			CString::new("CONSTELLATION_RESOURCES").unwrap(),
-			CString::new(serde_json::to_string(&resources).unwrap()).unwrap(),
-		)))
-		.collect(); //envp.split('\0').map(|x|{let (a,b) = x.split_at(x.chars().position(|x|x=='=').unwrap_or_else(||panic!("invalid envp {:?}", x)));(CString::new(a).unwrap(),CString::new(&b[1..]).unwrap())}).collect();
+	/// Create a [Sender] and a [Receiver] to `remote` as with [`duplex()`](duplex), and insert them. This method returns instantly.
+	///
+	/// Panics if `remote` is already in the map, or a `Sender` or `Receiver` to it otherwise already exists.
+	pub fn insert(&mut self, remote: Pid) -> &(Sender<S>, Receiver<R>) {
+		assert!(
+			!self.0.contains_key(&remote),
+			"PeerMap::insert() called for pid {} already in the map",
+			remote
+		);
+		self.0.entry(remote).or_insert_with(|| duplex(remote))
+	}
 
-	let our_pid = pid();
+	/// Get the [Sender] and [Receiver] to `remote`.
+	pub fn get(&self, remote: Pid) -> Option<&(Sender<S>, Receiver<R>)> {
+		self.0.get(&remote)
+	}
 
-	let (process_listener, process_id) = native_process_listener();
+	/// Remove and return the [Sender] and [Receiver] to `remote`.
+	pub fn remove(&mut self, remote: Pid) -> Option<(Sender<S>, Receiver<R>)> {
+		self.0.remove(&remote)
+	}
 
-	let mut spawn_arg: Vec<u8> = Vec::new();
-	let bridge_pid: Pid = BRIDGE.read().unwrap().unwrap();
-	bincode::serialize_into(&mut spawn_arg, &bridge_pid).unwrap();
-	bincode::serialize_into(&mut spawn_arg, &our_pid).unwrap();
-	bincode::serialize_into(&mut spawn_arg, &f).unwrap();
+	/// Send `t` to every remote process in the map, blocking until all have been sent.
+	///
+	/// As with [`Sender::send()`](Sender::send), this blocks forever if a remote process has exited, so consider [`remove_on_exit()`](PeerMap::remove_on_exit) first.
+	pub fn broadcast(&self, t: S)
+	where
+		S: Clone + 'static,
+	{
+		run(self
+			.0
+			.values()
+			.map(|&(ref sender, _)| {
+				let t = t.clone();
+				Box::new(sender.selectable_send(move || t)) as Box<Selectable>
+			})
+			.collect())
+	}
 
-	let mut arg = unsafe {
-		fs::File::from_raw_fd(memfd_create(&argv[0], false).expect("Failed to memfd_create"))
-	};
-	// assert_eq!(arg.as_raw_fd(), ARG_FD);
-	unistd::ftruncate(arg.as_raw_fd(), spawn_arg.len().try_into().unwrap()).unwrap();
-	arg.write_all(&spawn_arg).unwrap();
-	let x = unistd::lseek(arg.as_raw_fd(), 0, unistd::Whence::SeekSet).unwrap();
-	assert_eq!(x, 0);
+	/// Remove the remote processes that were spawned by this process and have since exited, returning their [Pid]s. Their exit status is still available to [`wait()`](wait).
+	///
+	/// Notice of an exit can arrive before the last of what the process sent, so a process is only removed once everything it sent has been received.
+	pub fn remove_on_exit(&mut self) -> Vec<Pid> {
+		let children = CHILDREN.0.lock().unwrap();
+		let exited = self
+			.0
+			.iter()
+			.filter(|&(remote, &(_, ref receiver))| {
+				children.get(remote).map_or(false, Option::is_some) && receiver.drained()
+			})
+			.map(|(&remote, _)| remote)
+			.collect::<Vec<_>>();
+		for remote in &exited {
+			let _ = self.0.remove(remote).unwrap();
+		}
+		exited
+	}
 
-	let exe = CString::new(<OsString as OsStringExt>::into_vec(
-		exe_path().unwrap().into(),
-		// std::env::current_exe().unwrap().into(),
-	))
-	.unwrap();
-	let envp = envp
-		.into_iter()
-		.map(|(key, value)| {
-			CString::new(format!(
-				"{}={}",
-				key.to_str().unwrap(),
-				value.to_str().unwrap()
-			))
-			.unwrap()
-		})
-		.collect::<Vec<_>>();
+	/// Iterate over the [Pid], [Sender] and [Receiver] of each remote process in the map, in arbitrary order.
+	pub fn iter<'a>(&'a self) -> impl Iterator<Item = (Pid, &'a Sender<S>, &'a Receiver<R>)> + 'a {
+		self.0
+			.iter()
+			.map(|(&remote, &(ref sender, ref receiver))| (remote, sender, receiver))
+	}
 
-	let _child_pid = match unistd::fork().expect("Fork failed") {
-		unistd::ForkResult::Child => {
-			// Memory can be in a weird state now. Imagine a thread has just taken out a lock,
-			// but we've just forked. Lock still held. Avoid deadlock by doing nothing fancy here.
-			// Ideally including malloc.
-
-			// let err = unsafe{libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL)}; assert_eq!(err, 0);
-			unsafe {
-				let _ = signal::sigaction(
-					signal::SIGCHLD,
-					&signal::SigAction::new(
-						signal::SigHandler::SigDfl,
-						signal::SaFlags::empty(),
-						signal::SigSet::empty(),
-					),
-				)
-				.unwrap();
-			};
+	/// The number of remote processes in the map.
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
 
-			let valgrind_start_fd = if is_valgrind() {
-				Some(valgrind_start_fd())
-			} else {
-				None
-			};
-			// FdIter uses libc::opendir which mallocs. Underlying syscall is getdents…
-			for fd in FdIter::new().unwrap().filter(|&fd| {
-				fd >= 3
-					&& fd != process_listener
-					&& fd != arg.as_raw_fd()
-					&& (valgrind_start_fd.is_none() || fd < valgrind_start_fd.unwrap())
-			}) {
-				unistd::close(fd).unwrap();
-			}
+	/// Whether the map is empty.
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+impl<S: serde::ser::Serialize, R: serde::de::DeserializeOwned> Default for PeerMap<S, R> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+impl<S: serde::ser::Serialize, R: serde::de::DeserializeOwned> fmt::Debug for PeerMap<S, R> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_set().entries(self.0.keys()).finish()
+	}
+}
 
-			if process_listener != LISTENER_FD {
-				move_fd(process_listener, LISTENER_FD, fcntl::OFlag::empty(), true).unwrap();
-			}
-			if arg.as_raw_fd() != ARG_FD {
-				move_fd(arg.as_raw_fd(), ARG_FD, fcntl::OFlag::empty(), true).unwrap();
-			}
+/// The receiving half of channels from a set of remote processes, merged into one stream of messages. Useful for a coordinator receiving the same type of message from each of its workers.
+///
+/// Unlike [AnyReceiver], which claims every remote process that connects, it receives only from the processes inserted. As it holds a [Receiver] to each, they can't also have a `Receiver` created separately while they're in it. The ordering of messages between different remote processes is arbitrary.
+pub struct MultiReceiver<T: serde::de::DeserializeOwned>(HashMap<Pid, Receiver<T>>);
+impl<T: serde::de::DeserializeOwned> MultiReceiver<T> {
+	/// Create a new `MultiReceiver<T>`, creating a [Receiver] from each of `remotes`. This method returns instantly.
+	///
+	/// Panics if a `Receiver` from any of `remotes` already exists.
+	pub fn new<I: IntoIterator<Item = Pid>>(remotes: I) -> Self {
+		let mut ret = MultiReceiver(HashMap::new());
+		for remote in remotes {
+			ret.insert(remote);
+		}
+		ret
+	}
 
-			if !is_valgrind() {
-				unistd::execve(&exe, &argv, &envp).expect("Failed to execve /proc/self/exe"); // or fexecve but on linux that uses proc also
-			} else {
-				let fd = fcntl::open::<path::PathBuf>(
-					&fd_path(valgrind_start_fd.unwrap()).unwrap(),
-					fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_CLOEXEC,
-					stat::Mode::empty(),
-				)
-				.unwrap();
-				let binary_desired_fd_ = valgrind_start_fd.unwrap() - 1;
-				assert!(binary_desired_fd_ > fd);
-				move_fd(fd, binary_desired_fd_, fcntl::OFlag::empty(), true).unwrap();
-				fexecve(binary_desired_fd_, &argv, &envp)
-					.expect("Failed to execve /proc/self/fd/n");
+	/// Create a [Receiver] from `remote`, and insert it. This method returns instantly.
+	///
+	/// Panics if `remote` is already in it, or a `Receiver` from it otherwise already exists.
+	pub fn insert(&mut self, remote: Pid) {
+		assert!(
+			!self.0.contains_key(&remote),
+			"MultiReceiver::insert() called for pid {} already in it",
+			remote
+		);
+		let _ = self.0.insert(remote, Receiver::new(remote));
+	}
+
+	/// Remove and return the [Receiver] from `remote`.
+	pub fn remove(&mut self, remote: Pid) -> Option<Receiver<T>> {
+		self.0.remove(&remote)
+	}
+
+	/// Blocking receive from any of the remote processes.
+	///
+	/// Returns the [Pid] of the remote process alongside what was received from it. A remote process that errors or exits is removed, and `None` is returned once none remain.
+	pub fn recv(&mut self) -> Option<(Pid, T)>
+	where
+		T: 'static,
+	{
+		while !self.0.is_empty() {
+			let event = cell::RefCell::new(None);
+			let _ =
+				select(vec![Box::new(self.selectable_recv(|remote, t| {
+					*event.borrow_mut() = Some((remote, t))
+				}))]);
+			match event.into_inner().unwrap() {
+				(remote, Ok(t)) => return Some((remote, t)),
+				(remote, Err(_)) => {
+					let _ = self.0.remove(&remote).unwrap();
+				}
 			}
-			unreachable!();
 		}
-		unistd::ForkResult::Parent { child, .. } => child,
-	};
-	unistd::close(process_listener).unwrap();
-	drop(arg);
-	let new_pid = Pid::new("127.0.0.1".parse().unwrap(), process_id);
-	// BRIDGE.read().unwrap().as_ref().unwrap().0.send(ProcessOutputEvent::Spawn(new_pid)).unwrap();
+		None
+	}
+
+	/// [Selectable] receive from any of the remote processes.
+	///
+	/// This needs to be passed to [`select()`](select) to be executed. `recv` is run with the [Pid] of the remote process received from. Unlike [`recv()`](MultiReceiver::recv), a remote process that errors or exits isn't removed, so should be [`remove()`](MultiReceiver::remove)d after its [ChannelError] is received.
+	pub fn selectable_recv<'a, F: FnOnce(Pid, Result<T, ChannelError>) + 'a>(
+		&'a self, recv: F,
+	) -> impl Selectable + 'a
+	where
+		T: 'static,
 	{
-		let file = unsafe { fs::File::from_raw_fd(MONITOR_FD) };
-		bincode::serialize_into(&mut &file, &ProcessOutputEvent::Spawn(new_pid)).unwrap();
-		let _ = file.into_raw_fd();
+		let recv = rc::Rc::new(cell::RefCell::new(Some(recv)));
+		MultiRecv(
+			self.0
+				.iter()
+				.map(|(&remote, receiver)| {
+					let recv = recv.clone();
+					Box::new(
+						receiver.selectable_recv(move |t| {
+							(recv.borrow_mut().take().unwrap())(remote, t)
+						}),
+					) as Box<Selectable + 'a>
+				})
+				.collect(),
+		)
 	}
-	Some(new_pid)
-}
 
-fn spawn_deployed(
-	resources: Resources, f: serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid,))>,
-) -> Option<Pid> {
-	trace!("spawn_deployed");
-	let stream = unsafe { net::TcpStream::from_raw_fd(SCHEDULER_FD) };
-	let (mut stream_read, mut stream_write) =
-		(BufferedStream::new(&stream), BufferedStream::new(&stream));
-	let mut stream_write_ = stream_write.write();
-	let binary = if !is_valgrind() {
-		exe().unwrap()
-	} else {
-		unsafe {
-			fs::File::from_raw_fd(
-				fcntl::open(
-					&fd_path(valgrind_start_fd()).unwrap(),
-					fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_CLOEXEC,
-					stat::Mode::empty(),
-				)
-				.unwrap(),
-			)
-		}
-	};
-	let len: u64 = binary.metadata().unwrap().len();
-	bincode::serialize_into(&mut stream_write_, &resources).unwrap();
-	bincode::serialize_into::<_, Vec<OsString>>(
-		&mut stream_write_,
-		&get_env::args_os().expect("Couldn't get argv"),
-	)
-	.unwrap();
-	bincode::serialize_into::<_, Vec<(OsString, OsString)>>(
-		&mut stream_write_,
-		&get_env::vars_os().expect("Couldn't get envp"),
-	)
-	.unwrap();
-	bincode::serialize_into(&mut stream_write_, &len).unwrap();
-	drop(stream_write_);
-	// copy(&mut &binary, &mut stream_write_, len as usize).unwrap();
-	copy_sendfile(&binary, &**stream_write.get_ref(), len).unwrap();
-	let mut stream_write_ = stream_write.write();
-	let mut arg_: Vec<u8> = Vec::new();
-	let bridge_pid: Pid = BRIDGE.read().unwrap().unwrap();
-	bincode::serialize_into(&mut arg_, &bridge_pid).unwrap();
-	bincode::serialize_into(&mut arg_, &pid()).unwrap();
-	bincode::serialize_into(&mut arg_, &f).unwrap();
-	bincode::serialize_into(&mut stream_write_, &arg_).unwrap();
-	drop(stream_write_);
-	let pid: Option<Pid> = bincode::deserialize_from(&mut stream_read)
-		.map_err(map_bincode_err)
-		.unwrap();
-	drop(stream_read);
-	trace!("{} spawned? {}", self::pid(), pid.unwrap());
-	if let Some(pid) = pid {
-		let file = unsafe { fs::File::from_raw_fd(MONITOR_FD) };
-		bincode::serialize_into(&mut &file, &ProcessOutputEvent::Spawn(pid)).unwrap();
-		let _ = file.into_raw_fd();
+	/// Iterate over the [Pid]s of the remote processes, in arbitrary order.
+	pub fn pids<'a>(&'a self) -> impl Iterator<Item = Pid> + 'a {
+		self.0.keys().cloned()
+	}
+
+	/// The number of remote processes.
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	/// Whether there are no remote processes.
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+impl<T: serde::de::DeserializeOwned> fmt::Debug for MultiReceiver<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_set().entries(self.0.keys()).finish()
 	}
-	let _ = stream.into_raw_fd();
-	pid
+}
+
+/// Progressable when any of its [Selectable]s is, executing the first found.
+#[derive(Debug)]
+struct MultiRecv<'a>(Vec<Box<Selectable + 'a>>);
+impl<'a> Selectable for MultiRecv<'a> {
+	fn subscribe(&self, thread: thread::Thread) {
+		for selectable in &self.0 {
+			selectable.subscribe(thread.clone());
+		}
+	}
+	fn available<'b>(&'b mut self, context: &'b channel::Reactor) -> Option<Box<FnBox() + 'b>> {
+		for selectable in &mut self.0 {
+			if let Some(run) = selectable.available(context) {
+				return Some(run);
+			}
+		}
+		None
+	}
+	fn unsubscribe(&self, thread: thread::Thread) {
+		for selectable in &self.0 {
+			selectable.unsubscribe(thread.clone());
+		}
+	}
+}
+
+/// A message of any type, for sending different types of message over one channel and dispatching on the type received.
+///
+/// It carries a tag identifying the type alongside the message serialized as bytes. A `Receiver<AnyMessage>` can receive messages wrapped with [`AnyMessage::new()`](AnyMessage::new) by the remote process, and the type of each can be tested with [`is()`](AnyMessage::is) or recovered with [`downcast()`](AnyMessage::downcast).
+///
+/// The tag is a hash of the type's name, as given by the compiler. So types are identified across processes running the same binary, or binaries built by the same compiler from the same source; but renaming or moving a type changes its tag, and two types with the same name in different builds are confused.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AnyMessage {
+	tag: u64,
+	bytes: Vec<u8>,
+}
+impl AnyMessage {
+	/// Wrap `t`, serializing it.
+	///
+	/// # Panics
+	///
+	/// Panics if `t` fails to serialize.
+	pub fn new<T: serde::ser::Serialize>(t: &T) -> Self {
+		AnyMessage {
+			tag: type_tag::<T>(),
+			bytes: bincode_config().serialize(t).unwrap(),
+		}
+	}
+
+	/// Whether this wraps a `T`.
+	pub fn is<T>(&self) -> bool {
+		self.tag == type_tag::<T>()
+	}
+
+	/// Unwrap the `T` this wraps, or return this back if it doesn't wrap a `T` (or doesn't deserialize as one), so another type can be tried.
+	pub fn downcast<T: serde::de::DeserializeOwned>(self) -> Result<T, Self> {
+		if !self.is::<T>() {
+			return Err(self);
+		}
+		bincode_config().deserialize(&self.bytes).map_err(|_| self)
+	}
+}
+
+/// A message compressed with zstd if it's large, for channels shipping big, compressible data like text or JSON logs.
+///
+/// A `Sender<Compressed<T>>` and `Receiver<Compressed<T>>` are opted in per channel. The message is serialized by [`Compressed::new()`](Compressed::new), and compressed only if that comes to more than 4 KiB (or the threshold given to [`with_threshold()`](Compressed::with_threshold)), so small messages don't pay for it. A flag byte records which, so the receiver knows whether to decompress. As both ends name the type, a channel can't be mismatched, and both must be built with the `compression` feature.
+///
+/// Requires the `compression` feature.
+#[cfg(feature = "compression")]
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Compressed<T> {
+	compressed: bool,
+	bytes: Vec<u8>,
+	#[serde(skip)]
+	marker: marker::PhantomData<fn() -> T>,
+}
+#[cfg(feature = "compression")]
+impl<T: serde::ser::Serialize> Compressed<T> {
+	/// Wrap `t`, serializing it and compressing it if that's more than 4 KiB.
+	///
+	/// # Panics
+	///
+	/// Panics if `t` fails to serialize.
+	pub fn new(t: &T) -> Self {
+		Self::with_threshold(t, COMPRESS_THRESHOLD)
+	}
+
+	/// Wrap `t`, serializing it and compressing it if that's more than `threshold` bytes.
+	///
+	/// # Panics
+	///
+	/// Panics if `t` fails to serialize.
+	pub fn with_threshold(t: &T, threshold: usize) -> Self {
+		let bytes = bincode_config().serialize(t).unwrap();
+		let compressed = bytes.len() > threshold;
+		let bytes = if compressed {
+			// 0 selects zstd's default level
+			zstd::stream::encode_all(&*bytes, 0).unwrap()
+		} else {
+			bytes
+		};
+		Compressed {
+			compressed,
+			bytes,
+			marker: marker::PhantomData,
+		}
+	}
+}
+#[cfg(feature = "compression")]
+impl<T: serde::de::DeserializeOwned> Compressed<T> {
+	/// Unwrap the `T` this wraps, decompressing it if necessary. Fails with [`InvalidData`](io::ErrorKind::InvalidData) if it's corrupt.
+	pub fn decompress(self) -> Result<T, io::Error> {
+		let bytes = if self.compressed {
+			zstd::stream::decode_all(&*self.bytes)?
+		} else {
+			self.bytes
+		};
+		bincode_config()
+			.deserialize(&bytes)
+			.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+	}
+}
+#[cfg(feature = "compression")]
+impl<T> fmt::Debug for Compressed<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Compressed")
+			.field("compressed", &self.compressed)
+			.field("len", &self.bytes.len())
+			.finish()
+	}
+}
+
+/// A stable 64-bit FNV-1a hash of the name of `T`.
+fn type_tag<T>() -> u64 {
+	let name = unsafe { intrinsics::type_name::<T>() };
+	name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+		(hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+	})
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// `select()` lets you block on multiple blocking operations until progress can be made on at least one.
+///
+/// [`Receiver::selectable_recv()`](Receiver::selectable_recv) and [`Sender::selectable_send()`](Sender::selectable_send) let one create [Selectable] objects, any number of which can be passed to `select()`. `select()` then blocks until at least one is progressable, and then from any that are progressable picks one at random and executes it. [RoundRobin] instead picks among them fairly.
+///
+/// It returns an iterator of all the [Selectable] objects bar the one that has been executed. As that means re-collecting them each time, a hot select loop should prefer [`select_mut()`](select_mut), which borrows them in place.
+///
+/// It is inspired by the `select()` of go, which itself draws from David May's language [occam](https://en.wikipedia.org/wiki/Occam_(programming_language)) and Tony Hoare’s formalisation of [Communicating Sequential Processes](https://en.wikipedia.org/wiki/Communicating_sequential_processes).
+pub fn select<'a>(
+	select: Vec<Box<Selectable + 'a>>,
+) -> impl Iterator<Item = Box<Selectable + 'a>> + 'a {
+	channel::select(select, &mut || {
+		BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+	})
+}
+/// Like [`select()`](select), but borrowing rather than taking ownership of the [Selectable] objects, so they needn't be boxed.
+///
+/// It returns the index of the [Selectable] object that has been executed. This is what the [`select!`](select!) macro expands to.
+pub fn select_mut<'a>(select: &mut [&mut (Selectable + 'a)]) -> usize {
+	channel::select_mut(select, &mut || {
+		BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+	})
+}
+/// Like [`select()`](select), but also returning the index of the [Selectable] object that has been executed, so it needn't be recorded by its callback.
+///
+/// The remaining [Selectable] objects are returned in their original order, without the one that has been executed.
+pub fn select_indexed<'a>(
+	mut select: Vec<Box<Selectable + 'a>>,
+) -> (usize, Vec<Box<Selectable + 'a>>) {
+	let i = select_mut(
+		&mut select
+			.iter_mut()
+			.map(|selectable| &mut **selectable)
+			.collect::<Vec<_>>(),
+	);
+	let _ = select.remove(i);
+	(i, select)
+}
+/// A fair alternative to [`select()`](select), that round-robins among the progressable [Selectable] objects rather than picking one at random.
+///
+/// It remembers the index of the last [Selectable] object it executed, and next time executes the first progressable one after it, wrapping around. So when passed the same objects in the same order each time, as in a coordinator servicing many peers, any that stays progressable is executed within that many selects. [`select()`](select) is random so that this holds only on average, which can starve some over short windows under sustained load.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RoundRobin {
+	next: usize,
+}
+impl RoundRobin {
+	/// Create a new `RoundRobin`, that starts from the first [Selectable] object.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Like [`select()`](select), but round-robin.
+	pub fn select<'a>(
+		&mut self, mut select: Vec<Box<Selectable + 'a>>,
+	) -> impl Iterator<Item = Box<Selectable + 'a>> + 'a {
+		let i = channel::select_mut_fair(&mut select, &mut self.next, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		let _ = select.remove(i);
+		select.into_iter()
+	}
+
+	/// Like [`select_mut()`](select_mut), but round-robin.
+	pub fn select_mut<'a>(&mut self, select: &mut [&mut (Selectable + 'a)]) -> usize {
+		channel::select_mut_fair(select, &mut self.next, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		})
+	}
+}
+/// The non-blocking sibling of [`select()`](select), for polling alongside other event sources.
+///
+/// If any of the [Selectable] objects are immediately progressable, one is picked as by [`select()`](select) and executed, and its index returned alongside the remaining objects in their original order. Otherwise `None` is returned alongside all of them unchanged: none are executed or consumed.
+pub fn try_select<'a>(
+	mut select: Vec<Box<Selectable + 'a>>,
+) -> (Option<usize>, Vec<Box<Selectable + 'a>>) {
+	let i = channel::try_select_mut(&mut select, &mut || {
+		BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+	});
+	if let Some(i) = i {
+		let _ = select.remove(i);
+	}
+	(i, select)
+}
+/// Block until any of `receivers` can receive, and receive from it, returning its index and what it received.
+///
+/// This is [`select()`](select) specialised to receiving on [Receiver]s of the same type, so the value received can be returned directly rather than passed out of a [`selectable_recv()`](Receiver::selectable_recv) callback. As with [`select()`](select), if several can receive one is picked at random.
+///
+/// # Panics
+///
+/// Panics if `receivers` is empty.
+pub fn select_recv<T: serde::de::DeserializeOwned + 'static>(
+	receivers: &[Receiver<T>],
+) -> (usize, Result<T, ChannelError>) {
+	assert!(!receivers.is_empty(), "select_recv() needs a Receiver");
+	let received = cell::RefCell::new(None);
+	let mut selectables = receivers
+		.iter()
+		.map(|receiver| {
+			let received = &received;
+			Box::new(receiver.selectable_recv(move |t| *received.borrow_mut() = Some(t)))
+				as Box<Selectable>
+		})
+		.collect::<Vec<_>>();
+	let i = select_mut(
+		&mut selectables
+			.iter_mut()
+			.map(|selectable| &mut **selectable)
+			.collect::<Vec<_>>(),
+	);
+	drop(selectables);
+	(i, received.into_inner().unwrap())
+}
+/// A thin wrapper around [`select()`](select) that loops until all [Selectable] objects have been executed.
+pub fn run<'a>(mut select: Vec<Box<Selectable + 'a>>) {
+	// Remove each executed one in place, rather than re-collecting into a new Vec each round
+	while !select.is_empty() {
+		let i = channel::select_mut(&mut select, &mut || {
+			BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option)
+		});
+		let _ = select.remove(i);
+	}
+}
+
+/// The readiness of a file descriptor that [`selectable_fd()`](selectable_fd) waits for.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Interest {
+	/// The file descriptor can be read from without blocking.
+	Read,
+	/// The file descriptor can be written to without blocking.
+	Write,
+}
+impl Interest {
+	fn events(&self) -> poll::EventFlags {
+		match *self {
+			Interest::Read => poll::EventFlags::POLLIN,
+			Interest::Write => poll::EventFlags::POLLOUT,
+		}
+	}
+}
+
+/// [Selectable] readiness of a raw file descriptor, so that external event sources like sockets, pipes or an `eventfd` can participate in the same [`select()`](select) as channel operations.
+///
+/// `f` is run when `fd` is ready for `interest` (or has errored or hung up). `fd` is not owned, and is left open. While selecting, `fd` is registered with the reactor that drives channels, which wakes the select on each change in its readiness. So `fd` must be pollable, which a regular file isn't.
+pub fn selectable_fd<'a, F: FnOnce() + 'a>(
+	fd: Fd, interest: Interest, f: F,
+) -> impl Selectable + 'a {
+	SelectableFd {
+		fd,
+		interest,
+		f: Some(f),
+		waiting: cell::Cell::new(None),
+	}
+}
+struct SelectableFd<F: FnOnce()> {
+	fd: Fd,
+	interest: Interest,
+	f: Option<F>,
+	/// A duplicate of `fd` registered with the reactor while subscribed, so that the same `fd` can be waited on by several of these at once.
+	waiting: cell::Cell<Option<Fd>>,
+}
+impl<F: FnOnce()> SelectableFd<F> {
+	/// Distinct from the reactor's other keys while subscribed, as select() doesn't move what it's selecting on.
+	fn key(&self) -> *const () {
+		self as *const Self as *const ()
+	}
+
+	fn stop(&self, context: &channel::Reactor) {
+		if let Some(fd) = self.waiting.take() {
+			context.remove_fd_waiter(self.key(), fd);
+			unistd::close(fd).unwrap();
+		}
+	}
+}
+impl<F: FnOnce()> Selectable for SelectableFd<F> {
+	fn subscribe(&self, thread: thread::Thread) {
+		let fd = fcntl::fcntl(self.fd, fcntl::FcntlArg::F_DUPFD_CLOEXEC(0)).unwrap();
+		let context = REACTOR.read().unwrap();
+		context
+			.as_ref()
+			.unwrap()
+			.add_fd_waiter(self.key(), fd, thread);
+		assert!(self.waiting.replace(Some(fd)).is_none());
+	}
+	fn available<'a>(&'a mut self, context: &'a channel::Reactor) -> Option<Box<FnBox() + 'a>> {
+		assert!(self.f.is_some());
+		let mut fds = [poll::PollFd::new(self.fd, self.interest.events())];
+		match poll::poll(&mut fds, 0) {
+			Ok(0) => None,
+			Ok(_) => Some(Box::new(move || {
+				self.stop(context);
+				(self.f.take().unwrap())()
+			})),
+			Err(nix::Error::Sys(errno::Errno::EINTR)) => None,
+			Err(err) => panic!("{:?}", err),
+		}
+	}
+	fn unsubscribe(&self, _thread: thread::Thread) {
+		let context = REACTOR.read().unwrap();
+		self.stop(context.as_ref().unwrap());
+	}
+}
+impl<F: FnOnce()> Drop for SelectableFd<F> {
+	fn drop(&mut self) {
+		if self.waiting.get().is_some() {
+			let context = REACTOR.read().unwrap();
+			self.stop(context.as_ref().unwrap());
+		}
+	}
+}
+impl<F: FnOnce()> fmt::Debug for SelectableFd<F> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("SelectableFd")
+			.field("fd", &self.fd)
+			.field("interest", &self.interest)
+			.finish()
+	}
+}
+
+/// Attach a `weight` to a [Selectable] object, biasing [`select()`](select) toward it.
+///
+/// When several [Selectable] objects are progressable at once, [`select()`](select) picks one with probability proportional to its weight. Objects without a weight attached have weight `1`. Weights are relative, and only matter when several are progressable at once: a progressable object is always picked over unprogressable ones however low its weight. This lets, for example, a control channel be prioritised over bulk data channels in the same select. [RoundRobin] ignores weights.
+///
+/// # Panics
+///
+/// Panics if `weight` is `0`.
+pub fn weighted<'a, S: Selectable + 'a>(weight: u32, selectable: S) -> impl Selectable + 'a {
+	assert_ne!(weight, 0, "weight must be positive");
+	Weighted(weight, selectable)
+}
+#[derive(Debug)]
+struct Weighted<S>(u32, S);
+impl<S: Selectable> Selectable for Weighted<S> {
+	fn subscribe(&self, thread: thread::Thread) {
+		self.1.subscribe(thread)
+	}
+	fn available<'a>(&'a mut self, context: &'a channel::Reactor) -> Option<Box<FnBox() + 'a>> {
+		self.1.available(context)
+	}
+	fn unsubscribe(&self, thread: thread::Thread) {
+		self.1.unsubscribe(thread)
+	}
+	fn weight(&self) -> u32 {
+		self.0
+	}
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Get the version of the protocol processes speak to each other, and the version of this crate.
+///
+/// Processes can only communicate if they were built against the same versions; otherwise channels fail with [`ChannelError::Mismatch`]. Tooling can use this to check compatibility before spawning. It's also what setting the env var `CONSTELLATION_VERSION` to `1` prints.
+pub fn version() -> (u32, &'static str) {
+	(channel::PROTOCOL_VERSION, env!("CARGO_PKG_VERSION"))
+}
+
+/// Get the [Pid] of the current process
+#[inline(always)]
+pub fn pid() -> Pid {
+	// TODO: panic!("You must call init() immediately inside your application's main() function")
+	// TODO: cache
+	#[cfg(not(feature = "test-transport"))]
+	let local_addr = platform::Native::channel_local_addr(LISTENER_FD).unwrap();
+	#[cfg(feature = "test-transport")]
+	let local_addr = transport::local().unwrap_or_else(|| {
+		panic!("You must call init() immediately inside your application's main() function, and call pid() on the thread that did or that runs a spawned closure")
+	});
+	Pid::new(local_addr.ip(), local_addr.port())
+}
+
+/// Report a named custom metric of this process, such as its progress or the number of items it has processed.
+///
+/// It's forwarded to the bridge as a [DeployOutputEvent] `Metric`, which the human output format shows attributed to this process's pid, and the json format emits as is. This is cheap: it writes a small frame to this process's monitor and returns, without waiting for it to be forwarded. It's dropped if the bridge has exited.
+pub fn report_metric(name: &str, value: f64) {
+	let _ = report(&ProcessOutputEvent::Metric(name.to_owned(), value));
+}
+
+/// Get the pids of the processes this process has spawned that haven't yet exited.
+///
+/// A process is included from when [`spawn()`](spawn) returns until this process is notified of its exit, so one that has only just exited may still be included.
+pub fn children() -> Vec<Pid> {
+	CHILDREN.0.lock().unwrap().running().collect()
+}
+
+/// Block until the process `pid`, spawned by this process, exits, and return its [ExitStatus].
+///
+/// Returns [`WaitError::NotAChild`] if `pid` wasn't spawned by this process, or has already been waited on.
+///
+/// The exit statuses of only the 4096 most recently exited processes that haven't been waited on are kept, so that those never waited on don't accumulate. Waiting on one that exited before those also returns `WaitError::NotAChild`.
+pub fn wait(pid: Pid) -> Result<ExitStatus, WaitError> {
+	let mut children = CHILDREN.0.lock().unwrap();
+	loop {
+		match children.get(&pid) {
+			None => return Err(WaitError::NotAChild),
+			Some(&Some(exit_code)) => {
+				children.remove(&pid);
+				return Ok(exit_code);
+			}
+			Some(&None) => children = CHILDREN.1.wait(children).unwrap(),
+		}
+	}
+}
+
+/// Register `f` to be run with the [ExitStatus] of the process `pid`, spawned by this process, when it exits – or promptly, if it already has. This lets exits be handled as they happen without a thread blocked in [`wait()`](wait) for each process.
+///
+/// `f` is run on a thread managed by constellation, which runs those of every process in turn, so it shouldn't block for long. Its exit status is still available to `wait()`.
+///
+/// Returns [`WaitError::NotAChild`] if `pid` wasn't spawned by this process, or has already been waited on.
+pub fn on_exit<F: FnOnce(ExitStatus) + Send + 'static>(pid: Pid, f: F) -> Result<(), WaitError> {
+	let children = CHILDREN.0.lock().unwrap();
+	match children.get(&pid) {
+		None => Err(WaitError::NotAChild),
+		Some(&Some(exit_code)) => {
+			let _ = thread_spawn(String::from("on-exit"), move || f(exit_code));
+			Ok(())
+		}
+		Some(&None) => {
+			// Under the CHILDREN lock, so the exit can't be recorded in between
+			ON_EXIT
+				.lock()
+				.unwrap()
+				.entry(pid)
+				.or_insert_with(Vec::new)
+				.push(Box::new(f));
+			Ok(())
+		}
+	}
+}
+
+/// Kill every process descended from this one, then abort this process. This is the emergency stop counterpart to waiting for processes with [`scope()`](scope), for when a fatal error means they shouldn't be left running.
+///
+/// The bridge kills this process's children, their children, and so on, other than those that have already exited, and those spawned with [`spawn_detached()`](spawn_detached) and their descendants. Descendants spawned by a process that has since exited aren't reached, as the bridge no longer knows their lineage.
+///
+/// This process then exits as [`process::abort()`](std::process::abort) does, with `SIGABRT` and without running destructors or `atexit` handlers, so output not yet forwarded may be lost. It takes no locks that a panic could leave held, so it's safe to call from a panic hook.
+pub fn abort() -> ! {
+	// Reported before exiting, so the bridge sees it before this process's exit
+	let _ = report(&ProcessOutputEvent::KillDescendants);
+	process::abort()
+}
+
+/// Error returned by [`wait()`](wait) and [`on_exit()`](on_exit).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WaitError {
+	/// The process wasn't spawned by this process, or has already been waited on.
+	NotAChild,
+}
+impl fmt::Display for WaitError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			WaitError::NotAChild => write!(f, "Not a child of this process"),
+		}
+	}
+}
+impl error::Error for WaitError {
+	fn description(&self) -> &str {
+		match *self {
+			WaitError::NotAChild => "not a child of this process",
+		}
+	}
+}
+
+/// Send a file descriptor to a process on the same node, which receives it with [`recv_fd()`](recv_fd).
+///
+/// The remote process gets its own copy of the fd, as if by `dup()`, so `fd` can be closed once this returns. This is Unix-only, as it's passed over a unix domain socket with `SCM_RIGHTS`, and so `remote` must be [local](Pid::is_local).
+pub fn send_fd(remote: Pid, fd: Fd) -> Result<(), io::Error> {
+	if !remote.is_local() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"can only send fds to processes on the same node",
+		));
+	}
+	platform::Native::fd_send(pid(), remote, fd)
+}
+
+/// Fds that have arrived for [`recv_fd()`](recv_fd) but not yet been received, by sender.
+struct PassedFds {
+	fds: HashMap<Pid, VecDeque<Fd>>,
+	/// Whether a thread is accepting the next fd, which the others wait for rather than accepting themselves.
+	accepting: bool,
+}
+
+/// Block until a file descriptor sent by `remote` with [`send_fd()`](send_fd) arrives, and return it.
+///
+/// Fds from the same process arrive in the order they were sent. The returned fd is owned by the caller, and has `FD_CLOEXEC` set. Fds sent by processes of other users are closed and ignored, as are malformed messages. This is Unix-only. Fails if listening for fds failed when [`init()`](init) was called, as it would if another process were already listening for this [Pid]'s.
+pub fn recv_fd(remote: Pid) -> Result<Fd, io::Error> {
+	let listener = FD_LISTENER
+		.read()
+		.unwrap()
+		.unwrap_or_else(|| {
+			panic!("You must call init() immediately inside your application's main() function")
+		})
+		.map_err(|kind| io::Error::new(kind, "listening for fds failed during init()"))?;
+	let mut passed = PASSED_FDS.0.lock().unwrap();
+	loop {
+		if let Some(fd) = passed.fds.get_mut(&remote).and_then(VecDeque::pop_front) {
+			return Ok(fd);
+		}
+		if passed.accepting {
+			passed = PASSED_FDS.1.wait(passed).unwrap();
+			continue;
+		}
+		// Accepted without the lock held, so threads receiving fds already arrived aren't held up
+		passed.accepting = true;
+		drop(passed);
+		let accepted = platform::Native::fd_accept(listener);
+		passed = PASSED_FDS.0.lock().unwrap();
+		passed.accepting = false;
+		PASSED_FDS.1.notify_all();
+		match accepted {
+			Ok((from, fd)) => passed
+				.fds
+				.entry(from)
+				.or_insert_with(VecDeque::new)
+				.push_back(fd),
+			Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+				warn!("recv_fd() ignored a malformed fd, or one from another user")
+			}
+			Err(err) => return Err(err),
+		}
+	}
+}
+
+fn map_nix_err(err: nix::Error) -> io::Error {
+	match err {
+		nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+		e => panic!("{:?}", e),
+	}
+}
+
+struct Counters {
+	spawned: sync::atomic::AtomicUsize,
+	exited: sync::atomic::AtomicUsize,
+	messages_sent: sync::atomic::AtomicUsize,
+	messages_received: sync::atomic::AtomicUsize,
+}
+
+/// A snapshot of counters of the current process's activity, returned by [`metrics()`](metrics).
+///
+/// The counters are of this process alone – they don't include the activity of the processes it has spawned.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Metrics {
+	/// The number of processes this process has spawned.
+	pub spawned: u64,
+	/// The number of processes this process has spawned that haven't yet exited.
+	pub alive: u64,
+	/// The number of messages sent on all [Sender]s. Each [`write()`](std::io::Write::write) to a `Sender<u8>` is one, however many bytes it writes.
+	pub messages_sent: u64,
+	/// The number of messages received on all [Receiver]s and [AnyReceiver]s. Each [`read()`](std::io::Read::read) from a `Receiver<u8>` is one, however many bytes it reads.
+	pub messages_received: u64,
+	/// The number of bytes written to the connections of all [Sender]s and [Receiver]s, including the handshake each connection starts with.
+	pub bytes_sent: u64,
+	/// The number of bytes read from the connections of all [Sender]s and [Receiver]s, including the handshake each connection starts with.
+	pub bytes_received: u64,
+}
+
+/// Get a snapshot of counters of the current process's activity. See [Metrics].
+pub fn metrics() -> Metrics {
+	let load =
+		|counter: &sync::atomic::AtomicUsize| counter.load(sync::atomic::Ordering::Relaxed) as u64;
+	let spawned = load(&METRICS.spawned);
+	Metrics {
+		spawned,
+		// Counted separately so may briefly be out of step
+		alive: spawned.saturating_sub(load(&METRICS.exited)),
+		messages_sent: load(&METRICS.messages_sent),
+		messages_received: load(&METRICS.messages_received),
+		bytes_sent: load(&channel::BYTES_SENT),
+		bytes_received: load(&channel::BYTES_RECEIVED),
+	}
+}
+
+/// Get the memory and CPU requirements configured at initialisation of the current process
+pub fn resources() -> Resources {
+	RESOURCES.read().unwrap().unwrap_or_else(|| {
+		panic!("You must call init() immediately inside your application's main() function")
+	})
+}
+
+/// Error returned by [`resources_available()`](resources_available).
+#[derive(Debug)]
+pub enum QueryError {
+	/// The scheduler predates this query, or the free resources of this machine couldn't be determined.
+	Unsupported,
+	/// Communicating with the scheduler, or reading system information, failed.
+	Io(io::Error),
+}
+impl fmt::Display for QueryError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			QueryError::Unsupported => write!(f, "Querying free resources is unsupported"),
+			QueryError::Io(ref err) => err.fmt(f),
+		}
+	}
+}
+impl error::Error for QueryError {
+	fn description(&self) -> &str {
+		match *self {
+			QueryError::Unsupported => "querying free resources is unsupported",
+			QueryError::Io(ref err) => err.description(),
+		}
+	}
+
+	fn cause(&self) -> Option<&error::Error> {
+		match *self {
+			QueryError::Unsupported => None,
+			QueryError::Io(ref err) => Some(err),
+		}
+	}
+}
+
+/// Get the memory and CPU currently free for new processes.
+///
+/// When deployed to a cluster this asks the scheduler for the aggregate free resources across all nodes. When run natively it is the available memory, the logical cores not taken up by the 1-minute load average, and free space in the temporary directory of the local machine.
+///
+/// This is a snapshot: by the time [`spawn()`](spawn) is called the resources may have been taken by another process.
+pub fn resources_available() -> Result<Resources, QueryError> {
+	let deployed = DEPLOYED.read().unwrap().unwrap_or_else(|| {
+		panic!("You must call init() immediately inside your application's main() function")
+	});
+	if !deployed {
+		resources_available_native()
+	} else {
+		let scheduler = SchedulerConnection::take().map_err(QueryError::Io)?;
+		resources_available_deployed(scheduler.0)
+	}
+}
+
+fn resources_available_native() -> Result<Resources, QueryError> {
+	resources_native(false)
+}
+
+fn resources_native(total: bool) -> Result<Resources, QueryError> {
+	let meminfo = fs::read_to_string("/proc/meminfo").map_err(QueryError::Io)?;
+	let key = if total { "MemTotal:" } else { "MemAvailable:" };
+	let mem = meminfo
+		.lines()
+		.find(|line| line.starts_with(key))
+		.and_then(|line| line.split_whitespace().nth(1))
+		.and_then(|kib| kib.parse::<u64>().ok())
+		.ok_or(QueryError::Unsupported)?;
+	let cpu = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+	if cpu <= 0 {
+		return Err(QueryError::Unsupported);
+	}
+	#[allow(clippy::cast_precision_loss)]
+	let mut cpu = cpu as f32;
+	if !total {
+		let loadavg = fs::read_to_string("/proc/loadavg").map_err(QueryError::Io)?;
+		let load = loadavg
+			.split_whitespace()
+			.next()
+			.and_then(|load| load.parse::<f32>().ok())
+			.ok_or(QueryError::Unsupported)?;
+		cpu = (cpu - load).max(0.0);
+	}
+	let disk = statvfs::statvfs(&std::env::temp_dir()).map_err(|_| QueryError::Unsupported)?;
+	#[allow(trivial_numeric_casts)]
+	let disk = if total {
+		disk.blocks() as u64
+	} else {
+		disk.blocks_available() as u64
+	} * disk.fragment_size() as u64;
+	Ok(Resources {
+		mem: mem * 1024,
+		cpu,
+		disk,
+	})
+}
+
+fn resources_available_deployed(scheduler: Fd) -> Result<Resources, QueryError> {
+	let stream = unsafe { net::TcpStream::from_raw_fd(scheduler) };
+	let free = resources_query(&stream);
+	// The connection is returned to the pool rather than closed
+	let _ = stream.into_raw_fd();
+	// An error is all a scheduler that predates the query can reply
+	free.map_err(QueryError::Io)?.map_err(|_| QueryError::Unsupported)
+}
+
+fn resources_query(
+	mut stream: &net::TcpStream,
+) -> Result<Result<Resources, SpawnFailure>, io::Error> {
+	// Shaped like a spawn request so that older schedulers parse it, and then reject it
+	let mut request: Vec<u8> = Vec::new();
+	let config = bincode_config();
+	config
+		.serialize_into(&mut request, &RESOURCES_QUERY)
+		.unwrap();
+	config
+		.serialize_into(&mut request, &None::<NodeId>)
+		.unwrap();
+	config
+		.serialize_into::<_, Vec<OsString>>(&mut request, &vec![])
+		.unwrap();
+	config
+		.serialize_into::<_, Vec<(OsString, OsString)>>(&mut request, &vec![])
+		.unwrap();
+	config
+		.serialize_into(&mut request, &None::<path::PathBuf>)
+		.unwrap();
+	config
+		.serialize_into(&mut request, &None::<path::PathBuf>)
+		.unwrap();
+	// No binary, arg or payload
+	config.serialize_into(&mut request, &0_u64).unwrap();
+	config
+		.serialize_into::<_, Vec<u8>>(&mut request, &vec![])
+		.unwrap();
+	config.serialize_into(&mut request, &0_u64).unwrap();
+	// Written in one go rather than through a BufferedStream, whose writer panics if flushing fails
+	stream.write_all(&request)?;
+	config
+		.deserialize_from(&mut BufferedStream::new(stream))
+		.map_err(map_bincode_err)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn spawn_native(
+	resources: Resources, env: Vec<(OsString, OsString)>,
+	exec: Option<(path::PathBuf, Vec<OsString>)>, dir: Option<path::PathBuf>, spawned: Spawned,
+	start: Start,
+) -> Result<Pid, SpawnError> {
+	trace!("spawn_native");
+	let mut spawn_arg: Vec<u8> = Vec::new();
+	let bridge_pid: Pid = spawned.bridge();
+	let config = bincode_config();
+	config.serialize_into(&mut spawn_arg, &bridge_pid).unwrap();
+	config.serialize_into(&mut spawn_arg, &pid()).unwrap();
+	config
+		.serialize_into(&mut spawn_arg, &start)
+		.map_err(map_serialize_err)?;
+
+	// Opened before forking so that a bad directory fails the spawn rather than the child
+	let dir = match dir {
+		Some(dir) => Some(
+			fcntl::open(
+				&dir,
+				fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_DIRECTORY | fcntl::OFlag::O_CLOEXEC,
+				stat::Mode::empty(),
+			)
+			.map_err(|_| SpawnError::InvalidDirectory(dir))?,
+		),
+		None => None,
+	};
+	let argv: Vec<CString> = exec
+		.as_ref()
+		.map_or_else(
+			|| get_env::args_os().expect("Couldn't get argv"),
+			|&(ref path, ref args)| exec_argv(path, args),
+		)
+		.iter()
+		.map(|x| CString::new(OsStringExt::into_vec(x.clone())).unwrap())
+		.collect(); // argv.split('\0').map(|x|CString::new(x).unwrap()).collect();
+	let envp: Vec<(CString, CString)> = env
+		.iter()
+		.map(|&(ref x, ref y)| {
+			(
+				CString::new(OsStringExt::into_vec(x.clone())).unwrap(),
+				CString::new(OsStringExt::into_vec(y.clone())).unwrap(),
+			)
+		})
+		.chain(iter::once((
+			CString::new("CONSTELLATION_RESOURCES").unwrap(),
+			CString::new(serde_json::to_string(&resources).unwrap()).unwrap(),
+		)))
+		.collect(); //envp.split('\0').map(|x|{let (a,b) = x.split_at(x.chars().position(|x|x=='=').unwrap_or_else(||panic!("invalid envp {:?}", x)));(CString::new(a).unwrap(),CString::new(&b[1..]).unwrap())}).collect();
+
+	let (process_listener, process_id) = native_process_listener();
+
+	let mut arg = unsafe {
+		fs::File::from_raw_fd(memfd_create(&argv[0], false).expect("Failed to memfd_create"))
+	};
+	// assert_eq!(arg.as_raw_fd(), ARG_FD);
+	let payload = start.payload();
+	unistd::ftruncate(
+		arg.as_raw_fd(),
+		(spawn_arg.len() + payload.len()).try_into().unwrap(),
+	)
+	.unwrap();
+	arg.write_all(&spawn_arg).unwrap();
+	arg.write_all(payload).unwrap();
+	let x = unistd::lseek(arg.as_raw_fd(), 0, unistd::Whence::SeekSet).unwrap();
+	assert_eq!(x, 0);
+
+	let exe = CString::new(<OsString as OsStringExt>::into_vec(
+		exec.as_ref()
+			.map_or_else(|| exe_path().unwrap(), |&(ref path, _)| path.clone())
+			.into(),
+		// std::env::current_exe().unwrap().into(),
+	))
+	.unwrap();
+	let envp = envp
+		.into_iter()
+		.map(|(key, value)| {
+			CString::new(format!(
+				"{}={}",
+				key.to_str().unwrap(),
+				value.to_str().unwrap()
+			))
+			.unwrap()
+		})
+		.collect::<Vec<_>>();
+
+	let child_pid = platform::Native::spawn(&platform::Command {
+		exe: &exe,
+		argv: &argv,
+		envp: &envp,
+		dir,
+		mem_limit: if *MEM_LIMIT.read().unwrap() {
+			Some(resources.mem)
+		} else {
+			None
+		},
+		fds: &[(process_listener, LISTENER_FD), (arg.as_raw_fd(), ARG_FD)],
+		reexec: exec.is_none(),
+	})
+	.map_err(|err| {
+		unistd::close(process_listener).unwrap();
+		if let Some(dir) = dir {
+			unistd::close(dir).unwrap();
+		}
+		SpawnError::Fork(err)
+	})?;
+	unistd::close(process_listener).unwrap();
+	if let Some(dir) = dir {
+		unistd::close(dir).unwrap();
+	}
+	drop(arg);
+	let new_pid = Pid::new(loopback(), process_id);
+	// BRIDGE.read().unwrap().as_ref().unwrap().0.send(ProcessOutputEvent::Spawn(new_pid)).unwrap();
+	report_spawn(new_pid, spawned).map_err(|err| {
+		// Its output would go nowhere, so don't leave it running
+		platform::Native::kill(child_pid);
+		err
+	})?;
+	Ok(new_pid)
+}
+
+fn spawn_deployed(
+	scheduler: Fd, resources: Resources, node: Option<NodeId>, env: Vec<(OsString, OsString)>,
+	exec: Option<(path::PathBuf, Vec<OsString>)>, dir: Option<path::PathBuf>, spawned: Spawned,
+	start: Start,
+) -> Result<Pid, SpawnError> {
+	trace!("spawn_deployed");
+	// Serialized before anything is sent, so a failure doesn't leave the scheduler mid-request
+	let mut arg_: Vec<u8> = Vec::new();
+	let bridge_pid: Pid = spawned.bridge();
+	let config = bincode_config();
+	config.serialize_into(&mut arg_, &bridge_pid).unwrap();
+	config.serialize_into(&mut arg_, &pid()).unwrap();
+	config
+		.serialize_into(&mut arg_, &start)
+		.map_err(map_serialize_err)?;
+	let stream = unsafe { net::TcpStream::from_raw_fd(scheduler) };
+	// The binary and payload are written directly, but argv, envp and the serialized closure can be large too
+	let (mut stream_read, mut stream_write) = (
+		BufferedStream::new(&stream),
+		BufferedStream::with_capacity(&stream, 0, SCHEDULER_BUFFER),
+	);
+	let mut stream_write_ = stream_write.write();
+	let binary = if !is_valgrind() {
+		exe().unwrap()
+	} else {
+		unsafe {
+			fs::File::from_raw_fd(
+				fcntl::open(
+					&fd_path(valgrind_start_fd()).unwrap(),
+					fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_CLOEXEC,
+					stat::Mode::empty(),
+				)
+				.unwrap(),
+			)
+		}
+	};
+	// A different executable is run from the node rather than sent
+	let len: u64 = if exec.is_none() {
+		binary.metadata().unwrap().len()
+	} else {
+		0
+	};
+	bincode_config()
+		.serialize_into(&mut stream_write_, &resources)
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &node)
+		.unwrap();
+	bincode_config()
+		.serialize_into::<_, Vec<OsString>>(
+			&mut stream_write_,
+			&exec.as_ref().map_or_else(
+				|| get_env::args_os().expect("Couldn't get argv"),
+				|&(ref path, ref args)| exec_argv(path, args),
+			),
+		)
+		.unwrap();
+	bincode_config()
+		.serialize_into::<_, Vec<(OsString, OsString)>>(&mut stream_write_, &env)
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &exec.map(|(path, _)| path))
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &dir)
+		.unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &len)
+		.unwrap();
+	if len > 0 {
+		bincode_config()
+			.serialize_into(&mut stream_write_, &file_hash(&binary).unwrap())
+			.unwrap();
+	}
+	drop(stream_write_);
+	// The scheduler caches binaries, so it's only sent if the scheduler doesn't have it already
+	if len > 0
+		&& !bincode_config()
+			.deserialize_from::<_, bool>(&mut stream_read)
+			.map_err(map_bincode_err)
+			.unwrap()
+	{
+		// copy(&mut &binary, &mut stream_write_, len as usize).unwrap();
+		copy_sendfile(&binary, &**stream_write.get_ref(), len).unwrap();
+	}
+	let mut stream_write_ = stream_write.write();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &arg_)
+		.unwrap();
+	let payload = start.payload();
+	let payload_len: u64 = payload.len().try_into().unwrap();
+	bincode_config()
+		.serialize_into(&mut stream_write_, &payload_len)
+		.unwrap();
+	if payload_len > 0 {
+		bincode_config()
+			.serialize_into(&mut stream_write_, &bytes_hash(payload))
+			.unwrap();
+	}
+	drop(stream_write_);
+	// Payloads are cached like binaries
+	if payload_len > 0
+		&& !bincode_config()
+			.deserialize_from::<_, bool>(&mut stream_read)
+			.map_err(map_bincode_err)
+			.unwrap()
+	{
+		(&**stream_write.get_ref()).write_all(payload).unwrap();
+	}
+	let pid: Result<Pid, SpawnFailure> = bincode_config()
+		.deserialize_from(&mut stream_read)
+		.map_err(map_bincode_err)
+		.unwrap();
+	drop(stream_read);
+	trace!("{} spawned? {:?}", self::pid(), pid);
+	let _ = stream.into_raw_fd();
+	let pid = pid.map_err(|failure| match (failure, dir) {
+		(SpawnFailure::Directory, Some(dir)) => SpawnError::InvalidDirectory(dir),
+		(SpawnFailure::Directory, None) => unreachable!(),
+		(_, _) if node.is_some() => SpawnError::NodeUnavailable(node.unwrap()),
+		(SpawnFailure::Resources, _) => {
+			SpawnError::InsufficientResources(resources_available_deployed(scheduler).ok())
+		}
+		(SpawnFailure::Unsatisfiable, _) => SpawnError::Unsatisfiable(None),
+	})?;
+	report_spawn(pid, spawned)?;
+	Ok(pid)
+}
+
+/// Tell the monitor on allocation failure, and exit with [`EXIT_MEM_LIMIT`], so the monitor can report it as exceeding the memory limit. It doesn't allocate. A process exiting with [`EXIT_MEM_LIMIT`] itself isn't mistaken for this, as it doesn't write to the pipe.
+fn mem_limit_hook(_layout: alloc::Layout) {
+	let msg = b"memory allocation failed: exceeded the mem given in Resources\n";
+	let _ = unistd::write(libc::STDERR_FILENO, msg);
+	let fd = MEM_LIMIT_FD.load(sync::atomic::Ordering::Relaxed) as Fd;
+	let _ = unistd::write(fd, &[0]);
+	unsafe { libc::_exit(EXIT_MEM_LIMIT.into()) }
+}
+
+/// Whether this process's data segment is limited, as [`spawn_native()`](spawn_native) does to the processes it spawns under `CONSTELLATION_MEM_LIMIT`.
+fn data_limited() -> bool {
+	let mut limit: libc::rlimit = unsafe { mem::zeroed() };
+	let err = unsafe { libc::getrlimit(libc::RLIMIT_DATA, &mut limit) };
+	assert_eq!(err, 0);
+	limit.rlim_cur != libc::RLIM_INFINITY
+}
+
+fn map_serialize_err(err: bincode::Error) -> SpawnError {
+	SpawnError::Serialize(err.to_string())
+}
+
+/// How a newly spawned process is reported to the bridge.
+#[derive(Copy, Clone, Debug)]
+enum Spawned {
+	/// A process that reports to this process's bridge, and whether it's detached.
+	Process(bool),
+	/// A child that reports to the given relay bridge rather than to this process's bridge.
+	Relayed(Pid),
+	/// A relay bridge, spawned by [`spawn_relay()`](spawn_relay).
+	Relay,
+}
+impl Spawned {
+	/// The bridge the new process reports to.
+	fn bridge(self) -> Pid {
+		match self {
+			Spawned::Relayed(relay) => relay,
+			Spawned::Process(_) | Spawned::Relay => BRIDGE.read().unwrap().unwrap(),
+		}
+	}
+}
+
+/// Tell the monitor, and through it the bridge, of a newly spawned process. The monitor stops listening once it finds the bridge has exited, in which case this fails.
+fn report_spawn(new_pid: Pid, spawned: Spawned) -> Result<(), SpawnError> {
+	let event = match spawned {
+		Spawned::Process(detached) => ProcessOutputEvent::Spawn(new_pid, detached),
+		Spawned::Relayed(relay) => ProcessOutputEvent::RelayedSpawn(new_pid, relay),
+		Spawned::Relay => ProcessOutputEvent::SpawnRelay(new_pid),
+	};
+	report(&event).map_err(|_| SpawnError::BridgeExited)
+}
+
+fn report(event: &ProcessOutputEvent) -> Result<(), io::Error> {
+	// Length-prefixed so the monitor can skip a corrupt frame
+	let event = bincode_config().serialize(event).unwrap();
+	let mut frame = bincode_config().serialize(&(event.len() as u64)).unwrap();
+	frame.extend(event);
+	// Locked so frames from different threads don't interleave. Ignore poisoning, as this is called from the panic hook
+	let _monitor = MONITOR.lock().unwrap_or_else(sync::PoisonError::into_inner);
+	let file = unsafe { fs::File::from_raw_fd(MONITOR_FD) };
+	let ret = (&file).write_all(&frame);
+	let _ = file.into_raw_fd();
+	ret
+}
+
+fn panic_hook(capture: bool) {
+	let default = panic::take_hook();
+	panic::set_hook(Box::new(move |info| {
+		let mut panic = format!("{}\n{:?}", info, backtrace::Backtrace::new());
+		let mut max = MONITOR_FRAME_MAX as usize - 64; // leave room for the framing
+		if panic.len() > max {
+			while !panic.is_char_boundary(max) {
+				max -= 1;
+			}
+			panic.truncate(max);
+		}
+		let _ = report(&ProcessOutputEvent::Panic(panic));
+		if !capture {
+			default(info);
+		}
+	}));
+}
+
+/// Error returned by [`try_spawn()`](try_spawn).
+#[derive(Debug)]
+pub enum SpawnError {
+	/// The requested resources can never be satisfied: `mem` is zero, or `cpu` isn't a positive finite number.
+	InvalidResources(Resources),
+	/// The requested resources exceed those available on the cluster right now, though they'd fit were other processes to exit. This only happens when deployed. Contains the resources that were available, if they could be determined.
+	InsufficientResources(Option<Resources>),
+	/// The requested resources exceed those of the local machine when run natively, or of every node of the cluster when deployed, so can never be satisfied. Contains the resources of the local machine when run natively.
+	Unsatisfiable(Option<Resources>),
+	/// [`spawn_on()`](spawn_on) named a node that isn't part of the cluster, or that can't satisfy the requested resources.
+	NodeUnavailable(NodeId),
+	/// [`spawn_on()`](spawn_on) was called when running natively, where there's no choice of node.
+	PlacementUnsupported,
+	/// The bridge, which forwards this process's output and tracks the processes it spawns, has exited. This process can't spawn any more processes. As it's noticed when the bridge is next sent to, a spawn shortly after the bridge exits may still succeed, though its output is lost.
+	BridgeExited,
+	/// The working directory given to [`spawn_in_dir()`](spawn_in_dir) couldn't be opened: it doesn't exist, isn't a directory, or isn't accessible.
+	InvalidDirectory(path::PathBuf),
+	/// The closure or payload the new process is started with failed to serialize. Contains the serializer's error message.
+	Serialize(String),
+	/// Forking the new process failed when run natively. An [`io::ErrorKind::WouldBlock`] error (`EAGAIN`) means a process limit was reached, which may be temporary.
+	Fork(io::Error),
+	/// Opening a new connection to the scheduler failed or timed out, when deployed with `CONSTELLATION_SPAWN_CONCURRENCY` above `1`. The timeout is set by `CONSTELLATION_SCHEDULER_TIMEOUT`.
+	SchedulerConnect(io::Error),
+}
+impl SpawnError {
+	/// Whether the spawn might succeed if retried: the resources requested were momentarily unavailable on the cluster, a process limit was reached when forking, or connecting to the scheduler timed out or was refused or reset. [`spawn_retry()`](spawn_retry) retries these.
+	///
+	/// Resources that can never be satisfied, [`SpawnError::Unsatisfiable`], aren't transient.
+	pub fn is_transient(&self) -> bool {
+		match *self {
+			SpawnError::InsufficientResources(_) => true,
+			SpawnError::Fork(ref err) => err.kind() == io::ErrorKind::WouldBlock,
+			SpawnError::SchedulerConnect(ref err) => match err.kind() {
+				io::ErrorKind::TimedOut
+				| io::ErrorKind::ConnectionRefused
+				| io::ErrorKind::ConnectionReset
+				| io::ErrorKind::ConnectionAborted
+				| io::ErrorKind::Interrupted => true,
+				_ => false,
+			},
+			_ => false,
+		}
+	}
+}
+impl fmt::Display for SpawnError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SpawnError::InvalidResources(ref resources) => {
+				write!(f, "Invalid resources requested: {:?}", resources)
+			}
+			SpawnError::InsufficientResources(Some(ref available)) => write!(
+				f,
+				"Insufficient resources to spawn; available: {:?}",
+				available
+			),
+			SpawnError::InsufficientResources(None) => write!(f, "Insufficient resources to spawn"),
+			SpawnError::Unsatisfiable(Some(ref total)) => write!(
+				f,
+				"Requested resources can never be satisfied; total: {:?}",
+				total
+			),
+			SpawnError::Unsatisfiable(None) => {
+				write!(f, "Requested resources can never be satisfied")
+			}
+			SpawnError::NodeUnavailable(ref node) => {
+				write!(f, "Node {} can't satisfy the requested resources", node)
+			}
+			SpawnError::PlacementUnsupported => {
+				write!(f, "Placement on a node is only supported when deployed")
+			}
+			SpawnError::BridgeExited => write!(f, "Bridge exited"),
+			SpawnError::InvalidDirectory(ref dir) => {
+				write!(f, "Invalid working directory: {}", dir.display())
+			}
+			SpawnError::Serialize(ref err) => write!(f, "Failed to serialize start: {}", err),
+			SpawnError::Fork(ref err) => write!(f, "Fork failed: {}", err),
+			SpawnError::SchedulerConnect(ref err) => {
+				write!(f, "Failed to connect to the scheduler: {}", err)
+			}
+		}
+	}
+}
+impl error::Error for SpawnError {
+	fn description(&self) -> &str {
+		match *self {
+			SpawnError::InvalidResources(_) => "invalid resources requested",
+			SpawnError::InsufficientResources(_) => "insufficient resources to spawn",
+			SpawnError::Unsatisfiable(_) => "requested resources can never be satisfied",
+			SpawnError::NodeUnavailable(_) => "node can't satisfy the requested resources",
+			SpawnError::PlacementUnsupported => {
+				"placement on a node is only supported when deployed"
+			}
+			SpawnError::BridgeExited => "bridge exited",
+			SpawnError::InvalidDirectory(_) => "invalid working directory",
+			SpawnError::Serialize(_) => "failed to serialize start",
+			SpawnError::Fork(_) => "fork failed",
+			SpawnError::SchedulerConnect(_) => "failed to connect to the scheduler",
+		}
+	}
+}
+
+fn resources_valid(resources: Resources) -> bool {
+	resources.mem > 0 && resources.cpu > 0.0 && resources.cpu.is_finite()
 }
 
 /// Spawn a new process.
@@ -686,57 +2672,757 @@ fn spawn_deployed(
 ///  * `resources`: memory and CPU resource requirements of the new process
 ///  * `start`: the closure to be run in the new process
 ///
-/// `spawn()` returns an Option<Pid>, which contains the [Pid] of the new process.
+/// `spawn()` returns an Option<Pid>, which contains the [Pid] of the new process. Use [`try_spawn()`](try_spawn) to find out why a spawn failed.
 pub fn spawn<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
 	resources: Resources, start: T,
 ) -> Option<Pid> {
-	let _scheduler = SCHEDULER.lock().unwrap();
-	let deployed = DEPLOYED.read().unwrap().unwrap_or_else(|| {
-		panic!("You must call init() immediately inside your application's main() function")
+	spawn_with_env(resources, Vec::new(), start)
+}
+
+/// Spawn a new process, returning why if it couldn't be.
+///
+/// This is the same as [`spawn()`](spawn), except impossible resource requests – zero memory, a non-positive CPU requirement, or more than the local machine has when run natively – are rejected up front with [`SpawnError::InvalidResources`] or [`SpawnError::Unsatisfiable`], and a rejection by the scheduler is returned as [`SpawnError::InsufficientResources`], or [`SpawnError::Unsatisfiable`] if no node could ever fit the process.
+pub fn try_spawn<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	resources: Resources, start: T,
+) -> Result<Pid, SpawnError> {
+	spawn_inner(resources, None, Vec::new(), None, false, None, start)
+}
+
+/// Spawn a new process on a particular node.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except the scheduler must place the new process on `node`. If `node` can't satisfy `resources`, it fails with [`SpawnError::NodeUnavailable`] rather than placing it elsewhere. This lets processes be placed near the data they use: [`Pid::node()`](Pid::node) gives the node of an existing process.
+///
+/// When run natively there is only the local node, and this fails with [`SpawnError::PlacementUnsupported`].
+pub fn spawn_on<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	node: NodeId, resources: Resources, start: T,
+) -> Result<Pid, SpawnError> {
+	spawn_inner(resources, Some(node), Vec::new(), None, false, None, start)
+}
+
+/// Spawn a new process with additional environment variables.
+///
+/// This is the same as [`spawn()`](spawn), except the new process's environment is this process's environment overlaid with `env`. Where a key is in both, the value from `env` is used. Keys beginning with `CONSTELLATION` are reserved for the runtime, and are ignored.
+pub fn spawn_with_env<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	resources: Resources, env: Vec<(OsString, OsString)>, start: T,
+) -> Option<Pid> {
+	spawn_inner(resources, None, env, None, false, None, start).ok()
+}
+
+/// Spawn a new process in a particular working directory.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except the new process starts in `dir` rather than the directory it would otherwise inherit. A relative `dir` is relative to that inherited directory: this process's working directory when run natively, or the directory `constellation` was started in on the node when deployed.
+///
+/// When run natively, a `dir` that can't be opened fails with [`SpawnError::InvalidDirectory`] before anything is started. When deployed, the node checks `dir` before starting the process, and the spawn likewise fails with [`SpawnError::InvalidDirectory`].
+pub fn spawn_in_dir<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	resources: Resources, dir: path::PathBuf, start: T,
+) -> Result<Pid, SpawnError> {
+	spawn_inner(resources, None, Vec::new(), Some(dir), false, None, start)
+}
+
+/// Spawn a new process that isn't a child of this process.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except the new process is detached from this one, for work such as a long-lived service that should carry on regardless of what this process does:
+///  * It isn't tracked by this process: it isn't included in [`children()`](children), [`wait()`](wait) and [`on_exit()`](on_exit) return [`WaitError::NotAChild`] for it, and this process can't kill it.
+///  * This process isn't notified of its exit, so nothing reaps it on this process's behalf: it's reported as exiting to the deployment like any other process, and its exit status is otherwise discarded.
+///
+/// It still runs as part of this deployment, so it's killed when the deployment ends, as every process is. A process already outlives the process that spawned it – it's the process's own monitor, not its parent, that it's killed along with – so detaching only affects tracking.
+///
+/// Its `start` is still passed the [Pid] of this process, so the two can communicate over channels as usual.
+pub fn spawn_detached<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	resources: Resources, start: T,
+) -> Result<Pid, SpawnError> {
+	spawn_inner(resources, None, Vec::new(), None, true, None, start)
+}
+
+/// Spawn a relay bridge, which processes spawned through it report to rather than to this process's bridge.
+///
+/// Every process reports its spawns, output and exit to a bridge, which by default is the one the deployment was started with. A program that spawns many processes can instead spread that load over a tree of relays: processes spawned with [`Relay::spawn()`](Relay::spawn), and the processes they in turn spawn, report to the relay, which passes their events on up to the bridge of the process that spawned it.
+///
+/// The new relay is reserved `resources`, and is detached from this process as [`spawn_detached()`](spawn_detached) processes are. It exits once this process has exited and the processes reporting to it have all exited. Processes spawned through it are children of their spawner as usual, so can be waited for and killed.
+///
+/// A relay can be spawned through by this process, and by other processes reporting to the same bridge, but not by the processes reporting to the relay itself: they can spawn relays of their own.
+///
+/// When run with the `test-transport` feature there are no bridges, and processes spawned through a relay are spawned as with [`try_spawn()`](try_spawn).
+pub fn spawn_relay(resources: Resources) -> Result<Relay, SpawnError> {
+	spawn_relay_inner(resources, None)
+}
+
+/// Spawn a relay bridge on a particular node.
+///
+/// This is the same as [`spawn_relay()`](spawn_relay), except the relay is placed on `node`, as [`spawn_on()`](spawn_on) places processes.
+pub fn spawn_relay_on(node: NodeId, resources: Resources) -> Result<Relay, SpawnError> {
+	spawn_relay_inner(resources, Some(node))
+}
+
+fn spawn_relay_inner(resources: Resources, node: Option<NodeId>) -> Result<Relay, SpawnError> {
+	if cfg!(feature = "test-transport") {
+		return Ok(Relay(None));
+	}
+	spawn_start(
+		resources,
+		node,
+		Vec::new(),
+		None,
+		None,
+		true,
+		None,
+		Start::Relay,
+	)
+	.map(|pid| Relay(Some(pid)))
+}
+
+/// A relay bridge, returned by [`spawn_relay()`](spawn_relay), that processes can be spawned to report to.
+#[derive(Copy, Clone, Debug)]
+pub struct Relay(Option<Pid>);
+impl Relay {
+	/// The [Pid] of the relay, or `None` when run with the `test-transport` feature.
+	pub fn pid(&self) -> Option<Pid> {
+		self.0
+	}
+
+	/// Spawn a new process that reports to this relay.
+	///
+	/// This is the same as [`try_spawn()`](try_spawn), except the new process, and the processes it spawns, report to the relay rather than to this process's bridge.
+	pub fn spawn<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+		&self, resources: Resources, start: T,
+	) -> Result<Pid, SpawnError> {
+		spawn_inner(resources, None, Vec::new(), None, false, self.0, start)
+	}
+
+	/// Spawn a new process on a particular node that reports to this relay.
+	///
+	/// This is the same as [`Relay::spawn()`](Relay::spawn), except the new process is placed on `node`, as with [`spawn_on()`](spawn_on).
+	pub fn spawn_on<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+		&self, node: NodeId, resources: Resources, start: T,
+	) -> Result<Pid, SpawnError> {
+		spawn_inner(
+			resources,
+			Some(node),
+			Vec::new(),
+			None,
+			false,
+			self.0,
+			start,
+		)
+	}
+}
+
+/// How [`spawn_retry()`](spawn_retry) retries.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RetryPolicy {
+	/// The most attempts made, including the first.
+	pub attempts: usize,
+	/// The wait before the first retry. It doubles with each subsequent retry.
+	pub backoff: time::Duration,
+	/// The longest wait between attempts.
+	pub max_backoff: time::Duration,
+}
+impl Default for RetryPolicy {
+	/// 5 attempts, waiting 100ms then doubling up to 5s.
+	fn default() -> Self {
+		RetryPolicy {
+			attempts: 5,
+			backoff: time::Duration::from_millis(100),
+			max_backoff: time::Duration::from_secs(5),
+		}
+	}
+}
+
+/// Spawn a new process, retrying if it fails transiently.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except failures for which [`SpawnError::is_transient()`](SpawnError::is_transient) is true – the cluster's resources being momentarily taken, or a process limit being hit – are retried per `policy`, with exponential backoff. Other failures, or the last transient one, are returned.
+pub fn spawn_retry<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned + Clone>(
+	resources: Resources, start: T, policy: RetryPolicy,
+) -> Result<Pid, SpawnError> {
+	let mut backoff = policy.backoff;
+	let mut attempt = 1;
+	loop {
+		match spawn_inner(
+			resources,
+			None,
+			Vec::new(),
+			None,
+			false,
+			None,
+			start.clone(),
+		) {
+			Err(ref err) if err.is_transient() && attempt < policy.attempts => {
+				debug!(
+					"spawn failed transiently, retrying in {:?}: {}",
+					backoff, err
+				);
+				thread::sleep(backoff);
+				backoff = (backoff * 2).min(policy.max_backoff);
+				attempt += 1;
+			}
+			ret => break ret,
+		}
+	}
+}
+
+/// Spawn a new process, returning a [ChildGuard] that kills it when dropped.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except the new process is killed if the guard is dropped – including during unwinding, should this process panic – before it has exited. Use [`ChildGuard::into_pid()`](ChildGuard::into_pid) to let it outlive the guard.
+pub fn spawn_guarded<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	resources: Resources, start: T,
+) -> Result<ChildGuard, SpawnError> {
+	spawn_inner(resources, None, Vec::new(), None, false, None, start).map(ChildGuard)
+}
+
+/// Spawn a new process that receives a stream of inputs from this process and sends back outputs.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except rather than the [Pid] of this process, `start` is passed a `Receiver<In>` and a `Sender<Out>` to it. This process is returned the matching `Sender<In>` and `Receiver<Out>`, so neither side needs to create channels itself. As only one [Sender] and one [Receiver] can exist per remote process, neither side can create further channels to the other.
+pub fn spawn_worker<
+	In: serde::ser::Serialize + serde::de::DeserializeOwned + 'static,
+	Out: serde::ser::Serialize + serde::de::DeserializeOwned + 'static,
+	T: FnOnce(Receiver<In>, Sender<Out>) + serde::ser::Serialize + serde::de::DeserializeOwned,
+>(
+	resources: Resources, start: T,
+) -> Result<(Sender<In>, Receiver<Out>), SpawnError> {
+	let arg: Vec<u8> = bincode_config()
+		.serialize(&start)
+		.map_err(map_serialize_err)?;
+	let start: serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid,))> = FnOnce!([arg]move|parent|{
+		let arg: Vec<u8> = arg;
+		let closure: T = bincode_config().deserialize(&arg).unwrap();
+		closure(Receiver::<In>::new(parent), Sender::<Out>::new(parent))
 	});
-	let arg: Vec<u8> = bincode::serialize(&start).unwrap();
+	let pid = spawn_start(
+		resources,
+		None,
+		Vec::new(),
+		None,
+		None,
+		false,
+		None,
+		Start::Closure(start),
+	)?;
+	Ok((Sender::new(pid), Receiver::new(pid)))
+}
+
+/// Spawn a new process, handing it `payload` separately from `start`.
+///
+/// This is the same as [`try_spawn()`](try_spawn), except `start` is also passed `payload`. Rather than being captured by `start` and serialized with it, `payload` is sent as it is alongside it, which suits a large dataset a process needs when it starts. When deployed, the scheduler caches payloads as it does binaries, so spawning many processes with the same payload only sends it once.
+pub fn spawn_with_payload<
+	T: FnOnce(Pid, Vec<u8>) + serde::ser::Serialize + serde::de::DeserializeOwned,
+>(
+	resources: Resources, payload: Vec<u8>, start: T,
+) -> Result<Pid, SpawnError> {
+	let arg: Vec<u8> = bincode_config()
+		.serialize(&start)
+		.map_err(map_serialize_err)?;
+	let start: serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid, Vec<u8>))> = FnOnce!([arg]move|parent,payload|{
+		let arg: Vec<u8> = arg;
+		let closure: T = bincode_config().deserialize(&arg).unwrap();
+		closure(parent, payload)
+	});
+	spawn_start(
+		resources,
+		None,
+		Vec::new(),
+		None,
+		None,
+		false,
+		None,
+		Start::ClosureWithPayload(start, payload),
+	)
+}
+
+/// Kills the process it guards when dropped, unless it has already exited. Returned by [`spawn_guarded()`](spawn_guarded).
+#[derive(Debug)]
+pub struct ChildGuard(Pid);
+impl ChildGuard {
+	/// The [Pid] of the guarded process.
+	pub fn pid(&self) -> Pid {
+		self.0
+	}
+
+	/// Release the guarded process, so it's no longer killed when this is dropped.
+	pub fn into_pid(self) -> Pid {
+		let pid = self.0;
+		mem::forget(self);
+		pid
+	}
+}
+impl Drop for ChildGuard {
+	fn drop(&mut self) {
+		// Ignore poisoning, as this may run while unwinding
+		let exited = CHILDREN
+			.0
+			.lock()
+			.unwrap_or_else(sync::PoisonError::into_inner)
+			.get(&self.0)
+			.map_or(true, Option::is_some);
+		if !exited {
+			// Fails only if the monitor has exited, in which case so has the child
+			let _ = report(&ProcessOutputEvent::Kill(self.0));
+		}
+	}
+}
+
+/// Spawn a new process from a different executable.
+///
+/// The closures passed to [`spawn()`](spawn) can only be run by the executable they're compiled into, so instead the new process is given `payload`. The executable must call [`init()`](init) like any other, after which [`exec_payload()`](exec_payload) returns the [Pid] of this process and `payload`, and its `main()` continues from there.
+///
+/// Constraints:
+///  * The executable must use this same version of this crate, so it can decode what it's started with.
+///  * When deployed, `path` is run from the node the scheduler places it on, rather than sent like this executable is, so it must already be on every node `resources` could be satisfied by. An absolute path is best; a relative one is relative to where `constellation` was started. If it can't be run there, the new process exits with an error.
+///  * The new process's `argv` is `path` followed by `args`. Its environment is this process's, as with [`spawn()`](spawn).
+pub fn spawn_exec(
+	resources: Resources, path: path::PathBuf, args: Vec<OsString>, payload: Vec<u8>,
+) -> Result<Pid, SpawnError> {
+	spawn_start(
+		resources,
+		None,
+		Vec::new(),
+		Some((path, args)),
+		None,
+		false,
+		None,
+		Start::Payload(payload),
+	)
+}
+
+/// Get the [Pid] of the process that spawned this one with [`spawn_exec()`](spawn_exec), and the payload it gave. `None` if this process wasn't spawned by `spawn_exec()`.
+pub fn exec_payload() -> Option<(Pid, Vec<u8>)> {
+	EXEC_PAYLOAD.read().unwrap().clone()
+}
+
+/// What a newly spawned process runs.
+#[derive(Serialize, Deserialize)]
+enum Start {
+	Closure(serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid,))>),
+	/// Spawned by [`spawn_exec()`](spawn_exec), so a different executable that returns from [`init()`](init).
+	Payload(Vec<u8>),
+	/// Spawned by [`spawn_with_payload()`](spawn_with_payload). The payload is sent after the serialized `Start` rather than as part of it.
+	ClosureWithPayload(
+		serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid, Vec<u8>))>,
+		#[serde(skip)] Vec<u8>,
+	),
+	/// Spawned by [`spawn_relay()`](spawn_relay), so a relay bridge rather than a process running user code.
+	Relay,
+}
+impl Start {
+	/// What's sent after the serialized `Start`.
+	fn payload(&self) -> &[u8] {
+		match *self {
+			Start::ClosureWithPayload(_, ref payload) => payload,
+			Start::Closure(_) | Start::Payload(_) | Start::Relay => &[],
+		}
+	}
+}
+
+fn exec_argv(path: &path::Path, args: &[OsString]) -> Vec<OsString> {
+	iter::once(path.as_os_str().to_owned())
+		.chain(args.iter().cloned())
+		.collect()
+}
+
+fn spawn_inner<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+	resources: Resources, node: Option<NodeId>, env: Vec<(OsString, OsString)>,
+	dir: Option<path::PathBuf>, detached: bool, relay: Option<Pid>, start: T,
+) -> Result<Pid, SpawnError> {
+	let arg: Vec<u8> = bincode_config()
+		.serialize(&start)
+		.map_err(map_serialize_err)?;
 	let start: serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid,))> = FnOnce!([arg]move|parent|{
 		let arg: Vec<u8> = arg;
-		let closure: T = bincode::deserialize(&arg).unwrap();
+		let closure: T = bincode_config().deserialize(&arg).unwrap();
 		closure(parent)
 	});
-	if !deployed {
-		spawn_native(resources, start)
+	spawn_start(
+		resources,
+		node,
+		env,
+		None,
+		dir,
+		detached,
+		relay,
+		Start::Closure(start),
+	)
+}
+
+#[cfg_attr(feature = "test-transport", allow(unreachable_code))]
+fn spawn_start(
+	resources: Resources, node: Option<NodeId>, env: Vec<(OsString, OsString)>,
+	exec: Option<(path::PathBuf, Vec<OsString>)>, dir: Option<path::PathBuf>, detached: bool,
+	relay: Option<Pid>, start: Start,
+) -> Result<Pid, SpawnError> {
+	if !resources_valid(resources) {
+		return Err(SpawnError::InvalidResources(resources));
+	}
+	if RECCE.0.lock().unwrap().is_some() {
+		return Ok(recce_spawn(resources, start));
+	}
+	#[cfg(feature = "test-transport")]
+	{
+		if node.is_some() {
+			return Err(SpawnError::PlacementUnsupported);
+		}
+		assert!(
+			exec.is_none(),
+			"Spawning a different executable isn't supported by the test-transport feature"
+		);
+		return Ok(test_transport_spawn(detached, start));
+	}
+	let scheduler = SchedulerConnection::take().map_err(SpawnError::SchedulerConnect)?;
+	let deployed = DEPLOYED.read().unwrap().unwrap_or_else(|| {
+		panic!("You must call init() immediately inside your application's main() function")
+	});
+	let env = overlay_env(get_env::vars_os().expect("Couldn't get envp"), env);
+	let spawned = match (&start, relay) {
+		(&Start::Relay, _) => Spawned::Relay,
+		(_, Some(relay)) => Spawned::Relayed(relay),
+		(_, None) => Spawned::Process(detached),
+	};
+	let pid = if !deployed {
+		if node.is_some() {
+			return Err(SpawnError::PlacementUnsupported);
+		}
+		if let Ok(total) = resources_native(true) {
+			if resources.mem > total.mem || resources.cpu > total.cpu || resources.disk > total.disk
+			{
+				return Err(SpawnError::Unsatisfiable(Some(total)));
+			}
+		}
+		spawn_native(resources, env, exec, dir, spawned, start)?
+	} else {
+		spawn_deployed(
+			scheduler.0,
+			resources,
+			node,
+			env,
+			exec,
+			dir,
+			spawned,
+			start,
+		)?
+	};
+	if !detached {
+		CHILDREN.0.lock().unwrap().spawned(pid);
+	}
+	let _ = METRICS
+		.spawned
+		.fetch_add(1, sync::atomic::Ordering::Relaxed);
+	Ok(pid)
+}
+
+fn overlay_env(
+	mut env: Vec<(OsString, OsString)>, overlay: Vec<(OsString, OsString)>,
+) -> Vec<(OsString, OsString)> {
+	for (key, value) in overlay {
+		if key.to_string_lossy().starts_with("CONSTELLATION") {
+			continue;
+		}
+		if let Some(&mut (_, ref mut value_)) =
+			env.iter_mut().find(|&&mut (ref key_, _)| *key_ == key)
+		{
+			*value_ = value;
+		} else {
+			env.push((key, value));
+		}
+	}
+	env
+}
+
+/// Run `f`, waiting for all processes it spawns with [`Scope::spawn()`](Scope::spawn) to exit before returning.
+///
+/// This is the process counterpart of scoped threads: it ensures no spawned process outlives the scope, rather than being orphaned if the parent forgets to wait for it. The processes are also waited for if `f` panics.
+///
+/// Returns [`ScopeError`] with the [Pid] and [ExitStatus] of each process that exited unsuccessfully.
+pub fn scope<F: FnOnce(&Scope) -> R, R>(f: F) -> Result<R, ScopeError> {
+	let scope = Scope {
+		pids: cell::RefCell::new(Vec::new()),
+	};
+	let ret = f(&scope);
+	let failed = scope.join();
+	if failed.is_empty() {
+		Ok(ret)
 	} else {
-		spawn_deployed(resources, start)
+		Err(ScopeError(failed))
+	}
+}
+
+/// A scope to spawn processes in. See [`scope()`](scope).
+#[derive(Debug)]
+pub struct Scope {
+	pids: cell::RefCell<Vec<Pid>>,
+}
+impl Scope {
+	/// Spawn a new process, that the scope waits for before returning. See [`spawn()`](spawn).
+	pub fn spawn<T: FnOnce(Pid) + serde::ser::Serialize + serde::de::DeserializeOwned>(
+		&self, resources: Resources, start: T,
+	) -> Option<Pid> {
+		let pid = spawn(resources, start);
+		if let Some(pid) = pid {
+			self.pids.borrow_mut().push(pid);
+		}
+		pid
+	}
+
+	fn join(&self) -> Vec<(Pid, ExitStatus)> {
+		let pids = mem::replace(&mut *self.pids.borrow_mut(), Vec::new());
+		let mut failed = Vec::new();
+		for pid in pids {
+			// Err if the user already waited on it
+			if let Ok(exit_code) = wait(pid) {
+				if !exit_code.success() {
+					failed.push((pid, exit_code));
+				}
+			}
+		}
+		failed
+	}
+}
+impl Drop for Scope {
+	fn drop(&mut self) {
+		let _ = self.join();
+	}
+}
+
+/// Error returned by [`scope()`](scope) if any of its processes exited unsuccessfully. Contains the [Pid] and [ExitStatus] of each.
+#[derive(Debug)]
+pub struct ScopeError(pub Vec<(Pid, ExitStatus)>);
+impl fmt::Display for ScopeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} process(es) exited unsuccessfully:", self.0.len())?;
+		for &(pid, exit_code) in &self.0 {
+			write!(f, " {} ({:?})", pid, exit_code)?;
+		}
+		Ok(())
+	}
+}
+impl error::Error for ScopeError {
+	fn description(&self) -> &str {
+		"process(es) exited unsuccessfully"
 	}
 }
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 extern "C" fn at_exit() {
+	shutdown();
+}
+
+/// The process tree being recorded by a `CONSTELLATION_RECCE_TREE` recce.
+struct Recce {
+	manifest: RecceManifest,
+	/// The number of recorded processes whose code is still running, up to when each finishes or creates its first channel.
+	running: usize,
+	/// Where the manifest is written, moved off fd 3 so that it can be the listener [`pid()`](pid) is read from.
+	fd: Fd,
+}
+thread_local! {
+	/// Which recorded process the current thread is running the code of: `0` for the initial process, or `i` for `spawns[i - 1]` of the [RecceManifest].
+	static RECCE_INDEX: cell::Cell<usize> = cell::Cell::new(0);
+}
+
+/// Record a spawn, and rather than create a process run `start` on a thread, so that the processes it would spawn are recorded too.
+fn recce_spawn(resources: Resources, start: Start) -> Pid {
+	let parent = RECCE_INDEX.with(cell::Cell::get);
+	let index = {
+		let mut recce = RECCE.0.lock().unwrap();
+		let recce = recce.as_mut().unwrap();
+		recce.manifest.spawns.push((parent, resources));
+		if let Start::Closure(_) | Start::ClosureWithPayload(..) = start {
+			recce.running += 1;
+		}
+		recce.manifest.spawns.len()
+	};
+	let parent = recce_pid(parent);
+	let pid = recce_pid(index);
+	let start = match start {
+		Start::Closure(start) => Box::new(move || start(parent)) as Box<FnBox() + Send>,
+		Start::ClosureWithPayload(start, payload) => Box::new(move || start(parent, payload)),
+		// A different executable, whose spawns can't be recorded, or a relay, which doesn't spawn
+		Start::Payload(_) | Start::Relay => return pid,
+	};
+	let _ = thread::Builder::new()
+		.name(format!("recce-{}", index))
+		.spawn(move || {
+			RECCE_INDEX.with(|recce_index| recce_index.set(index));
+			// Its panic is reported by the panic hook; the recce carries on with the other processes
+			let _ = panic::catch_unwind(panic::AssertUnwindSafe(start));
+			recce_finished();
+		})
+		.unwrap();
+	pid
+}
+
+/// The placeholder [Pid] of the recorded process at `index`; the initial process keeps its own.
+fn recce_pid(index: usize) -> Pid {
+	if index == 0 {
+		return pid();
+	}
+	// Spread over the address as well as the port, so there are more than 65535
+	let (ip, port): (u32, u16) = (
+		(index >> 16).try_into().unwrap(),
+		(index & 0xffff).try_into().unwrap(),
+	);
+	Pid::new(net::Ipv4Addr::from(ip).into(), port)
+}
+
+fn recce_finished() {
+	let mut recce = RECCE.0.lock().unwrap();
+	recce.as_mut().unwrap().running -= 1;
+	RECCE.1.notify_all();
+}
+
+extern "C" fn recce_at_exit() {
+	let mut recce = RECCE.0.lock().unwrap();
+	while recce.as_ref().map_or(false, |recce| recce.running > 0) {
+		recce = RECCE.1.wait(recce).unwrap();
+	}
+	if let Some(recce) = recce.take() {
+		let file = unsafe { fs::File::from_raw_fd(recce.fd) };
+		bincode_config()
+			.serialize_into(&file, &recce.manifest)
+			.unwrap();
+		drop(file);
+	}
+}
+
+/// Channels can't be made to processes that were only recorded, so a recorded process's code runs only up to its first. The initial process exits, writing the manifest once the others have got that far too.
+fn recce_end() {
+	if RECCE.0.lock().unwrap().is_none() {
+		return;
+	}
+	if RECCE_INDEX.with(cell::Cell::get) == 0 {
+		process::exit(0);
+	}
+	recce_finished();
+	loop {
+		thread::park();
+	}
+}
+
+/// The number of processes [`test_transport_pid()`](test_transport_pid) has handed out pids for.
+#[cfg(feature = "test-transport")]
+static TEST_TRANSPORT_PIDS: sync::atomic::AtomicUsize = sync::atomic::AtomicUsize::new(0);
+
+/// A [Pid] for a process run as a thread of this one, distinct from those of the others.
+#[cfg(feature = "test-transport")]
+fn test_transport_pid() -> Pid {
+	let index = TEST_TRANSPORT_PIDS.fetch_add(1, sync::atomic::Ordering::Relaxed) + 1;
+	let (ip, port): (u32, u16) = (
+		(index >> 16).try_into().unwrap(),
+		(index & 0xffff).try_into().unwrap(),
+	);
+	Pid::new(net::Ipv4Addr::from(0x7f00_0000 | ip).into(), port)
+}
+
+/// Set up just what the in-memory channels need: a [channel::Reactor] for them to be given as context, and a pid for the calling thread.
+#[cfg(feature = "test-transport")]
+fn test_transport_init(resources: Resources) {
+	assert!(
+		resources_valid(resources),
+		"init() resources must have nonzero mem and a positive finite cpu, got {:?}",
+		resources
+	);
+	{
+		let mut reactor = REACTOR.write().unwrap();
+		if reactor.is_none() {
+			*reactor = Some(channel::Reactor::new(net::Ipv4Addr::LOCALHOST.into()).0);
+		}
+	}
+	if transport::local().is_none() {
+		transport::set_local(test_transport_pid().addr());
+	}
+}
+
+/// Rather than create a process, run `start` on a thread with its own pid. Its exit status is `Success`, or that of a process that panicked if it panics.
+#[cfg(feature = "test-transport")]
+fn test_transport_spawn(detached: bool, start: Start) -> Pid {
+	let parent = pid();
+	let pid = test_transport_pid();
+	let start = match start {
+		Start::Closure(start) => Box::new(move || start(parent)) as Box<FnBox() + Send>,
+		Start::ClosureWithPayload(start, payload) => Box::new(move || start(parent, payload)),
+		Start::Payload(_) => panic!("Spawning a different executable isn't supported by the test-transport feature"),
+		Start::Relay => unreachable!(),
+	};
+	if !detached {
+		CHILDREN.0.lock().unwrap().spawned(pid);
+	}
+	let _ = METRICS
+		.spawned
+		.fetch_add(1, sync::atomic::Ordering::Relaxed);
+	let _ = thread_spawn(format!("process-{}", pid), move || {
+		transport::set_local(pid.addr());
+		let exit_code = match panic::catch_unwind(panic::AssertUnwindSafe(start)) {
+			Ok(()) => ExitStatus::Success,
+			// As a process whose main thread panicked exits with
+			Err(_) => ExitStatus::from_unix_status(101),
+		};
+		transport::exited(pid.addr());
+		if !detached {
+			child_exited(pid, exit_code);
+		}
+	});
+	pid
+}
+
+/// Record that the child `pid` has exited, waking those waiting on it.
+fn child_exited(pid: Pid, exit_code: ExitStatus) {
+	CHILDREN.0.lock().unwrap().exited(pid, exit_code);
+	CHILDREN.1.notify_all();
+	let _ = METRICS.exited.fetch_add(1, sync::atomic::Ordering::Relaxed);
+	// After the insert, so that on_exit() either sees the exit or has registered by now
+	for f in ON_EXIT.lock().unwrap().remove(&pid).unwrap_or_default() {
+		f(exit_code);
+	}
+}
+
+#[cfg_attr(feature = "test-transport", allow(unreachable_code))]
+fn shutdown() {
+	#[cfg(feature = "test-transport")]
+	{
+		transport::exited(pid().addr());
+		return;
+	}
 	let handle = HANDLE.try_write().unwrap().take().unwrap();
-	drop(handle);
-	let mut context = REACTOR.write().unwrap();
-	drop(context.take().unwrap());
+	// Bounded, as a channel isn't finished with until the remote closes its end too
+	if handle.shutdown(time::Duration::from_secs(SHUTDOWN_TIMEOUT_SECS)) {
+		let mut context = REACTOR.write().unwrap();
+		drop(context.take().unwrap());
+	}
 }
 
 #[doc(hidden)]
-pub fn bridge_init() -> net::TcpListener {
+pub fn bridge_init() -> Result<net::TcpListener, InitError> {
 	const BOUND_FD: Fd = 5; // from fabric
 	if is_valgrind() {
 		unistd::close(valgrind_start_fd() - 1 - 12).unwrap();
 	}
 	// init();
-	socket::listen(BOUND_FD, 100).unwrap();
-	let listener = unsafe { net::TcpListener::from_raw_fd(BOUND_FD) };
+	let envs = Envs::from_env();
+	let listen_backlog = envs.listen_backlog.map_or(LISTEN_BACKLOG, |x| {
+		x.filter(|&x| x > 0)
+			.expect("CONSTELLATION_LISTEN_BACKLOG must be a positive whole number")
+	});
 	{
 		let arg = unsafe { fs::File::from_raw_fd(ARG_FD) };
-		let sched_arg: SchedulerArg = bincode::deserialize_from(&mut &arg).unwrap();
+		let sched_arg: SchedulerArg = bincode_config().deserialize_from(&mut &arg).unwrap();
 		drop(arg);
-		let scheduler = net::TcpStream::connect(sched_arg.scheduler)
-			.unwrap()
-			.into_raw_fd();
+		let timeout =
+			envs.scheduler_timeout.map_or(
+				time::Duration::from_secs(SCHEDULER_TIMEOUT_SECS),
+				|x| {
+					x.expect("CONSTELLATION_SCHEDULER_TIMEOUT must be a positive whole number of seconds")
+				},
+			);
+		// Before listening, so that if it fails nothing has been set up
+		let scheduler =
+			scheduler_connect(sched_arg.scheduler, timeout).map_err(InitError::SchedulerConnect)?;
 		if scheduler != SCHEDULER_FD {
 			move_fd(scheduler, SCHEDULER_FD, fcntl::OFlag::empty(), true).unwrap();
 		}
-
+	}
+	platform::Native::channel_listen(BOUND_FD, listen_backlog).unwrap();
+	let listener = unsafe { net::TcpListener::from_raw_fd(BOUND_FD) };
+	{
 		let reactor = channel::Reactor::with_fd(LISTENER_FD);
+		platform::Native::channel_listen(LISTENER_FD, listen_backlog).unwrap();
 		*REACTOR.try_write().unwrap() = Some(reactor);
 		let handle = channel::Reactor::run(
 			|| BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option),
@@ -747,10 +3433,14 @@ pub fn bridge_init() -> net::TcpListener {
 		let err = unsafe { libc::atexit(at_exit) };
 		assert_eq!(err, 0);
 	}
-	listener
+	Ok(listener)
 }
 
-fn native_bridge(format: Format, our_pid: Pid) -> Pid {
+fn native_bridge(
+	format: Format, color: Color, output_encoding: OutputEncoding,
+	deadlock_timeout: Option<time::Duration>, liveness: Option<net::SocketAddr>,
+	listen_backlog: usize, our_pid: Pid,
+) -> Pid {
 	let (bridge_process_listener, bridge_process_id) = native_process_listener();
 
 	// No threads spawned between init and here so we're good
@@ -771,6 +3461,7 @@ fn native_bridge(format: Format, our_pid: Pid) -> Pid {
 		.unwrap();
 
 		let reactor = channel::Reactor::with_fd(LISTENER_FD);
+		platform::Native::channel_listen(LISTENER_FD, listen_backlog).unwrap();
 		*REACTOR.try_write().unwrap() = Some(reactor);
 		let handle = channel::Reactor::run(
 			|| BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option),
@@ -783,7 +3474,7 @@ fn native_bridge(format: Format, our_pid: Pid) -> Pid {
 
 		let x = thread_spawn(String::from("bridge-waitpid"), || {
 			loop {
-				match wait::waitpid(None, None) {
+				match retry_eintr(|| wait::waitpid(None, None)) {
 					Ok(wait::WaitStatus::Exited(_pid, code)) if code == 0 => (), //assert_eq!(pid, child),
 					// wait::WaitStatus::Signaled(pid, signal, _) if signal == signal::Signal::SIGKILL => assert_eq!(pid, child),
 					Err(nix::Error::Sys(errno::Errno::ECHILD)) => break,
@@ -794,115 +3485,418 @@ fn native_bridge(format: Format, our_pid: Pid) -> Pid {
 			}
 		});
 		let mut exit_code = ExitStatus::Success;
-		let mut formatter = if let Format::Human = format {
+		let formatter = sync::Arc::new(sync::Mutex::new(if let Format::Human = format {
 			Either::Left(Formatter::new(
 				our_pid,
-				if atty::is(atty::Stream::Stderr) {
-					StyleSupport::EightBit
-				} else {
-					StyleSupport::None
-				},
+				StyleSupport::from_color(color, atty::is(atty::Stream::Stderr)),
+				output_encoding,
 			))
 		} else {
 			Either::Right(io::stdout())
-		};
-		let mut processes = vec![(
+		}));
+		let watchdog = deadlock_timeout.map(|timeout| {
+			let formatter = formatter.clone();
+			Watchdog::new(our_pid, timeout, move |pids| {
+				write_event(
+					&mut formatter.lock().unwrap(),
+					&DeployOutputEvent::Deadlock(pids),
+				)
+			})
+		});
+		let liveness = liveness.and_then(|addr| {
+			Liveness::new(addr)
+				.map_err(|err| warn!("Failed to bind liveness address {}: {}", addr, err))
+				.ok()
+		});
+		let processes = vec![(
 			Sender::<ProcessInputEvent>::new(our_pid),
 			Receiver::<ProcessOutputEvent>::new(our_pid),
 		)];
-		while !processes.is_empty() {
-			// trace!("select");
-			let mut event = None;
-			let event_ = &cell::RefCell::new(&mut event);
+		bridge_loop(processes, None, |event| {
+			if let DeployOutputEvent::Exit(_, exit_code_, _) = event {
+				exit_code += exit_code_;
+			}
+			if let Some(ref watchdog) = watchdog {
+				watchdog.event(&event);
+			}
+			if let Some(ref liveness) = liveness {
+				liveness.event(&event);
+			}
+			write_event(&mut formatter.lock().unwrap(), &event);
+		});
+		x.join().unwrap();
+		process::exit(exit_code.into());
+	}
+	unistd::close(bridge_process_listener).unwrap();
+	Pid::new(loopback(), bridge_process_id)
+}
 
-			let _ = select(
-				processes
-					.iter()
-					.enumerate()
-					.map(|(i, &(_, ref receiver))| {
-						Box::new(receiver.selectable_recv(
-							move |t: Result<ProcessOutputEvent, _>| {
-								// trace!("ProcessOutputEvent {}: {:?}", i, t);
-								**event_.borrow_mut() = Some((i, t.unwrap()));
-							},
-						)) as Box<Selectable>
-					})
-					.collect(),
-			);
-			// trace!("/select");
-			// drop(event_);
-			let (i, event): (usize, ProcessOutputEvent) = event.unwrap();
-			let pid = processes[i].0.remote_pid();
-			let event = match event {
-				ProcessOutputEvent::Spawn(new_pid) => {
-					processes.push((
-						Sender::<ProcessInputEvent>::new(new_pid),
-						Receiver::<ProcessOutputEvent>::new(new_pid),
-					));
-					DeployOutputEvent::Spawn(pid, new_pid)
+/// Pass the events of `processes`, and of the processes they spawn, to `emit` until they've all exited, handling their spawns and kills and telling parents of their children's exits. A relay bridge is also given processes to adopt, and told when no more are coming, by its bridge over `upstream`; the native bridge has none, so returns once its processes have exited.
+fn bridge_loop(
+	mut processes: Vec<(Sender<ProcessInputEvent>, Receiver<ProcessOutputEvent>)>,
+	mut upstream: Option<&Receiver<ProcessInputEvent>>, mut emit: impl FnMut(DeployOutputEvent),
+) {
+	// Each process's parent, to tell of its exit
+	let mut parents = HashMap::new();
+	// The relay bridge each child spawned through one reports to, until it exits
+	let mut relayed: HashMap<Pid, Pid> = HashMap::new();
+	// Each relay bridge reporting to this bridge, and the process that spawned it, on whose exit it's closed
+	let mut relays: HashMap<Pid, Pid> = HashMap::new();
+	// Events yet to be sent to processes, like exits yet to be told to the parent. They're sent alongside receiving, so a process that isn't receiving doesn't hold up the events of the others
+	let mut pending: Vec<(Pid, ProcessInputEvent)> = Vec::new();
+	// Whether no more processes will be adopted, so this returns once those it has have exited
+	let mut closed = upstream.is_none();
+	let mut killed = false;
+	while !(closed && processes.is_empty()) {
+		// trace!("select");
+		let mut event: Option<Either<(usize, ProcessOutputEvent), Option<ProcessInputEvent>>> =
+			None;
+		let event_ = &cell::RefCell::new(&mut event);
+		// Only the first event pending for each process is sent at a time, so they arrive in order
+		let first = pending
+			.iter()
+			.enumerate()
+			.map(|(i, &(to, _))| pending[..i].iter().all(|&(to_, _)| to_ != to))
+			.collect::<Vec<_>>();
+		let sent = pending
+			.iter()
+			.map(|_| cell::RefCell::new(None))
+			.collect::<Vec<_>>();
+
+		let _ = select(
+			processes
+				.iter()
+				.enumerate()
+				.map(|(i, &(_, ref receiver))| {
+					Box::new(
+						receiver.selectable_recv(move |t: Result<ProcessOutputEvent, _>| {
+							// trace!("ProcessOutputEvent {}: {:?}", i, t);
+							**event_.borrow_mut() = Some(Either::Left((i, t.unwrap())));
+						}),
+					) as Box<Selectable>
+				})
+				.chain(upstream.map(|upstream| {
+					Box::new(
+						upstream.selectable_recv(move |t: Result<ProcessInputEvent, _>| {
+							**event_.borrow_mut() = Some(Either::Right(t.ok()));
+						}),
+					) as Box<Selectable>
+				}))
+				.chain(
+					pending
+						.iter()
+						.zip(&sent)
+						.zip(&first)
+						.filter(|&(_, &first)| first)
+						.map(|((&(to, ref event), sent), _)| {
+							let &(ref sender, _) = processes
+								.iter()
+								.find(|&&(ref sender, _)| sender.remote_pid() == to)
+								.unwrap();
+							Box::new(sender.selectable_try_send(event.clone(), sent))
+								as Box<Selectable>
+						}),
+				)
+				.collect(),
+		);
+		// trace!("/select");
+		// Sent, or failed as the process has exited, in which case there's no one to tell
+		let mut sent = sent.into_iter();
+		pending.retain(|_| sent.next().unwrap().into_inner().is_none());
+		let (i, event): (usize, ProcessOutputEvent) = match event {
+			Some(Either::Left(event)) => event,
+			Some(Either::Right(Some(event))) => {
+				match event {
+					ProcessInputEvent::Adopt(new_pid, parent) => {
+						processes.push((
+							Sender::<ProcessInputEvent>::new(new_pid),
+							Receiver::<ProcessOutputEvent>::new(new_pid),
+						));
+						let x = parents.insert(new_pid, parent);
+						assert!(x.is_none());
+						if killed {
+							pending.push((new_pid, ProcessInputEvent::Kill));
+						}
+					}
+					ProcessInputEvent::KillRelayed(pid, descendants) => {
+						for &(ref sender, _) in &processes {
+							let remote = sender.remote_pid();
+							if remote == pid || descendants && is_descendant(&parents, remote, pid)
+							{
+								let _ = sender.try_send(ProcessInputEvent::Kill);
+							}
+						}
+					}
+					ProcessInputEvent::Kill => {
+						for &(ref sender, _) in &processes {
+							let _ = sender.try_send(ProcessInputEvent::Kill);
+						}
+						closed = true;
+						killed = true;
+					}
+					ProcessInputEvent::Close => closed = true,
+					ProcessInputEvent::Input(..) | ProcessInputEvent::ChildExit(..) => {
+						unreachable!()
+					}
 				}
-				ProcessOutputEvent::Output(fd, output) => {
-					// sender_.send(OutputEventInt::Output(pid, fd, output)).expect("send failed 1");
-					// trace!("output: {:?} {:?}", fd, output);
-					// print!("{}", output);
-					DeployOutputEvent::Output(pid, fd, output)
+				continue;
+			}
+			Some(Either::Right(None)) => {
+				// The bridge has exited, so the deployment is ending
+				for &(ref sender, _) in &processes {
+					let _ = sender.try_send(ProcessInputEvent::Kill);
 				}
-				ProcessOutputEvent::Exit(exit_code_) => {
-					exit_code += exit_code_;
-					let _ = processes.remove(i);
-					DeployOutputEvent::Exit(pid, exit_code_)
+				upstream = None;
+				closed = true;
+				killed = true;
+				continue;
+			}
+			None => continue,
+		};
+		let pid = processes[i].0.remote_pid();
+		let event = match event {
+			ProcessOutputEvent::Spawn(new_pid, detached) => {
+				processes.push((
+					Sender::<ProcessInputEvent>::new(new_pid),
+					Receiver::<ProcessOutputEvent>::new(new_pid),
+				));
+				if !detached {
+					let x = parents.insert(new_pid, pid);
+					assert!(x.is_none());
 				}
-			};
-			match &mut formatter {
-				&mut Either::Left(ref mut formatter) => formatter.write(&event),
-				&mut Either::Right(ref mut stdout) => {
-					serde_json::to_writer(&mut *stdout, &event).unwrap();
-					stdout.write_all(b"\n").unwrap()
+				DeployOutputEvent::Spawn(pid, new_pid)
+			}
+			ProcessOutputEvent::SpawnRelay(relay) => {
+				processes.push((
+					Sender::<ProcessInputEvent>::new(relay),
+					Receiver::<ProcessOutputEvent>::new(relay),
+				));
+				let x = relays.insert(relay, pid);
+				assert!(x.is_none());
+				DeployOutputEvent::Spawn(pid, relay)
+			}
+			ProcessOutputEvent::RelayedSpawn(new_pid, relay) => {
+				let x = parents.insert(new_pid, pid);
+				assert!(x.is_none());
+				if relays.contains_key(&relay) {
+					let _ = relayed.insert(new_pid, relay);
+					pending.push((relay, ProcessInputEvent::Adopt(new_pid, pid)));
+				} else {
+					warn!(
+						"{} spawned {} through the relay bridge {}, which has exited or doesn't report to the same bridge, so its events are lost",
+						pid, new_pid, relay
+					);
+				}
+				DeployOutputEvent::Spawn(pid, new_pid)
+			}
+			ProcessOutputEvent::Relay(event) => {
+				if let DeployOutputEvent::Exit(child, exit_code, _) = event {
+					// Only a child spawned through the relay by a process here has a parent to tell
+					if relayed.remove(&child).is_some() {
+						if let Some(parent) = parents.remove(&child).filter(|&parent| {
+							processes
+								.iter()
+								.any(|&(ref sender, _)| sender.remote_pid() == parent)
+						}) {
+							pending.push((parent, ProcessInputEvent::ChildExit(child, exit_code)));
+						}
+					}
+				}
+				event
+			}
+			ProcessOutputEvent::Output(fd, output) => {
+				// sender_.send(OutputEventInt::Output(pid, fd, output)).expect("send failed 1");
+				// trace!("output: {:?} {:?}", fd, output);
+				// print!("{}", output);
+				DeployOutputEvent::Output(pid, fd, output)
+			}
+			ProcessOutputEvent::Panic(panic) => DeployOutputEvent::Panic(pid, panic),
+			ProcessOutputEvent::Metric(name, value) => DeployOutputEvent::Metric(pid, name, value),
+			ProcessOutputEvent::Exit(exit_code, usage) => {
+				let _ = processes.remove(i);
+				pending.retain(|&(to, _)| to != pid);
+				let _ = relays.remove(&pid);
+				for (&relay, _) in relays.iter().filter(|&(_, &owner)| owner == pid) {
+					pending.push((relay, ProcessInputEvent::Close));
+				}
+				// The parent may have exited, in which case there's no one to tell
+				if let Some(parent) = parents.remove(&pid).filter(|&parent| {
+					processes
+						.iter()
+						.any(|&(ref sender, _)| sender.remote_pid() == parent)
+				}) {
+					pending.push((parent, ProcessInputEvent::ChildExit(pid, exit_code)));
+				}
+				DeployOutputEvent::Exit(pid, exit_code, usage)
+			}
+			ProcessOutputEvent::Kill(child) => {
+				// Only a process's own children, and only if they haven't yet exited
+				if parents.get(&child) == Some(&pid) {
+					if let Some(&(ref sender, _)) = processes
+						.iter()
+						.find(|&&(ref sender, _)| sender.remote_pid() == child)
+					{
+						let _ = sender.try_send(ProcessInputEvent::Kill);
+					} else if let Some(&relay) = relayed.get(&child) {
+						pending.push((relay, ProcessInputEvent::KillRelayed(child, false)));
+					}
+				}
+				continue;
+			}
+			ProcessOutputEvent::KillDescendants => {
+				for &(ref sender, _) in &processes {
+					if is_descendant(&parents, sender.remote_pid(), pid) {
+						let _ = sender.try_send(ProcessInputEvent::Kill);
+					}
 				}
+				for (&child, &relay) in &relayed {
+					if is_descendant(&parents, child, pid) {
+						pending.push((relay, ProcessInputEvent::KillRelayed(child, true)));
+					}
+				}
+				continue;
 			}
+		};
+		emit(event);
+	}
+}
+
+/// Run as a relay bridge spawned by [`spawn_relay()`](spawn_relay): pass the events of the processes `bridge` has it adopt, and of the processes they spawn, on to `bridge`, until it's closed and they've all exited.
+///
+/// It isn't given a monitor, as it mustn't be killed before the processes reporting to it, and it talks to `bridge` directly rather than through one.
+fn relay_bridge(bridge: Pid, listen_backlog: usize) -> ! {
+	// Without a monitor there's nowhere to forward its stdio, so it's /dev/null rather than closed, lest sockets be opened as stdout or stderr
+	let fd = dev_null().expect("Failed to open /dev/null or a fallback");
+	for &stdio in &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+		if fd != stdio {
+			copy_fd(fd, stdio, fcntl::OFlag::empty(), true).unwrap();
 		}
-		x.join().unwrap();
-		process::exit(exit_code.into());
 	}
-	unistd::close(bridge_process_listener).unwrap();
-	Pid::new("127.0.0.1".parse().unwrap(), bridge_process_id)
+	if fd > libc::STDERR_FILENO {
+		unistd::close(fd).unwrap();
+	}
+
+	let reactor = channel::Reactor::with_fd(LISTENER_FD);
+	platform::Native::channel_listen(LISTENER_FD, listen_backlog).unwrap();
+	*REACTOR.try_write().unwrap() = Some(reactor);
+	let handle = channel::Reactor::run(
+		|| BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option),
+		|&_fd| None,
+	);
+	*HANDLE.try_write().unwrap() = Some(handle);
+
+	let err = unsafe { libc::atexit(at_exit) };
+	assert_eq!(err, 0);
+
+	let sender = Sender::<ProcessOutputEvent>::new(bridge);
+	let receiver = Receiver::<ProcessInputEvent>::new(bridge);
+	// Failing only once the bridge has exited, whereupon bridge_loop() kills what's left
+	bridge_loop(Vec::new(), Some(&receiver), |event| {
+		let _ = sender.try_send(ProcessOutputEvent::Relay(event));
+	});
+	let _ = sender.try_send(ProcessOutputEvent::Exit(
+		ExitStatus::Success,
+		usage(libc::RUSAGE_SELF),
+	));
+	let _ = sender.drain();
+	process::exit(0)
+}
+
+fn write_event(formatter: &mut Either<Formatter, io::Stdout>, event: &DeployOutputEvent) {
+	match formatter {
+		&mut Either::Left(ref mut formatter) => formatter.write(event),
+		&mut Either::Right(ref mut stdout) => {
+			serde_json::to_writer(&mut *stdout, event).unwrap();
+			stdout.write_all(b"\n").unwrap()
+		}
+	}
+}
+
+/// The address native processes listen on. Set by `CONSTELLATION_LOOPBACK`, default `127.0.0.1`.
+fn loopback() -> net::IpAddr {
+	LOOPBACK.read().unwrap().unwrap()
 }
 
 fn native_process_listener() -> (Fd, u16) {
-	let process_listener = socket(
-		socket::AddressFamily::Inet,
-		socket::SockType::Stream,
-		SockFlag::SOCK_NONBLOCK,
-		socket::SockProtocol::Tcp,
-	)
-	.unwrap();
-	socket::setsockopt(process_listener, sockopt::ReuseAddr, &true).unwrap();
-	socket::bind(
-		process_listener,
-		&socket::SockAddr::Inet(socket::InetAddr::from_std(&net::SocketAddr::new(
-			"127.0.0.1".parse().unwrap(),
-			0,
-		))),
-	)
-	.unwrap();
-	socket::setsockopt(process_listener, sockopt::ReusePort, &true).unwrap();
-	let process_id =
-		if let socket::SockAddr::Inet(inet) = socket::getsockname(process_listener).unwrap() {
-			inet.to_std()
-		} else {
-			panic!()
-		};
-	assert_eq!(
-		process_id.ip(),
-		"127.0.0.1".parse::<net::Ipv4Addr>().unwrap()
-	);
+	let loopback = loopback();
+	let process_listener = platform::Native::channel_listener(loopback).unwrap();
+	let process_id = platform::Native::channel_local_addr(process_listener).unwrap();
+	assert_eq!(process_id.ip(), loopback);
 
 	(process_listener, process_id.port())
 }
 
+fn set_socket_buffer(fd: Fd, size: usize) {
+	// Linux doubles the size asked for to allow for bookkeeping, after clamping it to net.core.rmem_max / net.core.wmem_max
+	let (rcv, snd) = platform::Native::channel_buffer(fd, size).unwrap();
+	if rcv < size || snd < size {
+		warn!(
+			"CONSTELLATION_SOCKET_BUFFER of {} bytes was clamped by the kernel to {} receive and {} send; see net.core.rmem_max and net.core.wmem_max",
+			size, rcv, snd
+		);
+	}
+}
+
+/// Open `/dev/null`, falling back to an empty memfd, or failing that a pipe with its write end closed, where it's missing (as in some minimal container images). All of them read as EOF; writes to the memfd are retained rather than discarded.
+fn dev_null() -> Result<Fd, nix::Error> {
+	let err = match fcntl::open("/dev/null", fcntl::OFlag::O_RDWR, stat::Mode::empty()) {
+		Ok(fd) => return Ok(fd),
+		Err(err) => err,
+	};
+	warn!(
+		"Failed to open /dev/null ({}); falling back to a memfd",
+		err
+	);
+	let err = match memfd_create(&CString::new("constellation-null").unwrap(), false) {
+		Ok(fd) => return Ok(fd),
+		Err(err) => err,
+	};
+	warn!("Failed to memfd_create ({}); falling back to a pipe", err);
+	let (reader, writer) = unistd::pipe()?;
+	unistd::close(writer)?;
+	Ok(reader)
+}
+
+/// Where the binary of the deployed process with OS pid `pid` is kept under `CONSTELLATION_KEEP_BINARY`, on platforms where it's otherwise only in memory.
+#[cfg(any(
+	target_os = "android",
+	target_os = "freebsd",
+	target_os = "linux",
+	target_os = "netbsd",
+	target_os = "openbsd"
+))]
+fn kept_binary_path(pid: unistd::Pid) -> path::PathBuf {
+	std::env::temp_dir().join(format!("constellation-{}", pid))
+}
+
+/// The resources used by `who`: this process with `RUSAGE_SELF`, or with `RUSAGE_CHILDREN` the children it has waited on. The monitor has only the one child, so that's the child's usage.
+fn usage(who: libc::c_int) -> ResourceUsage {
+	let mut usage: libc::rusage = unsafe { mem::zeroed() };
+	let err = unsafe { libc::getrusage(who, &mut usage) };
+	assert_eq!(err, 0);
+	let duration =
+		|time: libc::timeval| time::Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+	ResourceUsage {
+		// Kilobytes on Linux, bytes on macOS
+		max_rss: usage.ru_maxrss as u64 * if cfg!(target_os = "macos") { 1 } else { 1024 },
+		user_time: duration(usage.ru_utime),
+		system_time: duration(usage.ru_stime),
+	}
+}
+
 fn monitor_process(
-	bridge: Pid, deployed: bool,
-) -> (channel::SocketForwardee, Fd, Fd, Option<Fd>, Fd) {
+	bridge: Pid, deployed: bool, socket_buffer: Option<usize>, nodelay: bool, output_buffer: usize,
+	mem_limited: bool, keep_binary: bool, keepalive: Option<(u16, u16, u16)>,
+	listen_backlog: usize,
+) -> (
+	channel::SocketForwardee,
+	Fd,
+	Fd,
+	Option<Fd>,
+	Fd,
+	Fd,
+	Option<Fd>,
+) {
 	const FORWARD_STDERR: bool = true;
 
 	let (socket_forwarder, socket_forwardee) = channel::socket_forwarder();
@@ -917,12 +3911,25 @@ fn monitor_process(
 		(None, None)
 	};
 	let (stdin_reader, stdin_writer) = unistd::pipe().unwrap();
+	let (exits_reader, exits_writer) = unistd::pipe().unwrap();
+	// Written to by mem_limit_hook(), which the monitor checks once the process has exited
+	let (mem_limit_reader, mem_limit_writer) = if mem_limited {
+		let (reader, writer) =
+			unistd::pipe2(fcntl::OFlag::O_CLOEXEC | fcntl::OFlag::O_NONBLOCK).unwrap();
+		(Some(reader), Some(writer))
+	} else {
+		(None, None)
+	};
 
 	let (reader, writer) = unistd::pipe().unwrap(); // unistd::pipe2(fcntl::OFlag::empty())
 
 	// trace!("forking");
 	// No threads spawned between init and here so we're good
 	if let unistd::ForkResult::Parent { child } = unistd::fork().unwrap() {
+		if deployed {
+			// The scheduler connection is the process's, not the monitor's
+			unistd::close(SCHEDULER_FD).unwrap();
+		}
 		unistd::close(reader).unwrap();
 		unistd::close(monitor_writer).unwrap();
 		unistd::close(stdout_writer).unwrap();
@@ -930,6 +3937,10 @@ fn monitor_process(
 			unistd::close(stderr_writer).unwrap();
 		}
 		unistd::close(stdin_reader).unwrap();
+		unistd::close(exits_reader).unwrap();
+		if let Some(mem_limit_writer) = mem_limit_writer {
+			unistd::close(mem_limit_writer).unwrap();
+		}
 		let (bridge_outbound_sender, bridge_outbound_receiver) =
 			mpsc::sync_channel::<ProcessOutputEvent>(0);
 		let (bridge_inbound_sender, bridge_inbound_receiver) =
@@ -938,17 +3949,19 @@ fn monitor_process(
 			libc::STDOUT_FILENO,
 			stdout_reader,
 			bridge_outbound_sender.clone(),
+			output_buffer,
 		);
 		let stderr_thread = stderr_reader.map(|stderr_reader| {
 			forward_fd(
 				libc::STDERR_FILENO,
 				stderr_reader,
 				bridge_outbound_sender.clone(),
+				output_buffer,
 			)
 		});
 		let _stdin_thread =
 			forward_input_fd(libc::STDIN_FILENO, stdin_writer, bridge_inbound_receiver);
-		let fd = fcntl::open("/dev/null", fcntl::OFlag::O_RDWR, stat::Mode::empty()).unwrap();
+		let fd = dev_null().expect("Failed to open /dev/null or a fallback");
 		move_fd(fd, libc::STDIN_FILENO, fcntl::OFlag::empty(), false).unwrap();
 		copy_fd(
 			libc::STDIN_FILENO,
@@ -967,18 +3980,23 @@ fn monitor_process(
 			.unwrap();
 		}
 
+		if let Some(socket_buffer) = socket_buffer {
+			// Accepted connections inherit these from the listener
+			set_socket_buffer(LISTENER_FD, socket_buffer);
+		}
 		let reactor = channel::Reactor::with_fd(LISTENER_FD);
+		// After with_fd, as that listens with its own backlog
+		platform::Native::channel_listen(LISTENER_FD, listen_backlog).unwrap();
 		*REACTOR.try_write().unwrap() = Some(reactor);
 		let handle = channel::Reactor::run(
 			|| BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option),
 			move |&fd| {
-				if let Ok(remote) = socket::getpeername(fd).map(|remote| {
-					if let socket::SockAddr::Inet(inet) = remote {
-						inet.to_std()
-					} else {
-						panic!()
-					}
-				}) {
+				// Ignore failure, as the connection may already have been reset
+				let _ = platform::Native::channel_nodelay(fd, nodelay);
+				if let Some(keepalive) = keepalive {
+					let _ = platform::Native::channel_keepalive(fd, keepalive);
+				}
+				if let Ok(remote) = platform::Native::channel_peer_addr(fd) {
 					if remote == bridge.addr() {
 						trace!("{}: {:?} == {:?}", pid(), remote, bridge.addr());
 						None
@@ -1000,17 +4018,46 @@ fn monitor_process(
 		let sender = Sender::<ProcessOutputEvent>::new(bridge);
 		let receiver = Receiver::<ProcessInputEvent>::new(bridge);
 
+		// Set if the bridge exits before this process does
+		let bridge_exited = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+
 		let bridge_sender2 = bridge_outbound_sender.clone();
+		let bridge_exited2 = bridge_exited.clone();
 		let x3 = thread_spawn(String::from("monitor-monitorfd-to-channel"), move || {
 			let file = unsafe { fs::File::from_raw_fd(monitor_reader) };
 			loop {
-				let event: Result<ProcessOutputEvent, _> =
-					bincode::deserialize_from(&mut &file).map_err(map_bincode_err);
-				if event.is_err() {
+				let len: Result<u64, _> = bincode_config()
+					.deserialize_from(&mut &file)
+					.map_err(map_bincode_err);
+				if len.is_err() {
 					break;
 				}
-				let event = event.unwrap();
+				let len = len.unwrap();
+				if len > MONITOR_FRAME_MAX {
+					error!(
+						"monitor: frame length {} is corrupt, can't resynchronize",
+						len
+					);
+					break;
+				}
+				let mut frame = vec![0; len as usize];
+				if (&file).read_exact(&mut frame).is_err() {
+					error!("monitor: frame truncated by EOF");
+					break;
+				}
+				let event = match bincode_config().deserialize::<ProcessOutputEvent>(&frame) {
+					Ok(event) => event,
+					Err(err) => {
+						error!("monitor: skipping corrupt frame: {:?}", err);
+						continue;
+					}
+				};
 				bridge_sender2.send(event).unwrap();
+				if bridge_exited2.load(sync::atomic::Ordering::Relaxed) {
+					// Close the pipe, so the process's spawn()s fail rather than go untracked
+					drop(file);
+					return;
+				}
 			}
 			let _ = file.into_raw_fd();
 		});
@@ -1018,14 +4065,20 @@ fn monitor_process(
 		let x = thread_spawn(String::from("monitor-channel-to-bridge"), move || {
 			loop {
 				let event = bridge_outbound_receiver.recv().unwrap();
-				sender.send(event.clone());
-				if let ProcessOutputEvent::Exit(_) = event {
+				// Once the bridge has exited, drop events rather than block forever
+				if !bridge_exited.load(sync::atomic::Ordering::Relaxed)
+					&& sender.try_send(event.clone()).is_err()
+				{
+					bridge_exited.store(true, sync::atomic::Ordering::Relaxed);
+				}
+				if let ProcessOutputEvent::Exit(..) = event {
 					// trace!("xxx exit");
 					break;
 				}
 			}
 		});
 		let _x2 = thread_spawn(String::from("monitor-bridge-to-channel"), move || {
+			let exits = unsafe { fs::File::from_raw_fd(exits_writer) };
 			loop {
 				let event: Result<ProcessInputEvent, _> = receiver.recv();
 				if event.is_err() {
@@ -1049,6 +4102,14 @@ fn monitor_process(
 						});
 						break;
 					}
+					ProcessInputEvent::ChildExit(pid, exit_code) => {
+						// Fails only if the process has exited
+						let _ = bincode_config().serialize_into(&mut &exits, &(pid, exit_code));
+					}
+					// Only relay bridges are sent these, and they don't have monitors
+					ProcessInputEvent::Adopt(..)
+					| ProcessInputEvent::KillRelayed(..)
+					| ProcessInputEvent::Close => unreachable!(),
 				}
 			}
 		});
@@ -1061,7 +4122,8 @@ fn monitor_process(
 		);
 		// trace!("awaiting exit");
 
-		let exit = wait::waitpid(child, None).unwrap();
+		let exit = retry_eintr(|| wait::waitpid(child, None)).unwrap();
+		let usage = usage(libc::RUSAGE_CHILDREN);
 		trace!(
 			"PROCESS {}:{}: exited {:?}",
 			unistd::getpid(),
@@ -1077,7 +4139,7 @@ fn monitor_process(
 		)))]
 		{
 			use std::env;
-			if deployed {
+			if deployed && !keep_binary {
 				unistd::unlink(&env::current_exe().unwrap()).unwrap();
 			}
 		}
@@ -1089,14 +4151,28 @@ fn monitor_process(
 			target_os = "openbsd"
 		))]
 		{
-			let _ = deployed;
+			// The binary was only ever in memory, so copy it out to be kept
+			if deployed && keep_binary {
+				let path = kept_binary_path(child);
+				if let Err(err) = fs::copy(exe_path().unwrap(), &path) {
+					warn!("Failed to keep binary at {}: {}", path.display(), err);
+				}
+			}
 		}
 
 		let code = match exit {
 			wait::WaitStatus::Exited(pid, code) => {
 				assert_eq!(pid, child);
 				assert!(0 <= code && code <= i32::from(u8::max_value()));
-				ExitStatus::from_unix_status(code.try_into().unwrap())
+				// Nonblocking, as the process may have exited for another reason, leaving it empty
+				let hook_ran = mem_limit_reader.map_or(false, |mem_limit_reader| {
+					unistd::read(mem_limit_reader, &mut [0]).ok() == Some(1)
+				});
+				if hook_ran && code == i32::from(EXIT_MEM_LIMIT) {
+					ExitStatus::Error(ExitStatusError::MemoryLimit)
+				} else {
+					ExitStatus::from_unix_status(code.try_into().unwrap())
+				}
 			}
 			wait::WaitStatus::Signaled(pid, signal, _) => {
 				assert_eq!(pid, child);
@@ -1113,7 +4189,7 @@ fn monitor_process(
 		// trace!("joining x3");
 		x3.join().unwrap();
 		bridge_outbound_sender
-			.send(ProcessOutputEvent::Exit(code))
+			.send(ProcessOutputEvent::Exit(code, usage))
 			.unwrap();
 		drop(bridge_outbound_sender);
 		// trace!("joining x");
@@ -1130,6 +4206,10 @@ fn monitor_process(
 	unistd::close(monitor_reader).unwrap();
 	unistd::close(writer).unwrap();
 	unistd::close(stdin_writer).unwrap();
+	unistd::close(exits_writer).unwrap();
+	if let Some(mem_limit_reader) = mem_limit_reader {
+		unistd::close(mem_limit_reader).unwrap();
+	}
 	if FORWARD_STDERR {
 		unistd::close(stderr_reader.unwrap()).unwrap();
 	}
@@ -1140,7 +4220,7 @@ fn monitor_process(
 		assert_eq!(err, 0);
 	}
 	trace!("awaiting ready");
-	let err = unistd::read(reader, &mut [0]).unwrap();
+	let err = retry_eintr(|| unistd::read(reader, &mut [0])).unwrap();
 	assert_eq!(err, 0);
 	unistd::close(reader).unwrap();
 	trace!("ready");
@@ -1151,13 +4231,92 @@ fn monitor_process(
 		stdout_writer,
 		stderr_writer,
 		stdin_reader,
+		exits_reader,
+		mem_limit_writer,
 	)
 }
 
-/// Initialise the [deploy](self) runtime. This must be called immediately inside your application's `main()` function.
+/// Initialise the [deploy](self) runtime. This must be called immediately inside your application's `main()` function, after only [`set_child_init()`](set_child_init) if that's used.
 ///
 /// The `resources` argument describes memory and CPU requirements for the initial process.
+///
+/// # Panics
+///
+/// Panics if initialisation fails with an [InitError]. [`try_init()`](try_init) instead returns it.
 pub fn init(resources: Resources) {
+	init_inner(resources, true).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Initialise the [deploy](self) runtime like [`init()`](init), but return an [InitError] rather than panicking if it fails, for example because the scheduler couldn't be connected to.
+///
+/// The scheduler is connected to before anything else is set up, so on error no threads or processes have been started and output isn't being redirected. What the process was started with has been consumed though, so it can't retry, and should report the error and exit.
+pub fn try_init(resources: Resources) -> Result<(), InitError> {
+	init_inner(resources, true)
+}
+
+/// Initialise the [deploy](self) runtime like [`init()`](init), but tear it down when the returned [ConstellationGuard] is dropped, rather than with an `atexit` handler.
+///
+/// This gives deterministic teardown for applications that run constellation alongside other subsystems, avoiding hazards with the order `atexit` handlers and other global destructors run in. The guard should be held until `main()` returns; if the process exits without it being dropped, for example with [`process::exit()`](std::process::exit), the runtime isn't torn down.
+///
+/// In spawned processes that run a closure, the runtime is torn down once the closure returns.
+pub fn init_with_shutdown(resources: Resources) -> ConstellationGuard {
+	init_inner(resources, false).unwrap_or_else(|err| panic!("{}", err));
+	ConstellationGuard(())
+}
+
+/// Returned by [`init_with_shutdown()`](init_with_shutdown). Dropping it tears down the runtime, after which channels can't be used and processes can't be spawned.
+#[must_use]
+#[derive(Debug)]
+pub struct ConstellationGuard(());
+impl Drop for ConstellationGuard {
+	fn drop(&mut self) {
+		shutdown();
+	}
+}
+
+/// Register `f` to be run in every process this one spawns, to set up thread pools, loggers, allocators and the like consistently across them.
+///
+/// Spawned processes run this binary's `main()` again, and run the closure they were spawned with from within [`init()`](init), never returning from it. So unlike everything else, this must be called *before* `init()`, on every run, such that the registration is re-established in each spawned process: it's run there once `init()` has set up the runtime – channels and spawning can be used, and panics are reported – but before the closure. It isn't run in the initial process.
+pub fn set_child_init(f: fn()) {
+	*CHILD_INIT.write().unwrap() = Some(f);
+}
+
+/// Error returned by [`try_init()`](try_init).
+#[derive(Debug)]
+pub enum InitError {
+	/// Connecting to the scheduler failed, or timed out after `CONSTELLATION_SCHEDULER_TIMEOUT`.
+	SchedulerConnect(io::Error),
+}
+impl fmt::Display for InitError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			InitError::SchedulerConnect(ref err) => {
+				write!(f, "Failed to connect to the scheduler: {}", err)
+			}
+		}
+	}
+}
+impl error::Error for InitError {
+	fn description(&self) -> &str {
+		match *self {
+			InitError::SchedulerConnect(_) => "failed to connect to the scheduler",
+		}
+	}
+
+	fn cause(&self) -> Option<&error::Error> {
+		match *self {
+			InitError::SchedulerConnect(ref err) => Some(err),
+		}
+	}
+}
+
+#[cfg_attr(feature = "test-transport", allow(unreachable_code))]
+fn init_inner(resources: Resources, register_at_exit: bool) -> Result<(), InitError> {
+	#[cfg(feature = "test-transport")]
+	{
+		test_transport_init(resources);
+		return Ok(());
+	}
 	if is_valgrind() {
 		let _ = unistd::close(valgrind_start_fd() - 1 - 12); // close non CLOEXEC'd fd of this binary
 	}
@@ -1168,28 +4327,162 @@ pub fn init(resources: Resources) {
 	let recce = envs
 		.recce
 		.map_or(false, |x| x.expect("CONSTELLATION_RECCE must be 0 or 1"));
+	let recce_tree = envs.recce_tree.map_or(false, |x| {
+		x.expect("CONSTELLATION_RECCE_TREE must be 0 or 1")
+	});
 	let format = envs.format.map_or(Format::Human, |x| {
 		x.expect("CONSTELLATION_FORMAT must be json or human")
 	});
+	let color = envs.color.map_or(Color::Auto, |x| {
+		x.expect("CONSTELLATION_COLOR must be always, never, auto, 256 or truecolor")
+	});
+	let output_encoding = envs.output_encoding.map_or(OutputEncoding::Lossy, |x| {
+		x.expect("CONSTELLATION_OUTPUT_ENCODING must be lossy, escape or raw")
+	});
 	let deployed = envs.deploy == Some(Some(Deploy::Fabric));
+	let loopback = envs.loopback.map_or(net::Ipv4Addr::LOCALHOST.into(), |x| {
+		x.expect("CONSTELLATION_LOOPBACK must be an IP address, like 127.0.0.1 or ::1")
+	});
+	assert!(
+		loopback.is_loopback(),
+		"CONSTELLATION_LOOPBACK must be a loopback address, like 127.0.0.1 or ::1"
+	);
+	*LOOPBACK.write().unwrap() = Some(loopback);
+	assert!(
+		resources_valid(resources),
+		"init() resources must have nonzero mem and a positive finite cpu, got {:?}",
+		resources
+	);
+	let deadlock_timeout = envs
+		.deadlock_timeout
+		.map(|x| x.expect("CONSTELLATION_DEADLOCK_TIMEOUT must be a whole number of seconds"));
+	let liveness_port = envs
+		.liveness_port
+		.map(|x| x.expect("CONSTELLATION_LIVENESS_PORT must be a port number"));
+	let liveness_host = envs.liveness_host.map(|x| {
+		x.expect("CONSTELLATION_LIVENESS_HOST must be an IP address, like 127.0.0.1 or 0.0.0.0")
+	});
+	let panic_capture = envs.panic_capture.map_or(false, |x| {
+		x.expect("CONSTELLATION_PANIC_CAPTURE must be 0 or 1")
+	});
+	let socket_buffer = envs
+		.socket_buffer
+		.map(|x| x.expect("CONSTELLATION_SOCKET_BUFFER must be a whole number of bytes"));
+	let listen_backlog = envs.listen_backlog.map_or(LISTEN_BACKLOG, |x| {
+		x.filter(|&x| x > 0)
+			.expect("CONSTELLATION_LISTEN_BACKLOG must be a positive whole number")
+	});
+	let select_seed = envs
+		.select_seed
+		.map(|x| x.expect("CONSTELLATION_SELECT_SEED must be a whole number"));
+	let channel_token = envs
+		.channel_token
+		.map(|x| x.expect("CONSTELLATION_CHANNEL_TOKEN must be 32 hex digits"));
+	#[cfg(not(feature = "tls"))]
+	assert!(
+		envs.tls_cert.is_none(),
+		"CONSTELLATION_TLS_CERT requires constellation to be built with the tls feature"
+	);
+	#[cfg(feature = "tls")]
+	let tls = envs.tls_cert.map(|cert| {
+		let cert = cert.unwrap();
+		let key = envs
+			.tls_key
+			.and_then(|x| x)
+			.expect("CONSTELLATION_TLS_KEY must be set along with CONSTELLATION_TLS_CERT");
+		let ca = envs
+			.tls_ca
+			.and_then(|x| x)
+			.expect("CONSTELLATION_TLS_CA must be set along with CONSTELLATION_TLS_CERT");
+		let name = envs.tls_name.map_or(Some(String::from("constellation")), |x| x);
+		let name = name.expect("CONSTELLATION_TLS_NAME must be a DNS name");
+		channel::TlsConfig::from_files(&cert, &key, &ca, &name)
+			.unwrap_or_else(|err| panic!("Failed to load the TLS certificates: {}", err))
+	});
+	let nodelay = envs
+		.nodelay
+		.map_or(true, |x| x.expect("CONSTELLATION_NODELAY must be 0 or 1"));
+	let mem_limit = envs.mem_limit.map_or(false, |x| {
+		x.expect("CONSTELLATION_MEM_LIMIT must be 0 or 1")
+	});
+	*MEM_LIMIT.write().unwrap() = mem_limit;
+	// Only processes spawned natively under CONSTELLATION_MEM_LIMIT are limited, and so report allocation failure as exceeding it
+	let mem_limited = mem_limit && data_limited();
+	let keep_binary = envs.keep_binary.map_or(false, |x| {
+		x.expect("CONSTELLATION_KEEP_BINARY must be 0 or 1")
+	});
+	let output_buffer = envs.output_buffer.map_or(OUTPUT_BUFFER, |x| {
+		x.expect("CONSTELLATION_OUTPUT_BUFFER must be a whole number of bytes")
+	});
+	let spawn_concurrency = envs.spawn_concurrency.map_or(1, |x| {
+		x.filter(|&x| x > 0)
+			.expect("CONSTELLATION_SPAWN_CONCURRENCY must be a positive whole number")
+	});
+	let keepalive_idle = envs.keepalive_idle.map_or(KEEPALIVE_IDLE_SECS, |x| {
+		x.filter(|&x| x <= KEEPALIVE_SECS_MAX)
+			.expect("CONSTELLATION_KEEPALIVE_IDLE must be a whole number of seconds, at most 32767")
+	});
+	let keepalive_interval = envs
+		.keepalive_interval
+		.map_or(KEEPALIVE_INTERVAL_SECS, |x| {
+			x.filter(|&x| x > 0 && x <= KEEPALIVE_SECS_MAX).expect(
+				"CONSTELLATION_KEEPALIVE_INTERVAL must be a positive whole number of seconds, at most 32767",
+			)
+		});
+	let keepalive_count = envs.keepalive_count.map_or(KEEPALIVE_COUNT, |x| {
+		x.filter(|&x| x > 0 && x <= KEEPALIVE_COUNT_MAX)
+			.expect("CONSTELLATION_KEEPALIVE_COUNT must be a positive whole number, at most 127")
+	});
+	let keepalive = if keepalive_idle > 0 {
+		Some((keepalive_idle, keepalive_interval, keepalive_count))
+	} else {
+		None
+	};
+	let scheduler_timeout = envs
+		.scheduler_timeout
+		.map_or(time::Duration::from_secs(SCHEDULER_TIMEOUT_SECS), |x| {
+			x.expect("CONSTELLATION_SCHEDULER_TIMEOUT must be a positive whole number of seconds")
+		});
 	if version {
 		assert!(!recce);
-		write!(io::stdout(), "deploy-lib {}", env!("CARGO_PKG_VERSION")).unwrap();
+		write!(io::stdout(), "deploy-lib {}", version().1).unwrap();
 		process::exit(0);
 	}
 	if recce {
 		let file = unsafe { fs::File::from_raw_fd(3) };
-		bincode::serialize_into(&file, &resources).unwrap();
+		bincode_config().serialize_into(&file, &resources).unwrap();
 		drop(file);
 		process::exit(0);
 	}
+	if recce_tree {
+		// Moved off fd 3, so the listener pid() is read from can take its place
+		let fd = fcntl::fcntl(
+			LISTENER_FD,
+			fcntl::FcntlArg::F_DUPFD_CLOEXEC(LISTENER_FD + 1),
+		)
+		.unwrap();
+		let (listener, _) = native_process_listener();
+		move_fd(listener, LISTENER_FD, fcntl::OFlag::empty(), false).unwrap();
+		*RECCE.0.lock().unwrap() = Some(Recce {
+			manifest: RecceManifest {
+				root: resources,
+				spawns: Vec::new(),
+			},
+			running: 0,
+			fd,
+		});
+		let err = unsafe { libc::atexit(recce_at_exit) };
+		assert_eq!(err, 0);
+		return Ok(());
+	}
 	let (subprocess, resources, argument, bridge, scheduler) = {
 		if !deployed {
 			if envs.resources.is_none() {
 				(false, resources, vec![], None, None)
 			} else {
 				let arg = unsafe { fs::File::from_raw_fd(ARG_FD) };
-				let bridge = bincode::deserialize_from(&mut &arg)
+				let bridge = bincode_config()
+					.deserialize_from(&mut &arg)
 					.map_err(map_bincode_err)
 					.unwrap();
 				let mut prog_arg = Vec::new();
@@ -1204,8 +4497,8 @@ pub fn init(resources: Resources) {
 			}
 		} else {
 			let arg = unsafe { fs::File::from_raw_fd(ARG_FD) };
-			let sched_arg: SchedulerArg = bincode::deserialize_from(&mut &arg).unwrap();
-			let bridge: Pid = bincode::deserialize_from(&mut &arg).unwrap();
+			let sched_arg: SchedulerArg = bincode_config().deserialize_from(&mut &arg).unwrap();
+			let bridge: Pid = bincode_config().deserialize_from(&mut &arg).unwrap();
 			let mut prog_arg = Vec::new();
 			let _ = (&arg).read_to_end(&mut prog_arg).unwrap();
 			let subprocess = !prog_arg.is_empty();
@@ -1221,6 +4514,22 @@ pub fn init(resources: Resources) {
 			)
 		}
 	};
+	// A relay bridge neither spawns nor runs user code, so needs neither the scheduler nor a monitor
+	if subprocess {
+		let start = bincode_config().deserialize_from::<_, (Pid, Start)>(&mut &*argument);
+		if let Ok((_, Start::Relay)) = start {
+			relay_bridge(bridge.unwrap(), listen_backlog);
+		}
+	}
+	// Connected before anything else is set up, so failing leaves the runtime uninitialised rather than half so
+	if let Some(scheduler) = scheduler {
+		let scheduler =
+			scheduler_connect(scheduler, scheduler_timeout).map_err(InitError::SchedulerConnect)?;
+		// ARG_FD was closed above, so it may have been reused
+		if scheduler != SCHEDULER_FD {
+			move_fd(scheduler, SCHEDULER_FD, fcntl::OFlag::empty(), false).unwrap();
+		}
+	}
 
 	trace!(
 		"PROCESS {}:{}: start setup; pid: {}",
@@ -1241,26 +4550,57 @@ pub fn init(resources: Resources) {
 			)
 			.unwrap();
 		}
-		let our_pid = Pid::new("127.0.0.1".parse().unwrap(), our_process_id);
+		let our_pid = Pid::new(loopback(), our_process_id);
 		assert_eq!(our_pid, pid());
-		native_bridge(format, our_pid)
+		native_bridge(
+			format,
+			color,
+			output_encoding,
+			deadlock_timeout,
+			liveness_port
+				.map(|port| net::SocketAddr::new(liveness_host.unwrap_or_else(loopback), port)),
+			listen_backlog,
+			our_pid,
+		)
 		// let err = unsafe{libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL)}; assert_eq!(err, 0);
 	});
 
 	*DEPLOYED.write().unwrap() = Some(deployed);
 	*RESOURCES.write().unwrap() = Some(resources);
 	*BRIDGE.write().unwrap() = Some(bridge);
+	NodeId::set_local(pid().node());
 
-	let fd = fcntl::open("/dev/null", fcntl::OFlag::O_RDWR, stat::Mode::empty()).unwrap();
+	let fd = dev_null().expect("Failed to open /dev/null or a fallback");
 	if fd != SCHEDULER_FD {
 		move_fd(fd, SCHEDULER_FD, fcntl::OFlag::empty(), true).unwrap();
 	}
 	copy_fd(SCHEDULER_FD, MONITOR_FD, fcntl::OFlag::empty(), true).unwrap();
 
-	let (socket_forwardee, monitor_writer, stdout_writer, stderr_writer, stdin_reader) =
-		monitor_process(bridge, deployed);
+	let (
+		socket_forwardee,
+		monitor_writer,
+		stdout_writer,
+		stderr_writer,
+		stdin_reader,
+		exits_reader,
+		mem_limit_writer,
+	) = monitor_process(
+		bridge,
+		deployed,
+		socket_buffer,
+		nodelay,
+		output_buffer,
+		mem_limited,
+		keep_binary,
+		keepalive,
+		listen_backlog,
+	);
 	assert_ne!(monitor_writer, MONITOR_FD);
 	move_fd(monitor_writer, MONITOR_FD, fcntl::OFlag::empty(), false).unwrap();
+	if let Some(mem_limit_writer) = mem_limit_writer {
+		MEM_LIMIT_FD.store(mem_limit_writer as isize, sync::atomic::Ordering::Relaxed);
+		alloc::set_alloc_error_hook(mem_limit_hook);
+	}
 	move_fd(
 		stdout_writer,
 		libc::STDOUT_FILENO,
@@ -1285,15 +4625,30 @@ pub fn init(resources: Resources) {
 	)
 	.unwrap();
 
-	if deployed {
-		let scheduler = net::TcpStream::connect(scheduler.unwrap())
-			.unwrap()
-			.into_raw_fd();
-		assert_ne!(scheduler, SCHEDULER_FD);
-		move_fd(scheduler, SCHEDULER_FD, fcntl::OFlag::empty(), false).unwrap();
+	{
+		let mut pool = SCHEDULER.0.lock().unwrap();
+		// Native spawns fork, so are made one at a time: a fork during another spawn would inherit the fds being set up for its process
+		pool.limit = if deployed { spawn_concurrency } else { 1 };
+		pool.addr = scheduler.filter(|_| deployed);
+		pool.timeout = scheduler_timeout;
 	}
 
-	let reactor = channel::Reactor::with_forwardee(socket_forwardee, pid().addr());
+	if let Some(seed) = select_seed {
+		channel::seed_select(seed);
+	}
+	if let Some(token) = channel_token {
+		channel::set_token(token, bridge.addr());
+	}
+	#[cfg(feature = "tls")]
+	{
+		if let Some(tls) = tls {
+			channel::set_tls(tls, bridge.addr());
+		}
+	}
+	if let Some(keepalive) = keepalive {
+		channel::set_keepalive(keepalive);
+	}
+	let reactor = channel::Reactor::with_forwardee(socket_forwardee, pid().addr(), listen_backlog);
 	*REACTOR.try_write().unwrap() = Some(reactor);
 	let handle = channel::Reactor::run(
 		|| BorrowMap::new(REACTOR.read().unwrap(), borrow_unwrap_option),
@@ -1301,8 +4656,27 @@ pub fn init(resources: Resources) {
 	);
 	*HANDLE.try_write().unwrap() = Some(handle);
 
-	let err = unsafe { libc::atexit(at_exit) };
-	assert_eq!(err, 0);
+	// Only recv_fd() needs it, so failing to listen fails that rather than init
+	let fd_listener = platform::Native::fd_listen(pid(), listen_backlog).map_err(|err| {
+		warn!("Failed to listen for fds, so recv_fd() will fail: {}", err);
+		err.kind()
+	});
+	*FD_LISTENER.try_write().unwrap() = Some(fd_listener);
+
+	if register_at_exit {
+		let err = unsafe { libc::atexit(at_exit) };
+		assert_eq!(err, 0);
+	}
+
+	let _ = thread_spawn(String::from("child-exits"), move || {
+		let exits = unsafe { fs::File::from_raw_fd(exits_reader) };
+		while let Ok((pid, exit_code)) = bincode_config()
+			.deserialize_from::<_, (Pid, ExitStatus)>(&mut &exits)
+			.map_err(map_bincode_err)
+		{
+			child_exited(pid, exit_code);
+		}
+	});
 
 	unsafe {
 		let _ = signal::sigaction(
@@ -1325,40 +4699,92 @@ pub fn init(resources: Resources) {
 	);
 
 	if !subprocess {
-		return;
+		Ok(())
 	} else {
 		let (start, parent) = {
 			let mut argument = io::Cursor::new(&argument);
-			let parent: Pid = bincode::deserialize_from(&mut argument)
+			let config = bincode_config();
+			let parent: Pid = config
+				.deserialize_from(&mut argument)
 				.map_err(map_bincode_err)
 				.unwrap();
-			let start: serde_closure::FnOnce<(Vec<u8>,), fn((Vec<u8>,), (Pid,))> =
-				bincode::deserialize_from(&mut argument)
-					.map_err(map_bincode_err)
-					.unwrap();
+			let mut start: Start = config
+				.deserialize_from(&mut argument)
+				.map_err(map_bincode_err)
+				.unwrap();
+			if let Start::ClosureWithPayload(_, ref mut payload) = start {
+				let offset: usize = argument.position().try_into().unwrap();
+				*payload = argument.get_ref()[offset..].to_vec();
+			}
 			(start, parent)
 		};
-		start(parent);
-		process::exit(0);
+		panic_hook(panic_capture);
+		if let Some(child_init) = *CHILD_INIT.read().unwrap() {
+			child_init();
+		}
+		match start {
+			Start::Closure(start) => {
+				start(parent);
+				if !register_at_exit {
+					shutdown();
+				}
+				process::exit(0);
+			}
+			Start::Payload(payload) => *EXEC_PAYLOAD.write().unwrap() = Some((parent, payload)),
+			Start::ClosureWithPayload(start, payload) => {
+				start(parent, payload);
+				if !register_at_exit {
+					shutdown();
+				}
+				process::exit(0);
+			}
+			Start::Relay => unreachable!(),
+		}
+		Ok(())
 	}
 }
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 fn forward_fd(
-	fd: Fd, reader: Fd, bridge_sender: mpsc::SyncSender<ProcessOutputEvent>,
+	fd: Fd, reader: Fd, bridge_sender: mpsc::SyncSender<ProcessOutputEvent>, buffer: usize,
 ) -> thread::JoinHandle<()> {
 	thread_spawn(String::from("monitor-forward_fd"), move || {
 		let reader = unsafe { fs::File::from_raw_fd(reader) };
 		let _ = fcntl::fcntl(reader.as_raw_fd(), fcntl::FcntlArg::F_GETFD).unwrap();
+		// Zeroed once per thread rather than per read, so zeroing costs nothing measurable; see examples/output_throughput.rs
+		let mut buf = vec![0; buffer.max(1)];
 		loop {
-			let mut buf: [u8; 1024] = unsafe { mem::uninitialized() };
-			let n = (&reader).read(&mut buf).unwrap();
+			let mut n = retry_eintr(|| unistd::read(reader.as_raw_fd(), &mut buf)).unwrap();
+			let mut eof = n == 0;
+			// Coalesce whatever else is written shortly after, so chatty output isn't a message per write
+			let flush = time::Instant::now() + time::Duration::from_millis(OUTPUT_FLUSH_MS);
+			while !eof && n < buf.len() {
+				let now = time::Instant::now();
+				if now >= flush {
+					break;
+				}
+				let timeout = (flush - now).subsec_millis().try_into().unwrap();
+				let mut fds = [poll::PollFd::new(
+					reader.as_raw_fd(),
+					poll::EventFlags::POLLIN,
+				)];
+				match poll::poll(&mut fds, timeout) {
+					Ok(0) => break,
+					Ok(_) => (),
+					Err(nix::Error::Sys(errno::Errno::EINTR)) => continue,
+					Err(err) => panic!("{:?}", err),
+				}
+				let read = retry_eintr(|| unistd::read(reader.as_raw_fd(), &mut buf[n..])).unwrap();
+				eof = read == 0;
+				n += read;
+			}
 			if n > 0 {
 				bridge_sender
 					.send(ProcessOutputEvent::Output(fd, buf[..n].to_owned()))
 					.unwrap();
-			} else {
+			}
+			if eof {
 				drop(reader);
 				bridge_sender
 					.send(ProcessOutputEvent::Output(fd, Vec::new()))
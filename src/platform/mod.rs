@@ -0,0 +1,72 @@
+//! What the runtime needs from the OS to pass fds between processes on the same node, to start processes, and for the sockets channels are carried over, behind [`Platform`] so it isn't bound to Unix.
+//!
+//! [`Native`] is the implementation for the OS being built for. Only Unix has one so far; for Windows, fds (handles) would be passed over named pipes with `DuplicateHandle`, processes started with `CreateProcess`, passing the inherited handles in its `STARTUPINFOEX` attribute list, and channel sockets set up with the equivalent Winsock calls.
+
+#[cfg(target_family = "unix")]
+mod unix;
+
+use constellation_internal::Pid;
+use std::{ffi::CString, io, net, os};
+
+#[cfg(target_family = "unix")]
+type Fd = os::unix::io::RawFd;
+#[cfg(target_family = "windows")]
+type Fd = os::windows::io::RawHandle;
+
+#[cfg(target_family = "unix")]
+pub use self::unix::Unix as Native;
+
+/// A process to start with [`Platform::spawn()`](Platform::spawn).
+pub struct Command<'a> {
+	/// The executable to run.
+	pub exe: &'a CString,
+	pub argv: &'a [CString],
+	/// `key=value` pairs.
+	pub envp: &'a [CString],
+	/// An already opened directory to run it in.
+	pub dir: Option<Fd>,
+	/// A limit on the memory it can allocate, in bytes.
+	pub mem_limit: Option<u64>,
+	/// The fds it inherits, and the fds they're moved to in it. All others are closed.
+	pub fds: &'a [(Fd, Fd)],
+	/// Whether `exe` is the binary of this process, which under valgrind is run from the fd valgrind holds it open with rather than by path.
+	pub reexec: bool,
+}
+
+pub trait Platform {
+	/// A process started with [`spawn()`](Platform::spawn).
+	type Process;
+
+	/// Start listening for fds sent to `pid`, queueing up to `backlog` unaccepted senders.
+	fn fd_listen(pid: Pid, backlog: usize) -> Result<Fd, io::Error>;
+	/// Send a copy of `fd` from `from` to `remote`, which must be on the same node.
+	fn fd_send(from: Pid, remote: Pid, fd: Fd) -> Result<(), io::Error>;
+	/// Block until an fd sent to `listener` arrives, returning who sent it and the copy, which is owned by the caller and not inherited by processes it starts. Returns [`InvalidData`](io::ErrorKind::InvalidData), having closed any fds that came with it, if what arrived is malformed or not from a process of the same user.
+	fn fd_accept(listener: Fd) -> Result<(Pid, Fd), io::Error>;
+	/// Start a process running `command`.
+	fn spawn(command: &Command) -> Result<Self::Process, io::Error>;
+	/// Kill a process started with [`spawn()`](Platform::spawn), ignoring failure as it may already have exited.
+	fn kill(process: Self::Process);
+
+	/// Create a nonblocking TCP socket bound to an ephemeral port of `ip`, for other processes to connect channels to. It's made to listen with [`channel_listen()`](Platform::channel_listen), so that it can first be moved to where it's expected.
+	fn channel_listener(ip: net::IpAddr) -> Result<Fd, io::Error>;
+	/// Start listening on a socket from [`channel_listener()`](Platform::channel_listener), queueing up to `backlog` unaccepted connections.
+	fn channel_listen(listener: Fd, backlog: usize) -> Result<(), io::Error>;
+	/// The address a channel socket is bound to.
+	fn channel_local_addr(fd: Fd) -> Result<net::SocketAddr, io::Error>;
+	/// The address of the other end of a channel connection.
+	fn channel_peer_addr(fd: Fd) -> Result<net::SocketAddr, io::Error>;
+	/// Whether to send small writes on a channel connection immediately, rather than coalescing them.
+	fn channel_nodelay(fd: Fd, nodelay: bool) -> Result<(), io::Error>;
+	/// Enable TCP keepalive on a channel connection. `keepalive` is `(idle, interval, count)`: probe after it's been idle for `idle` seconds, then every `interval` seconds, and error the connection after `count` unanswered probes.
+	fn channel_keepalive(fd: Fd, keepalive: (u16, u16, u16)) -> Result<(), io::Error>;
+	/// Set the send and receive buffer sizes of a channel socket to `size` bytes, returning the receive and send sizes the OS actually gave it.
+	fn channel_buffer(fd: Fd, size: usize) -> Result<(usize, usize), io::Error>;
+
+	/// Create a nonblocking unix domain socket listening for channel connections to `local`, a channel listener's address, from processes on the same node, queueing up to `backlog` unaccepted connections.
+	fn channel_unix_listener(local: net::SocketAddr, backlog: usize) -> Result<Fd, io::Error>;
+	/// Accept a connection on a socket from [`channel_unix_listener()`](Platform::channel_unix_listener), returning it nonblocking. Returns [`WouldBlock`](io::ErrorKind::WouldBlock) if there are none, and [`InvalidData`](io::ErrorKind::InvalidData), having closed it, if it's from a process of another user.
+	fn channel_unix_accept(listener: Fd) -> Result<Fd, io::Error>;
+	/// Connect a nonblocking unix domain socket to the [`channel_unix_listener()`](Platform::channel_unix_listener) for `remote`. Fails rather than waiting if it isn't listening or its backlog is full.
+	fn channel_unix_connect(remote: net::SocketAddr) -> Result<Fd, io::Error>;
+}
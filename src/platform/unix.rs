@@ -0,0 +1,385 @@
+use super::{Command, Fd, Platform};
+use constellation_internal::{bincode_config, retry_eintr, Pid};
+use nix::{
+	errno, fcntl, libc, sys::{signal, socket::{self, sockopt}, stat, uio}, unistd
+};
+use palaver::{fexecve, is_valgrind, valgrind_start_fd};
+use proc_self::{fd_path, FdIter};
+use std::{io, mem, net, path};
+use {map_nix_err, move_fd};
+
+/// Fds are passed over unix domain sockets with `SCM_RIGHTS`, processes started with `fork()` and `execve()`, and channel sockets set up with the BSD socket calls.
+pub struct Unix;
+impl Platform for Unix {
+	type Process = unistd::Pid;
+
+	fn fd_listen(pid: Pid, backlog: usize) -> Result<Fd, io::Error> {
+		let listener = socket::socket(
+			socket::AddressFamily::Unix,
+			socket::SockType::SeqPacket,
+			socket::SockFlag::SOCK_CLOEXEC,
+			None,
+		)
+		.map_err(map_nix_err)?;
+		// A socket file left by a process that had this pid before is in the way
+		#[cfg(not(any(target_os = "android", target_os = "linux")))]
+		let _ = unistd::unlink(&fd_socket_path(pid));
+		let ret =
+			socket::bind(listener, &fd_addr(pid)).and_then(|()| socket::listen(listener, backlog));
+		if let Err(err) = ret {
+			unistd::close(listener).unwrap();
+			return Err(map_nix_err(err));
+		}
+		Ok(listener)
+	}
+
+	fn fd_send(from: Pid, remote: Pid, fd: Fd) -> Result<(), io::Error> {
+		let from = bincode_config().serialize(&from).unwrap();
+		let stream = socket::socket(
+			socket::AddressFamily::Unix,
+			socket::SockType::SeqPacket,
+			socket::SockFlag::SOCK_CLOEXEC,
+			None,
+		)
+		.map_err(map_nix_err)?;
+		let ret = socket::connect(stream, &fd_addr(remote)).and_then(|()| {
+			socket::sendmsg(
+				stream,
+				&[uio::IoVec::from_slice(&from)],
+				&[socket::ControlMessage::ScmRights(&[fd])],
+				socket::MsgFlags::empty(),
+				None,
+			)
+		});
+		unistd::close(stream).unwrap();
+		ret.map(|_| ()).map_err(map_nix_err)
+	}
+
+	fn fd_accept(listener: Fd) -> Result<(Pid, Fd), io::Error> {
+		let stream = retry_eintr(|| socket::accept4(listener, socket::SockFlag::SOCK_CLOEXEC))
+			.map_err(map_nix_err)?;
+		// Any process on the node can connect to the socket, so take fds only from those of the same user, which could take them with ptrace anyway
+		let same_user = peer_uid(stream) == Some(unistd::geteuid());
+		let mut from = [0; 16];
+		let mut cmsgs: socket::CmsgSpace<[Fd; 1]> = socket::CmsgSpace::new();
+		let received = retry_eintr(|| {
+			socket::recvmsg(
+				stream,
+				&[uio::IoVec::from_mut_slice(&mut from)],
+				Some(&mut cmsgs),
+				socket::MsgFlags::empty(),
+			)
+			.map(|msg| {
+				let mut fds = Vec::new();
+				for cmsg in msg.cmsgs() {
+					if let socket::ControlMessage::ScmRights(received) = cmsg {
+						fds.extend_from_slice(received);
+					}
+				}
+				(msg.bytes, fds)
+			})
+		});
+		unistd::close(stream).unwrap();
+		let (bytes, fds) = received.map_err(map_nix_err)?;
+		let accepted = match (same_user, bytes, &*fds) {
+			(true, 16, &[fd]) => bincode_config()
+				.deserialize(&from)
+				.ok()
+				.map(|from| (from, fd)),
+			_ => None,
+		};
+		if let Some((from, fd)) = accepted {
+			let _ = fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFD(fcntl::FdFlag::FD_CLOEXEC)).unwrap();
+			Ok((from, fd))
+		} else {
+			for fd in fds {
+				unistd::close(fd).unwrap();
+			}
+			Err(io::ErrorKind::InvalidData.into())
+		}
+	}
+
+	fn spawn(command: &Command) -> Result<Self::Process, io::Error> {
+		let mem_limit = command.mem_limit.map(|mem_limit| libc::rlimit {
+			rlim_cur: mem_limit,
+			rlim_max: mem_limit,
+		});
+		match unistd::fork().map_err(map_nix_err)? {
+			unistd::ForkResult::Child => {
+				// Memory can be in a weird state now. Imagine a thread has just taken out a lock,
+				// but we've just forked. Lock still held. Avoid deadlock by doing nothing fancy here.
+				// Ideally including malloc.
+
+				// let err = unsafe{libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL)}; assert_eq!(err, 0);
+				unsafe {
+					let _ = signal::sigaction(
+						signal::SIGCHLD,
+						&signal::SigAction::new(
+							signal::SigHandler::SigDfl,
+							signal::SaFlags::empty(),
+							signal::SigSet::empty(),
+						),
+					)
+					.unwrap();
+				};
+
+				if let Some(dir) = command.dir {
+					unistd::fchdir(dir).unwrap();
+				}
+				if let Some(mem_limit) = mem_limit {
+					// RLIMIT_DATA rather than RLIMIT_AS, so address space reserved but not used isn't counted
+					let err = unsafe { libc::setrlimit(libc::RLIMIT_DATA, &mem_limit) };
+					assert_eq!(err, 0);
+				}
+
+				let valgrind_start_fd = if is_valgrind() {
+					Some(valgrind_start_fd())
+				} else {
+					None
+				};
+				// FdIter uses libc::opendir which mallocs. Underlying syscall is getdents…
+				for fd in FdIter::new().unwrap().filter(|&fd| {
+					fd >= 3
+						&& command.fds.iter().all(|&(from, _)| fd != from)
+						&& (valgrind_start_fd.is_none() || fd < valgrind_start_fd.unwrap())
+				}) {
+					unistd::close(fd).unwrap();
+				}
+
+				for &(from, to) in command.fds {
+					if from != to {
+						move_fd(from, to, fcntl::OFlag::empty(), true).unwrap();
+					}
+				}
+
+				if !is_valgrind() || !command.reexec {
+					unistd::execve(command.exe, command.argv, command.envp)
+						.expect("Failed to execve /proc/self/exe"); // or fexecve but on linux that uses proc also
+				} else {
+					let fd = fcntl::open::<path::PathBuf>(
+						&fd_path(valgrind_start_fd.unwrap()).unwrap(),
+						fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_CLOEXEC,
+						stat::Mode::empty(),
+					)
+					.unwrap();
+					let binary_desired_fd_ = valgrind_start_fd.unwrap() - 1;
+					assert!(binary_desired_fd_ > fd);
+					move_fd(fd, binary_desired_fd_, fcntl::OFlag::empty(), true).unwrap();
+					fexecve(binary_desired_fd_, command.argv, command.envp)
+						.expect("Failed to execve /proc/self/fd/n");
+				}
+				unreachable!();
+			}
+			unistd::ForkResult::Parent { child, .. } => Ok(child),
+		}
+	}
+
+	fn kill(process: Self::Process) {
+		let _ = signal::kill(process, signal::Signal::SIGKILL);
+	}
+
+	fn channel_listener(ip: net::IpAddr) -> Result<Fd, io::Error> {
+		let listener = socket::socket(
+			if ip.is_ipv4() {
+				socket::AddressFamily::Inet
+			} else {
+				socket::AddressFamily::Inet6
+			},
+			socket::SockType::Stream,
+			socket::SockFlag::SOCK_NONBLOCK,
+			socket::SockProtocol::Tcp,
+		)
+		.map_err(map_nix_err)?;
+		let ret = socket::setsockopt(listener, sockopt::ReuseAddr, &true)
+			.and_then(|()| {
+				socket::bind(
+					listener,
+					&socket::SockAddr::Inet(socket::InetAddr::from_std(&net::SocketAddr::new(
+						ip, 0,
+					))),
+				)
+			})
+			.and_then(|()| socket::setsockopt(listener, sockopt::ReusePort, &true));
+		if let Err(err) = ret {
+			unistd::close(listener).unwrap();
+			return Err(map_nix_err(err));
+		}
+		Ok(listener)
+	}
+
+	fn channel_listen(listener: Fd, backlog: usize) -> Result<(), io::Error> {
+		socket::listen(listener, backlog).map_err(map_nix_err)
+	}
+
+	fn channel_local_addr(fd: Fd) -> Result<net::SocketAddr, io::Error> {
+		socket::getsockname(fd)
+			.map_err(map_nix_err)
+			.and_then(inet_addr)
+	}
+
+	fn channel_peer_addr(fd: Fd) -> Result<net::SocketAddr, io::Error> {
+		socket::getpeername(fd)
+			.map_err(map_nix_err)
+			.and_then(inet_addr)
+	}
+
+	fn channel_nodelay(fd: Fd, nodelay: bool) -> Result<(), io::Error> {
+		socket::setsockopt(fd, sockopt::TcpNoDelay, &nodelay).map_err(map_nix_err)
+	}
+
+	fn channel_keepalive(
+		fd: Fd, (idle, interval, count): (u16, u16, u16),
+	) -> Result<(), io::Error> {
+		socket::setsockopt(fd, sockopt::KeepAlive, &true).map_err(map_nix_err)?;
+		#[cfg(any(target_os = "android", target_os = "linux"))]
+		{
+			for &(opt, value) in &[
+				(libc::TCP_KEEPIDLE, idle),
+				(libc::TCP_KEEPINTVL, interval),
+				(libc::TCP_KEEPCNT, count),
+			] {
+				let value = libc::c_int::from(value);
+				let err = unsafe {
+					libc::setsockopt(
+						fd,
+						libc::IPPROTO_TCP,
+						opt,
+						&value as *const libc::c_int as *const libc::c_void,
+						mem::size_of::<libc::c_int>() as libc::socklen_t,
+					)
+				};
+				let _ = errno::Errno::result(err).map_err(map_nix_err)?;
+			}
+		}
+		#[cfg(not(any(target_os = "android", target_os = "linux")))]
+		{
+			let _ = (idle, interval, count);
+		}
+		Ok(())
+	}
+
+	fn channel_buffer(fd: Fd, size: usize) -> Result<(usize, usize), io::Error> {
+		socket::setsockopt(fd, sockopt::RcvBuf, &size)
+			.and_then(|()| socket::setsockopt(fd, sockopt::SndBuf, &size))
+			.and_then(|()| {
+				Ok((
+					socket::getsockopt(fd, sockopt::RcvBuf)?,
+					socket::getsockopt(fd, sockopt::SndBuf)?,
+				))
+			})
+			.map_err(map_nix_err)
+	}
+
+	fn channel_unix_listener(local: net::SocketAddr, backlog: usize) -> Result<Fd, io::Error> {
+		let listener = socket::socket(
+			socket::AddressFamily::Unix,
+			socket::SockType::Stream,
+			socket::SockFlag::SOCK_NONBLOCK | socket::SockFlag::SOCK_CLOEXEC,
+			None,
+		)
+		.map_err(map_nix_err)?;
+		// A socket file left by a process that had this address before is in the way
+		#[cfg(not(any(target_os = "android", target_os = "linux")))]
+		let _ = unistd::unlink(&channel_socket_path(local));
+		let ret = socket::bind(listener, &channel_addr(local))
+			.and_then(|()| socket::listen(listener, backlog));
+		if let Err(err) = ret {
+			unistd::close(listener).unwrap();
+			return Err(map_nix_err(err));
+		}
+		Ok(listener)
+	}
+
+	fn channel_unix_accept(listener: Fd) -> Result<Fd, io::Error> {
+		let stream = retry_eintr(|| {
+			socket::accept4(
+				listener,
+				socket::SockFlag::SOCK_NONBLOCK | socket::SockFlag::SOCK_CLOEXEC,
+			)
+		})
+		.map_err(map_nix_err)?;
+		// As with fds, only from processes of the same user, which a TCP connection from the same node would have to be to have a channel listener's port
+		if peer_uid(stream) != Some(unistd::geteuid()) {
+			unistd::close(stream).unwrap();
+			return Err(io::ErrorKind::InvalidData.into());
+		}
+		Ok(stream)
+	}
+
+	fn channel_unix_connect(remote: net::SocketAddr) -> Result<Fd, io::Error> {
+		let stream = socket::socket(
+			socket::AddressFamily::Unix,
+			socket::SockType::Stream,
+			socket::SockFlag::SOCK_NONBLOCK | socket::SockFlag::SOCK_CLOEXEC,
+			None,
+		)
+		.map_err(map_nix_err)?;
+		if let Err(err) = socket::connect(stream, &channel_addr(remote)) {
+			unistd::close(stream).unwrap();
+			return Err(map_nix_err(err));
+		}
+		Ok(stream)
+	}
+}
+
+fn inet_addr(addr: socket::SockAddr) -> Result<net::SocketAddr, io::Error> {
+	if let socket::SockAddr::Inet(inet) = addr {
+		Ok(inet.to_std())
+	} else {
+		Err(io::ErrorKind::InvalidInput.into())
+	}
+}
+
+/// Where fds sent to `pid` are listened for: an abstract socket where there are such, so nothing is left behind on the filesystem.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn fd_addr(pid: Pid) -> socket::SockAddr {
+	socket::SockAddr::Unix(
+		socket::UnixAddr::new_abstract(format!("constellation-fds-{}", pid).as_bytes()).unwrap(),
+	)
+}
+/// Where fds sent to `pid` are listened for: a socket file in the temporary directory, as there are no abstract sockets.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn fd_addr(pid: Pid) -> socket::SockAddr {
+	socket::SockAddr::Unix(socket::UnixAddr::new(&fd_socket_path(pid)).unwrap())
+}
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn fd_socket_path(pid: Pid) -> path::PathBuf {
+	std::env::temp_dir().join(format!("constellation-fds-{}", pid))
+}
+
+/// Where channel connections to `local` from processes on the same node are listened for, as with [`fd_addr()`].
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn channel_addr(local: net::SocketAddr) -> socket::SockAddr {
+	socket::SockAddr::Unix(
+		socket::UnixAddr::new_abstract(format!("constellation-channel-{}", local).as_bytes())
+			.unwrap(),
+	)
+}
+/// Where channel connections to `local` from processes on the same node are listened for, as with [`fd_addr()`].
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn channel_addr(local: net::SocketAddr) -> socket::SockAddr {
+	socket::SockAddr::Unix(socket::UnixAddr::new(&channel_socket_path(local)).unwrap())
+}
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn channel_socket_path(local: net::SocketAddr) -> path::PathBuf {
+	std::env::temp_dir().join(format!("constellation-channel-{}", local))
+}
+
+/// The user of the process at the other end of the unix domain socket `stream`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn peer_uid(stream: Fd) -> Option<unistd::Uid> {
+	socket::getsockopt(stream, sockopt::PeerCredentials)
+		.ok()
+		.map(|creds| unistd::Uid::from_raw(creds.uid()))
+}
+/// The user of the process at the other end of the unix domain socket `stream`.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn peer_uid(stream: Fd) -> Option<unistd::Uid> {
+	let (mut uid, mut gid) = (0, 0);
+	let err = unsafe { libc::getpeereid(stream, &mut uid, &mut gid) };
+	if err == 0 {
+		Some(unistd::Uid::from_raw(uid))
+	} else {
+		None
+	}
+}
@@ -0,0 +1,92 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "6\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	for i in 1..=3 {
+		let _pid = spawn(
+			Resources {
+				mem: 20 * 1024 * 1024,
+				..Resources::default()
+			},
+			FnOnce!([i] move |parent| {
+				let sender = Sender::<usize>::new(parent);
+				sender.send(i);
+			}),
+		)
+		.expect("SPAWN FAILED");
+	}
+	let receiver = AnyReceiver::<usize>::new();
+	let (mut received, mut sum) = (0, 0);
+	while received < 3 {
+		if let (_pid, Ok(i)) = receiver.recv_any() {
+			received += 1;
+			sum += i;
+		}
+	}
+	println!("{}", sum);
+}
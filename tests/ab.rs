@@ -0,0 +1,37 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let compact = pid().to_compact();
+	assert_eq!(Pid::from_compact(&compact), Ok(pid()));
+	assert_eq!(Pid::from_compact(&compact.to_uppercase()), Ok(pid()));
+	assert_eq!(
+		Pid::from_compact("!"),
+		Err(ParsePidError::InvalidCharacter('!'))
+	);
+	assert_eq!(
+		Pid::from_compact(&"a".repeat(27)),
+		Err(ParsePidError::InvalidLength)
+	);
+	assert_eq!(Pid::from_addr(pid().addr()), pid());
+}
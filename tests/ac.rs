@@ -0,0 +1,73 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "1234\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "1235\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+#[macro_use]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let pid = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let sender = Sender::<usize>::new(parent);
+			let receiver = Receiver::<usize>::new(parent);
+			select! {
+				send(sender, 1234) => (),
+			}
+			let x: usize = select! {
+				recv(receiver) -> x => x.unwrap(),
+			};
+			println!("{}", x);
+		}),
+	)
+	.expect("SPAWN FAILED");
+	let sender = Sender::<usize>::new(pid);
+	let receiver = Receiver::<usize>::new(pid);
+	let x = select! {
+		recv(receiver) -> x => {
+			let x: usize = x.unwrap();
+			println!("{}", x);
+			x
+		}
+	};
+	select! {
+		send(sender, x + 1) => {}
+	}
+}
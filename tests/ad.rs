@@ -0,0 +1,68 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+#![allow(clippy::unused_unit)] // for FnOnce!
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	match try_spawn(
+		Resources {
+			mem: 0,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| ()),
+	) {
+		Err(SpawnError::InvalidResources(_)) => (),
+		x => panic!("{:?}", x),
+	}
+	match try_spawn(
+		Resources {
+			cpu: 0.0,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| ()),
+	) {
+		Err(SpawnError::InvalidResources(_)) => (),
+		x => panic!("{:?}", x),
+	}
+	match try_spawn(
+		Resources {
+			mem: std::u64::MAX,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| ()),
+	) {
+		// More than any node has, so retrying would never help
+		Err(ref err @ SpawnError::Unsatisfiable(_)) if !err.is_transient() => (),
+		x => panic!("{:?}", x),
+	}
+	assert!(SpawnError::InsufficientResources(None).is_transient());
+	assert!(spawn(
+		Resources {
+			mem: 0,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| ()),
+	)
+	.is_none());
+}
@@ -0,0 +1,93 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "3 true\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{thread, time};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let start = time::Instant::now();
+	let spawned = scope(|scope| {
+		(0..3)
+			.filter_map(|_| {
+				scope.spawn(
+					Resources {
+						mem: 20 * 1024 * 1024,
+						..Resources::default()
+					},
+					FnOnce!(|_parent| {
+						thread::sleep(time::Duration::from_millis(500));
+					}),
+				)
+			})
+			.count()
+	})
+	.unwrap();
+	println!(
+		"{} {}",
+		spawned,
+		start.elapsed() >= time::Duration::from_millis(500)
+	);
+}
@@ -0,0 +1,66 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "false true true\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": {
+//=         "Error": {
+//=           "Unix": {
+//=             "Status": 1
+//=           }
+//=         }
+//=       }
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::process;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| {
+			process::exit(1);
+		}),
+	)
+	.expect("SPAWN FAILED");
+	let exit_code = wait(child).unwrap();
+	println!(
+		"{} {} {}",
+		exit_code.success(),
+		wait(child) == Err(WaitError::NotAChild),
+		wait(pid()) == Err(WaitError::NotAChild)
+	);
+}
@@ -0,0 +1,58 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "hi\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{fs, io::Write, os::unix::io::{FromRawFd, IntoRawFd}};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	// Inject a frame, whose event is truncated, into the monitor's pipe
+	let monitor = unsafe { fs::File::from_raw_fd(5) };
+	(&monitor).write_all(&[1, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+	let _ = monitor.into_raw_fd();
+	// The monitor should skip it and still track this child
+	let _child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| {
+			println!("hi");
+		}),
+	)
+	.expect("SPAWN FAILED");
+}
@@ -0,0 +1,86 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "14 14 2\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "7\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "7\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{thread, time};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let mut peers = PeerMap::<u32, u32>::new();
+	for _ in 0..2 {
+		let child = spawn(
+			Resources {
+				mem: 20 * 1024 * 1024,
+				..Resources::default()
+			},
+			FnOnce!(|parent| {
+				let receiver = Receiver::<u32>::new(parent);
+				let sender = Sender::<u32>::new(parent);
+				let x = receiver.recv().unwrap();
+				println!("{}", x);
+				sender.send(x * 2);
+			}),
+		)
+		.expect("SPAWN FAILED");
+		let _ = peers.insert(child);
+	}
+	peers.broadcast(7);
+	let replies = peers
+		.iter()
+		.map(|(_, _, receiver)| receiver.recv().unwrap())
+		.collect::<Vec<_>>();
+	let mut exited = 0;
+	while !peers.is_empty() {
+		exited += peers.remove_on_exit().len();
+		thread::sleep(time::Duration::from_millis(10));
+	}
+	println!("{} {} {}", replies[0], replies[1], exited);
+}
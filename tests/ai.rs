@@ -0,0 +1,60 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "payload true\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+use constellation::*;
+use std::{env, path::PathBuf, str};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	if let Some((parent, payload)) = exec_payload() {
+		println!(
+			"{} {}",
+			str::from_utf8(&payload).unwrap(),
+			env::args().nth(1) == Some(String::from("arg")) && parent != pid()
+		);
+		return;
+	}
+	// Spawn this same executable, but as if it were a different one
+	let _child = spawn_exec(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		PathBuf::from(env::args_os().next().unwrap()),
+		vec!["arg".into()],
+		b"payload".to_vec(),
+	)
+	.expect("SPAWN FAILED");
+}
@@ -0,0 +1,65 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "hello\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{
+	io::{Read, Write}, os::unix::{
+		io::{AsRawFd, FromRawFd}, net::UnixStream
+	}
+};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let fd = recv_fd(parent).unwrap();
+			let mut stream = unsafe { UnixStream::from_raw_fd(fd) };
+			let mut message = String::new();
+			let _ = stream.read_to_string(&mut message).unwrap();
+			println!("{}", message);
+		}),
+	)
+	.expect("SPAWN FAILED");
+	let (mut ours, theirs) = UnixStream::pair().unwrap();
+	send_fd(child, theirs.as_raw_fd()).unwrap();
+	drop(theirs);
+	ours.write_all(b"hello").unwrap();
+}
@@ -0,0 +1,92 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "hello\nworld\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{
+	io::{Read, Write}, os::unix::{io::AsRawFd, net::UnixStream}, thread, time
+};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let receiver = Receiver::<()>::new(parent);
+			receiver.recv().unwrap();
+			Sender::<String>::new(parent).send(String::from("hello"));
+			receiver.recv().unwrap();
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let sender = Sender::<()>::new(child);
+	let receiver = Receiver::<String>::new(child);
+	let (mut a, mut b) = UnixStream::pair().unwrap();
+	let fd = b.as_raw_fd();
+
+	// Nothing has been written to the fd, so the select is woken by the channel
+	sender.send(());
+	let mut received = None;
+	let _ = select(vec![
+		Box::new(selectable_fd(fd, Interest::Read, || {
+			panic!("nothing was written")
+		})),
+		Box::new(receiver.selectable_recv(|t| received = Some(t.unwrap()))),
+	]);
+	println!("{}", received.unwrap());
+
+	// Nothing more is sent on the channel until after, so the select is woken by the fd being written to once it's blocked
+	let writer = thread::spawn(move || {
+		thread::sleep(time::Duration::from_millis(100));
+		a.write_all(b"world").unwrap();
+	});
+	let start = time::Instant::now();
+	let mut buf = [0; 5];
+	let _ = select(vec![
+		Box::new(selectable_fd(fd, Interest::Read, || {
+			b.read_exact(&mut buf).unwrap();
+		})),
+		Box::new(receiver.selectable_recv(|_| panic!("nothing was sent"))),
+	]);
+	assert!(start.elapsed() >= time::Duration::from_millis(100));
+	writer.join().unwrap();
+	println!("{}", std::str::from_utf8(&buf).unwrap());
+	sender.send(());
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
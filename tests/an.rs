@@ -0,0 +1,71 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "true\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "/\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{env, path::PathBuf};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let resources = Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	};
+	let _child = spawn_in_dir(
+		resources,
+		PathBuf::from("/"),
+		FnOnce!(|_parent| {
+			println!("{}", env::current_dir().unwrap().display());
+		}),
+	)
+	.expect("SPAWN FAILED");
+	let missing = spawn_in_dir(
+		resources,
+		PathBuf::from("/nonexistent-constellation-dir"),
+		FnOnce!(|_parent| {
+			unreachable!();
+		}),
+	);
+	// Deployed, the node reports the directory as invalid, rather than the spawn as lacking resources
+	println!(
+		"{}",
+		match missing {
+			Err(SpawnError::InvalidDirectory(dir)) =>
+				dir == PathBuf::from("/nonexistent-constellation-dir"),
+			_ => false,
+		}
+	);
+}
@@ -0,0 +1,60 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "110\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let (sender, receiver) = spawn_worker::<usize, usize, _>(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|receiver, sender| {
+			for _ in 0..10 {
+				sender.send(receiver.recv().unwrap() * 2);
+			}
+		}),
+	)
+	.expect("SPAWN FAILED");
+	let mut sum = 0;
+	for i in 1..=10 {
+		sender.send(i);
+		sum += receiver.recv().unwrap();
+	}
+	println!("{}", sum);
+}
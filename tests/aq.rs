@@ -0,0 +1,88 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "true\nff0080efbeadde0000000000000080ffffffffffffffff0403020100000000feffffffffffffff\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "255 -32768 3735928559 -9223372036854775808 18446744073709551615 16909060 -2\nff0080efbeadde0000000000000080ffffffffffffffff0403020100000000feffffffffffffff\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate bincode;
+extern crate constellation;
+extern crate hex;
+#[macro_use]
+extern crate serde_closure;
+#[macro_use]
+extern crate serde_derive;
+use constellation::*;
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+struct Ints {
+	a: u8,
+	b: i16,
+	c: u32,
+	d: i64,
+	e: u64,
+	f: usize,
+	g: isize,
+}
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let ints = Ints {
+		a: u8::max_value(),
+		b: i16::min_value(),
+		c: 0xdead_beef,
+		d: i64::min_value(),
+		e: u64::max_value(),
+		f: 0x0102_0304,
+		g: -2,
+	};
+	let sent = ints.clone();
+	// Through the spawn arg, then back over a channel
+	let pid = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!([sent] move |parent| {
+			let ints: Ints = sent;
+			println!(
+				"{} {} {} {} {} {} {}",
+				ints.a, ints.b, ints.c, ints.d, ints.e, ints.f, ints.g
+			);
+			println!("{}", hex::encode(bincode::serialize(&ints).unwrap()));
+			Sender::<Ints>::new(parent).send(ints);
+		}),
+	)
+	.expect("SPAWN FAILED");
+	let received = Receiver::<Ints>::new(pid).recv().unwrap();
+	println!("{}", received == ints);
+	// As encoded on channels: little-endian and fixed-width, whichever machine it's on
+	println!("{}", hex::encode(bincode::serialize(&received).unwrap()));
+}
@@ -0,0 +1,96 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "\\[0, 0, 1, 1, 2, 2\\]\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let pids = (0..3)
+		.map(|i| {
+			spawn(
+				Resources {
+					mem: 20 * 1024 * 1024,
+					..Resources::default()
+				},
+				FnOnce!([i] move |parent| {
+					let sender = Sender::<usize>::new(parent);
+					sender.send(i);
+					sender.send(i);
+				}),
+			)
+			.expect("spawn() failed to allocate process")
+		})
+		.collect::<Vec<_>>();
+	let mut receiver = MultiReceiver::<usize>::new(pids.iter().cloned());
+	assert_eq!(receiver.len(), 3);
+	let mut received = Vec::new();
+	while let Some((pid, i)) = receiver.recv() {
+		assert_eq!(pid, pids[i]);
+		received.push(i);
+	}
+	assert!(receiver.is_empty());
+	received.sort();
+	println!("{:?}", received);
+}
@@ -0,0 +1,73 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "received 4194304 bytes\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::io::{self, Read, Write};
+
+const LEN: usize = 4 * 1024 * 1024;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let data = (0..LEN).map(|i| i as u8).collect::<Vec<_>>();
+			let mut sender = Sender::<u8>::new(parent);
+			sender.write_all(&data).unwrap();
+			// Each write is a message, however many bytes it writes
+			let messages_sent = metrics().messages_sent;
+			assert!(messages_sent > 0 && messages_sent < LEN as u64);
+			assert!(metrics().bytes_sent >= LEN as u64);
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let mut receiver = Receiver::<u8>::new(child);
+	let mut data = vec![0; LEN];
+	receiver.read_exact(&mut data).unwrap();
+	assert!(data.iter().enumerate().all(|(i, &x)| x == i as u8));
+	assert_eq!(
+		receiver.read(&mut [0]).unwrap_err().kind(),
+		io::ErrorKind::UnexpectedEof
+	);
+	let messages_received = metrics().messages_received;
+	assert!(messages_received > 0 && messages_received < LEN as u64);
+	assert!(metrics().bytes_received >= LEN as u64);
+	println!("received {} bytes", data.len());
+}
@@ -0,0 +1,60 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "Success\nSuccess\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::sync::mpsc;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| ()),
+	)
+	.expect("spawn() failed to allocate process");
+	let (sender, receiver) = mpsc::channel();
+	let sender1 = sender.clone();
+	on_exit(child, move |exit_code| sender1.send(exit_code).unwrap()).unwrap();
+	println!("{:?}", receiver.recv().unwrap());
+	// Already exited, so this fires promptly
+	on_exit(child, move |exit_code| sender.send(exit_code).unwrap()).unwrap();
+	println!("{:?}", receiver.recv().unwrap());
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+	assert_eq!(on_exit(child, |_| ()), Err(WaitError::NotAChild));
+}
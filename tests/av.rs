@@ -0,0 +1,62 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "detached\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn_detached(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let receiver = Receiver::<String>::new(parent);
+			let sender = Sender::<String>::new(parent);
+			sender.send(receiver.recv().unwrap());
+		}),
+	)
+	.expect("spawn_detached() failed to allocate process");
+	// Not tracked as a child
+	assert!(children().is_empty());
+	assert_eq!(wait(child), Err(WaitError::NotAChild));
+	assert_eq!(on_exit(child, |_| ()), Err(WaitError::NotAChild));
+	let sender = Sender::<String>::new(child);
+	let receiver = Receiver::<String>::new(child);
+	sender.send(String::from("detached"));
+	println!("{}", receiver.recv().unwrap());
+}
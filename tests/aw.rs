@@ -0,0 +1,51 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+extern crate nix;
+use constellation::*;
+use nix::sys::signal::Signal;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let success = ExitStatus::Success;
+	let status = ExitStatus::from_unix_status(3);
+	let other = ExitStatus::from_unix_status(4);
+	let signal = ExitStatus::from_unix_signal(Signal::SIGKILL);
+	// Success is the identity
+	assert_eq!(success + success, success);
+	assert_eq!(success + status, status);
+	assert_eq!(status + success, status);
+	assert_eq!(signal + success, signal);
+	// The same error is kept, differing errors are indeterminate
+	assert_eq!(status + status, status);
+	assert_eq!(i32::from(status + other), 101);
+	assert_eq!(i32::from(status + signal), 101);
+	assert_eq!(i32::from(signal + status + success), 101);
+	// The result doesn't depend on order
+	let statuses = [success, status, signal, status, success];
+	let forward = statuses.iter().fold(success, |a, &b| a + b);
+	let backward = statuses.iter().rev().fold(success, |a, &b| a + b);
+	assert_eq!(forward, backward);
+	// Codes a shell would report
+	assert_eq!(i32::from(success), 0);
+	assert_eq!(i32::from(status), 3);
+	assert_eq!(i32::from(signal), 128 + 9);
+	assert_eq!(i32::from(ExitStatus::from_unix_status(255)), 255);
+}
@@ -0,0 +1,64 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "initialised\n",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INITIALISED: AtomicBool = AtomicBool::new(false);
+
+fn child_init() {
+	INITIALISED.store(true, Ordering::SeqCst);
+}
+
+fn main() {
+	set_child_init(child_init);
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	// Not run in the initial process
+	assert!(!INITIALISED.load(Ordering::SeqCst));
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| {
+			assert!(INITIALISED.load(Ordering::SeqCst));
+			println!("initialised");
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
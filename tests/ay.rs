@@ -0,0 +1,70 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "received 3 then disconnected\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let sender = Sender::<usize>::new(parent);
+			for i in 0..3 {
+				sender.send(i);
+			}
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let receiver = Receiver::<usize>::new(child);
+	let mut received = 0;
+	loop {
+		match receiver.recv_event() {
+			RecvEvent::Message(i) => {
+				assert_eq!(i, received);
+				received += 1;
+			}
+			RecvEvent::Disconnected => break,
+			RecvEvent::Failed(err) => panic!("{}", err),
+		}
+	}
+	println!("received {} then disconnected", received);
+	assert_eq!(receiver.recv(), Err(ChannelError::Exited));
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
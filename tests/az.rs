@@ -0,0 +1,114 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [
+//=         {
+//=           "output": {
+//=             "2": [
+//=               "",
+//=               true
+//=             ],
+//=             "1": [
+//=               "",
+//=               true
+//=             ]
+//=           },
+//=           "children": [],
+//=           "exit": {
+//=             "Error": {
+//=               "Unix": {
+//=                 "Signal": "SIGKILL"
+//=               }
+//=             }
+//=           }
+//=         }
+//=       ],
+//=       "exit": {
+//=         "Error": {
+//=           "Unix": {
+//=             "Signal": "SIGKILL"
+//=           }
+//=         }
+//=       }
+//=     }
+//=   ],
+//=   "exit": {
+//=     "Error": {
+//=       "Unix": {
+//=         "Signal": "SIGABRT"
+//=       }
+//=     }
+//=   }
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+extern crate nix;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{thread, time};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let _grandchild = spawn(
+				Resources {
+					mem: 20 * 1024 * 1024,
+					..Resources::default()
+				},
+				FnOnce!(|_parent| loop {
+					thread::sleep(time::Duration::from_secs(1));
+				}),
+			)
+			.expect("spawn() failed to allocate process");
+			Sender::<()>::new(parent).send(());
+			loop {
+				thread::sleep(time::Duration::from_secs(1));
+			}
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	Receiver::<()>::new(child).recv().unwrap();
+	// Give the bridge time to learn of the grandchild, which is reported by the child's monitor rather than ours
+	thread::sleep(time::Duration::from_secs(1));
+	let err = unsafe {
+		nix::libc::setrlimit(
+			nix::libc::RLIMIT_CORE,
+			&nix::libc::rlimit {
+				rlim_cur: 0,
+				rlim_max: 0,
+			},
+		)
+	};
+	assert_eq!(err, 0);
+	abort()
+}
@@ -0,0 +1,71 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "echoed 0 1 2\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::env;
+
+fn main() {
+	env::set_var(
+		"CONSTELLATION_CHANNEL_TOKEN",
+		"000102030405060708090a0b0c0d0e0f",
+	);
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let receiver = Receiver::<usize>::new(parent);
+			let sender = Sender::<usize>::new(parent);
+			for _ in 0..3 {
+				sender.send(receiver.recv().unwrap());
+			}
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let sender = Sender::<usize>::new(child);
+	let receiver = Receiver::<usize>::new(child);
+	let echoed = (0..3)
+		.map(|i| {
+			sender.send(i);
+			receiver.recv().unwrap().to_string()
+		})
+		.collect::<Vec<_>>();
+	println!("echoed {}", echoed.join(" "));
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
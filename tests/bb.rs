@@ -0,0 +1,68 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "copied 100000 bytes\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::io::{self, Read, Write};
+
+// Many times io::copy()'s buffer
+const LEN: usize = 100_000;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let mut receiver = Receiver::<u8>::new(parent);
+			let mut data = vec![0; LEN];
+			receiver.read_exact(&mut data).unwrap();
+			assert!(data.iter().enumerate().all(|(i, &x)| x == (i % 251) as u8));
+			let mut done = [0; 4];
+			receiver.read_exact(&mut done).unwrap();
+			assert_eq!(&done, b"done");
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let data = (0..LEN).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+	let mut sender = Sender::<u8>::new(child);
+	let copied = io::copy(&mut &data[..], &mut sender).unwrap();
+	write!(sender, "done").unwrap();
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+	println!("copied {} bytes", copied);
+}
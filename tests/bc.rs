@@ -0,0 +1,72 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "received 135\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let receiver = Receiver::<usize>::new(parent);
+			let sum = (0..30).map(|_| receiver.recv().unwrap()).sum::<usize>();
+			Sender::<usize>::new(parent).send(sum);
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+
+	// Each returns once all but `capacity` of those sent have been received
+	for &capacity in &[0, 1, 2] {
+		let sender = Sender::<usize>::with_capacity(child, capacity);
+		for i in 0..5 {
+			sender.send(i);
+		}
+		for i in 5..10 {
+			let _ = select(vec![Box::new(sender.selectable_send(move || i))]);
+		}
+	}
+	let receiver = Receiver::<usize>::new(child);
+	println!("received {}", receiver.recv().unwrap());
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+
+	// The child can no longer receive, so this returns rather than waiting on it
+	let sender = Sender::<usize>::with_capacity(child, 0);
+	assert!(sender.try_send(0).is_err());
+}
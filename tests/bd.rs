@@ -0,0 +1,75 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "received 1048576 bytes\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": {
+//=         "Error": {
+//=           "Unix": {
+//=             "Signal": "SIGKILL"
+//=           }
+//=         }
+//=       }
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+extern crate nix;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use nix::{sys::signal, unistd};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let sender = Sender::<Vec<u8>>::new(parent);
+			for i in 0..16 {
+				sender.send(vec![i as u8; 64 * 1024]);
+			}
+			sender.drain().unwrap();
+			// Exit without unwinding or running exit handlers: what was drained must already be with the OS
+			signal::kill(unistd::getpid(), signal::Signal::SIGKILL).unwrap();
+			unreachable!()
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let receiver = Receiver::<Vec<u8>>::new(child);
+	let mut received = 0;
+	for i in 0..16 {
+		let bytes = receiver.recv().unwrap();
+		assert_eq!(bytes, vec![i as u8; 64 * 1024]);
+		received += bytes.len();
+	}
+	println!("received {} bytes", received);
+}
@@ -0,0 +1,139 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "\\[0, 1, 2, 0, 1, 2\\]\n\\[1, 2, 1, 2\\]\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::cell::RefCell;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let children = (0..3)
+		.map(|_| {
+			spawn(
+				Resources {
+					mem: 20 * 1024 * 1024,
+					..Resources::default()
+				},
+				FnOnce!(|parent| {
+					let receiver = Receiver::<usize>::new(parent);
+					while receiver.recv().unwrap() != 0 {}
+				}),
+			)
+			.expect("spawn() failed to allocate process")
+		})
+		.collect::<Vec<_>>();
+	let senders = children
+		.iter()
+		.map(|&child| Sender::<usize>::new(child))
+		.collect::<Vec<_>>();
+	// Once connected, and what's been sent flushed, each can be sent to without blocking
+	for sender in &senders {
+		sender.send(1);
+		sender.flush();
+	}
+
+	// Each stays progressable, as what's sent is flushed before the next select, so each is executed in turn
+	let order = RefCell::new(Vec::new());
+	let mut round_robin = RoundRobin::new();
+	for _ in 0..6 {
+		let i = {
+			let push = |i| {
+				order.borrow_mut().push(i);
+				1
+			};
+			let mut a = senders[0].selectable_send(|| push(0));
+			let mut b = senders[1].selectable_send(|| push(1));
+			let mut c = senders[2].selectable_send(|| push(2));
+			round_robin.select_mut(&mut [&mut a, &mut b, &mut c])
+		};
+		senders[i].flush();
+	}
+	println!("{:?}", order.borrow());
+
+	// Nothing is sent by the first child, so receiving from it isn't progressable, and it's skipped over
+	let receiver = Receiver::<usize>::new(children[0]);
+	let order = RefCell::new(Vec::new());
+	let mut round_robin = RoundRobin::new();
+	for _ in 0..4 {
+		let i = {
+			let push = |i| {
+				order.borrow_mut().push(i);
+				1
+			};
+			let mut a = receiver.selectable_recv(|_| order.borrow_mut().push(0));
+			let mut b = senders[1].selectable_send(|| push(1));
+			let mut c = senders[2].selectable_send(|| push(2));
+			round_robin.select_mut(&mut [&mut a, &mut b, &mut c])
+		};
+		senders[i].flush();
+	}
+	println!("{:?}", order.borrow());
+
+	for sender in &senders {
+		sender.send(0);
+	}
+	for child in children {
+		assert_eq!(wait(child), Ok(ExitStatus::Success));
+	}
+}
@@ -0,0 +1,122 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "100\ntrue\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::cell::Cell;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let children = (0..2)
+		.map(|_| {
+			spawn(
+				Resources {
+					mem: 20 * 1024 * 1024,
+					..Resources::default()
+				},
+				FnOnce!(|parent| {
+					let receiver = Receiver::<usize>::new(parent);
+					while receiver.recv().unwrap() != 0 {}
+				}),
+			)
+			.expect("spawn() failed to allocate process")
+		})
+		.collect::<Vec<_>>();
+	let senders = children
+		.iter()
+		.map(|&child| Sender::<usize>::new(child))
+		.collect::<Vec<_>>();
+	// Once connected, and what's been sent flushed, each can be sent to without blocking
+	for sender in &senders {
+		sender.send(1);
+		sender.flush();
+	}
+
+	// Nothing is sent by the first child, so receiving from it isn't progressable, and sending is picked however heavily that's weighted
+	let receiver = Receiver::<usize>::new(children[0]);
+	let picked = Cell::new(0);
+	for _ in 0..100 {
+		{
+			let mut heavy = weighted(100, receiver.selectable_recv(|_| ()));
+			let mut light = senders[1].selectable_send(|| {
+				picked.set(picked.get() + 1);
+				1
+			});
+			let _ = select_mut(&mut [&mut heavy, &mut light]);
+		}
+		senders[1].flush();
+	}
+	println!("{}", picked.get());
+
+	// Of two progressable sends, one weighted 3 is picked three quarters of the time
+	let picked = Cell::new(0);
+	for _ in 0..1000 {
+		let i = {
+			let mut heavy = weighted(
+				3,
+				senders[0].selectable_send(|| {
+					picked.set(picked.get() + 1);
+					1
+				}),
+			);
+			let mut light = senders[1].selectable_send(|| 1);
+			select_mut(&mut [&mut heavy, &mut light])
+		};
+		senders[i].flush();
+	}
+	println!("{}", 650 < picked.get() && picked.get() < 850);
+
+	for sender in &senders {
+		sender.send(0);
+	}
+	for child in children {
+		assert_eq!(wait(child), Ok(ExitStatus::Success));
+	}
+}
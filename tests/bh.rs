@@ -0,0 +1,117 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "None 2 false\nSome\\(1\\) 1 true\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::{cell::Cell, rc::Rc, thread};
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let children = (0..2)
+		.map(|_| {
+			spawn(
+				Resources {
+					mem: 20 * 1024 * 1024,
+					..Resources::default()
+				},
+				FnOnce!(|parent| {
+					// Send once told to, then exit once told to
+					let receiver = Receiver::<bool>::new(parent);
+					if receiver.recv().unwrap() {
+						Sender::<()>::new(parent).send(());
+						let _ = receiver.recv().unwrap();
+					}
+				}),
+			)
+			.expect("spawn() failed to allocate process")
+		})
+		.collect::<Vec<_>>();
+	let senders = children
+		.iter()
+		.map(|&child| Sender::<bool>::new(child))
+		.collect::<Vec<_>>();
+	let receivers = children
+		.iter()
+		.map(|&child| Receiver::<()>::new(child))
+		.collect::<Vec<_>>();
+	let executed = Rc::new(Cell::new(false));
+	let executed_ = executed.clone();
+	let selectables: Vec<Box<Selectable>> = vec![
+		Box::new(receivers[0].selectable_recv(|_| ())),
+		Box::new(receivers[1].selectable_recv(move |t| {
+			t.unwrap();
+			executed_.set(true)
+		})),
+	];
+
+	// Nothing has been sent, so it returns rather than blocking, and none are consumed
+	let (i, selectables) = try_select(selectables);
+	println!("{:?} {} {}", i, selectables.len(), executed.get());
+
+	// Until what the second child sends arrives, it keeps returning all of them
+	senders[1].send(true);
+	let (mut i, mut selectables) = try_select(selectables);
+	while i.is_none() {
+		assert!(selectables.len() == 2 && !executed.get());
+		thread::yield_now();
+		let (i_, selectables_) = try_select(selectables);
+		i = i_;
+		selectables = selectables_;
+	}
+	println!("{:?} {} {}", i, selectables.len(), executed.get());
+
+	drop(selectables);
+	for sender in &senders {
+		sender.send(false);
+	}
+	for child in children {
+		assert_eq!(wait(child), Ok(ExitStatus::Success));
+	}
+}
@@ -0,0 +1,93 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "\\[\\(2, 5\\), \\(3, 7\\), \\(20, 2\\), \\(20, 3\\)\\] 37\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate bincode;
+extern crate constellation;
+extern crate nix;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use nix::unistd;
+use std::{env, fs, io, os::unix::{io::FromRawFd, process::CommandExt}, process};
+
+const MIB: u64 = 1024 * 1024;
+
+fn main() {
+	init(Resources {
+		mem: 20 * MIB,
+		..Resources::default()
+	});
+	if env::var_os("CONSTELLATION_RECCE_TREE").is_some() {
+		// Recorded rather than run: two children, each spawning a grandchild before waiting on their parent
+		// Still has its own pid, despite there being no listener from the fabric
+		let _ = pid();
+		let children = [(2, 5), (3, 7)]
+			.iter()
+			.map(|&(child, grandchild)| {
+				spawn(
+					Resources {
+						mem: child * MIB,
+						..Resources::default()
+					},
+					FnOnce!([grandchild] move |parent| {
+						let _ = spawn(
+							Resources {
+								mem: grandchild * MIB,
+								..Resources::default()
+							},
+							FnOnce!(|_parent| ()),
+						)
+						.unwrap();
+						let _ = Receiver::<()>::new(parent);
+						unreachable!()
+					}),
+				)
+				.unwrap()
+			})
+			.collect::<Vec<_>>();
+		// Ends the recce, once the children have got as far
+		let _ = Receiver::<()>::new(children[0]);
+		unreachable!()
+	}
+	let (reader, writer) = unistd::pipe().unwrap();
+	// SIGCHLD is ignored, so it's reaped for us, and its output being complete shows it exited
+	let _child = process::Command::new("/proc/self/exe")
+		.env("CONSTELLATION_RECCE_TREE", "1")
+		.stdout(process::Stdio::null())
+		.before_exec(move || {
+			let _ = unistd::dup2(writer, 3).map_err(|_| io::Error::last_os_error())?;
+			Ok(())
+		})
+		.spawn()
+		.unwrap();
+	unistd::close(writer).unwrap();
+	let reader = unsafe { fs::File::from_raw_fd(reader) };
+	let manifest: RecceManifest = bincode::deserialize_from(reader).unwrap();
+	let mem = |index: usize| {
+		if index == 0 {
+			manifest.root.mem / MIB
+		} else {
+			manifest.spawns[index - 1].1.mem / MIB
+		}
+	};
+	let mut tree = manifest
+		.spawns
+		.iter()
+		.map(|&(parent, resources)| (mem(parent), resources.mem / MIB))
+		.collect::<Vec<_>>();
+	tree.sort();
+	println!("{:?} {}", tree, manifest.total().mem / MIB);
+}
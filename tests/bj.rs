@@ -0,0 +1,58 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let free = resources_available().unwrap();
+	assert!(free.mem > 0 && free.cpu >= 0.0, "{:?}", free);
+	// The scheduler connection is still in step afterwards, so spawning and querying again work
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| {
+			let free = resources_available().unwrap();
+			assert!(free.mem > 0 && free.cpu >= 0.0, "{:?}", free);
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let _ = resources_available().unwrap();
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
@@ -0,0 +1,68 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let receiver = Receiver::<usize>::new(parent);
+			let received = (0..10)
+				.map(|_| receiver.recv().unwrap())
+				.collect::<Vec<_>>();
+			assert_eq!(received, (0..10).collect::<Vec<_>>());
+			let sender = Sender::<usize>::new(parent);
+			sender.send(received.len());
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let sender = Sender::<usize>::new(child);
+	for i in 0..10 {
+		sender.send_confirmed(i).unwrap();
+	}
+	let receiver = Receiver::<usize>::new(child);
+	assert_eq!(receiver.recv().unwrap(), 10);
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+	// The child has exited, so can't acknowledge it
+	assert!(sender.send_confirmed(10).is_err());
+}
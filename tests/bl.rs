@@ -0,0 +1,95 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let sender = Sender::<usize>::new(parent);
+			for i in 0..20 {
+				sender.send(i);
+			}
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let receiver = Receiver::<usize>::new(child).with_filter(|i| i % 2 == 0);
+	let received = (0..10)
+		.map(|_| receiver.recv().unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(received, (0..20).step_by(2).collect::<Vec<_>>());
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+	// The odd numbers were dropped, so there's nothing left to receive
+	let (i, _) = try_select(vec![Box::new(receiver.selectable_recv(|_| ())) as Box<Selectable>]);
+	assert!(i.is_none());
+
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let sender = Sender::<AnyMessage>::new(parent);
+			sender.send(AnyMessage::new(&String::from("skipped")));
+			sender.send(AnyMessage::new(&20usize));
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let receiver = Receiver::<AnyMessage>::new(child).with_filter(AnyMessage::is::<usize>);
+	assert_eq!(receiver.recv().unwrap().downcast::<usize>().unwrap(), 20);
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
@@ -0,0 +1,69 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [],
+//=   "exit": "Success"
+//= }
+
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use std::panic;
+
+// Built with the test-transport feature, so the children are threads of this process
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			assert_ne!(pid(), parent);
+			let receiver = Receiver::<usize>::new(parent);
+			let sender = Sender::<usize>::new(parent);
+			for _ in 0..100 {
+				sender.send(receiver.recv().unwrap() + 1);
+			}
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	assert_ne!(child, pid());
+	let sender = Sender::<usize>::new(child);
+	let receiver = Receiver::<usize>::new(child);
+	for i in 0..100 {
+		sender.send(i);
+		assert_eq!(receiver.recv().unwrap(), i + 1);
+	}
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+	// It has exited, so its end is closed
+	assert_eq!(receiver.recv(), Err(ChannelError::Exited));
+
+	// Its panic would otherwise be printed, as it's on a thread of this process
+	panic::set_hook(Box::new(|_| ()));
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|_parent| {
+			panic!("child panicked");
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	assert!(!wait(child).unwrap().success());
+}
@@ -0,0 +1,86 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+extern crate serde;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+use serde::de::{Deserialize, DeserializeSeed, Deserializer};
+use std::cell::RefCell;
+
+/// Deserializes a `String`, returning its index in a table of those seen so far.
+struct Intern<'a>(&'a RefCell<Vec<String>>);
+impl<'a, 'de> DeserializeSeed<'de> for Intern<'a> {
+	type Value = usize;
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+		let string = String::deserialize(deserializer)?;
+		let mut strings = self.0.borrow_mut();
+		let index = strings.iter().position(|s| *s == string);
+		Ok(index.unwrap_or_else(|| {
+			strings.push(string);
+			strings.len() - 1
+		}))
+	}
+}
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let child = spawn(
+		Resources {
+			mem: 20 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let sender = Sender::<String>::new(parent);
+			for string in &["a", "b", "a", "c", "b", "a"] {
+				sender.send(String::from(*string));
+			}
+			sender.send(String::from("d"));
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let receiver = Receiver::<String>::new(child);
+	let strings = RefCell::new(Vec::new());
+	let indices = (0..6)
+		.map(|_| receiver.recv_deserialize_with(Intern(&strings)).unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(indices, vec![0, 1, 0, 2, 1, 0]);
+	assert_eq!(strings.into_inner(), vec!["a", "b", "c"]);
+	// Seeded and unseeded receives can be mixed
+	assert_eq!(receiver.recv().unwrap(), "d");
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
@@ -0,0 +1,72 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+const CHUNKS: usize = 64;
+const CHUNK: usize = 256 * 1024;
+
+fn chunk(i: usize) -> Vec<u8> {
+	(0..CHUNK).map(|j| (i * 7 + j) as u8).collect()
+}
+
+fn main() {
+	init(Resources {
+		mem: 64 * 1024 * 1024,
+		..Resources::default()
+	});
+	// Processes on the same node, so each chunk is carried there and back over a unix domain socket, many times what it buffers
+	let child = spawn(
+		Resources {
+			mem: 64 * 1024 * 1024,
+			..Resources::default()
+		},
+		FnOnce!(|parent| {
+			let receiver = Receiver::<Vec<u8>>::new(parent);
+			let sender = Sender::<Vec<u8>>::new(parent);
+			for _ in 0..CHUNKS {
+				sender.send(receiver.recv().unwrap());
+			}
+		}),
+	)
+	.expect("spawn() failed to allocate process");
+	let sender = Sender::<Vec<u8>>::new(child);
+	let receiver = Receiver::<Vec<u8>>::new(child);
+	for i in 0..CHUNKS {
+		sender.send(chunk(i));
+		assert!(receiver.recv().unwrap() == chunk(i));
+	}
+	assert_eq!(wait(child), Ok(ExitStatus::Success));
+}
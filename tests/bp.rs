@@ -0,0 +1,121 @@
+//= {
+//=   "output": {
+//=     "2": [
+//=       "",
+//=       true
+//=     ],
+//=     "1": [
+//=       "1\n",
+//=       true
+//=     ]
+//=   },
+//=   "children": [
+//=     {
+//=       "output": {},
+//=       "children": [],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [
+//=         {
+//=           "output": {
+//=             "2": [
+//=               "",
+//=               true
+//=             ],
+//=             "1": [
+//=               "grandchild\n",
+//=               true
+//=             ]
+//=           },
+//=           "children": [],
+//=           "exit": "Success"
+//=         }
+//=       ],
+//=       "exit": "Success"
+//=     },
+//=     {
+//=       "output": {
+//=         "2": [
+//=           "",
+//=           true
+//=         ],
+//=         "1": [
+//=           "",
+//=           true
+//=         ]
+//=       },
+//=       "children": [],
+//=       "exit": "Success"
+//=     }
+//=   ],
+//=   "exit": "Success"
+//= }
+
+#![deny(warnings, deprecated)]
+extern crate constellation;
+#[macro_use]
+extern crate serde_closure;
+use constellation::*;
+
+fn main() {
+	init(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	});
+	let relay = spawn_relay(Resources {
+		mem: 20 * 1024 * 1024,
+		..Resources::default()
+	})
+	.expect("spawn_relay() failed to allocate process");
+	let pids = (0..2)
+		.map(|i| {
+			relay
+				.spawn(
+					Resources {
+						mem: 20 * 1024 * 1024,
+						..Resources::default()
+					},
+					FnOnce!([i] move |parent| {
+						// Spawned through the relay too, as it's this process's bridge
+						if i == 0 {
+							let grandchild = spawn(
+								Resources {
+									mem: 20 * 1024 * 1024,
+									..Resources::default()
+								},
+								FnOnce!(|_parent| {
+									println!("grandchild");
+								}),
+							)
+							.expect("spawn() failed to allocate process");
+							assert_eq!(wait(grandchild), Ok(ExitStatus::Success));
+						}
+						let sender = Sender::<usize>::new(parent);
+						sender.send(i);
+					}),
+				)
+				.expect("Relay::spawn() failed to allocate process")
+		})
+		.collect::<Vec<_>>();
+	// The relay is detached, but what's spawned through it are children
+	assert_eq!(children().len(), 2);
+	let mut sum = 0;
+	for &pid in &pids {
+		let receiver = Receiver::<usize>::new(pid);
+		sum += receiver.recv().unwrap();
+		drop(receiver);
+		assert_eq!(wait(pid), Ok(ExitStatus::Success));
+	}
+	println!("{}", sum);
+}
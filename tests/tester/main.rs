@@ -167,13 +167,16 @@ fn parse_output(output: &process::Output) -> Result<Output, Option<serde_json::E
 				let x = log.insert(b, (HashMap::new(), Vec::new(), None));
 				assert!(x.is_none());
 			}
-			constellation_internal::DeployOutputEvent::Exit(a, b) => {
+			constellation_internal::DeployOutputEvent::Exit(a, b, _) => {
 				if top.is_none() {
 					top = Some(a);
 					let _ = log.insert(a, (HashMap::new(), Vec::new(), None));
 				}
 				log.get_mut(&a).unwrap().2 = Some(b);
 			}
+			constellation_internal::DeployOutputEvent::Panic(..)
+			| constellation_internal::DeployOutputEvent::Deadlock(_)
+			| constellation_internal::DeployOutputEvent::Metric(..) => (),
 		}
 	}
 	let top = top.unwrap();